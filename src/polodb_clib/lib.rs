@@ -876,6 +876,48 @@ fn error_code_of_db_err(err: &DbErr) -> i32 {
         DbErr::Busy => 41,
         DbErr::NotAValidField(_) => 42,
         DbErr::CollectionAlreadyExits(_) => 43,
+        DbErr::TransactionAllocLimitExceeded(_) => 44,
+        DbErr::IntentOpInProgress => 45,
+        DbErr::NoIntentOpInProgress => 46,
+        DbErr::IntentOpNotFound(_) => 47,
+        DbErr::InvalidCheckpointThreshold(_) => 48,
+        DbErr::PageChecksumMismatch { .. } => 49,
+        DbErr::ReadOnly => 50,
+        DbErr::SavepointNotFound(_) => 51,
+        DbErr::DataTicketNotFound { .. } => 52,
+        DbErr::InvalidEncryptionKey => 53,
+        DbErr::InvalidPageSize(_) => 54,
+        DbErr::PageSizeMismatch(_, _) => 55,
+        DbErr::NotADatabaseFile => 56,
+        DbErr::UnsupportedVersion { .. } => 57,
+        DbErr::DestinationExists(_) => 58,
+        DbErr::CannotFreeHeaderPage => 59,
+        DbErr::PageIdOutOfRange { .. } => 60,
+        DbErr::CheckpointDuringTransaction => 61,
+        DbErr::CheckpointInProgress => 62,
+        DbErr::InvalidInitBlockCount(_) => 63,
+        DbErr::TransactionTooLarge(_) => 64,
+        DbErr::PageOffsetOutOfBounds { .. } => 65,
+        DbErr::Internal(_) => 66,
+        DbErr::NotADataPage(_) => 67,
+        DbErr::JournalPathMismatch => 68,
+        DbErr::ReservedPagesFull(_) => 69,
+        DbErr::PageReserved(_) => 70,
+        DbErr::PageNotReserved(_) => 71,
+        DbErr::PageIo { .. } => 72,
+        DbErr::CacheFull => 73,
+        DbErr::DatabaseLocked { .. } => 74,
+        DbErr::CompressionNotSupported => 75,
+        DbErr::CorruptCompressedPage(_) => 76,
+        DbErr::BrokenFreeList(_) => 77,
+        DbErr::AlreadyExists(_) => 78,
+        DbErr::NotFound(_) => 79,
+        DbErr::OutOfSpace { .. } => 80,
+        DbErr::NoDatabaseFile => 81,
+        DbErr::FileSizeLimitExceeded { .. } => 82,
+        DbErr::NotInReadTransaction => 83,
+        DbErr::PageAlreadyFree(_) => 84,
+        DbErr::CannotReplaceEncrypted => 85,
 
     }
 }
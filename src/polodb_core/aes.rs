@@ -0,0 +1,322 @@
+// AES-256 (FIPS-197) and the XTS mode (IEEE 1619) for the page bodies,
+// the tables are computed at compile time.
+//
+// The S-box is a table indexed by the secret state, it's not constant-time:
+// on a machine shared with an attacker, the cache timing may leak the key.
+pub const AES_BLOCK_SIZE: usize = 16;
+
+const AES256_ROUNDS: usize = 14;
+
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1B;
+        }
+        b >>= 1;
+    }
+    result
+}
+
+// the multiplicative inverse is x^254
+const fn gf_inverse(x: u8) -> u8 {
+    let mut result = 1u8;
+    let mut i = 0;
+    while i < 254 {
+        result = gf_mul(result, x);
+        i += 1;
+    }
+    result
+}
+
+const fn make_sbox() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let b = gf_inverse(i as u8);
+        table[i] = b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63;
+        i += 1;
+    }
+    table
+}
+
+const fn make_inv_sbox(sbox: &[u8; 256]) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[sbox[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+}
+
+static SBOX: [u8; 256] = make_sbox();
+static INV_SBOX: [u8; 256] = make_inv_sbox(&SBOX);
+
+#[derive(Clone, Copy)]
+pub struct Aes256 {
+    round_keys: [[u8; AES_BLOCK_SIZE]; AES256_ROUNDS + 1],
+}
+
+impl Aes256 {
+
+    pub fn new(key: &[u8; 32]) -> Aes256 {
+        let mut words = [[0u8; 4]; 4 * (AES256_ROUNDS + 1)];
+        for (i, word) in words.iter_mut().take(8).enumerate() {
+            word.copy_from_slice(&key[(i * 4)..(i * 4 + 4)]);
+        }
+
+        let mut rcon = 1u8;
+        for i in 8..words.len() {
+            let mut temp = words[i - 1];
+            if i % 8 == 0 {
+                temp = [
+                    SBOX[temp[1] as usize] ^ rcon,
+                    SBOX[temp[2] as usize],
+                    SBOX[temp[3] as usize],
+                    SBOX[temp[0] as usize],
+                ];
+                rcon = gf_mul(rcon, 2);
+            } else if i % 8 == 4 {
+                for byte in temp.iter_mut() {
+                    *byte = SBOX[*byte as usize];
+                }
+            }
+            for j in 0..4 {
+                words[i][j] = words[i - 8][j] ^ temp[j];
+            }
+        }
+
+        let mut round_keys = [[0u8; AES_BLOCK_SIZE]; AES256_ROUNDS + 1];
+        for (round, round_key) in round_keys.iter_mut().enumerate() {
+            for col in 0..4 {
+                round_key[(col * 4)..(col * 4 + 4)].copy_from_slice(&words[round * 4 + col]);
+            }
+        }
+
+        Aes256 { round_keys }
+    }
+
+    #[inline]
+    fn add_round_key(state: &mut [u8; AES_BLOCK_SIZE], round_key: &[u8; AES_BLOCK_SIZE]) {
+        for (byte, key_byte) in state.iter_mut().zip(round_key.iter()) {
+            *byte ^= *key_byte;
+        }
+    }
+
+    // the state is column-major, row r is shifted by r
+    fn shift_rows(state: &mut [u8; AES_BLOCK_SIZE], inverse: bool) {
+        let origin = *state;
+        for row in 1..4 {
+            for col in 0..4 {
+                let from = if inverse { (col + 4 - row) % 4 } else { (col + row) % 4 };
+                state[row + 4 * col] = origin[row + 4 * from];
+            }
+        }
+    }
+
+    fn mix_columns(state: &mut [u8; AES_BLOCK_SIZE], inverse: bool) {
+        let coefficients: [u8; 4] = if inverse { [14, 11, 13, 9] } else { [2, 3, 1, 1] };
+        for col in 0..4 {
+            let column = [state[4 * col], state[4 * col + 1], state[4 * col + 2], state[4 * col + 3]];
+            for row in 0..4 {
+                let mut value = 0u8;
+                for (k, byte) in column.iter().enumerate() {
+                    value ^= gf_mul(*byte, coefficients[(k + 4 - row) % 4]);
+                }
+                state[4 * col + row] = value;
+            }
+        }
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        Aes256::add_round_key(block, &self.round_keys[0]);
+        for round in 1..=AES256_ROUNDS {
+            for byte in block.iter_mut() {
+                *byte = SBOX[*byte as usize];
+            }
+            Aes256::shift_rows(block, false);
+            if round != AES256_ROUNDS {
+                Aes256::mix_columns(block, false);
+            }
+            Aes256::add_round_key(block, &self.round_keys[round]);
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        Aes256::add_round_key(block, &self.round_keys[AES256_ROUNDS]);
+        for round in (0..AES256_ROUNDS).rev() {
+            Aes256::shift_rows(block, true);
+            for byte in block.iter_mut() {
+                *byte = INV_SBOX[*byte as usize];
+            }
+            Aes256::add_round_key(block, &self.round_keys[round]);
+            if round != 0 {
+                Aes256::mix_columns(block, true);
+            }
+        }
+    }
+
+}
+
+/**
+ * XTS with ciphertext stealing, the length is preserved,
+ * so a page body of any size (at least a block) is encrypted in place.
+ *
+ * The tweak is the sector number, i.e. the page id.
+ */
+#[derive(Clone, Copy)]
+pub struct AesXts {
+    data_cipher:  Aes256,
+    tweak_cipher: Aes256,
+}
+
+impl AesXts {
+
+    pub fn new(data_key: &[u8; 32], tweak_key: &[u8; 32]) -> AesXts {
+        AesXts {
+            data_cipher: Aes256::new(data_key),
+            tweak_cipher: Aes256::new(tweak_key),
+        }
+    }
+
+    fn initial_tweak(&self, sector: u64) -> [u8; AES_BLOCK_SIZE] {
+        let mut tweak = [0u8; AES_BLOCK_SIZE];
+        tweak[0..8].copy_from_slice(&sector.to_le_bytes());
+        self.tweak_cipher.encrypt_block(&mut tweak);
+        tweak
+    }
+
+    // multiply by the primitive element in GF(2^128), little-endian
+    fn next_tweak(tweak: &mut [u8; AES_BLOCK_SIZE]) {
+        let mut carry = 0u8;
+        for byte in tweak.iter_mut() {
+            let next_carry = *byte >> 7;
+            *byte = (*byte << 1) | carry;
+            carry = next_carry;
+        }
+        if carry != 0 {
+            tweak[0] ^= 0x87;
+        }
+    }
+
+    fn crypt_block(&self, block: &mut [u8], tweak: &[u8; AES_BLOCK_SIZE], encrypt: bool) {
+        let mut buffer = [0u8; AES_BLOCK_SIZE];
+        for i in 0..AES_BLOCK_SIZE {
+            buffer[i] = block[i] ^ tweak[i];
+        }
+        if encrypt {
+            self.data_cipher.encrypt_block(&mut buffer);
+        } else {
+            self.data_cipher.decrypt_block(&mut buffer);
+        }
+        for i in 0..AES_BLOCK_SIZE {
+            block[i] = buffer[i] ^ tweak[i];
+        }
+    }
+
+    pub fn encrypt(&self, sector: u64, data: &mut [u8]) {
+        self.crypt(sector, data, true)
+    }
+
+    pub fn decrypt(&self, sector: u64, data: &mut [u8]) {
+        self.crypt(sector, data, false)
+    }
+
+    fn crypt(&self, sector: u64, data: &mut [u8], encrypt: bool) {
+        assert!(data.len() >= AES_BLOCK_SIZE, "data of xts is shorter than a block");
+
+        let tail_len = data.len() % AES_BLOCK_SIZE;
+        let block_count = data.len() / AES_BLOCK_SIZE;
+
+        // the last full block is handled with the tail
+        let normal_blocks = if tail_len == 0 { block_count } else { block_count - 1 };
+
+        let mut tweak = self.initial_tweak(sector);
+        for index in 0..normal_blocks {
+            let begin = index * AES_BLOCK_SIZE;
+            self.crypt_block(&mut data[begin..(begin + AES_BLOCK_SIZE)], &tweak, encrypt);
+            AesXts::next_tweak(&mut tweak);
+        }
+
+        if tail_len == 0 {
+            return;
+        }
+
+        let last_begin = normal_blocks * AES_BLOCK_SIZE;
+        let tail_begin = last_begin + AES_BLOCK_SIZE;
+
+        let mut last_tweak = tweak;
+        AesXts::next_tweak(&mut last_tweak);
+
+        // decrypting swaps the order of the tweaks
+        let (first_tweak, second_tweak) = if encrypt { (tweak, last_tweak) } else { (last_tweak, tweak) };
+
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        block.copy_from_slice(&data[last_begin..tail_begin]);
+        self.crypt_block(&mut block, &first_tweak, encrypt);
+
+        // steal the ciphertext to pad the tail
+        let mut stolen = block;
+        stolen[0..tail_len].copy_from_slice(&data[tail_begin..]);
+        data[tail_begin..].copy_from_slice(&block[0..tail_len]);
+
+        self.crypt_block(&mut stolen, &second_tweak, encrypt);
+        data[last_begin..tail_begin].copy_from_slice(&stolen);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aes::{Aes256, AesXts};
+
+    #[test]
+    fn test_aes256_block() {
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let cipher = Aes256::new(&key);
+
+        let plain: [u8; 16] = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+            0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+        ];
+        let mut block = plain;
+        cipher.encrypt_block(&mut block);
+        assert_eq!(block, [
+            0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf,
+            0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49, 0x60, 0x89,
+        ]);
+
+        cipher.decrypt_block(&mut block);
+        assert_eq!(block, plain);
+    }
+
+    #[test]
+    fn test_xts_round_trip() {
+        let xts = AesXts::new(&[1; 32], &[2; 32]);
+
+        for len in &[16usize, 32, 4092, 4096, 37] {
+            let plain: Vec<u8> = (0..*len).map(|i| (i % 251) as u8).collect();
+            let mut data = plain.clone();
+
+            xts.encrypt(7, &mut data);
+            assert_ne!(data, plain);
+
+            let mut other_sector = plain.clone();
+            xts.encrypt(8, &mut other_sector);
+            assert_ne!(data, other_sector);
+
+            xts.decrypt(7, &mut data);
+            assert_eq!(data, plain);
+        }
+    }
+
+}
@@ -248,7 +248,7 @@ impl BTreeNode {
     fn parse_data_item_ticket(page: &RawPage, begin_offset: u32) -> DataTicket {
         let ticket_bytes = (begin_offset + 6 + 12) as usize;
         let ticket_bytes = &page.data[ticket_bytes..(ticket_bytes + 6)];
-        DataTicket::from_bytes(ticket_bytes)
+        DataTicket::from_store_bytes(ticket_bytes)
     }
 
     pub(crate) fn to_raw(&self, page: &mut RawPage) -> DbResult<()> {
@@ -285,7 +285,7 @@ impl BTreeNode {
             BTreeNode::entry_key_to_bytes(page, &item.key)?;
 
             // 6 bytes for ticket
-            let ticket_bytes = item.data_ticket.to_bytes();
+            let ticket_bytes = item.data_ticket.to_store_bytes();
             page.put(&ticket_bytes);
 
             index += 1;
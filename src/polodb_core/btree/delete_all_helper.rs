@@ -3,6 +3,7 @@ use crate::meta_doc_helper::MetaDocEntry;
 use crate::page::PageHandler;
 use super::wrapper_base::cal_item_size;
 use crate::btree::BTreeNode;
+use crate::data_ticket::DataTicket;
 
 pub(crate) fn delete_all(page_handler: &mut PageHandler, collection_meta: MetaDocEntry) -> DbResult<()> {
     let item_size = cal_item_size(page_handler.page_size);
@@ -16,9 +17,10 @@ fn delete_all_by_btree_pid(page_handler: &mut PageHandler, item_size: u32, paren
         return Ok(())
     }
 
-    for item in btree.content {
-        page_handler.free_data_ticket(&item.data_ticket)?;
-    }
+    let data_tickets: Vec<DataTicket> = btree.content.into_iter()
+        .map(|item| item.data_ticket)
+        .collect();
+    page_handler.free_data_tickets(&data_tickets)?;
 
     for child_pid in btree.indexes {
         delete_all_by_btree_pid(page_handler, item_size, pid, child_pid)?;
@@ -0,0 +1,251 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::page::{PageStore, MemoryPageStore};
+use crate::DbResult;
+use crate::error::DbErr;
+
+static HEADER_DESP: &str          = "PoloDB Change Map v0.1";
+const MAP_ID_OFFSET: u64          = 32;
+const SEQUENCE_OFFSET: u64        = 40;
+const CHANGE_MAP_DATA_BEGIN: u64  = 64;
+const ENTRY_SIZE: u64             = 8;
+
+/**
+ * The position of an incremental backup,
+ * the pages committed after it are changed since the backup.
+ *
+ * The map id tells the change maps apart,
+ * a marker of another map can't be compared.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BackupMarker {
+    pub map_id:   u64,
+    pub sequence: u64,
+}
+
+// title:  32 bytes
+// map id: 8 bytes at 32
+// committed sequence: 8 bytes at 40
+// data begin: 64
+//
+// entry of page n at 64 + n * 8:
+// | the sequence of the last commit writing the page |
+// | 8 bytes                                          |
+//
+// An entry is 0 if the page is not written since the map was created.
+// The entries are written before the journal commits,
+// if the commit doesn't finish the pages are reported even unchanged,
+// a changed page is never missed.
+pub(crate) struct ChangeMap {
+    file:      Box<dyn PageStore>,
+    map_id:    u64,
+    sequence:  u64,
+}
+
+fn generate_map_id() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0);
+    let random = unsafe {
+        libc::rand() as u64
+    };
+    // never 0, the default marker is of no map
+    (nanos ^ (random << 32)) | 1
+}
+
+impl ChangeMap {
+
+    pub(crate) fn mk_path(db_path: &Path) -> PathBuf {
+        let mut buf = db_path.to_path_buf();
+        let filename = buf.file_name().unwrap().to_str().unwrap();
+        let new_filename = String::from(filename) + ".changes";
+        buf.set_file_name(new_filename);
+        buf
+    }
+
+    // a new database drops the map of the file used to be at the path
+    pub(crate) fn open(path: &Path, reset: bool) -> DbResult<ChangeMap> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .read(true)
+            .open(path)?;
+
+        ChangeMap::open_store(Box::new(file), reset)
+    }
+
+    // None if the database has no map
+    pub(crate) fn open_read_only(path: &Path) -> DbResult<Option<ChangeMap>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(path)?;
+
+        let file_len = file.metadata()?.len();
+        if file_len < CHANGE_MAP_DATA_BEGIN {
+            return Ok(None);
+        }
+
+        let mut result = ChangeMap {
+            file: Box::new(file),
+            map_id: 0,
+            sequence: 0,
+        };
+        result.load_header()?;
+
+        Ok(Some(result))
+    }
+
+    pub(crate) fn open_in_memory() -> DbResult<ChangeMap> {
+        ChangeMap::open_store(Box::new(MemoryPageStore::new()), true)
+    }
+
+    fn open_store(file: Box<dyn PageStore>, reset: bool) -> DbResult<ChangeMap> {
+        let file_len = file.len()?;
+
+        let mut result = ChangeMap {
+            file,
+            map_id: 0,
+            sequence: 0,
+        };
+
+        if reset || file_len < CHANGE_MAP_DATA_BEGIN {
+            result.init_header_to_file()?;
+        } else {
+            result.load_header()?;
+        }
+
+        Ok(result)
+    }
+
+    fn init_header_to_file(&mut self) -> DbResult<()> {
+        self.map_id = generate_map_id();
+        self.sequence = 0;
+
+        let mut header64 = [0u8; CHANGE_MAP_DATA_BEGIN as usize];
+        let title_bytes = HEADER_DESP.as_bytes();
+        header64[0..title_bytes.len()].copy_from_slice(title_bytes);
+        header64[(MAP_ID_OFFSET as usize)..(SEQUENCE_OFFSET as usize)].copy_from_slice(&self.map_id.to_be_bytes());
+
+        self.file.set_len(0)?;
+        self.file.write_at(0, &header64)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn load_header(&mut self) -> DbResult<()> {
+        let mut header64 = [0u8; CHANGE_MAP_DATA_BEGIN as usize];
+        self.file.read_at(0, &mut header64)?;
+
+        let title_bytes = HEADER_DESP.as_bytes();
+        if &header64[0..title_bytes.len()] != title_bytes {
+            return Err(DbErr::ChecksumMismatch);
+        }
+
+        let mut buffer: [u8; 8] = [0; 8];
+        buffer.copy_from_slice(&header64[(MAP_ID_OFFSET as usize)..(SEQUENCE_OFFSET as usize)]);
+        self.map_id = u64::from_be_bytes(buffer);
+
+        buffer.copy_from_slice(&header64[(SEQUENCE_OFFSET as usize)..((SEQUENCE_OFFSET + 8) as usize)]);
+        self.sequence = u64::from_be_bytes(buffer);
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn marker(&self) -> BackupMarker {
+        BackupMarker {
+            map_id: self.map_id,
+            sequence: self.sequence,
+        }
+    }
+
+    // the pages are written by the next sequence
+    pub(crate) fn record_commit(&mut self, page_ids: &[u32], sync: bool) -> DbResult<()> {
+        if page_ids.is_empty() {
+            return Ok(());
+        }
+
+        let sequence = self.sequence + 1;
+        let bytes = sequence.to_be_bytes();
+        for pid in page_ids {
+            self.file.write_at(CHANGE_MAP_DATA_BEGIN + (*pid as u64) * ENTRY_SIZE, &bytes)?;
+        }
+        self.file.write_at(SEQUENCE_OFFSET, &bytes)?;
+
+        if sync {
+            self.file.sync_data()?;
+        }
+        self.sequence = sequence;
+
+        Ok(())
+    }
+
+    // the pages before page_count written after the sequence
+    pub(crate) fn changed_since(&self, sequence: u64, page_count: u32) -> DbResult<Vec<u32>> {
+        let file_len = self.file.len()?;
+        let entries_end = std::cmp::min(
+            file_len,
+            CHANGE_MAP_DATA_BEGIN + (page_count as u64) * ENTRY_SIZE,
+        );
+        if entries_end <= CHANGE_MAP_DATA_BEGIN {
+            return Ok(vec![]);
+        }
+
+        let entries_len = (entries_end - CHANGE_MAP_DATA_BEGIN) / ENTRY_SIZE;
+        let mut content = vec![0u8; (entries_len * ENTRY_SIZE) as usize];
+        self.file.read_at(CHANGE_MAP_DATA_BEGIN, &mut content)?;
+
+        let mut result = vec![];
+        for (pid, entry) in content.chunks(ENTRY_SIZE as usize).enumerate() {
+            let mut buffer: [u8; 8] = [0; 8];
+            buffer.copy_from_slice(entry);
+            if u64::from_be_bytes(buffer) > sequence {
+                result.push(pid as u32);
+            }
+        }
+
+        Ok(result)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::change_map::ChangeMap;
+
+    #[test]
+    fn test_reopen_change_map() {
+        const TEST_FILE: &str = "/tmp/test-change-map";
+        let _ = std::fs::remove_file(TEST_FILE);
+
+        let marker = {
+            let mut change_map = ChangeMap::open(TEST_FILE.as_ref(), false).unwrap();
+            change_map.record_commit(&[1, 5], true).unwrap();
+            let marker = change_map.marker();
+            change_map.record_commit(&[5, 9], true).unwrap();
+            marker
+        };
+
+        let change_map = ChangeMap::open(TEST_FILE.as_ref(), false).unwrap();
+        assert_eq!(change_map.marker().map_id, marker.map_id);
+        assert_eq!(change_map.marker().sequence, 2);
+        assert_eq!(change_map.changed_since(marker.sequence, 100).unwrap(), vec![5, 9]);
+        assert_eq!(change_map.changed_since(0, 100).unwrap(), vec![1, 5, 9]);
+        assert_eq!(change_map.changed_since(0, 6).unwrap(), vec![1, 5]);
+
+        let change_map = ChangeMap::open_read_only(TEST_FILE.as_ref()).unwrap().unwrap();
+        assert_eq!(change_map.marker(), ChangeMap::open(TEST_FILE.as_ref(), false).unwrap().marker());
+
+        // another map
+        let change_map = ChangeMap::open(TEST_FILE.as_ref(), true).unwrap();
+        assert_ne!(change_map.marker().map_id, marker.map_id);
+        assert_eq!(change_map.marker().sequence, 0);
+        assert!(change_map.changed_since(0, 100).unwrap().is_empty());
+    }
+
+}
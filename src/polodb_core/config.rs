@@ -0,0 +1,137 @@
+use crate::page::CachePolicy;
+
+/**
+ * How the file grows when the pages are allocated beyond it,
+ * the file is extended when checkpointing.
+ *
+ * Fixed grows by the count of pages every time.
+ * Geometric grows by (factor - 1) times the current pages,
+ * but not more than cap pages at a time.
+ * The file grows by 1 page at least.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GrowthStrategy {
+    Fixed(u32),
+    Geometric { factor: u32, cap: u32 },
+}
+
+impl GrowthStrategy {
+
+    // the pages to grow when the file has current_pages
+    pub(crate) fn grow_pages(&self, current_pages: u32) -> u32 {
+        let pages = match self {
+            GrowthStrategy::Fixed(pages) => *pages,
+            GrowthStrategy::Geometric { factor, cap } => {
+                current_pages.saturating_mul(factor.saturating_sub(1)).min(*cap)
+            }
+        };
+        pages.max(1)
+    }
+
+}
+
+impl Default for GrowthStrategy {
+
+    fn default() -> Self {
+        GrowthStrategy::Fixed(16)
+    }
+
+}
+
+/**
+ * How the free pids are handed out.
+ *
+ * Lifo hands out the last freed pid, it's likely still in the cache.
+ * LowestFirst keeps the free list sorted, and hands out the smallest pid,
+ * the pages are packed toward the start of the file.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum AllocationOrder {
+    #[default]
+    Lifo,
+    LowestFirst,
+}
+
+/**
+ * How the data pages are picked and kept for the documents.
+ *
+ * A page is picked if it has preserve_bytes more than the document,
+ * never less than the 2 bytes of the slot bar.
+ * A page is kept for the next documents if at least min_remain_size bytes remain,
+ * lower it for the tiny documents to fill the pages fuller.
+ */
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DataPagePolicy {
+    pub preserve_bytes:  u32,
+    pub min_remain_size: u32,
+}
+
+impl Default for DataPagePolicy {
+
+    fn default() -> Self {
+        DataPagePolicy {
+            preserve_bytes: 2,
+            min_remain_size: 16,
+        }
+    }
+
+}
+
+/**
+ * Options to open the database
+ */
+#[derive(Debug, Clone)]
+pub struct Config {
+    // sort the free list when checkpointing,
+    // so the allocation can hand out contiguous runs of pages
+    pub coalesce_free_list_on_checkpoint: bool,
+
+    // the max count of pages a single transaction may allocate,
+    // the transaction is rolled back when it's exceeded
+    pub max_alloc_pages_per_txn: Option<u32>,
+
+    // the memory of the page cache in bytes,
+    // 1024 pages are cached if it's None
+    pub cache_capacity_bytes: Option<usize>,
+
+    // TwoQueue suits the scan-heavy workloads
+    pub cache_policy: CachePolicy,
+
+    // store a CRC32 in the trailing bytes of every page of a new database,
+    // and verify it when the page is read from the main file.
+    // An existing database has checksums if its header records it
+    pub page_checksum: bool,
+
+    // compress the pages of a new database in the main file,
+    // it needs the compression feature.
+    // An existing database is compressed if its header records it
+    pub page_compression: bool,
+
+    // the pages of a new database file, at least 1
+    pub init_block_count: u32,
+
+    pub growth_strategy: GrowthStrategy,
+
+    pub allocation_order: AllocationOrder,
+
+    pub data_page_policy: DataPagePolicy,
+}
+
+impl Default for Config {
+
+    fn default() -> Self {
+        Config {
+            coalesce_free_list_on_checkpoint: false,
+            max_alloc_pages_per_txn: None,
+            cache_capacity_bytes: None,
+            cache_policy: CachePolicy::default(),
+            page_checksum: true,
+            page_compression: false,
+            init_block_count: 16,
+            growth_strategy: GrowthStrategy::default(),
+            allocation_order: AllocationOrder::default(),
+            data_page_policy: DataPagePolicy::default(),
+        }
+    }
+
+}
@@ -9,9 +9,13 @@ use crate::db::DbResult;
 use crate::meta_doc_helper::{meta_doc_key, MetaDocEntry};
 use crate::index_ctx::{IndexCtx, merge_options_into_default};
 use crate::btree::*;
-use crate::page::{RawPage, TransactionState};
+use crate::page::{RawPage, TransactionState, TransactionStateKind, HealthReport, QuickVerifyReport, IntegrityReport, WriteStats, VacuumStats, StorageStats, CacheMetrics};
 use crate::db_handle::DbHandle;
-use crate::journal::TransactionType;
+use crate::journal::{TransactionType, DurabilityMode, JournalMode, RecoveryReport};
+use crate::lazy_doc::LazyDocIter;
+use crate::config::Config;
+use crate::intent_log::IntentRecord;
+use crate::change_map::BackupMarker;
 
 macro_rules! try_db_op {
     ($self: tt, $action: expr) => {
@@ -60,11 +64,28 @@ pub struct CollectionMeta {
 
 impl DbContext {
 
+    #[inline]
     pub fn new(path: &Path) -> DbResult<DbContext> {
+        DbContext::new_with_config(path, Config::default())
+    }
+
+    pub fn new_with_config(path: &Path, config: Config) -> DbResult<DbContext> {
+        let page_size = 4096;
+
+        let page_handler = PageHandler::new_with_config(path, page_size, config)?;
+
+        DbContext::from_page_handler(page_handler)
+    }
+
+    pub fn new_in_memory(config: Config) -> DbResult<DbContext> {
         let page_size = 4096;
 
-        let page_handler = PageHandler::new(path, page_size)?;
+        let page_handler = PageHandler::new_in_memory_with_config(page_size, config)?;
+
+        DbContext::from_page_handler(page_handler)
+    }
 
+    fn from_page_handler(page_handler: PageHandler) -> DbResult<DbContext> {
         let obj_id_maker = ObjectIdMaker::new();
 
         let mut ctx = DbContext {
@@ -435,6 +456,38 @@ impl DbContext {
         Ok(handle)
     }
 
+    /// iterate all the documents of the collection
+    /// without decoding them
+    ///
+    /// is_scan: the pages read are not inserted into the cache
+    ///
+    /// the read transaction started automatically is committed
+    /// when the iterator is exhausted or dropped
+    pub fn find_all_lazy(&mut self, col_id: u32, meta_version: u32, is_scan: bool) -> DbResult<LazyDocIter<'_>> {
+        self.check_meta_version(meta_version)?;
+
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let root_pid = match self.internal_find_root_pid(col_id) {
+            Ok(root_pid) => root_pid,
+            Err(err) => {
+                self.page_handler.auto_rollback()?;
+                self.reset_meta_version()?;
+                return Err(err);
+            }
+        };
+
+        let item_size = self.item_size();
+        LazyDocIter::new(&mut self.page_handler, item_size, root_pid, is_scan)
+    }
+
+    fn internal_find_root_pid(&mut self, col_id: u32) -> DbResult<u32> {
+        let meta_source = self.get_meta_source()?;
+        let (collection_meta, _meta_doc) = self.find_collection_root_pid_by_id(
+            0, meta_source.meta_pid, col_id)?;
+        Ok(collection_meta.root_pid)
+    }
+
     pub fn update(&mut self, col_id: u32, meta_version: u32, query: Option<&Document>, update: &Document) -> DbResult<usize> {
         self.check_meta_version(meta_version)?;
 
@@ -668,6 +721,201 @@ impl DbContext {
         Ok(())
     }
 
+    #[inline]
+    pub fn upgrade_transaction_to_write(&mut self) -> DbResult<()> {
+        self.page_handler.upgrade_transaction_to_write()
+    }
+
+    #[inline]
+    pub fn transaction_state_kind(&self) -> TransactionStateKind {
+        self.page_handler.transaction_state_kind()
+    }
+
+    #[inline]
+    pub fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        self.page_handler.savepoint(name)
+    }
+
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> DbResult<()> {
+        self.page_handler.rollback_to_savepoint(name)?;
+        self.reset_meta_version()
+    }
+
+    #[inline]
+    pub fn export_page_map_csv(&mut self, writer: &mut dyn std::io::Write) -> DbResult<()> {
+        self.page_handler.export_page_map_csv(writer)
+    }
+
+    #[inline]
+    pub fn quick_verify(&mut self, sample_rate: f64) -> DbResult<QuickVerifyReport> {
+        self.page_handler.quick_verify(sample_rate)
+    }
+
+    #[inline]
+    pub fn check_integrity(&mut self) -> DbResult<IntegrityReport> {
+        self.page_handler.check_integrity()
+    }
+
+    #[inline]
+    pub fn free_page_count(&mut self) -> DbResult<u32> {
+        self.page_handler.free_page_count()
+    }
+
+    #[inline]
+    pub fn collect_free_list(&mut self) -> DbResult<Vec<u32>> {
+        self.page_handler.collect_free_list()
+    }
+
+    #[inline]
+    pub fn stats(&self) -> DbResult<StorageStats> {
+        self.page_handler.stats()
+    }
+
+    #[inline]
+    pub fn fragmentation_ratio(&mut self) -> DbResult<f32> {
+        self.page_handler.fragmentation_ratio()
+    }
+
+    #[inline]
+    pub fn current_backup_marker(&self) -> BackupMarker {
+        self.page_handler.current_backup_marker()
+    }
+
+    #[inline]
+    pub fn changed_pages_since(&mut self, marker: BackupMarker) -> DbResult<Vec<u32>> {
+        self.page_handler.changed_pages_since(marker)
+    }
+
+    #[inline]
+    pub fn write_stats(&self) -> WriteStats {
+        self.page_handler.write_stats()
+    }
+
+    #[inline]
+    pub fn set_reuse_empty_data_pages(&mut self, reuse: bool) {
+        self.page_handler.set_reuse_empty_data_pages(reuse)
+    }
+
+    #[inline]
+    pub fn set_zero_freed_pages(&mut self, zero: bool) {
+        self.page_handler.set_zero_freed_pages(zero)
+    }
+
+    #[inline]
+    pub fn backup_to(&mut self, dest: &Path, overwrite: bool) -> DbResult<()> {
+        self.page_handler.backup_to(dest, overwrite)
+    }
+
+    #[inline]
+    pub fn cache_metrics(&self) -> CacheMetrics {
+        self.page_handler.cache_metrics()
+    }
+
+    #[inline]
+    pub fn reset_cache_metrics(&mut self) {
+        self.page_handler.reset_cache_metrics()
+    }
+
+    #[inline]
+    pub fn shrink_cache_to(&mut self, target: usize) -> usize {
+        self.page_handler.shrink_cache_to(target)
+    }
+
+    #[inline]
+    pub fn set_cache_soft_limit(&mut self, bytes: usize) {
+        self.page_handler.set_cache_soft_limit(bytes)
+    }
+
+    #[inline]
+    pub fn begin_op(&mut self, intent: &[u8]) -> DbResult<u64> {
+        self.page_handler.begin_op(intent)
+    }
+
+    #[inline]
+    pub fn end_op(&mut self) -> DbResult<()> {
+        self.page_handler.end_op()
+    }
+
+    #[inline]
+    pub fn incomplete_ops(&self) -> &[IntentRecord] {
+        self.page_handler.incomplete_ops()
+    }
+
+    #[inline]
+    pub fn resolve_incomplete_op(&mut self, op_id: u64) -> DbResult<()> {
+        self.page_handler.resolve_incomplete_op(op_id)
+    }
+
+    #[inline]
+    pub fn vacuum(&mut self) -> DbResult<VacuumStats> {
+        self.page_handler.vacuum()
+    }
+
+    #[inline]
+    pub fn high_water_pid(&mut self) -> DbResult<u32> {
+        self.page_handler.high_water_pid()
+    }
+
+    #[inline]
+    pub fn truncate_to_high_water(&mut self) -> DbResult<u64> {
+        self.page_handler.truncate_to_high_water()
+    }
+
+    #[inline]
+    pub fn checkpoint(&mut self) -> DbResult<()> {
+        self.page_handler.checkpoint()
+    }
+
+    #[inline]
+    pub fn set_journal_checkpoint_threshold(&mut self, pages: u32) -> DbResult<()> {
+        self.page_handler.set_journal_checkpoint_threshold(pages)
+    }
+
+    #[inline]
+    pub fn journal_checkpoint_threshold(&self) -> u32 {
+        self.page_handler.journal_checkpoint_threshold()
+    }
+
+    #[inline]
+    pub fn journal_len(&self) -> usize {
+        self.page_handler.journal_len()
+    }
+
+    #[inline]
+    pub fn set_durability_mode(&mut self, mode: DurabilityMode) {
+        self.page_handler.set_durability_mode(mode)
+    }
+
+    #[inline]
+    pub fn durability_mode(&self) -> DurabilityMode {
+        self.page_handler.durability_mode()
+    }
+
+    #[inline]
+    pub fn flush_journal(&mut self) -> DbResult<()> {
+        self.page_handler.flush_journal()
+    }
+
+    #[inline]
+    pub fn set_journal_mode(&mut self, mode: JournalMode) {
+        self.page_handler.set_journal_mode(mode)
+    }
+
+    #[inline]
+    pub fn journal_mode(&self) -> JournalMode {
+        self.page_handler.journal_mode()
+    }
+
+    #[inline]
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.page_handler.recovery_report()
+    }
+
+    #[inline]
+    pub fn health(&self) -> HealthReport {
+        self.page_handler.health()
+    }
+
     #[inline]
     pub fn object_id_maker(&mut self) -> &mut ObjectIdMaker {
         &mut self.obj_id_maker
@@ -683,10 +931,17 @@ impl DbContext {
 impl Drop for DbContext {
 
     fn drop(&mut self) {
-        let path = self.page_handler.journal_file_path().to_path_buf();
-        let checkpoint_result = self.page_handler.checkpoint_journal();  // ignored
-        if let Ok(_) = checkpoint_result {
-            let _ = std::fs::remove_file(path);  // ignore the result
+        if let Some(path) = self.page_handler.journal_file_path() {
+            let path = path.to_path_buf();
+            let checkpoint_result = self.page_handler.checkpoint();  // ignored
+            if let Ok(_) = checkpoint_result {
+                let _ = std::fs::remove_file(path);  // ignore the result
+            }
+        }
+        if self.page_handler.is_intent_log_clean() {
+            if let Some(intent_log_path) = self.page_handler.intent_log_path() {
+                let _ = std::fs::remove_file(intent_log_path);  // ignore the result
+            }
         }
     }
 
@@ -1,5 +1,3 @@
-use std::mem;
-
 pub static CRC64_TAB : &'static [&'static [u64; 256]; 8] = &[
     &[ 0x0000000000000000, 0x7ad870c830358979,
         0xf5b0e190606b12f2, 0x8f689158505e9b8b,
@@ -1041,14 +1039,14 @@ pub static CRC64_TAB : &'static [&'static [u64; 256]; 8] = &[
     ]
 ];
 
-// transmute slice of 8 u8 values to one u64 (drop the length)
+// read slice of 8 u8 values as one u64 in native endian,
+// the slice is not always aligned
 macro_rules! slice_to_long {
     ($curVec:expr) => {
         {
-            unsafe {
-                let (tmp, _) : (*const u64, usize) = mem::transmute(&$curVec);
-                *tmp
-            }
+            let mut buffer: [u8; 8] = [0; 8];
+            buffer.copy_from_slice(&$curVec);
+            u64::from_ne_bytes(buffer)
         }
     }
 }
@@ -5,6 +5,7 @@ use crate::page::{PageHandler, RawPage};
 use crate::btree::*;
 use crate::DbResult;
 use crate::data_ticket::DataTicket;
+use crate::lazy_doc::LazyDoc;
 
 #[derive(Clone)]
 struct CursorItem {
@@ -201,13 +202,37 @@ impl Cursor {
     }
 
     pub fn next(&mut self, page_handler: &mut PageHandler) -> DbResult<Option<Rc<Document>>> {
+        let result_ticket = match self.advance(page_handler)? {
+            Some(ticket) => ticket,
+            None => return Ok(None),
+        };
+        let result = page_handler.get_doc_from_ticket(&result_ticket)?.unwrap();
+
+        self.current = Some(result.clone());
+        Ok(Some(result))
+    }
+
+    // the same as next(), but the bytes of the document
+    // are not decoded
+    pub fn next_lazy(&mut self, page_handler: &mut PageHandler) -> DbResult<Option<LazyDoc>> {
+        let result_ticket = match self.advance(page_handler)? {
+            Some(ticket) => ticket,
+            None => return Ok(None),
+        };
+        let bytes = page_handler.get_raw_from_ticket(&result_ticket)?.unwrap();
+
+        Ok(Some(LazyDoc::new(bytes)))
+    }
+
+    // move to the next item,
+    // return the ticket of the current item
+    fn advance(&mut self, page_handler: &mut PageHandler) -> DbResult<Option<DataTicket>> {
         if self.btree_stack.is_empty() {
             return Ok(None);
         }
 
         let top = self.btree_stack.pop_back().unwrap();
-        let result_ticket = &top.node.content[top.index].data_ticket;
-        let result = page_handler.get_doc_from_ticket(&result_ticket)?.unwrap();
+        let result_ticket = top.node.content[top.index].data_ticket.clone();
 
         let next_index = top.index + 1;
 
@@ -222,13 +247,13 @@ impl Cursor {
 
                 self.push_all_left_nodes(page_handler)?;
 
-                return Ok(Some(result));
+                return Ok(Some(result_ticket));
             }
 
             // pop
             self.pop_all_right_most_item();
 
-            return Ok(Some(result));
+            return Ok(Some(result_ticket));
         }
 
         self.btree_stack.push_back(CursorItem {
@@ -238,8 +263,7 @@ impl Cursor {
 
         self.push_all_left_nodes(page_handler)?;
 
-        self.current = Some(result.clone());
-        Ok(Some(result))
+        Ok(Some(result_ticket))
     }
 
     pub fn pop_all_right_most_item(&mut self) {
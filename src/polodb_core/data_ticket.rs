@@ -1,17 +1,49 @@
 use std::fmt;
+use crate::DbResult;
+use crate::error::DbErr;
 
 // 6 bytes in store
-#[derive(Clone)]
-pub(crate) struct DataTicket {
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DataTicket {
     pub pid: u32,
     pub index: u16,
 }
 
 impl DataTicket {
 
+    // the stable encoding for the external structures,
+    // little-endian pid(4 bytes) and index(2 bytes)
     pub fn to_bytes(&self) -> [u8; 6] {
         let mut result = [0; 6];
 
+        result[0..4].copy_from_slice(&self.pid.to_le_bytes());
+        result[4..6].copy_from_slice(&self.index.to_le_bytes());
+
+        result
+    }
+
+    // the bytes after the first 6 are ignored
+    pub fn from_bytes(bytes: &[u8]) -> DbResult<DataTicket> {
+        if bytes.len() < 6 {
+            return Err(DbErr::DecodeEOF);
+        }
+
+        let mut pid_bytes = [0; 4];
+        let mut index_bytes = [0; 2];
+
+        pid_bytes.copy_from_slice(&bytes[0..4]);
+        index_bytes.copy_from_slice(&bytes[4..6]);
+
+        let pid = u32::from_le_bytes(pid_bytes);
+        let index = u16::from_le_bytes(index_bytes);
+
+        Ok(DataTicket { pid, index })
+    }
+
+    // big-endian, stored in the btree pages and the indexes
+    pub(crate) fn to_store_bytes(&self) -> [u8; 6] {
+        let mut result = [0; 6];
+
         let pid_bytes = self.pid.to_be_bytes();
         let index_bytes = self.index.to_be_bytes();
 
@@ -21,7 +53,7 @@ impl DataTicket {
         result
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> DataTicket {
+    pub(crate) fn from_store_bytes(bytes: &[u8]) -> DataTicket {
         let mut pid_bytes = [0; 4];
         let mut index_bytes = [0; 2];
 
@@ -43,3 +75,41 @@ impl fmt::Display for DataTicket {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::DbErr;
+    use crate::data_ticket::DataTicket;
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let data_ticket = DataTicket {
+            pid: 0x01020304,
+            index: 0x0506,
+        };
+
+        let bytes = data_ticket.to_bytes();
+        assert_eq!(bytes, [4, 3, 2, 1, 6, 5]);
+        assert_eq!(DataTicket::from_bytes(&bytes).unwrap(), data_ticket);
+
+        let data_ticket = DataTicket {
+            pid: u32::MAX,
+            index: u16::MAX,
+        };
+        let mut bytes = data_ticket.to_bytes().to_vec();
+        bytes.push(0);
+        assert_eq!(DataTicket::from_bytes(&bytes).unwrap(), data_ticket);
+
+        // the store encoding is untouched
+        assert_eq!(DataTicket::from_store_bytes(&data_ticket.to_store_bytes()), data_ticket);
+    }
+
+    #[test]
+    fn test_from_short_bytes() {
+        let bytes = DataTicket { pid: 1, index: 2 }.to_bytes();
+        for len in 0..6 {
+            assert!(matches!(DataTicket::from_bytes(&bytes[0..len]), Err(DbErr::DecodeEOF)));
+        }
+    }
+
+}
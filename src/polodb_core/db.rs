@@ -3,7 +3,7 @@ use std::path::Path;
 use polodb_bson::{Document, ObjectId, Value};
 use super::error::DbErr;
 use crate::context::DbContext;
-use crate::{DbHandle, TransactionType};
+use crate::{DbHandle, TransactionType, DurabilityMode, JournalMode, RecoveryReport, LazyDocIter, HealthReport, QuickVerifyReport, IntegrityReport, WriteStats, VacuumStats, StorageStats, CacheMetrics, Config, IntentRecord, TransactionStateKind, BackupMarker};
 
 fn consume_handle_to_vec(handle: &mut DbHandle, result: &mut Vec<Rc<Document>>) -> DbResult<()> {
     handle.step()?;
@@ -46,6 +46,20 @@ impl<'a>  Collection<'a> {
         Ok(result)
     }
 
+    /// iterate all the documents, the documents are decoded
+    /// only when `LazyDoc::decode()` is called
+    #[inline]
+    pub fn find_all_lazy(&mut self) -> DbResult<LazyDocIter<'_>> {
+        self.db.ctx.find_all_lazy(self.id, self.meta_version, false)
+    }
+
+    /// the same as `find_all_lazy`, but the pages read by a one-shot
+    /// full scan don't pollute the page cache
+    #[inline]
+    pub fn scan_all_lazy(&mut self) -> DbResult<LazyDocIter<'_>> {
+        self.db.ctx.find_all_lazy(self.id, self.meta_version, true)
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -103,6 +117,26 @@ impl Database {
         })
     }
 
+    pub fn open_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<Database>  {
+        let ctx = DbContext::new_with_config(path.as_ref(), config)?;
+        let rc_ctx = Box::new(ctx);
+
+        Ok(Database {
+            ctx: rc_ctx,
+        })
+    }
+
+    /// open a database in memory, nothing touches the filesystem,
+    /// all the data is lost when it's dropped
+    pub fn open_memory(config: Config) -> DbResult<Database>  {
+        let ctx = DbContext::new_in_memory(config)?;
+        let rc_ctx = Box::new(ctx);
+
+        Ok(Database {
+            ctx: rc_ctx,
+        })
+    }
+
     pub fn create_collection(&mut self, name: &str) -> DbResult<Collection> {
         let collection_meta = self.ctx.create_collection(name)?;
         Ok(Collection::new(self,
@@ -136,6 +170,262 @@ impl Database {
         self.ctx.rollback()
     }
 
+    /// turn the current read transaction into a write transaction,
+    /// without rolling back what's read
+    #[inline]
+    pub fn upgrade_transaction_to_write(&mut self) -> DbResult<()> {
+        self.ctx.upgrade_transaction_to_write()
+    }
+
+    /// whether the current transaction is started by the user or an operation
+    #[inline]
+    pub fn transaction_state_kind(&self) -> TransactionStateKind {
+        self.ctx.transaction_state_kind()
+    }
+
+    /// mark a position in the current write transaction,
+    /// a savepoint with the same name shadows the previous one
+    #[inline]
+    pub fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        self.ctx.savepoint(name)
+    }
+
+    /// discard the changes after the savepoint, the transaction goes on
+    #[inline]
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> DbResult<()> {
+        self.ctx.rollback_to_savepoint(name)
+    }
+
+    /// dump a row per page with its kind, live entry count,
+    /// used bytes, remaining bytes, and whether it's free
+    #[inline]
+    pub fn export_page_map_csv(&mut self, writer: &mut dyn std::io::Write) -> DbResult<()> {
+        self.ctx.export_page_map_csv(writer)
+    }
+
+    /// check the header and a strided sample of the pages,
+    /// `sample_rate` is in [0.0, 1.0], 0.0 checks the header only
+    #[inline]
+    pub fn quick_verify(&mut self, sample_rate: f64) -> DbResult<QuickVerifyReport> {
+        self.ctx.quick_verify(sample_rate)
+    }
+
+    /// check the free list and every page referred by the collections,
+    /// the anomalies are listed in the report, nothing is written
+    #[inline]
+    pub fn check_integrity(&mut self) -> DbResult<IntegrityReport> {
+        self.ctx.check_integrity()
+    }
+
+    /// the pages in the free list, the ones freed by the transaction are counted
+    #[inline]
+    pub fn free_page_count(&mut self) -> DbResult<u32> {
+        self.ctx.free_page_count()
+    }
+
+    /// the free pids in the order of the free list, for the diagnostics
+    #[inline]
+    pub fn collect_free_list(&mut self) -> DbResult<Vec<u32>> {
+        self.ctx.collect_free_list()
+    }
+
+    /// the live and free pages of the database, helps to decide when to vacuum
+    #[inline]
+    pub fn stats(&self) -> DbResult<StorageStats> {
+        self.ctx.stats()
+    }
+
+    /// the unused bytes of the data pages relative to their size, all the pages are read,
+    /// near 1.0 it's worth to vacuum
+    #[inline]
+    pub fn fragmentation_ratio(&mut self) -> DbResult<f32> {
+        self.ctx.fragmentation_ratio()
+    }
+
+    /// the position of the last commit, keep it with a backup
+    /// to find the pages changed since then
+    #[inline]
+    pub fn current_backup_marker(&self) -> BackupMarker {
+        self.ctx.current_backup_marker()
+    }
+
+    /// the pages to copy onto the backup taken at the marker,
+    /// the freed pages are included, they are found in the free list of the header
+    #[inline]
+    pub fn changed_pages_since(&mut self, marker: BackupMarker) -> DbResult<Vec<u32>> {
+        self.ctx.changed_pages_since(marker)
+    }
+
+    /// keep the emptied data pages for the next documents instead of freeing them,
+    /// they are freed by vacuum if they stay empty
+    #[inline]
+    pub fn set_reuse_empty_data_pages(&mut self, reuse: bool) {
+        self.ctx.set_reuse_empty_data_pages(reuse)
+    }
+
+    /// overwrite the freed pages with zeros, so the deleted data doesn't linger,
+    /// it costs a page write for every page freed
+    #[inline]
+    pub fn set_zero_freed_pages(&mut self, zero: bool) {
+        self.ctx.set_zero_freed_pages(zero)
+    }
+
+    /// copy the committed state to a new database file,
+    /// an existing file is replaced only if `overwrite` is true
+    #[inline]
+    pub fn backup_to(&mut self, dest: &Path, overwrite: bool) -> DbResult<()> {
+        self.ctx.backup_to(dest, overwrite)
+    }
+
+    /// the cache hits, journal hits and disk reads of the pages
+    #[inline]
+    pub fn cache_metrics(&self) -> CacheMetrics {
+        self.ctx.cache_metrics()
+    }
+
+    /// start a new window of the cache metrics
+    #[inline]
+    pub fn reset_cache_metrics(&mut self) {
+        self.ctx.reset_cache_metrics()
+    }
+
+    /// the journal frames and main file pages written by the recent commits and checkpoints
+    #[inline]
+    pub fn write_stats(&self) -> WriteStats {
+        self.ctx.write_stats()
+    }
+
+    /// physical pages written / logical pages changed, over the recent commits
+    #[inline]
+    pub fn write_amplification(&self) -> f64 {
+        self.ctx.write_stats().write_amplification
+    }
+
+    /// evict the coldest pages of the cache until at most `target` pages remain,
+    /// return the count of evicted pages
+    #[inline]
+    pub fn shrink_cache_to(&mut self, target: usize) -> usize {
+        self.ctx.shrink_cache_to(target)
+    }
+
+    /// the cache begins to evict the coldest pages when it holds more than `bytes`,
+    /// a few pages a time, before reaching the capacity
+    #[inline]
+    pub fn set_cache_soft_limit(&mut self, bytes: usize) {
+        self.ctx.set_cache_soft_limit(bytes)
+    }
+
+    /// Bracket a logical operation spanning multiple documents and indexes.
+    /// If the process crashes before `end_op()`, the operation is reported by
+    /// `incomplete_ops()` after reopening.
+    #[inline]
+    pub fn begin_op(&mut self, intent: &[u8]) -> DbResult<u64> {
+        self.ctx.begin_op(intent)
+    }
+
+    #[inline]
+    pub fn end_op(&mut self) -> DbResult<()> {
+        self.ctx.end_op()
+    }
+
+    #[inline]
+    pub fn incomplete_ops(&self) -> &[IntentRecord] {
+        self.ctx.incomplete_ops()
+    }
+
+    /// mark the incomplete operation as replayed or discarded
+    #[inline]
+    pub fn resolve_incomplete_op(&mut self, op_id: u64) -> DbResult<()> {
+        self.ctx.resolve_incomplete_op(op_id)
+    }
+
+    /// pack the documents into fewer pages and truncate the free pages at the tail,
+    /// can't be called in a transaction
+    #[inline]
+    pub fn vacuum(&mut self) -> DbResult<VacuumStats> {
+        self.ctx.vacuum()
+    }
+
+    /// the largest pid ever allocated, it doesn't fall when the pages are freed
+    #[inline]
+    pub fn high_water_pid(&mut self) -> DbResult<u32> {
+        self.ctx.high_water_pid()
+    }
+
+    /// shrink the file by the free pages at the tail, the new size of the file is returned
+    #[inline]
+    pub fn truncate_to_high_water(&mut self) -> DbResult<u64> {
+        self.ctx.truncate_to_high_water()
+    }
+
+    /// write all the journal frames to the main file and truncate the journal,
+    /// e.g. at a quiescent moment before a backup, it fails in a transaction
+    #[inline]
+    pub fn checkpoint(&mut self) -> DbResult<()> {
+        self.ctx.checkpoint()
+    }
+
+    /// checkpoint the journal when committing if it has at least `pages` frames,
+    /// the default is 1000, 0 is rejected
+    #[inline]
+    pub fn set_journal_checkpoint_threshold(&mut self, pages: u32) -> DbResult<()> {
+        self.ctx.set_journal_checkpoint_threshold(pages)
+    }
+
+    #[inline]
+    pub fn journal_checkpoint_threshold(&self) -> u32 {
+        self.ctx.journal_checkpoint_threshold()
+    }
+
+    /// the frames in the journal, compared with the threshold
+    /// to checkpoint in an idle period
+    #[inline]
+    pub fn journal_len(&self) -> usize {
+        self.ctx.journal_len()
+    }
+
+    /// when the journal and the main file are synced to the disk,
+    /// the default is FullSync
+    #[inline]
+    pub fn set_durability_mode(&mut self, mode: DurabilityMode) {
+        self.ctx.set_durability_mode(mode)
+    }
+
+    #[inline]
+    pub fn durability_mode(&self) -> DurabilityMode {
+        self.ctx.durability_mode()
+    }
+
+    /// sync the journal to the disk without checkpointing,
+    /// useful with the durability modes not syncing every commit
+    #[inline]
+    pub fn flush_journal(&mut self) -> DbResult<()> {
+        self.ctx.flush_journal()
+    }
+
+    /// how the pages are stored in the journal,
+    /// the default is FullPage
+    #[inline]
+    pub fn set_journal_mode(&mut self, mode: JournalMode) {
+        self.ctx.set_journal_mode(mode)
+    }
+
+    #[inline]
+    pub fn journal_mode(&self) -> JournalMode {
+        self.ctx.journal_mode()
+    }
+
+    /// the frames of the journal recovered and discarded when opening
+    #[inline]
+    pub fn recovery_report(&self) -> RecoveryReport {
+        self.ctx.recovery_report()
+    }
+
+    #[inline]
+    pub fn health(&self) -> HealthReport {
+        self.ctx.health()
+    }
+
     #[allow(dead_code)]
     pub(crate) fn query_all_meta(&mut self) -> DbResult<Vec<Rc<Document>>> {
         self.ctx.query_all_meta()
@@ -148,7 +438,7 @@ mod tests {
     use std::rc::Rc;
     use std::env;
     use polodb_bson::{Document, Value, mk_document};
-    use crate::Database;
+    use crate::{Database, Config, DbErr};
     use std::borrow::Borrow;
 
     static TEST_SIZE: usize = 1000;
@@ -200,6 +490,106 @@ mod tests {
         assert_eq!(TEST_SIZE, all.len())
     }
 
+    #[test]
+    fn test_open_memory() {
+        let mut db = Database::open_memory(Config::default()).unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+
+        for i in 0..100 {
+            let new_doc = mk_document! {
+                "_id": i,
+                "content": i.to_string(),
+            };
+            collection.insert(Rc::new(new_doc)).unwrap();
+        }
+
+        let all = collection.find(None).unwrap();
+        assert_eq!(all.len(), 100);
+    }
+
+    #[test]
+    fn test_find_all_lazy() {
+        let mut db = create_and_return_db_with_items("test-find-all-lazy", TEST_SIZE);
+        let mut collection = db.collection("test").unwrap();
+
+        let mut count = 0;
+        let mut matched = vec![];
+        for item in collection.find_all_lazy().unwrap() {
+            let lazy_doc = item.unwrap();
+            count += 1;
+
+            // filter by the raw bytes without decoding
+            let needle = b"999";
+            if lazy_doc.raw_bytes().windows(needle.len()).any(|w| w == needle) {
+                matched.push(lazy_doc.decode().unwrap());
+            }
+        }
+
+        assert_eq!(count, TEST_SIZE);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].get("content").unwrap().unwrap_string(), "999");
+
+        let scan_count = collection.scan_all_lazy().unwrap().count();
+        assert_eq!(scan_count, TEST_SIZE);
+
+        // the read transaction ends when the iterator is dropped half way
+        let mut iter = collection.find_all_lazy().unwrap();
+        iter.next().unwrap().unwrap();
+        drop(iter);
+        assert!(matches!(db.transaction_state_kind(), crate::TransactionStateKind::NoTrans));
+        db.start_transaction(Some(crate::TransactionType::Write)).unwrap();
+        db.commit().unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut db = create_and_return_db_with_items("test-checkpoint", 100);
+        assert!(db.health().journal_lag > 0);
+
+        db.start_transaction(None).unwrap();
+        assert!(matches!(db.checkpoint(), Err(DbErr::CheckpointDuringTransaction)));
+        db.commit().unwrap();
+
+        db.checkpoint().unwrap();
+        assert_eq!(db.health().journal_lag, 0);
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 100);
+    }
+
+    #[test]
+    fn test_health() {
+        let db = create_and_return_db_with_items("test-health", 100);
+
+        let report = db.health();
+        assert!(!report.recovered_on_open);
+        assert!(report.journal_lag > 0);
+        assert!(report.cache_hit_rate > 0.0);
+        assert!(report.write_transaction_open.is_none());
+        assert!(report.is_healthy());
+
+        // the files left by a crash, the journal is not checkpointed,
+        // the lock is not inherited by the copies
+        let mut db_path = env::temp_dir();
+        db_path.push("test-health.db");
+        let mut crashed_path = env::temp_dir();
+        crashed_path.push("test-health-crashed.db");
+        let crashed_journal_path = crate::page::PageHandler::mk_journal_path(crashed_path.as_path());
+        let _ = std::fs::remove_file(crashed_journal_path.as_path());
+        std::fs::copy(db_path.as_path(), crashed_path.as_path()).unwrap();
+        std::fs::copy(crate::page::PageHandler::mk_journal_path(db_path.as_path()), crashed_journal_path.as_path()).unwrap();
+        drop(db);
+
+        let db = Database::open(crashed_path.as_path().to_str().unwrap()).unwrap();
+        let report = db.health();
+        assert!(report.recovered_on_open);
+
+        let recovery_report = db.recovery_report();
+        assert!(recovery_report.frames_recovered > 0);
+        assert_eq!(recovery_report.frames_discarded, 0);
+        assert!(!recovery_report.corrupt_frame_found);
+    }
+
     #[test]
     fn test_transaction_commit() {
         let mut db = prepare_db("test-transaction");
@@ -440,4 +830,256 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_transaction_snapshot() {
+        let mut db = prepare_db("test-read-transaction-snapshot");
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..10 {
+            collection.insert(Rc::new(mk_document! { "_id": i })).unwrap();
+        }
+
+        db.start_transaction(Some(crate::TransactionType::Read)).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 10);
+
+        let ids = |collection: &mut super::Collection| -> Vec<i64> {
+            collection.find_all_lazy().unwrap()
+                .map(|item| item.unwrap().decode().unwrap().get("_id").unwrap().unwrap_int())
+                .collect()
+        };
+        let before = ids(&mut collection);
+
+        // the auto-writes are committed by their own,
+        // the read transaction doesn't see them
+        collection.insert(Rc::new(mk_document! { "_id": 10 })).unwrap();
+        assert!(collection.delete(&Value::Int(0)).unwrap().is_some());
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 10);
+        assert_eq!(ids(&mut collection), before);
+
+        // another handle can't open the file in use
+        let mut db_path = env::temp_dir();
+        db_path.push("test-read-transaction-snapshot.db");
+        let result = Database::open(db_path.as_path().to_str().unwrap());
+        assert!(matches!(result, Err(DbErr::DatabaseLocked { .. })), "{:?}", result.err());
+
+        db.commit().unwrap();
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 10);
+        assert_eq!(ids(&mut collection), (1..11).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let mut db = prepare_db("test-check-integrity");
+        let mut collection = db.create_collection("test").unwrap();
+        collection.create_index(&mk_document! { "user_id": 1 }, None).unwrap();
+
+        for i in 0..100 {
+            let new_doc = mk_document! {
+                "_id": i,
+                "user_id": i.to_string(),
+            };
+            collection.insert(Rc::new(new_doc)).unwrap();
+        }
+
+        // stored in the overflow pages
+        let large_doc = mk_document! {
+            "_id": 100,
+            "user_id": "large",
+            "content": "x".repeat(20000),
+        };
+        collection.insert(Rc::new(large_doc)).unwrap();
+
+        let report = db.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+        assert!(report.checked_pages > 5);
+        drop(db);
+
+        let mut db_path = env::temp_dir();
+        db_path.push("test-check-integrity.db");
+        let mut page_handler = crate::page::PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+
+        // the meta page is still referred by the header
+        let first_page = page_handler.get_first_page().unwrap();
+        let meta_pid = crate::page::header_page_wrapper::HeaderPageWrapper::from_raw_page(first_page).get_meta_page_id();
+        page_handler.start_transaction(crate::TransactionType::Write).unwrap();
+        page_handler.free_page(meta_pid).unwrap();
+        page_handler.commit().unwrap();
+
+        let report = page_handler.check_integrity().unwrap();
+        assert!(report.anomalies.contains(&crate::IntegrityAnomaly::AllocatedAndFree { pid: meta_pid }), "{}", report);
+    }
+
+    #[test]
+    fn test_vacuum_frees_empty_data_pages() {
+        let mut db = prepare_db("test-vacuum-frees-empty-data-pages");
+        db.set_reuse_empty_data_pages(true);
+        let mut collection = db.create_collection("test").unwrap();
+
+        for i in 0..100 {
+            let new_doc = mk_document! {
+                "_id": i,
+                "content": i.to_string().repeat(1500),
+            };
+            collection.insert(Rc::new(new_doc)).unwrap();
+        }
+
+        for i in 0..100 {
+            assert!(collection.delete(&Value::Int(i)).unwrap().is_some());
+        }
+
+        // the emptied pages are kept for reuse
+        let before = db.stats().unwrap();
+        assert!(before.tracked_data_pages > 50);
+
+        let stats = db.vacuum().unwrap();
+        assert!(stats.pages_freed > 50);
+
+        let after = db.stats().unwrap();
+        assert!(after.tracked_data_pages <= 1);
+        assert!(after.page_count * 2 < before.page_count);
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let mut db = prepare_db("test-vacuum");
+        let mut collection = db.create_collection("test").unwrap();
+
+        for i in 0..150 {
+            let new_doc = mk_document! {
+                "_id": i,
+                "content": i.to_string().repeat(20),
+            };
+            collection.insert(Rc::new(new_doc)).unwrap();
+        }
+
+        // leave the data pages sparse
+        for i in 0..150 {
+            if i % 4 != 0 {
+                assert!(collection.delete(&Value::Int(i)).unwrap().is_some());
+            }
+        }
+
+        let stats = db.vacuum().unwrap();
+        assert!(stats.docs_relocated >= 38);
+        assert!(stats.pages_freed > 0);
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 38);
+        for i in (0..150).step_by(4) {
+            let find_doc = mk_document! {
+                "_id": i,
+            };
+            let result = collection.find(Some(&find_doc)).unwrap();
+            assert_eq!(result.len(), 1);
+            assert_eq!(result[0].get("content").unwrap().unwrap_string(), i.to_string().repeat(20));
+        }
+
+        // still writable
+        let new_doc = mk_document! {
+            "_id": 1001,
+            "content": "new",
+        };
+        collection.insert(Rc::new(new_doc)).unwrap();
+        assert_eq!(collection.count().unwrap(), 39);
+    }
+
+    // (indexed value, document) of the entries of the index tree
+    fn collect_index_entries(page_handler: &mut crate::page::PageHandler, pid: u32, result: &mut Vec<(Value, Rc<Document>)>) {
+        let item_size = (page_handler.page_size - crate::btree::HEADER_SIZE) / crate::btree::ITEM_SIZE;
+        let page = page_handler.pipeline_read_page(pid).unwrap();
+        let btree_node = crate::btree::BTreeNode::from_raw(&page, 0, item_size, page_handler).unwrap();
+
+        for item in &btree_node.content {
+            let entry_doc = page_handler.get_doc_from_ticket(&item.data_ticket).unwrap().unwrap();
+            let doc_ticket = match entry_doc.get("value") {
+                Some(Value::Binary(bytes)) => crate::data_ticket::DataTicket::from_store_bytes(bytes),
+                _ => panic!("not an index entry"),
+            };
+            let doc = page_handler.get_doc_from_ticket(&doc_ticket).unwrap().unwrap();
+            result.push((item.key.clone(), doc));
+        }
+
+        for child_pid in &btree_node.indexes {
+            if *child_pid != 0 {
+                collect_index_entries(page_handler, *child_pid, result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vacuum_indexed_collection() {
+        let mut db = prepare_db("test-vacuum-indexed-collection");
+        let mut collection = db.create_collection("test").unwrap();
+        collection.create_index(&mk_document! { "user_id": 1 }, None).unwrap();
+
+        for i in 0..120 {
+            let new_doc = mk_document! {
+                "_id": i,
+                "user_id": i.to_string(),
+                "content": i.to_string().repeat(20),
+            };
+            collection.insert(Rc::new(new_doc)).unwrap();
+        }
+
+        for i in 0..120 {
+            if i % 4 != 0 {
+                assert!(collection.delete(&Value::Int(i)).unwrap().is_some());
+            }
+        }
+
+        // the documents and the index entries are both relocated
+        let stats = db.vacuum().unwrap();
+        assert!(stats.docs_relocated >= 60);
+        assert!(stats.pages_freed > 0);
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 30);
+        let report = db.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+        drop(db);
+
+        let mut db_path = env::temp_dir();
+        db_path.push("test-vacuum-indexed-collection.db");
+        let mut page_handler = crate::page::PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let meta_pid = crate::page::header_page_wrapper::HeaderPageWrapper::from_raw_page(first_page).get_meta_page_id();
+        let page = page_handler.pipeline_read_page(meta_pid).unwrap();
+        let item_size = (page_handler.page_size - crate::btree::HEADER_SIZE) / crate::btree::ITEM_SIZE;
+        let meta_node = crate::btree::BTreeNode::from_raw(&page, 0, item_size, &mut page_handler).unwrap();
+        let meta_doc = page_handler.get_doc_from_ticket(&meta_node.content[0].data_ticket).unwrap().unwrap();
+        let index_doc = meta_doc.get("indexes").unwrap().unwrap_document().get("user_id").unwrap().unwrap_document().clone();
+        let index_root_pid = index_doc.get("root_pid").unwrap().unwrap_int() as u32;
+
+        // the entries refer to the relocated documents
+        let mut entries = vec![];
+        collect_index_entries(&mut page_handler, index_root_pid, &mut entries);
+        assert_eq!(entries.len(), 30);
+        for (key, doc) in &entries {
+            assert_eq!(doc.get("user_id").unwrap().unwrap_string(), key.unwrap_string());
+        }
+    }
+
+    #[test]
+    fn test_vacuum_in_transaction() {
+        let mut db = prepare_db("test-vacuum-in-transaction");
+        db.create_collection("test").unwrap();
+
+        db.start_transaction(Some(crate::TransactionType::Write)).unwrap();
+        match db.vacuum() {
+            Err(DbErr::StartTransactionInAnotherTransaction) => (),
+            _ => panic!("should fail"),
+        }
+
+        // the transaction is untouched
+        db.rollback().unwrap();
+        db.vacuum().unwrap();
+    }
+
 }
@@ -70,6 +70,48 @@ pub enum DbErr {
     VmIsHalt,
     MetaVersionMismatched(u32, u32),
     CollectionAlreadyExits(String),
+    TransactionAllocLimitExceeded(u32),
+    IntentOpInProgress,
+    NoIntentOpInProgress,
+    IntentOpNotFound(u64),
+    InvalidCheckpointThreshold(u32),
+    PageChecksumMismatch { page_id: u32, expected: u32, actual: u32 },
+    ReadOnly,
+    SavepointNotFound(String),
+    DataTicketNotFound { pid: u32, index: u16 },
+    InvalidEncryptionKey,
+    InvalidPageSize(u32),
+    PageSizeMismatch(u32, u32),
+    NotADatabaseFile,
+    UnsupportedVersion { found: [u8; 4], supported: [u8; 4] },
+    DestinationExists(String),
+    CannotFreeHeaderPage,
+    PageIdOutOfRange { pid: u32, max: u32 },
+    CheckpointDuringTransaction,
+    CheckpointInProgress,
+    InvalidInitBlockCount(u32),
+    TransactionTooLarge(u32),
+    PageOffsetOutOfBounds { offset: u32, width: u32, page_size: u32 },
+    Internal(String),
+    NotADataPage(u32),
+    JournalPathMismatch,
+    ReservedPagesFull(u32),
+    PageReserved(u32),
+    PageNotReserved(u32),
+    PageIo { page_id: u32, offset: u64, source: Box<io::Error> },
+    CacheFull,
+    DatabaseLocked { path: String },
+    CompressionNotSupported,
+    CorruptCompressedPage(u32),
+    BrokenFreeList(u32),
+    AlreadyExists(String),
+    NotFound(String),
+    OutOfSpace { requested_bytes: u64 },
+    NoDatabaseFile,
+    FileSizeLimitExceeded { limit: u64 },
+    NotInReadTransaction,
+    PageAlreadyFree(u32),
+    CannotReplaceEncrypted,
     Busy
 }
 
@@ -127,6 +169,54 @@ impl fmt::Display for DbErr {
             DbErr::MetaVersionMismatched(expected, actual) => write!(f, "meta version mismatched, expect: {}, actual: {}", expected, actual),
             DbErr::Busy => write!(f, "database busy"),
             DbErr::CollectionAlreadyExits(name) => write!(f, "collection name '{}' already exists", name),
+            DbErr::TransactionAllocLimitExceeded(limit) => write!(f, "transaction allocates more than {} pages, rolled back", limit),
+            DbErr::IntentOpInProgress => write!(f, "another operation is in progress"),
+            DbErr::NoIntentOpInProgress => write!(f, "no operation is in progress"),
+            DbErr::IntentOpNotFound(op_id) => write!(f, "incomplete operation {} not found", op_id),
+            DbErr::InvalidCheckpointThreshold(threshold) => write!(f, "invalid journal checkpoint threshold: {}", threshold),
+            DbErr::PageChecksumMismatch { page_id, expected, actual } =>
+                write!(f, "checksum of page {} is mismatched, expect: {:#010x}, actual: {:#010x}, database maybe corrupt", page_id, expected, actual),
+            DbErr::ReadOnly => write!(f, "the database is opened read-only"),
+            DbErr::SavepointNotFound(name) => write!(f, "savepoint \"{}\" not found", name),
+            DbErr::DataTicketNotFound { pid, index } => write!(f, "data ticket not found, pid: {}, index: {}, maybe freed twice", pid, index),
+            DbErr::InvalidEncryptionKey => write!(f, "the encryption key is invalid for the database"),
+            DbErr::InvalidPageSize(page_size) => write!(f, "invalid page size: {}, it should be a power of two between 512 and 65536", page_size),
+            DbErr::PageSizeMismatch(expect, actual) => write!(f, "page size is mismatch with database, expect: {}, actual: {}", expect, actual),
+            DbErr::NotADatabaseFile => write!(f, "the file is not a database"),
+            DbErr::UnsupportedVersion { found, supported } =>
+                write!(f, "unsupported version of database: {}.{}.{}.{}, supported: {}.{}.{}.{}",
+                       found[0], found[1], found[2], found[3],
+                       supported[0], supported[1], supported[2], supported[3]),
+            DbErr::DestinationExists(path) => write!(f, "the destination \"{}\" already exists", path),
+            DbErr::CannotFreeHeaderPage => write!(f, "the header page can not be freed"),
+            DbErr::PageIdOutOfRange { pid, max } => write!(f, "page id {} is out of range, max: {}", pid, max),
+            DbErr::CheckpointDuringTransaction => write!(f, "can not checkpoint the journal in a transaction"),
+            DbErr::CheckpointInProgress => write!(f, "another checkpoint is in progress"),
+            DbErr::InvalidInitBlockCount(count) => write!(f, "invalid init block count: {}, it should be at least 1", count),
+            DbErr::Internal(msg) => write!(f, "internal error: {}", msg),
+            DbErr::PageOffsetOutOfBounds { offset, width, page_size } =>
+                write!(f, "{} bytes at offset {} is out of the page, page size: {}", width, offset, page_size),
+            DbErr::TransactionTooLarge(max) => write!(f, "transaction writes more than {} pages, it should be rolled back", max),
+            DbErr::NotADataPage(pid) => write!(f, "page {} is not a data page", pid),
+            DbErr::JournalPathMismatch => write!(f, "the journal in use is at another path, recorded in the header"),
+            DbErr::ReservedPagesFull(max) => write!(f, "at most {} pages can be reserved", max),
+            DbErr::PageReserved(pid) => write!(f, "page {} is reserved, it should be released before freed", pid),
+            DbErr::PageNotReserved(pid) => write!(f, "page {} is not reserved", pid),
+            DbErr::PageIo { page_id, offset, source } =>
+                write!(f, "io error of page {} at offset {}: {}", page_id, offset, source),
+            DbErr::CacheFull => write!(f, "all the pages in the cache are pinned"),
+            DbErr::DatabaseLocked { path } => write!(f, "the database is locked by another handle: {}", path),
+            DbErr::CompressionNotSupported => write!(f, "the database is compressed, but the compression feature is not enabled"),
+            DbErr::CorruptCompressedPage(pid) => write!(f, "the compressed page {} can not be decompressed", pid),
+            DbErr::BrokenFreeList(pid) => write!(f, "the free list is broken at page {}", pid),
+            DbErr::AlreadyExists(path) => write!(f, "the database \"{}\" already exists", path),
+            DbErr::NotFound(path) => write!(f, "the database \"{}\" is not found", path),
+            DbErr::OutOfSpace { requested_bytes } => write!(f, "no space to grow the file by {} bytes", requested_bytes),
+            DbErr::NoDatabaseFile => write!(f, "the database is not stored in a file"),
+            DbErr::FileSizeLimitExceeded { limit } => write!(f, "the file can't grow beyond the limit of {} bytes", limit),
+            DbErr::NotInReadTransaction => write!(f, "not in a read transaction"),
+            DbErr::PageAlreadyFree(pid) => write!(f, "page {} is already free", pid),
+            DbErr::CannotReplaceEncrypted => write!(f, "an encrypted database can't be replaced, it can't be reopened without the key"),
         }
     }
 
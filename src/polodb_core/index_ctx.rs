@@ -153,7 +153,7 @@ impl IndexEntry {
         let mut doc = Document::new_without_id();
         doc.insert("_id".into(), data_value.clone());
 
-        let data_ticket_bytes = data_ticket.to_bytes().to_vec();
+        let data_ticket_bytes = data_ticket.to_store_bytes().to_vec();
         doc.insert("value".into(), Value::Binary(Rc::new(data_ticket_bytes)));
 
         doc
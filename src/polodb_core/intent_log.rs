@@ -0,0 +1,343 @@
+use std::path::{Path, PathBuf};
+use crate::crc64::crc64;
+use crate::page::{PageStore, MemoryPageStore};
+use crate::DbResult;
+use crate::error::DbErr;
+
+static HEADER_DESP: &str       = "PoloDB Intent Log v0.1";
+const NEXT_OP_ID_OFFSET: u64     = 24;
+const INTENT_LOG_DATA_BEGIN: u64 = 32;
+const RECORD_HEADER_SIZE: usize  = 21;
+
+const RECORD_KIND_BEGIN: u8 = 1;
+const RECORD_KIND_END: u8   = 2;
+
+/**
+ * An operation which began but didn't end before the crash
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntentRecord {
+    pub op_id:  u64,
+    pub intent: Vec<u8>,
+}
+
+// title:      24 bytes
+// next op id: 8 bytes, written when the log is truncated,
+//             the ids are never reused across the sessions
+// data begin: 32
+//
+// record:
+// | kind    | op_id   | intent len | checksum | intent |
+// | 1 byte  | 8 bytes | 4 bytes    | 8 bytes  | ...    |
+//
+// checksum is calculated over kind, op_id, len and the intent
+//
+// The intent log provides logical atomicity on top of the physical page journal.
+// A caller brackets a logical operation with begin_op()/end_op(),
+// the operations without an end record are reported after reopening,
+// the higher layers decide to replay or to discard them.
+pub(crate) struct IntentLog {
+    // None if the log is in memory
+    file_path:      Option<PathBuf>,
+    file:           Box<dyn PageStore>,
+    next_op_id:     u64,
+    current_op:     Option<u64>,
+    incomplete_ops: Vec<IntentRecord>,
+}
+
+fn record_checksum(kind: u8, op_id: u64, intent: &[u8]) -> u64 {
+    let mut header = [0u8; 13];
+    header[0] = kind;
+    header[1..9].copy_from_slice(&op_id.to_be_bytes());
+    header[9..13].copy_from_slice(&(intent.len() as u32).to_be_bytes());
+    let checksum = crc64(0, &header);
+    crc64(checksum, intent)
+}
+
+impl IntentLog {
+
+    pub(crate) fn mk_path(db_path: &Path) -> PathBuf {
+        let mut buf = db_path.to_path_buf();
+        let filename = buf.file_name().unwrap().to_str().unwrap();
+        let new_filename = String::from(filename) + ".intent";
+        buf.set_file_name(new_filename);
+        buf
+    }
+
+    pub(crate) fn open(path: &Path) -> DbResult<IntentLog> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .read(true)
+            .open(path)?;
+
+        IntentLog::open_store(Some(path.to_path_buf()), Box::new(file))
+    }
+
+    pub(crate) fn open_in_memory() -> DbResult<IntentLog> {
+        IntentLog::open_store(None, Box::new(MemoryPageStore::new()))
+    }
+
+    fn open_store(file_path: Option<PathBuf>, file: Box<dyn PageStore>) -> DbResult<IntentLog> {
+        let file_len = file.len()?;
+
+        let mut result = IntentLog {
+            file_path,
+            file,
+            next_op_id: 1,
+            current_op: None,
+            incomplete_ops: vec![],
+        };
+
+        if file_len < INTENT_LOG_DATA_BEGIN {
+            result.init_header_to_file()?;
+        } else {
+            result.load_records(file_len)?;
+        }
+
+        Ok(result)
+    }
+
+    fn init_header_to_file(&mut self) -> DbResult<()> {
+        let mut header32 = [0u8; INTENT_LOG_DATA_BEGIN as usize];
+        let title_bytes = HEADER_DESP.as_bytes();
+        header32[0..title_bytes.len()].copy_from_slice(title_bytes);
+        header32[(NEXT_OP_ID_OFFSET as usize)..].copy_from_slice(&self.next_op_id.to_be_bytes());
+
+        self.file.set_len(0)?;
+        self.file.write_at(0, &header32)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn load_records(&mut self, file_len: u64) -> DbResult<()> {
+        let mut content = vec![0u8; file_len as usize];
+        self.file.read_at(0, &mut content)?;
+
+        let title_bytes = HEADER_DESP.as_bytes();
+        if &content[0..title_bytes.len()] != title_bytes {
+            return Err(DbErr::ChecksumMismatch);
+        }
+
+        let mut buffer: [u8; 8] = [0; 8];
+        buffer.copy_from_slice(&content[(NEXT_OP_ID_OFFSET as usize)..(INTENT_LOG_DATA_BEGIN as usize)]);
+        self.next_op_id = std::cmp::max(u64::from_be_bytes(buffer), 1);
+
+        let mut pos = INTENT_LOG_DATA_BEGIN as usize;
+        let mut begins: Vec<IntentRecord> = vec![];
+
+        while pos + RECORD_HEADER_SIZE <= content.len() {
+            let kind = content[pos];
+
+            let mut buffer: [u8; 8] = [0; 8];
+            buffer.copy_from_slice(&content[(pos + 1)..(pos + 9)]);
+            let op_id = u64::from_be_bytes(buffer);
+
+            let mut buffer: [u8; 4] = [0; 4];
+            buffer.copy_from_slice(&content[(pos + 9)..(pos + 13)]);
+            let intent_len = u32::from_be_bytes(buffer) as usize;
+
+            let mut buffer: [u8; 8] = [0; 8];
+            buffer.copy_from_slice(&content[(pos + 13)..(pos + 21)]);
+            let checksum = u64::from_be_bytes(buffer);
+
+            let intent_begin = pos + RECORD_HEADER_SIZE;
+            if intent_begin + intent_len > content.len() {  // torn record
+                break;
+            }
+            let intent = &content[intent_begin..(intent_begin + intent_len)];
+
+            if record_checksum(kind, op_id, intent) != checksum {
+                break;
+            }
+
+            match kind {
+                RECORD_KIND_BEGIN => begins.push(IntentRecord {
+                    op_id,
+                    intent: intent.to_vec(),
+                }),
+
+                RECORD_KIND_END => begins.retain(|record| record.op_id != op_id),
+
+                _ => break,
+            }
+
+            if op_id >= self.next_op_id {
+                self.next_op_id = op_id + 1;
+            }
+
+            pos = intent_begin + intent_len;
+        }
+
+        // trim the torn tail
+        if pos < content.len() {
+            self.file.set_len(pos as u64)?;
+        }
+
+        self.incomplete_ops = begins;
+
+        Ok(())
+    }
+
+    fn append_record(&mut self, kind: u8, op_id: u64, intent: &[u8]) -> DbResult<()> {
+        let mut buffer = Vec::with_capacity(RECORD_HEADER_SIZE + intent.len());
+        buffer.push(kind);
+        buffer.extend_from_slice(&op_id.to_be_bytes());
+        buffer.extend_from_slice(&(intent.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(&record_checksum(kind, op_id, intent).to_be_bytes());
+        buffer.extend_from_slice(intent);
+
+        let end = self.file.len()?;
+        self.file.write_at(end, &buffer)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    // Nothing is pending, the log can be truncated.
+    // The next id is kept in the header before the records are dropped.
+    fn try_truncate(&mut self) -> DbResult<()> {
+        if self.is_clean() {
+            self.file.write_at(NEXT_OP_ID_OFFSET, &self.next_op_id.to_be_bytes())?;
+            self.file.sync_data()?;
+            self.file.set_len(INTENT_LOG_DATA_BEGIN)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn begin_op(&mut self, intent: &[u8]) -> DbResult<u64> {
+        if self.current_op.is_some() {
+            return Err(DbErr::IntentOpInProgress);
+        }
+
+        let op_id = self.next_op_id;
+        self.append_record(RECORD_KIND_BEGIN, op_id, intent)?;
+
+        self.next_op_id += 1;
+        self.current_op = Some(op_id);
+
+        Ok(op_id)
+    }
+
+    pub(crate) fn end_op(&mut self) -> DbResult<()> {
+        let op_id = match self.current_op {
+            Some(op_id) => op_id,
+            None => return Err(DbErr::NoIntentOpInProgress),
+        };
+
+        self.append_record(RECORD_KIND_END, op_id, &[])?;
+        self.current_op = None;
+
+        self.try_truncate()
+    }
+
+    // the operation is replayed or discarded by the caller
+    pub(crate) fn resolve_incomplete_op(&mut self, op_id: u64) -> DbResult<()> {
+        let index = match self.incomplete_ops.iter().position(|record| record.op_id == op_id) {
+            Some(index) => index,
+            None => return Err(DbErr::IntentOpNotFound(op_id)),
+        };
+
+        self.append_record(RECORD_KIND_END, op_id, &[])?;
+        self.incomplete_ops.remove(index);
+
+        self.try_truncate()
+    }
+
+    #[inline]
+    pub(crate) fn incomplete_ops(&self) -> &[IntentRecord] {
+        &self.incomplete_ops
+    }
+
+    #[inline]
+    pub(crate) fn is_clean(&self) -> bool {
+        self.current_op.is_none() && self.incomplete_ops.is_empty()
+    }
+
+    #[inline]
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.file_path.as_deref()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intent_log::IntentLog;
+    use crate::DbErr;
+
+    #[test]
+    fn test_incomplete_ops() {
+        const TEST_FILE: &str = "/tmp/test-intent-log";
+        let _ = std::fs::remove_file(TEST_FILE);
+
+        {
+            let mut intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+            let first = intent_log.begin_op(b"first").unwrap();
+            intent_log.end_op().unwrap();
+
+            let second = intent_log.begin_op(b"second").unwrap();
+            assert!(second > first);
+
+            match intent_log.begin_op(b"nested") {
+                Err(DbErr::IntentOpInProgress) => (),
+                _ => panic!("nested op should fail"),
+            }
+            // crash before end_op()
+        }
+
+        let mut intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+        assert_eq!(intent_log.incomplete_ops().len(), 1);
+        assert_eq!(intent_log.incomplete_ops()[0].intent, b"second");
+
+        let op_id = intent_log.incomplete_ops()[0].op_id;
+        intent_log.resolve_incomplete_op(op_id).unwrap();
+        assert!(intent_log.is_clean());
+
+        let intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+        assert!(intent_log.incomplete_ops().is_empty());
+    }
+
+    #[test]
+    fn test_op_id_not_reused() {
+        const TEST_FILE: &str = "/tmp/test-intent-log-op-id";
+        let _ = std::fs::remove_file(TEST_FILE);
+
+        let first = {
+            let mut intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+            let first = intent_log.begin_op(b"first").unwrap();
+            intent_log.end_op().unwrap();
+            first
+        };
+        // the log is truncated to its header
+        assert_eq!(std::fs::metadata(TEST_FILE).unwrap().len(), super::INTENT_LOG_DATA_BEGIN);
+
+        let mut intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+        let second = intent_log.begin_op(b"second").unwrap();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn test_torn_record() {
+        const TEST_FILE: &str = "/tmp/test-intent-log-torn";
+        let _ = std::fs::remove_file(TEST_FILE);
+
+        {
+            let mut intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+            intent_log.begin_op(b"complete").unwrap();
+        }
+
+        // append half of a record
+        {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new().append(true).open(TEST_FILE).unwrap();
+            file.write_all(&[1, 0, 0, 0]).unwrap();
+        }
+
+        let intent_log = IntentLog::open(TEST_FILE.as_ref()).unwrap();
+        assert_eq!(intent_log.incomplete_ops().len(), 1);
+        assert_eq!(intent_log.incomplete_ops()[0].intent, b"complete");
+    }
+
+}
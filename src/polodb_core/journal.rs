@@ -1,10 +1,10 @@
-use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::collections::BTreeMap;
-use std::io::{Seek, Write, SeekFrom, Read};
 use std::cell::Cell;
+use std::fmt;
 use libc::rand;
-use crate::page::RawPage;
+use crate::page::{RawPage, PageLayout, PageStore, MemoryPageStore, IntegrityReport};
+use crate::page::header_page_wrapper::HeaderPageWrapper;
 use crate::crc64::crc64;
 use crate::DbResult;
 use crate::error::DbErr;
@@ -16,12 +16,28 @@ static HEADER_DESP: &str       = "PipeappleDB Journal v0.1";
 const JOURNAL_DATA_BEGIN: u32 = 64;
 const FRAME_HEADER_SIZE: u32  = 40;
 
+// a delta begins with the position of the full frame it's against,
+// followed by the changed ranges: offset(4 bytes), len(4 bytes), bytes
+const DELTA_BASE_SIZE: usize = 8;
+const DELTA_RANGE_HEADER_SIZE: usize = 8;
+
+// the consecutive pages written at once by the checkpoint
+const CHECKPOINT_BATCH_PAGES: usize = 64;
+
+// the committed pages kept in the offset map when the journal is replayed on opening,
+// the ones beyond are written to the main file
+pub(crate) const REPLAY_BOUND_PAGES: usize = 1024;
+
 // 24 bytes
 pub(crate) struct FrameHeader {
     // the page_id of the main database
     // page_id * offset represents the real offset from the beginning
     page_id:       u32,  // offset 0
 
+    // 0 if the frame keeps the full page,
+    // otherwise the length of the delta after the header
+    data_len:      u32,  // offset 4
+
     // usually 0
     // if this frame is the final commit of a transaction
     // this field represents the read db_size
@@ -41,6 +57,10 @@ impl FrameHeader {
 
         let page_id = u32::from_be_bytes(buffer);
 
+        let mut buffer: [u8; 4] = [0; 4];
+        buffer.copy_from_slice(&bytes[4..8]);
+        let data_len = u32::from_be_bytes(buffer);
+
         let mut buffer: [u8; 8] = [0; 8];
         buffer.copy_from_slice(&bytes[8..16]);
         let db_size = u64::from_be_bytes(buffer);
@@ -55,6 +75,7 @@ impl FrameHeader {
 
         FrameHeader {
             page_id,
+            data_len,
             db_size,
             salt1, salt2
         }
@@ -65,6 +86,9 @@ impl FrameHeader {
         let page_id_be = self.page_id.to_be_bytes();
         buffer[0..4].copy_from_slice(&page_id_be);
 
+        let data_len_be = self.data_len.to_be_bytes();
+        buffer[4..8].copy_from_slice(&data_len_be);
+
         let db_size_be = self.db_size.to_be_bytes();
         buffer[8..16].copy_from_slice(&db_size_be);
 
@@ -83,11 +107,121 @@ pub enum TransactionType {
     Write,
 }
 
+/**
+ * When the data is synced to the disk.
+ *
+ * FullSync: the journal is synced when committing,
+ * and the main file is synced before the journal is truncated by checkpoint.
+ * A committed transaction survives a power failure.
+ *
+ * AsyncFlush: the journal is not synced when committing,
+ * but the main file is synced before the journal is truncated by checkpoint.
+ * The last transactions may be lost on power failure,
+ * the database is not corrupted.
+ *
+ * NoSync: nothing is flushed or synced when committing.
+ * The last transactions may be lost on power failure.
+ * With the main file not synced when checkpointing,
+ * the database may be corrupted on power failure.
+ */
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum DurabilityMode {
+    #[default]
+    FullSync,
+    AsyncFlush,
+    NoSync,
+}
+
+impl DurabilityMode {
+
+    // the main file is synced before the journal is truncated,
+    // or the pages not on the disk yet are lost with the journal
+    #[inline]
+    fn syncs_checkpoint(self) -> bool {
+        self != DurabilityMode::NoSync
+    }
+
+}
+
+/**
+ * How the pages are stored in the journal.
+ *
+ * FullPage: every frame keeps the whole image of the page.
+ *
+ * Delta: a page already in the journal is stored as the byte ranges
+ * changed against its last full frame.
+ * The full image is written if the delta is not smaller than the page.
+ */
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum JournalMode {
+    #[default]
+    FullPage,
+    Delta,
+}
+
+/**
+ * What is found in the journal when it's opened.
+ *
+ * Every frame is verified by the checksums of the header and the page,
+ * the replay stops at the first bad frame.
+ * Only the frames of the committed transactions before it are recovered.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    pub frames_recovered: u32,
+
+    // the complete frames not recovered,
+    // of a transaction not committed, a bad frame or the frames after it
+    pub frames_discarded: u32,
+
+    // a frame fails the checksum or the salt
+    pub corrupt_frame_found: bool,
+
+    // the tail of the journal trimmed, including a partial frame
+    pub bytes_truncated: u64,
+
+    // written to the main file while replaying, to bound the memory
+    pub pages_replayed: u32,
+
+    // the journal file is gone while the main file is marked behind it,
+    // the committed pages in the journal are lost
+    pub journal_missing: bool,
+
+    // checked if the journal is missing,
+    // the main file may be written partially
+    pub integrity: Option<IntegrityReport>,
+}
+
+impl fmt::Display for RecoveryReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RecoveryReport(frames_recovered: {}, frames_discarded: {}, corrupt_frame_found: {}, bytes_truncated: {}, pages_replayed: {}, journal_missing: {})",
+               self.frames_recovered, self.frames_discarded, self.corrupt_frame_found, self.bytes_truncated,
+               self.pages_replayed, self.journal_missing)
+    }
+
+}
+
+// the state of the transaction when the savepoint is set,
+// the frames after journal_len are discarded when rolling back to it
+struct Savepoint {
+    name: String,
+    journal_len: u64,
+    offset_map: BTreeMap<u32, u64>,
+    frame_count: u32,
+    db_file_size: u64,
+    last_frame_pos: Option<u64>,
+}
+
 struct TransactionState {
     ty: TransactionType,
     offset_map: BTreeMap<u32, u64>,
     frame_count: u32,
     db_file_size: u64,
+    savepoints: Vec<Savepoint>,
+
+    // the frame marked as the commit
+    last_frame_pos: Option<u64>,
 }
 
 impl TransactionState {
@@ -98,6 +232,8 @@ impl TransactionState {
             offset_map: BTreeMap::new(),
             frame_count,
             db_file_size,
+            savepoints: vec![],
+            last_frame_pos: None,
         }
     }
 
@@ -111,10 +247,14 @@ impl TransactionState {
 // checksum before 48:   8bytes(offset 48)
 // data begin: 64 bytes
 pub(crate) struct JournalManager {
-    file_path:        PathBuf,
-    journal_file:     File,
+    // None if the journal is in memory
+    file_path:        Option<PathBuf>,
+    journal_file:     Box<dyn PageStore>,
     version:          [u8; 4],
+
+    // the size of the page body
     page_size:        u32,
+    page_layout:      PageLayout,
     salt1:            u32,
     salt2:            u32,
     transaction_state:   Option<Box<TransactionState>>,
@@ -127,6 +267,18 @@ pub(crate) struct JournalManager {
 
     // count of all frames
     count:            u32,
+
+    // the end of the last committed frame
+    committed_len:    u64,
+
+    // some frames are found in the journal when opening
+    is_recovered:     bool,
+
+    recovery_report:  RecoveryReport,
+
+    durability_mode:  DurabilityMode,
+
+    journal_mode:     JournalMode,
 }
 
 fn generate_a_salt() -> u32 {
@@ -137,20 +289,49 @@ fn generate_a_salt() -> u32 {
 
 impl JournalManager {
 
-    pub fn open(path: &Path, page_size: u32, db_file_size: u64) -> DbResult<JournalManager> {
+    #[allow(dead_code)]
+    pub fn open(path: &Path, page_layout: PageLayout, db_file_size: u64) -> DbResult<JournalManager> {
+        JournalManager::open_file(path, page_layout, db_file_size, None)
+    }
+
+    // the committed pages beyond the bound are written to the main file when replaying
+    pub(crate) fn open_replaying(
+        path: &Path, page_layout: PageLayout, db_file_size: u64, db_file: &mut dyn PageStore,
+    ) -> DbResult<JournalManager> {
+        JournalManager::open_file(path, page_layout, db_file_size, Some(db_file))
+    }
+
+    fn open_file(
+        path: &Path, page_layout: PageLayout, db_file_size: u64, db_file: Option<&mut dyn PageStore>,
+    ) -> DbResult<JournalManager> {
         let journal_file = std::fs::OpenOptions::new()
             .create(true)
             .write(true)
             .read(true)
             .open(path)?;
-        let meta = journal_file.metadata()?;
 
         let file_path: PathBuf = path.to_path_buf();
+        JournalManager::open_store(Some(file_path), Box::new(journal_file), page_layout, db_file_size, db_file)
+    }
+
+    #[allow(dead_code)]
+    pub fn open_in_memory(page_layout: PageLayout, db_file_size: u64) -> DbResult<JournalManager> {
+        JournalManager::open_store(None, Box::new(MemoryPageStore::new()), page_layout, db_file_size, None)
+    }
+
+    // all the committed frames are kept in the offset map without the main file
+    pub(crate) fn open_store(
+        file_path: Option<PathBuf>, journal_file: Box<dyn PageStore>,
+        page_layout: PageLayout, db_file_size: u64, db_file: Option<&mut dyn PageStore>,
+    ) -> DbResult<JournalManager> {
+        let file_len = journal_file.len()?;
+
         let mut result = JournalManager {
             file_path,
             journal_file,
             version: [0, 0, 1, 0],
-            page_size,
+            page_size: page_layout.body_size(),
+            page_layout,
             db_file_size,
             salt1: 0,
             salt2: 0,
@@ -158,16 +339,20 @@ impl JournalManager {
 
             offset_map: BTreeMap::new(),
             count: 0,
+            committed_len: JOURNAL_DATA_BEGIN as u64,
+            is_recovered: false,
+            recovery_report: RecoveryReport::default(),
+            durability_mode: DurabilityMode::default(),
+            journal_mode: JournalMode::default(),
         };
 
-        if meta.len() == 0 {  // init the file
+        if file_len == 0 {  // init the file
             result.init_header_to_file()?;
         } else {
             result.read_and_check_from_file()?;
         }
 
-        result.journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN as u64))?;
-        result.load_all_pages(meta.len())?;
+        result.load_all_pages(file_len, db_file)?;
 
         Ok(result)
     }
@@ -175,6 +360,9 @@ impl JournalManager {
     fn init_header_to_file(&mut self) -> DbResult<()> {
         self.salt1 = generate_a_salt();
         self.salt2 = generate_a_salt();
+
+        // frames are appended after the header
+        self.journal_file.set_len(JOURNAL_DATA_BEGIN as u64)?;
         self.write_header_to_file()
     }
 
@@ -199,14 +387,12 @@ impl JournalManager {
         let salt_2_be = self.salt2.to_be_bytes();
         header48[44..48].copy_from_slice(&salt_2_be);
 
-        self.journal_file.seek(SeekFrom::Start(0))?;
-        self.journal_file.write_all(&header48)?;
+        self.journal_file.write_at(0, &header48)?;
 
         let checksum = crc64(0, &header48);
         let checksum_be = checksum.to_be_bytes();
 
-        self.journal_file.seek(SeekFrom::Start(48))?;
-        self.journal_file.write_all(&checksum_be)?;
+        self.journal_file.write_at(48, &checksum_be)?;
 
         Ok(())
     }
@@ -214,7 +400,7 @@ impl JournalManager {
     fn read_and_check_from_file(&mut self) -> DbResult<()> {
         let mut header48: Vec<u8> = Vec::with_capacity(48);
         header48.resize(48, 0);
-        self.journal_file.read_exact(&mut header48)?;
+        self.journal_file.read_at(0, &mut header48)?;
 
         let checksum = crc64(0, &header48);
         let checksum_from_file = self.read_checksum_from_file()?;
@@ -249,9 +435,8 @@ impl JournalManager {
     }
 
     fn read_checksum_from_file(&mut self) -> DbResult<u64> {
-        self.journal_file.seek(SeekFrom::Start(48))?;
         let mut buffer: [u8; 8] = [0; 8];
-        self.journal_file.read_exact(&mut buffer)?;
+        self.journal_file.read_at(48, &mut buffer)?;
         Ok(u64::from_be_bytes(buffer))
     }
 
@@ -266,11 +451,45 @@ impl JournalManager {
         (self.page_size as u64) + (FRAME_HEADER_SIZE as u64)
     }
 
-    fn load_all_pages(&mut self, file_size: u64) -> DbResult<()> {
-        let mut current_pos = self.journal_file.seek(SeekFrom::Current(0))?;
-        let frame_size = self.full_frame_size();
+    // the length of the page or the delta after the frame header
+    #[inline]
+    fn frame_data_len(&self, frame_header: &FrameHeader) -> u64 {
+        if frame_header.data_len == 0 {
+            self.page_size as u64
+        } else {
+            frame_header.data_len as u64
+        }
+    }
+
+    // The frames are read one at a time, only the positions are kept.
+    // Once the committed pages exceed the bound, they are written to the main file,
+    // and dropped from the offset map, the journal is untouched.
+    // An interrupted replay is replayed again from the beginning,
+    // the committed images are written in the same order, so it ends in the same state.
+    fn load_all_pages(&mut self, file_size: u64, mut db_file: Option<&mut dyn PageStore>) -> DbResult<()> {
+        let mut current_pos = JOURNAL_DATA_BEGIN as u64;
+        let mut bad_frame_pos = None;
+
+        while current_pos + (FRAME_HEADER_SIZE as u64) <= file_size {
+            let mut header40: [u8; FRAME_HEADER_SIZE as usize] = [0; FRAME_HEADER_SIZE as usize];
+            self.journal_file.read_at(current_pos, &mut header40)?;
+
+            let frame_header = match self.check_frame_header(&header40) {
+                Ok(frame_header) => frame_header,
+                Err(DbErr::ChecksumMismatch) => {
+                    bad_frame_pos = Some(current_pos);
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let frame_size = (FRAME_HEADER_SIZE as u64) + self.frame_data_len(&frame_header);
+            if current_pos + frame_size > file_size {
+                break;  // a partial frame
+            }
+
+            self.is_recovered = true;
 
-        while current_pos + frame_size <= file_size {
             if self.transaction_state.is_none() {
                 self.new_write_state();
             }
@@ -278,15 +497,14 @@ impl JournalManager {
             let mut buffer = vec![];
             buffer.resize(frame_size as usize, 0);
 
-            self.journal_file.read_exact(&mut buffer)?;
+            self.journal_file.read_at(current_pos, &mut buffer)?;
 
             let is_commit = Cell::new(false);
-            match self.check_and_load_frame(current_pos, &buffer, &is_commit) {
+            match self.check_and_load_frame(current_pos, &frame_header, &buffer, &is_commit) {
                 Ok(()) => (),
                 Err(DbErr::SaltMismatch) |
                 Err(DbErr::ChecksumMismatch) => {
-                    self.journal_file.set_len(current_pos)?;  // trim the tail
-                    self.journal_file.seek(SeekFrom::End(0))?;  // recover position
+                    bad_frame_pos = Some(current_pos);
                     break;  // finish the loop
                 }
                 Err(err) => return Err(err),
@@ -294,31 +512,64 @@ impl JournalManager {
 
             let state = self.transaction_state.as_mut().unwrap();
             state.frame_count += 1;
-            current_pos = self.journal_file.seek(SeekFrom::Current(0))?;
+            current_pos += frame_size;
 
             if is_commit.get() {
                 self.merge_transaction_state();
+                self.committed_len = current_pos;
+
+                if let Some(db_file) = db_file.as_mut() {
+                    if self.offset_map.len() > REPLAY_BOUND_PAGES {
+                        self.replay_committed_pages(*db_file)?;
+                    }
+                }
             }
         }
 
         // remain transaction, abandon
-        if self.transaction_state.is_some() {
-            self.recover_file_and_state()?;
+        let uncommitted_frames = match self.transaction_state.take() {
+            Some(state) => state.frame_count - self.count,
+            None => 0,
+        };
+
+        // trim the tail after the last commit,
+        // a bad frame and a partial frame are trimmed too
+        if file_size > self.committed_len {
+            self.journal_file.set_len(self.committed_len)?;
+            self.recovery_report.bytes_truncated = file_size - self.committed_len;
+            self.recovery_report.frames_discarded = uncommitted_frames;
+        }
+
+        // the frames after a bad one can't be parsed,
+        // they are counted as full pages
+        if let Some(pos) = bad_frame_pos {
+            self.recovery_report.corrupt_frame_found = true;
+            self.recovery_report.frames_discarded += ((file_size - pos) / self.full_frame_size()) as u32;
         }
+        self.recovery_report.frames_recovered = self.count;
+
+        Ok(())
+    }
+
+    // The header is kept in the journal, so the main file is still marked
+    // behind the journal until the checkpoint.
+    fn replay_committed_pages(&mut self, db_file: &mut dyn PageStore) -> DbResult<()> {
+        self.write_committed_pages(db_file, false)?;
+        db_file.flush()?;
+
+        self.recovery_report.pages_replayed += (self.offset_map.len() as u32) - (self.offset_map.contains_key(&0) as u32);
+        self.offset_map.retain(|page_id, _| *page_id == 0);
 
         Ok(())
     }
 
     fn recover_file_and_state(&mut self) -> DbResult<()> {
         self.transaction_state = None;
-        let frame_size = (FRAME_HEADER_SIZE as u64) + (self.page_size as u64);
-        let expected_journal_file_size = (JOURNAL_DATA_BEGIN as u64) + frame_size * (self.count as u64);
-        self.journal_file.set_len(expected_journal_file_size)?;
-        self.journal_file.seek(SeekFrom::End(0))?;
+        self.journal_file.set_len(self.committed_len)?;
         Ok(())
     }
 
-    fn check_and_load_frame(&mut self, current_pos: u64, bytes: &[u8], is_commit: &Cell<bool>) -> DbResult<()> {
+    fn check_frame_header(&self, bytes: &[u8]) -> DbResult<FrameHeader> {
         let frame_header = FrameHeader::from_bytes(&bytes[0..24]);
         let checksum1 = {
             let mut buffer: [u8; 8] = [0; 8];
@@ -326,18 +577,27 @@ impl JournalManager {
             u64::from_be_bytes(buffer)
         };
 
-        let checksum2 = {
-            let mut buffer: [u8; 8] = [0; 8];
-            buffer.copy_from_slice(&bytes[32..40]);
-            u64::from_be_bytes(buffer)
-        };
-
         let actual_header_checksum = crc64(0, &bytes[0..24]);
 
         if actual_header_checksum != checksum1 {
             return Err(DbErr::ChecksumMismatch);
         }
 
+        // a delta is always smaller than the page
+        if frame_header.data_len >= self.page_size {
+            return Err(DbErr::ChecksumMismatch);
+        }
+
+        Ok(frame_header)
+    }
+
+    fn check_and_load_frame(&mut self, current_pos: u64, frame_header: &FrameHeader, bytes: &[u8], is_commit: &Cell<bool>) -> DbResult<()> {
+        let checksum2 = {
+            let mut buffer: [u8; 8] = [0; 8];
+            buffer.copy_from_slice(&bytes[32..40]);
+            u64::from_be_bytes(buffer)
+        };
+
         let actual_page_checksum = crc64(0, &bytes[(FRAME_HEADER_SIZE as usize)..]);
 
         if actual_page_checksum != checksum2 {
@@ -348,12 +608,13 @@ impl JournalManager {
             return Err(DbErr::SaltMismatch);
         }
 
-        // load frame
-        self.offset_map.insert(frame_header.page_id, current_pos);
+        // load frame, it's merged when the transaction is committed
+        let state = self.transaction_state.as_mut().unwrap();
+        state.offset_map.insert(frame_header.page_id, current_pos);
 
         // is a commit frame
         if frame_header.db_size != 0 {
-            self.db_file_size = frame_header.db_size;
+            state.db_file_size = frame_header.db_size;
             is_commit.set(true);
         }
         Ok(())
@@ -369,11 +630,9 @@ impl JournalManager {
         state.ty
     }
 
-    fn update_last_frame(&mut self) -> DbResult<()> {
-        let full_frame_size = self.full_frame_size();
-        let begin_loc = self.journal_file.seek(SeekFrom::End((full_frame_size as i64) * -1))?;
+    fn update_last_frame(&mut self, begin_loc: u64) -> DbResult<()> {
         let mut data: [u8; FRAME_HEADER_SIZE as usize] = [0; FRAME_HEADER_SIZE as usize];
-        self.journal_file.read_exact(&mut data)?;
+        self.journal_file.read_at(begin_loc, &mut data)?;
         let mut frame_header = FrameHeader::from_bytes(&data);
 
         frame_header.db_size = self.db_file_size;
@@ -382,36 +641,30 @@ impl JournalManager {
         let mut header24: [u8; 24] = [0; 24];
         frame_header.to_bytes(&mut header24);
 
-        self.journal_file.seek(SeekFrom::Start(begin_loc))?;
-        self.journal_file.write_all(&header24)?;
+        self.journal_file.write_at(begin_loc, &header24)?;
 
         // update header checksum
         let checksum1 = crc64(0, &header24);
         let checksum1_be = checksum1.to_be_bytes();
-        self.journal_file.write_all(&checksum1_be)?;
+        self.journal_file.write_at(begin_loc + 24, &checksum1_be)?;
 
-        self.journal_file.seek(SeekFrom::End(0))?;
         Ok(())
     }
 
     // frame_header: 24 bytes
     // checksum1:    8 bytes(offset 24)  header24 checksum
     // checksum2:    8 bytes(offset 32)  page checksum
-    // data_begin:   page size(offset 40)
-    pub fn append_frame_header(&mut self, frame_header: &FrameHeader, checksum2: u64) -> std::io::Result<()> {
-        let mut header24: [u8; 24] = [0; 24];
-        frame_header.to_bytes(&mut header24);
-
-        self.journal_file.write_all(&header24)?;
+    // data_begin:   page size or the delta(offset 40)
+    pub fn append_frame_header(&mut self, pos: u64, frame_header: &FrameHeader, checksum2: u64) -> std::io::Result<()> {
+        let mut header40: [u8; FRAME_HEADER_SIZE as usize] = [0; FRAME_HEADER_SIZE as usize];
+        frame_header.to_bytes(&mut header40[0..24]);
 
-        let checksum1 = crc64(0, &header24);
-        let checksum1_be = checksum1.to_be_bytes();
-        self.journal_file.write_all(&checksum1_be)?;
+        let checksum1 = crc64(0, &header40[0..24]);
+        header40[24..32].copy_from_slice(&checksum1.to_be_bytes());
 
-        let checksum2_be = checksum2.to_be_bytes();
-        self.journal_file.write_all(&checksum2_be)?;
+        header40[32..40].copy_from_slice(&checksum2.to_be_bytes());
 
-        Ok(())
+        self.journal_file.write_at(pos, &header40)
     }
 
     pub(crate) fn append_raw_page(&mut self, raw_page: &RawPage) -> DbResult<()> {
@@ -420,27 +673,40 @@ impl JournalManager {
             _ => return Err(DbErr::CannotWriteDbWithoutTransaction),
         }
 
-        let start_pos = self.journal_file.seek(SeekFrom::Current(0))?;
+        // frames are appended to the end
+        let start_pos = self.journal_file.len()?;
+
+        // the journal keeps the encrypted images, as the main file
+        let sealed = self.page_layout.sealed_copy(raw_page);
+        let page_data = &sealed.as_ref().unwrap_or(raw_page).data;
+
+        let delta = match self.journal_mode {
+            JournalMode::FullPage => None,
+            JournalMode::Delta => self.delta_of(raw_page.page_id, page_data)?,
+        };
+        let data: &[u8] = delta.as_deref().unwrap_or(page_data);
 
         let frame_header = FrameHeader {
             page_id: raw_page.page_id,
+            data_len: if delta.is_some() { data.len() as u32 } else { 0 },
             db_size: 0,
             salt1: self.salt1,
             salt2: self.salt2,
         };
 
         // calculate checksum of page data
-        let checksum2 = crc64(0, &raw_page.data);
+        let checksum2 = crc64(0, data);
 
-        self.append_frame_header(&frame_header, checksum2)?;
+        self.append_frame_header(start_pos, &frame_header, checksum2)?;
 
-        self.journal_file.write_all(&raw_page.data)?;
+        self.journal_file.write_at(start_pos + (FRAME_HEADER_SIZE as u64), data)?;
 
         let state = self.transaction_state.as_mut().unwrap();
         state.offset_map.insert(raw_page.page_id, start_pos);
         state.frame_count += 1;
+        state.last_frame_pos = Some(start_pos);
 
-        let expected_db_size = (raw_page.page_id as u64) * (self.page_size as u64);
+        let expected_db_size = self.page_layout.offset_of(raw_page.page_id);
         if expected_db_size > state.db_file_size {
             state.db_file_size = expected_db_size;
         }
@@ -451,41 +717,134 @@ impl JournalManager {
         Ok(())
     }
 
-    pub(crate) fn read_page(&mut self, page_id: u32) -> std::io::Result<Option<RawPage>> {
-        let offset = match &self.transaction_state {
+    // the delta against the last full frame of the page,
+    // None if the page is not in the journal, or the delta is not smaller
+    fn delta_of(&self, page_id: u32, data: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        let offset = match self.frame_position(page_id) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
 
-            // currently in transaction state
-            // find it in state firstly
-            Some(state) => {
-                match state.offset_map.get(&page_id) {
-                    Some(offset) => *offset,
+        let base_pos = self.base_frame_position(offset)?;
+        let mut base = RawPage::new(page_id, self.page_size);
+        base.read_from_file(self.journal_file.as_ref(), base_pos + (FRAME_HEADER_SIZE as u64))?;
 
-                    // not found in transaction_state
-                    // find offset in original journal
-                    None => {
-                        match self.offset_map.get(&page_id) {
-                            Some(offset) => *offset,
-                            None => return Ok(None),
-                        }
-                    }
-                }
+        let mut result = base_pos.to_be_bytes().to_vec();
+        let mut index = 0;
+        while index < data.len() {
+            if base.data[index] == data[index] {
+                index += 1;
+                continue;
             }
 
-            None => {
-                match self.offset_map.get(&page_id) {
-                    Some(offset) => *offset,
-                    None => return Ok(None),
+            let begin = index;
+            let mut end = index + 1;
+
+            // the changes closer than a range header are merged
+            while index < data.len() && index < end + DELTA_RANGE_HEADER_SIZE {
+                if base.data[index] != data[index] {
+                    end = index + 1;
                 }
+                index += 1;
             }
 
-        };
+            result.extend_from_slice(&(begin as u32).to_be_bytes());
+            result.extend_from_slice(&((end - begin) as u32).to_be_bytes());
+            result.extend_from_slice(&data[begin..end]);
+
+            if result.len() >= data.len() {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(result))
+    }
+
+    // the full frame which the frame at the offset is based on
+    fn base_frame_position(&self, offset: u64) -> std::io::Result<u64> {
+        let mut header24: [u8; 24] = [0; 24];
+        self.journal_file.read_at(offset, &mut header24)?;
+        let frame_header = FrameHeader::from_bytes(&header24);
+        if frame_header.data_len == 0 {
+            return Ok(offset);
+        }
 
-        let data_offset = offset + (FRAME_HEADER_SIZE as u64);
+        let mut buffer: [u8; DELTA_BASE_SIZE] = [0; DELTA_BASE_SIZE];
+        self.journal_file.read_at(offset + (FRAME_HEADER_SIZE as u64), &mut buffer)?;
+        Ok(u64::from_be_bytes(buffer))
+    }
 
-        self.journal_file.seek(SeekFrom::Start(data_offset))?;
+    // the sealed image of the page in the frame,
+    // a delta is applied on the full frame it's based on
+    fn read_frame_image(&self, page_id: u32, offset: u64) -> std::io::Result<RawPage> {
+        let mut header24: [u8; 24] = [0; 24];
+        self.journal_file.read_at(offset, &mut header24)?;
+        let frame_header = FrameHeader::from_bytes(&header24);
 
         let mut result = RawPage::new(page_id, self.page_size);
-        result.read_from_file(&mut self.journal_file, data_offset)?;
+        if frame_header.data_len == 0 {
+            result.read_from_file(self.journal_file.as_ref(), offset + (FRAME_HEADER_SIZE as u64))?;
+            return Ok(result);
+        }
+
+        let mut delta = vec![0; frame_header.data_len as usize];
+        self.journal_file.read_at(offset + (FRAME_HEADER_SIZE as u64), &mut delta)?;
+
+        let invalid_delta = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid delta frame in the journal");
+        if delta.len() < DELTA_BASE_SIZE {
+            return Err(invalid_delta());
+        }
+
+        let mut buffer: [u8; DELTA_BASE_SIZE] = [0; DELTA_BASE_SIZE];
+        buffer.copy_from_slice(&delta[0..DELTA_BASE_SIZE]);
+        let base_pos = u64::from_be_bytes(buffer);
+        if base_pos >= offset {
+            return Err(invalid_delta());
+        }
+        result.read_from_file(self.journal_file.as_ref(), base_pos + (FRAME_HEADER_SIZE as u64))?;
+
+        let mut index = DELTA_BASE_SIZE;
+        while index < delta.len() {
+            if index + DELTA_RANGE_HEADER_SIZE > delta.len() {
+                return Err(invalid_delta());
+            }
+            let mut buffer: [u8; 4] = [0; 4];
+            buffer.copy_from_slice(&delta[index..(index + 4)]);
+            let begin = u32::from_be_bytes(buffer) as usize;
+            buffer.copy_from_slice(&delta[(index + 4)..(index + 8)]);
+            let len = u32::from_be_bytes(buffer) as usize;
+            index += DELTA_RANGE_HEADER_SIZE;
+
+            if index + len > delta.len() || begin + len > result.data.len() {
+                return Err(invalid_delta());
+            }
+            result.data[begin..(begin + len)].copy_from_slice(&delta[index..(index + len)]);
+            index += len;
+        }
+
+        Ok(result)
+    }
+
+    // the frame of the page, the current transaction is looked up firstly
+    pub(crate) fn frame_position(&self, page_id: u32) -> Option<u64> {
+        let in_transaction = self.transaction_state.as_ref()
+            .and_then(|state| state.offset_map.get(&page_id));
+        in_transaction.or_else(|| self.offset_map.get(&page_id)).copied()
+    }
+
+    #[inline]
+    pub(crate) fn contains_page(&self, page_id: u32) -> bool {
+        self.frame_position(page_id).is_some()
+    }
+
+    pub(crate) fn read_page(&self, page_id: u32) -> std::io::Result<Option<RawPage>> {
+        let offset = match self.frame_position(page_id) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        let mut result = self.read_frame_image(page_id, offset)?;
+        self.page_layout.unseal(&mut result);
 
         #[cfg(feature = "log")]
             eprintln!("read page from journal, page_id: {}, data_offset:\t\t0x{:0>8X}", page_id, offset);
@@ -493,21 +852,78 @@ impl JournalManager {
         Ok(Some(result))
     }
 
-    pub(crate) fn checkpoint_journal(&mut self, db_file: &mut File) -> DbResult<()> {
+    // the frame positions of the committed pages,
+    // they stay valid until the checkpoint truncates the journal
+    pub(crate) fn committed_offsets(&self) -> BTreeMap<u32, u64> {
+        self.offset_map.clone()
+    }
+
+    pub(crate) fn read_page_at(&self, page_id: u32, offset: u64) -> std::io::Result<RawPage> {
+        let mut result = self.read_frame_image(page_id, offset)?;
+        self.page_layout.unseal(&mut result);
+        Ok(result)
+    }
+
+    // Return the count of pages written to the main file.
+    //
+    // Only the last frame of a page is in the offset map,
+    // so a page journaled many times is written once.
+    // The pages are written in the order of the pids,
+    // the ones in a row are batched into a single write.
+    pub(crate) fn checkpoint_journal(&mut self, db_file: &mut dyn PageStore) -> DbResult<u32> {
+        self.write_committed_pages(db_file, true)?;
+
+        db_file.flush()?;  // only checkpoint flush the file
+
+        // the pages must be durable before the journal is truncated
+        if self.durability_mode.syncs_checkpoint() {
+            db_file.sync_data()?;
+        }
+
+        let page_count = self.offset_map.len() as u32;
+
+        self.checkpoint_finished()?;
+
+        Ok(page_count)
+    }
+
+    fn write_committed_pages(&self, db_file: &mut dyn PageStore, with_header: bool) -> DbResult<()> {
+        let mut batch_begin: u32 = 0;
+        let mut batch_len: usize = 0;
+        let mut batch: Vec<u8> = vec![];
+
         for (page_id, offset) in &self.offset_map {
-            let data_offset = offset + (FRAME_HEADER_SIZE as u64);
+            let mut result = self.read_frame_image(*page_id, *offset)?;
+            self.page_layout.unseal(&mut result);
 
-            self.journal_file.seek(SeekFrom::Start(data_offset))?;
+            if *page_id == 0 {
+                if !with_header {
+                    continue;
+                }
+                let sync = self.durability_mode.syncs_checkpoint();
+                HeaderPageWrapper::from_raw_page(result).write_to_store(self.page_layout, db_file, sync)?;
+                continue;
+            }
 
-            let mut result = RawPage::new(*page_id, self.page_size);
-            result.read_from_file(&mut self.journal_file, data_offset)?;
+            let is_next = batch_len > 0 && *page_id == batch_begin + (batch_len as u32);
+            if batch_len > 0 && (!is_next || batch_len >= CHECKPOINT_BATCH_PAGES) {
+                db_file.write_at(self.page_layout.offset_of(batch_begin), &batch)?;
+                batch.clear();
+                batch_len = 0;
+            }
 
-            result.sync_to_file(db_file, (*page_id as u64) * (self.page_size as u64))?;
+            if batch_len == 0 {
+                batch_begin = *page_id;
+            }
+            batch.extend_from_slice(&self.page_layout.encode_page(&result));
+            batch_len += 1;
         }
 
-        db_file.flush()?;  // only checkpoint flush the file
+        if batch_len > 0 {
+            db_file.write_at(self.page_layout.offset_of(batch_begin), &batch)?;
+        }
 
-        self.checkpoint_finished()
+        Ok(())
     }
 
     fn plus_salt1(&mut self) {
@@ -519,10 +935,11 @@ impl JournalManager {
     }
 
     fn checkpoint_finished(&mut self) -> DbResult<()> {
-        self.journal_file.set_len(64)?;  // truncate file to 64 bytes
+        self.journal_file.set_len(JOURNAL_DATA_BEGIN as u64)?;  // truncate file to 64 bytes
 
         // clear all data
         self.count = 0;
+        self.committed_len = JOURNAL_DATA_BEGIN as u64;
 
         self.offset_map.clear();
 
@@ -560,9 +977,20 @@ impl JournalManager {
             return Err(DbErr::CannotWriteDbWithoutTransaction);
         }
 
+        let last_frame_pos = self.transaction_state.as_ref().and_then(|state| state.last_frame_pos);
         let transaction_ty = self.merge_transaction_state();
         if transaction_ty == TransactionType::Write {
-            self.update_last_frame()?;
+            // nothing is written if the transaction appends no frame
+            if let Some(last_frame_pos) = last_frame_pos {
+                self.update_last_frame(last_frame_pos)?;
+                self.committed_len = self.journal_file.len()?;
+            }
+
+            match self.durability_mode {
+                DurabilityMode::FullSync => self.journal_file.sync_data()?,
+                DurabilityMode::AsyncFlush => self.journal_file.flush()?,
+                DurabilityMode::NoSync => (),
+            }
         }
         self.unlock_file()?;
 
@@ -580,6 +1008,58 @@ impl JournalManager {
         Ok(())
     }
 
+    // a savepoint with the same name shadows the previous one
+    pub(crate) fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        let journal_len = self.journal_file.len()?;
+        let state = match self.transaction_state.as_mut() {
+            Some(state) if state.ty == TransactionType::Write => state,
+            _ => return Err(DbErr::CannotWriteDbWithoutTransaction),
+        };
+
+        state.savepoints.push(Savepoint {
+            name: name.into(),
+            journal_len,
+            offset_map: state.offset_map.clone(),
+            frame_count: state.frame_count,
+            db_file_size: state.db_file_size,
+            last_frame_pos: state.last_frame_pos,
+        });
+
+        Ok(())
+    }
+
+    // The frames after the savepoint are discarded,
+    // the savepoint is kept, the later ones are released.
+    //
+    // return the pages changed after the savepoint
+    pub(crate) fn rollback_to_savepoint(&mut self, name: &str) -> DbResult<Vec<u32>> {
+        let state = match self.transaction_state.as_mut() {
+            Some(state) if state.ty == TransactionType::Write => state,
+            _ => return Err(DbErr::RollbackNotInTransaction),
+        };
+
+        let index = match state.savepoints.iter().rposition(|savepoint| savepoint.name == name) {
+            Some(index) => index,
+            None => return Err(DbErr::SavepointNotFound(name.into())),
+        };
+        state.savepoints.truncate(index + 1);
+        let savepoint = &state.savepoints[index];
+
+        let affected_pages: Vec<u32> = state.offset_map.iter()
+            .filter(|(page_id, offset)| savepoint.offset_map.get(page_id) != Some(offset))
+            .map(|(page_id, _)| *page_id)
+            .collect();
+
+        state.offset_map = savepoint.offset_map.clone();
+        state.frame_count = savepoint.frame_count;
+        state.db_file_size = savepoint.db_file_size;
+        state.last_frame_pos = savepoint.last_frame_pos;
+
+        self.journal_file.set_len(savepoint.journal_len)?;
+
+        Ok(affected_pages)
+    }
+
     pub(crate) fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
         debug_assert!(self.transaction_state.is_some(), "can not upgrade transaction because there is no transaction");
 
@@ -599,7 +1079,10 @@ impl JournalManager {
         use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
         use winapi::ctypes;
 
-        let handle = self.journal_file.as_raw_handle();
+        let handle = match self.journal_file.as_file() {
+            Some(file) => file.as_raw_handle(),
+            None => return Ok(()),  // the journal in memory is not shared
+        };
 
         let bl = unsafe {
             let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
@@ -626,7 +1109,10 @@ impl JournalManager {
         use winapi::um::minwinbase::LOCKFILE_FAIL_IMMEDIATELY;
         use winapi::ctypes;
 
-        let handle = self.journal_file.as_raw_handle();
+        let handle = match self.journal_file.as_file() {
+            Some(file) => file.as_raw_handle(),
+            None => return Ok(()),  // the journal in memory is not shared
+        };
 
         let bl = unsafe {
             let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
@@ -649,7 +1135,10 @@ impl JournalManager {
         use winapi::um::minwinbase::OVERLAPPED;
         use winapi::ctypes;
 
-        let handle = self.journal_file.as_raw_handle();
+        let handle = match self.journal_file.as_file() {
+            Some(file) => file.as_raw_handle(),
+            None => return Ok(()),  // the journal in memory is not shared
+        };
 
         let bl = unsafe {
             let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
@@ -671,7 +1160,10 @@ impl JournalManager {
         use std::os::unix::prelude::*;
         use libc::{flock, LOCK_EX, LOCK_NB};
 
-        let fd = self.journal_file.as_raw_fd();
+        let fd = match self.journal_file.as_file() {
+            Some(file) => file.as_raw_fd(),
+            None => return Ok(()),  // the journal in memory is not shared
+        };
         let result = unsafe {
             flock(fd, LOCK_EX | LOCK_NB)
         };
@@ -688,7 +1180,10 @@ impl JournalManager {
         use std::os::unix::prelude::*;
         use libc::{flock, LOCK_SH, LOCK_NB};
 
-        let fd = self.journal_file.as_raw_fd();
+        let fd = match self.journal_file.as_file() {
+            Some(file) => file.as_raw_fd(),
+            None => return Ok(()),  // the journal in memory is not shared
+        };
         let result = unsafe {
             flock(fd, LOCK_SH | LOCK_NB)
         };
@@ -707,7 +1202,10 @@ impl JournalManager {
         use std::os::unix::prelude::*;
         use libc::{flock, LOCK_UN, LOCK_NB};
 
-        let fd = self.journal_file.as_raw_fd();
+        let fd = match self.journal_file.as_file() {
+            Some(file) => file.as_raw_fd(),
+            None => return Ok(()),  // the journal in memory is not shared
+        };
         let result = unsafe {
             flock(fd, LOCK_UN | LOCK_NB)
         };
@@ -719,9 +1217,27 @@ impl JournalManager {
         }
     }
 
+    // the frames written so far are on the disk,
+    // whatever the durability mode is
+    pub(crate) fn sync(&mut self) -> DbResult<()> {
+        self.journal_file.flush()?;
+        self.journal_file.sync_data()?;
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn set_durability_mode(&mut self, mode: DurabilityMode) {
+        self.durability_mode = mode;
+    }
+
     #[inline]
-    pub(crate) fn path(&self) -> &Path {
-        self.file_path.as_path()
+    pub(crate) fn set_journal_mode(&mut self, mode: JournalMode) {
+        self.journal_mode = mode;
+    }
+
+    #[inline]
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.file_path.as_deref()
     }
 
     #[inline]
@@ -729,6 +1245,43 @@ impl JournalManager {
         self.count
     }
 
+    // the largest page id in the journal, including the current transaction
+    pub(crate) fn max_page_id(&self) -> Option<u32> {
+        let committed = self.offset_map.keys().next_back().copied();
+        let in_transaction = self.transaction_state.as_ref()
+            .and_then(|state| state.offset_map.keys().next_back().copied());
+        committed.max(in_transaction)
+    }
+
+    #[inline]
+    pub(crate) fn is_recovered(&self) -> bool {
+        self.is_recovered
+    }
+
+    #[inline]
+    pub(crate) fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    // (frames appended, distinct pages written) of the current write transaction
+    pub(crate) fn transaction_write_counts(&self) -> Option<(u32, u32)> {
+        match &self.transaction_state {
+            Some(state) if state.ty == TransactionType::Write => {
+                Some((state.frame_count - self.count, state.offset_map.len() as u32))
+            }
+            _ => None,
+        }
+    }
+
+    // the pages written by the write transaction
+    pub(crate) fn transaction_page_ids(&self) -> Vec<u32> {
+        match &self.transaction_state {
+            Some(state) if state.ty == TransactionType::Write => state.offset_map.keys().copied().collect(),
+            _ => vec![],
+        }
+    }
+
+    #[inline]
     pub(crate) fn transaction_type(&self) -> Option<TransactionType> {
         self.transaction_state.as_ref().map(|state| state.ty)
     }
@@ -737,8 +1290,8 @@ impl JournalManager {
 
 #[cfg(test)]
 mod tests {
-    use crate::journal::JournalManager;
-    use crate::page::RawPage;
+    use crate::journal::{JournalManager, JournalMode, JOURNAL_DATA_BEGIN, FRAME_HEADER_SIZE};
+    use crate::page::{RawPage, PageLayout, MemoryPageStore};
     use crate::TransactionType;
 
     static TEST_PAGE_LEN: u32 = 100;
@@ -758,7 +1311,7 @@ mod tests {
     #[test]
     fn test_journal() {
         let _ = std::fs::remove_file("/tmp/test-journal");
-        let mut journal_manager = JournalManager::open("/tmp/test-journal".as_ref(), 4096, 4096).unwrap();
+        let mut journal_manager = JournalManager::open("/tmp/test-journal".as_ref(), PageLayout::new(4096, false), 4096).unwrap();
 
         journal_manager.start_transaction(TransactionType::Write).unwrap();
 
@@ -791,7 +1344,7 @@ mod tests {
         let _ = std::fs::remove_file(TEST_FILE);
         let mem_count;
         {
-            let mut journal_manager = JournalManager::open(TEST_FILE.as_ref(), 4096, 4096).unwrap();
+            let mut journal_manager = JournalManager::open(TEST_FILE.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
 
             journal_manager.start_transaction(TransactionType::Write).unwrap();
 
@@ -809,8 +1362,113 @@ mod tests {
             mem_count = journal_manager.count;
         }
 
-        let journal_manager = JournalManager::open(TEST_FILE.as_ref(), 4096, 4096).unwrap();
+        let journal_manager = JournalManager::open(TEST_FILE.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
         assert_eq!(mem_count, journal_manager.count);
+        assert_eq!(journal_manager.recovery_report().frames_recovered, mem_count);
+        assert_eq!(journal_manager.recovery_report().frames_discarded, 0);
+    }
+
+    fn write_two_transactions(path: &str) -> JournalManager {
+        let _ = std::fs::remove_file(path);
+        let mut journal_manager = JournalManager::open(path.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
+
+        for range in &[0..10, 10..15] {
+            journal_manager.start_transaction(TransactionType::Write).unwrap();
+            for i in range.clone() {
+                journal_manager.append_raw_page(&make_raw_page(i)).unwrap();
+            }
+            journal_manager.commit().unwrap();
+        }
+
+        journal_manager
+    }
+
+    #[test]
+    fn test_recover_truncated_frame() {
+        const TEST_FILE: &str = "/tmp/test-journal-truncated-frame";
+
+        let frame_size = write_two_transactions(TEST_FILE).full_frame_size();
+
+        // cut the 13th frame in the middle
+        let truncated_len = (JOURNAL_DATA_BEGIN as u64) + frame_size * 12 + frame_size / 2;
+        let file = std::fs::OpenOptions::new().write(true).open(TEST_FILE).unwrap();
+        file.set_len(truncated_len).unwrap();
+
+        let journal_manager = JournalManager::open(TEST_FILE.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
+        let report = journal_manager.recovery_report();
+        assert_eq!(report.frames_recovered, 10);
+        assert_eq!(report.frames_discarded, 2);
+        assert!(!report.corrupt_frame_found);
+        assert_eq!(report.bytes_truncated, truncated_len - (JOURNAL_DATA_BEGIN as u64) - frame_size * 10);
+
+        assert_eq!(journal_manager.len(), 10);
+        assert!(journal_manager.read_page(9).unwrap().is_some());
+        assert!(journal_manager.read_page(10).unwrap().is_none());
+        assert_eq!(std::fs::metadata(TEST_FILE).unwrap().len(), (JOURNAL_DATA_BEGIN as u64) + frame_size * 10);
+    }
+
+    #[test]
+    fn test_recover_corrupt_frame() {
+        const TEST_FILE: &str = "/tmp/test-journal-corrupt-frame";
+
+        let frame_size = write_two_transactions(TEST_FILE).full_frame_size();
+
+        // flip a byte in the page of the 3rd frame
+        let pos = (JOURNAL_DATA_BEGIN as u64) + frame_size * 2 + (FRAME_HEADER_SIZE as u64) + 100;
+        let mut bytes = std::fs::read(TEST_FILE).unwrap();
+        bytes[pos as usize] ^= 0xFF;
+        std::fs::write(TEST_FILE, &bytes).unwrap();
+
+        let journal_manager = JournalManager::open(TEST_FILE.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
+        let report = journal_manager.recovery_report();
+        assert_eq!(report.frames_recovered, 0);
+        assert_eq!(report.frames_discarded, 15);
+        assert!(report.corrupt_frame_found);
+        assert!(journal_manager.read_page(0).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_delta_frame() {
+        const TEST_FILE: &str = "/tmp/test-journal-delta-frame";
+
+        let write_twice = |mode: JournalMode| -> (u64, RawPage) {
+            let _ = std::fs::remove_file(TEST_FILE);
+            let mut journal_manager = JournalManager::open(TEST_FILE.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
+            journal_manager.set_journal_mode(mode);
+
+            journal_manager.start_transaction(TransactionType::Write).unwrap();
+            let mut page = make_raw_page(3);
+            journal_manager.append_raw_page(&page).unwrap();
+            journal_manager.commit().unwrap();
+
+            journal_manager.start_transaction(TransactionType::Write).unwrap();
+            for i in 1000..1008 {
+                page.data[i] = !page.data[i];
+            }
+            let before = journal_manager.journal_file.len().unwrap();
+            journal_manager.append_raw_page(&page).unwrap();
+            let frame_size = journal_manager.journal_file.len().unwrap() - before;
+            assert_eq!(journal_manager.read_page(3).unwrap().unwrap().data, page.data);
+            journal_manager.commit().unwrap();
+
+            (frame_size, page)
+        };
+
+        let (full_frame_size, _) = write_twice(JournalMode::FullPage);
+        assert_eq!(full_frame_size, (FRAME_HEADER_SIZE as u64) + 4096);
+
+        let (delta_frame_size, page) = write_twice(JournalMode::Delta);
+        assert!(delta_frame_size < full_frame_size);
+
+        // replayed when it's opened again
+        let mut journal_manager = JournalManager::open(TEST_FILE.as_ref(), PageLayout::new(4096, false), 4096).unwrap();
+        assert_eq!(journal_manager.recovery_report().frames_recovered, 2);
+        assert_eq!(journal_manager.read_page(3).unwrap().unwrap().data, page.data);
+
+        let mut db_file = MemoryPageStore::new();
+        assert_eq!(journal_manager.checkpoint_journal(&mut db_file).unwrap(), 1);
+        let checkpointed = PageLayout::new(4096, false).read_page(3, &db_file).unwrap();
+        assert_eq!(checkpointed.data, page.data);
     }
 
 }
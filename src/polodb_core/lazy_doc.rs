@@ -0,0 +1,132 @@
+use std::rc::Rc;
+use polodb_bson::Document;
+use crate::cursor::Cursor;
+use crate::page::PageHandler;
+use crate::DbResult;
+
+/**
+ * The raw bytes of a document read from the data page.
+ *
+ * Decoding is deferred until `decode()` is called,
+ * so the caller can filter it by the bytes cheaply.
+ */
+pub struct LazyDoc {
+    bytes: Vec<u8>,
+}
+
+impl LazyDoc {
+
+    #[inline]
+    pub(crate) fn new(bytes: Vec<u8>) -> LazyDoc {
+        LazyDoc { bytes }
+    }
+
+    #[inline]
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn decode(&self) -> DbResult<Rc<Document>> {
+        let doc = Document::from_bytes(&self.bytes)?;
+        Ok(Rc::new(doc))
+    }
+
+}
+
+/**
+ * Iterate all the documents of a collection,
+ * yield LazyDoc instead of Document
+ *
+ * In scan mode, the pages read by the iterator
+ * are not inserted into the page cache.
+ *
+ * The pages are read in the read transaction started by the caller,
+ * it's committed when the iterator is exhausted, fails or is dropped,
+ * like the VM does when it's closed.
+ */
+pub struct LazyDocIter<'a> {
+    page_handler: &'a mut PageHandler,
+    cursor:       Cursor,
+    is_halt:      bool,
+    is_scan:      bool,
+    is_committed: bool,
+}
+
+impl<'a> LazyDocIter<'a> {
+
+    pub(crate) fn new(page_handler: &mut PageHandler, item_size: u32, root_pid: u32, is_scan: bool) -> DbResult<LazyDocIter<'_>> {
+        if is_scan {
+            page_handler.begin_scan();
+        }
+
+        let mut cursor = Cursor::new(item_size, root_pid);
+        let reset_result = cursor.reset(page_handler);
+
+        let iter = LazyDocIter {
+            page_handler,
+            cursor,
+            is_halt: false,
+            is_scan,
+            is_committed: false,
+        };
+
+        reset_result?;
+
+        Ok(iter)
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        if self.is_committed {
+            return Ok(());
+        }
+        self.is_committed = true;
+        self.page_handler.auto_commit()
+    }
+
+}
+
+impl<'a> Drop for LazyDocIter<'a> {
+
+    fn drop(&mut self) {
+        if self.is_scan {
+            self.page_handler.end_scan();
+        }
+        let result = self.commit();
+        #[cfg(debug_assertions)]
+        if let Err(err) = result {
+            panic!("commit fatal: {}", err);
+        }
+    }
+
+}
+
+impl<'a> Iterator for LazyDocIter<'a> {
+    type Item = DbResult<LazyDoc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_halt || !self.cursor.has_next() {
+            return self.commit().err().map(Err);
+        }
+
+        match self.cursor.next_lazy(self.page_handler) {
+            Ok(Some(doc)) => Some(Ok(doc)),
+            Ok(None) => self.commit().err().map(Err),
+            Err(err) => {
+                self.is_halt = true;
+                let _ = self.commit();
+                Some(Err(err))
+            }
+        }
+    }
+
+}
@@ -1,8 +1,12 @@
 mod btree;
 mod page;
 mod journal;
+mod intent_log;
+mod change_map;
 mod vm;
 mod crc64;
+mod crc32;
+mod aes;
 mod error;
 mod cursor;
 
@@ -12,9 +16,17 @@ mod index_ctx;
 mod meta_doc_helper;
 mod context;
 mod db_handle;
+mod lazy_doc;
+mod config;
 
 pub use db::{Database, DbResult};
-pub use journal::TransactionType;
+pub use journal::{TransactionType, DurabilityMode, JournalMode, RecoveryReport};
 pub use context::DbContext;
 pub use db_handle::DbHandle;
 pub use error::DbErr;
+pub use lazy_doc::{LazyDoc, LazyDocIter};
+pub use data_ticket::DataTicket;
+pub use page::{HealthReport, QuickVerifyReport, IntegrityReport, IntegrityAnomaly, SalvageReport, WriteStats, VacuumStats, StorageStats, CacheMetrics, CachePolicy, TransactionStateKind};
+pub use config::{Config, GrowthStrategy, AllocationOrder, DataPagePolicy};
+pub use intent_log::IntentRecord;
+pub use change_map::BackupMarker;
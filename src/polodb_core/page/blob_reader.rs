@@ -0,0 +1,80 @@
+use std::io::{self, Read};
+use super::page_handler::PageHandler;
+use super::overflow_page_wrapper::OverflowPageWrapper;
+
+/**
+ * Read the bytes of a blob page by page,
+ * only the data of one overflow page is held at a time.
+ *
+ * A blob stored in the data page is yielded at once.
+ * The errors of the handler are returned as io errors,
+ * the stream stops at the failed page.
+ */
+pub(crate) struct BlobReader<'a> {
+    page_handler: &'a mut PageHandler,
+    chunk:        Vec<u8>,
+    offset:       usize,
+    next_pid:     u32,
+}
+
+impl<'a> BlobReader<'a> {
+
+    pub(crate) fn inline(page_handler: &'a mut PageHandler, bytes: Vec<u8>) -> BlobReader<'a> {
+        BlobReader {
+            page_handler,
+            chunk: bytes,
+            offset: 0,
+            next_pid: 0,
+        }
+    }
+
+    pub(crate) fn overflow(page_handler: &'a mut PageHandler, first_pid: u32) -> BlobReader<'a> {
+        BlobReader {
+            page_handler,
+            chunk: vec![],
+            offset: 0,
+            next_pid: first_pid,
+        }
+    }
+
+    fn load_next_page(&mut self) -> io::Result<()> {
+        let page = self.page_handler.pipeline_read_page(self.next_pid)
+            .map_err(|err| {
+                self.next_pid = 0;
+                io::Error::other(err.to_string())
+            })?;
+        let wrapper = OverflowPageWrapper::from_raw_page(page);
+
+        self.chunk.clear();
+        self.chunk.extend_from_slice(wrapper.data());
+        self.offset = 0;
+        self.next_pid = wrapper.next_pid();
+
+        Ok(())
+    }
+
+}
+
+impl<'a> Read for BlobReader<'a> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // an overflow page may carry no data
+        while self.offset >= self.chunk.len() {
+            if self.next_pid == 0 {
+                return Ok(0);
+            }
+            self.load_next_page()?;
+        }
+
+        let len = std::cmp::min(buf.len(), self.chunk.len() - self.offset);
+        buf[0..len].copy_from_slice(&self.chunk[self.offset..(self.offset + len)]);
+        self.offset += len;
+
+        Ok(len)
+    }
+
+}
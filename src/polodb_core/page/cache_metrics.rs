@@ -0,0 +1,44 @@
+use std::fmt;
+
+/**
+ * Where the pages are read from by the pipeline,
+ * since the database is opened or the metrics are reset.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CacheMetrics {
+    // found in the page cache
+    pub hits:         u64,
+
+    // missed in the cache, found in the journal
+    pub journal_hits: u64,
+
+    // missed in the cache and the journal, read from the main file
+    pub disk_reads:   u64,
+}
+
+impl CacheMetrics {
+
+    #[inline]
+    pub fn misses(&self) -> u64 {
+        self.journal_hits + self.disk_reads
+    }
+
+    // 0.0 if nothing is read
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses();
+        if total == 0 {
+            return 0.0;
+        }
+        (self.hits as f64) / (total as f64)
+    }
+
+}
+
+impl fmt::Display for CacheMetrics {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CacheMetrics(hits: {}, journal_hits: {}, disk_reads: {})",
+               self.hits, self.journal_hits, self.disk_reads)
+    }
+
+}
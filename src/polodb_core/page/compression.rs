@@ -0,0 +1,244 @@
+use crate::DbResult;
+use crate::error::DbErr;
+use super::page::RawPage;
+
+const COMPRESSED_PAGE_MARK: u8 = 0xFE;
+const COMPRESSED_PREFIX_SIZE: usize = 4;
+
+const MIN_MATCH: usize = 4;
+const LAST_LITERALS: usize = 5;
+const MATCH_FIND_LIMIT: usize = 12;
+const HASH_LOG: u32 = 12;
+
+/**
+ * The page compressed in the LZ4 block format,
+ * padded with zeros to the page size.
+ *
+ * | mark   | reserved | compressed len | compressed bytes | zeros |
+ * | 1 byte | 1 byte   | 2 bytes        | ...              | ...   |
+ *
+ * Only the typed pages are compressed, they begin with 0xFF,
+ * the header and the blank pages are stored as they are,
+ * so are the typed pages not smaller when compressed.
+ */
+pub(crate) fn compressed_copy(page: &RawPage) -> Option<RawPage> {
+    if page.data.first() != Some(&0xFF) {
+        return None;
+    }
+
+    let compressed = compress(&page.data);
+    if compressed.len() + COMPRESSED_PREFIX_SIZE > page.data.len() {
+        return None;
+    }
+
+    let mut result = RawPage::new(page.page_id, page.len());
+    result.data[0] = COMPRESSED_PAGE_MARK;
+    result.data[2..4].copy_from_slice(&(compressed.len() as u16).to_be_bytes());
+    result.data[COMPRESSED_PREFIX_SIZE..(COMPRESSED_PREFIX_SIZE + compressed.len())].copy_from_slice(&compressed);
+    Some(result)
+}
+
+// the page stored as it is is untouched
+pub(crate) fn decompress_page(page: &mut RawPage) -> DbResult<()> {
+    if page.data.first() != Some(&COMPRESSED_PAGE_MARK) {
+        return Ok(());
+    }
+
+    let compressed_len = u16::from_be_bytes([page.data[2], page.data[3]]) as usize;
+    let end = COMPRESSED_PREFIX_SIZE + compressed_len;
+    if end > page.data.len() {
+        return Err(DbErr::CorruptCompressedPage(page.page_id));
+    }
+
+    let bytes = decompress(&page.data[COMPRESSED_PREFIX_SIZE..end], page.data.len())
+        .ok_or(DbErr::CorruptCompressedPage(page.page_id))?;
+    page.data.copy_from_slice(&bytes);
+    Ok(())
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+#[inline]
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn write_length(output: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        output.push(255);
+        len -= 255;
+    }
+    output.push(len as u8);
+}
+
+// the literals, followed by the offset and the length of the match,
+// the last sequence has no match
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let literals_len = literals.len();
+    let match_code = found.map_or(0, |(_, match_len)| match_len - MIN_MATCH);
+
+    let token = ((literals_len.min(15) as u8) << 4) | (match_code.min(15) as u8);
+    output.push(token);
+    if literals_len >= 15 {
+        write_length(output, literals_len - 15);
+    }
+    output.extend_from_slice(literals);
+
+    if let Some((offset, _)) = found {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_code >= 15 {
+            write_length(output, match_code - 15);
+        }
+    }
+}
+
+// greedy, the latest position of a hash is the only candidate
+fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    let mut table = vec![0u32; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    if input.len() > MATCH_FIND_LIMIT {
+        let match_limit = input.len() - MATCH_FIND_LIMIT;
+        let end_limit = input.len() - LAST_LITERALS;
+
+        while pos < match_limit {
+            let sequence = read_u32(input, pos);
+            let slot = hash(sequence);
+            // the position is stored plus one, zero is empty
+            let candidate = table[slot] as usize;
+            table[slot] = (pos + 1) as u32;
+
+            if candidate != 0 {
+                let candidate = candidate - 1;
+                if pos - candidate <= (u16::MAX as usize) && read_u32(input, candidate) == sequence {
+                    let mut match_len = MIN_MATCH;
+                    while pos + match_len < end_limit && input[candidate + match_len] == input[pos + match_len] {
+                        match_len += 1;
+                    }
+
+                    write_sequence(&mut output, &input[anchor..pos], Some((pos - candidate, match_len)));
+                    pos += match_len;
+                    anchor = pos;
+                    continue;
+                }
+            }
+
+            pos += 1;
+        }
+    }
+
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+fn read_length(input: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut result = 0;
+    loop {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        result += byte as usize;
+        if byte != 255 {
+            return Some(result);
+        }
+    }
+}
+
+// None if the bytes are not a block of exactly output_len bytes
+fn decompress(input: &[u8], output_len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_len);
+    let mut pos = 0;
+
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+
+        let mut literals_len = (token >> 4) as usize;
+        if literals_len == 15 {
+            literals_len += read_length(input, &mut pos)?;
+        }
+        let literals = input.get(pos..(pos + literals_len))?;
+        if output.len() + literals_len > output_len {
+            return None;
+        }
+        output.extend_from_slice(literals);
+        pos += literals_len;
+
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+
+        let mut match_len = ((token & 0x0F) as usize) + MIN_MATCH;
+        if token & 0x0F == 0x0F {
+            match_len += read_length(input, &mut pos)?;
+        }
+        if offset == 0 || offset > output.len() || output.len() + match_len > output_len {
+            return None;
+        }
+
+        // the match may overlap the bytes it produces
+        let begin = output.len() - offset;
+        for index in 0..match_len {
+            let byte = output[begin + index];
+            output.push(byte);
+        }
+    }
+
+    if output.len() == output_len {
+        Some(output)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::RawPage;
+    use super::{compress, decompress, compressed_copy, decompress_page};
+
+    #[test]
+    fn test_compress_round_trip() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            b"short".to_vec(),
+            vec![7u8; 4096],
+            (0..4096).map(|i| (i % 251) as u8).collect(),
+            b"the quick brown fox jumps over the lazy dog, ".repeat(100),
+        ];
+
+        for input in &inputs {
+            let compressed = compress(input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), *input);
+        }
+
+        // not the expected length
+        let compressed = compress(&inputs[2]);
+        assert!(decompress(&compressed, 4095).is_none());
+        assert!(decompress(&compressed[0..(compressed.len() - 1)], 4096).is_none());
+    }
+
+    #[test]
+    fn test_compressed_page() {
+        let mut page = RawPage::new(3, 4092);
+        page.data[0] = 0xFF;
+        page.data[1] = 3;
+        let text = b"{\"name\": \"PoloDB\", \"content\": \"text\"}".repeat(80);
+        page.data[2..(2 + text.len())].copy_from_slice(&text);
+
+        let mut compressed = compressed_copy(&page).unwrap();
+        assert_eq!(compressed.data[0], 0xFE);
+        decompress_page(&mut compressed).unwrap();
+        assert_eq!(compressed.data, page.data);
+
+        // the blank pages are kept
+        assert!(compressed_copy(&RawPage::new(3, 4092)).is_none());
+    }
+
+}
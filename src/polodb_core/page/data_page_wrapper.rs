@@ -31,6 +31,12 @@ impl DataPageWrapper {
         }
     }
 
+    // the largest item can be put into an empty page
+    #[inline]
+    pub(crate) fn max_item_size(page_size: u32) -> u32 {
+        page_size - DATA_PAGE_HEADER_SIZE - 2 - 2
+    }
+
     pub(crate) fn from_raw(raw_page: RawPage) -> DataPageWrapper {
         let bar_len = raw_page.get_u16(6);
 
@@ -42,13 +48,37 @@ impl DataPageWrapper {
         }
     }
 
+    // the bars should be non-increasing from the end of the page,
+    // and not overlap the bars themselves,
+    // the index of the first invalid bar is returned
+    pub(crate) fn first_invalid_bar(raw_page: &RawPage) -> Option<u32> {
+        let bar_len = raw_page.get_u16(6) as u32;
+        let bars_end = DATA_PAGE_HEADER_SIZE + bar_len * 2;
+        let mut prev_bar = raw_page.len();
+        for index in 0..bar_len {
+            let bar = match raw_page.read_u16(DATA_PAGE_HEADER_SIZE + index * 2) {
+                Ok(bar) => bar as u32,
+                Err(_) => return Some(index),
+            };
+            if bar > prev_bar || bar < bars_end {
+                return Some(index);
+            }
+            prev_bar = bar;
+        }
+        None
+    }
+
+    // the bar of the next slot is reserved, as put() counts it,
+    // a bar_len running past the page leaves no room
     fn get_remain_size(raw_page: &RawPage, bar_len: u32) -> u32 {
         if bar_len == 0 {
             raw_page.len() - DATA_PAGE_HEADER_SIZE - 2
         } else {
-            let last_bar_index = DATA_PAGE_HEADER_SIZE + (bar_len - 1) * 2;
-            let last_bar = raw_page.get_u16(DATA_PAGE_HEADER_SIZE + (bar_len - 1) * 2);
-            (last_bar as u32) - last_bar_index - 2
+            let bars_end = DATA_PAGE_HEADER_SIZE + bar_len * 2;
+            match raw_page.read_u16(DATA_PAGE_HEADER_SIZE + (bar_len - 1) * 2) {
+                Ok(last_bar) => (last_bar as u32).saturating_sub(bars_end + 2),
+                Err(_) => 0,
+            }
         }
     }
 
@@ -150,6 +180,76 @@ impl DataPageWrapper {
         self.remain_size += item_len as u32;
     }
 
+    #[inline]
+    fn is_removed(&self, index: u32) -> bool {
+        let (begin_bar, end_bar) = self.get_bars_by_index(index);
+        begin_bar == end_bar
+    }
+
+    // the indices of the items not removed
+    pub(crate) fn occupied_indices(&self) -> Vec<u32> {
+        (0..self.bar_len())
+            .filter(|index| !self.is_removed(*index))
+            .collect()
+    }
+
+    // The data is already contiguous because removing shifts it,
+    // the space wasted is the bars of the removed items.
+    // Drop these bars and return the new index of every old index,
+    // None for the removed ones, the tickets referring to the page
+    // must be updated by the mapping.
+    #[allow(dead_code)]
+    pub(crate) fn compact(&mut self) -> Vec<Option<u32>> {
+        let total_len = self.bar_len();
+        let mut mapping = Vec::with_capacity(total_len as usize);
+        let mut new_len = 0;
+        for index in 0..total_len {
+            if self.is_removed(index) {
+                mapping.push(None);
+                continue;
+            }
+
+            // the slots before are not behind, it's safe to move it forward
+            let bar = self.get_bar_value(index);
+            self.page.seek(DATA_PAGE_HEADER_SIZE + new_len * 2);
+            self.page.put_u16(bar);
+
+            mapping.push(Some(new_len));
+            new_len += 1;
+        }
+
+        self.reset_bar_len(new_len);
+
+        mapping
+    }
+
+    // The removed items at the end are dropped,
+    // the index of all the other items are preserved,
+    // so nothing refers to the page needs to be updated.
+    pub(crate) fn truncate_removed_tail(&mut self) {
+        let mut new_len = self.bar_len();
+        while new_len > 0 && self.is_removed(new_len - 1) {
+            new_len -= 1;
+        }
+
+        self.reset_bar_len(new_len);
+    }
+
+    fn reset_bar_len(&mut self, new_len: u32) {
+        let old_len = self.bar_len();
+        if new_len == old_len {
+            return;
+        }
+
+        for index in new_len..old_len {
+            self.page.seek(DATA_PAGE_HEADER_SIZE + index * 2);
+            self.page.put_u16(0);
+        }
+
+        self.set_bar_len(new_len);
+        self.remain_size += (old_len - new_len) * 2;
+    }
+
     #[inline]
     fn get_bar_value(&self, index: u32) -> u16 {
         let index = DATA_PAGE_HEADER_SIZE + index * 2;
@@ -291,4 +391,78 @@ mod tests {
         assert!(wrapper.get(1).is_none());
     }
 
+    fn fragmented_wrapper() -> DataPageWrapper {
+        let mut wrapper = DataPageWrapper::init(1, 4096);
+        for i in 0..600 {
+            let item: [u8; 4] = [i as u8; 4];
+            wrapper.put(&item);
+        }
+
+        // keep the items 100 and 300
+        for i in 0..600 {
+            if i != 100 && i != 300 {
+                wrapper.remove(i);
+            }
+        }
+
+        wrapper
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut wrapper = fragmented_wrapper();
+        assert_eq!(wrapper.data_len(), 2);
+
+        // the bars of the removed items take the space
+        let large_item = vec![7u8; 3000];
+        assert!(wrapper.remain_size() < (large_item.len() as u32) + 2);
+
+        let mapping = wrapper.compact();
+        assert_eq!(mapping.len(), 600);
+        assert_eq!(mapping[100], Some(0));
+        assert_eq!(mapping[300], Some(1));
+        assert_eq!(mapping.iter().filter(|index| index.is_some()).count(), 2);
+
+        assert_eq!(wrapper.bar_len(), 2);
+        assert_eq!(wrapper.get(0).unwrap(), [100u8; 4]);
+        assert_eq!(wrapper.get(1).unwrap(), [(300 % 256) as u8; 4]);
+
+        // fits after compaction
+        assert!(wrapper.remain_size() >= (large_item.len() as u32) + 2);
+        wrapper.put(&large_item);
+        assert_eq!(wrapper.get(2).unwrap(), large_item.as_slice());
+
+        let wrapper = DataPageWrapper::from_raw(wrapper.consume_page());
+        assert_eq!(wrapper.data_len(), 3);
+        assert_eq!(wrapper.get(0).unwrap(), [100u8; 4]);
+        assert_eq!(wrapper.get(2).unwrap(), large_item.as_slice());
+    }
+
+    #[test]
+    fn test_bar_len_past_page() {
+        let mut wrapper = DataPageWrapper::init(1, 512);
+        wrapper.put(&[1u8; 4]);
+        wrapper.set_bar_len(1000);
+
+        let raw_page = wrapper.consume_page();
+        assert_eq!(DataPageWrapper::first_invalid_bar(&raw_page), Some(0));
+        let wrapper = DataPageWrapper::from_raw(raw_page);
+        assert_eq!(wrapper.remain_size(), 0);
+    }
+
+    #[test]
+    fn test_truncate_removed_tail() {
+        let mut wrapper = fragmented_wrapper();
+        let remain_size = wrapper.remain_size();
+
+        wrapper.truncate_removed_tail();
+        assert_eq!(wrapper.bar_len(), 301);
+        assert_eq!(wrapper.remain_size(), remain_size + 299 * 2);
+
+        // the indexes are preserved
+        assert_eq!(wrapper.get(100).unwrap(), [100u8; 4]);
+        assert!(wrapper.get(200).is_none());
+        assert_eq!(wrapper.get(300).unwrap(), [(300 % 256) as u8; 4]);
+    }
+
 }
@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::path::Path;
+use crate::DbResult;
+use crate::error::DbErr;
+
+#[cfg(target_os = "windows")]
+use std::os::windows::io::AsRawHandle;
+
+// The advisory lock of the main file is held by the file handle,
+// it's released when the handle is closed,
+// so the handler never unlocks it explicitly.
+//
+// A writable handler takes it exclusively,
+// the read-only handlers share it.
+pub(crate) fn lock_main_file(file: &File, path: &Path, exclusive: bool) -> DbResult<()> {
+    if try_lock(file, exclusive) {
+        Ok(())
+    } else {
+        Err(DbErr::DatabaseLocked { path: path.to_string_lossy().into() })
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn try_lock(file: &File, exclusive: bool) -> bool {
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::OVERLAPPED;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+    use winapi::ctypes;
+
+    let flags = if exclusive {
+        LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY
+    } else {
+        LOCKFILE_FAIL_IMMEDIATELY
+    };
+
+    let bl = unsafe {
+        let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
+        libc::memset(overlapped.cast::<libc::c_void>(), 0, std::mem::size_of::<OVERLAPPED>());
+        let result: i32 = LockFileEx(file.as_raw_handle().cast::<ctypes::c_void>(), flags, 0, 0, 0, overlapped);
+        libc::free(overlapped.cast::<libc::c_void>());
+        result
+    };
+
+    bl != 0
+}
+
+/// LOCK_NB: non-blocking
+#[cfg(not(target_os = "windows"))]
+fn try_lock(file: &File, exclusive: bool) -> bool {
+    use std::os::unix::prelude::*;
+    use libc::{flock, LOCK_EX, LOCK_SH, LOCK_NB};
+
+    let operation = if exclusive { LOCK_EX } else { LOCK_SH };
+    let result = unsafe {
+        flock(file.as_raw_fd(), operation | LOCK_NB)
+    };
+
+    result == 0
+}
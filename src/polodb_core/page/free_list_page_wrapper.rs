@@ -0,0 +1,104 @@
+use super::page::{RawPage, PageType};
+use crate::DbResult;
+
+const FREE_LIST_SIZE_OFFSET: u32 = 4;
+const FREE_LIST_NEXT_OFFSET: u32 = 8;
+const FREE_LIST_CONTENT_OFFSET: u32 = 12;
+
+/**
+ * The free list spilled from the header page
+ *
+ * Offset 0 (2 bytes): magic number
+ * Offset 4 (4 bytes): size
+ * Offset 8 (4 bytes): next free list page id, 0 if this is the last one
+ * Offset 12: content
+ * | 4b     | 4b    | ... |
+ * | free 1 | free2 | ... |
+ *
+ * The header links to the head of the chain.
+ */
+pub(crate) struct FreeListPageWrapper(pub RawPage);
+
+impl FreeListPageWrapper {
+
+    pub(crate) fn init(page_id: u32, page_size: u32) -> FreeListPageWrapper {
+        let mut raw_page = RawPage::new(page_id, page_size);
+        let page_type = PageType::FreeList;
+        raw_page.put(&page_type.to_magic());
+        FreeListPageWrapper(raw_page)
+    }
+
+    #[inline]
+    pub(crate) fn from_raw_page(page: RawPage) -> FreeListPageWrapper {
+        FreeListPageWrapper(page)
+    }
+
+    #[inline]
+    pub(crate) fn pid(&self) -> u32 {
+        self.0.page_id
+    }
+
+    #[inline]
+    pub(crate) fn capacity(&self) -> u32 {
+        (self.0.len() - FREE_LIST_CONTENT_OFFSET) / 4
+    }
+
+    #[inline]
+    pub(crate) fn size(&self) -> u32 {
+        self.0.get_u32(FREE_LIST_SIZE_OFFSET)
+    }
+
+    #[inline]
+    fn set_size(&mut self, size: u32) {
+        self.0.seek(FREE_LIST_SIZE_OFFSET);
+        self.0.put_u32(size);
+    }
+
+    #[inline]
+    pub(crate) fn is_full(&self) -> bool {
+        self.size() >= self.capacity()
+    }
+
+    #[inline]
+    pub(crate) fn next_pid(&self) -> u32 {
+        self.0.get_u32(FREE_LIST_NEXT_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn set_next_pid(&mut self, pid: u32) {
+        self.0.seek(FREE_LIST_NEXT_OFFSET);
+        self.0.put_u32(pid);
+    }
+
+    #[inline]
+    pub(crate) fn get_content(&self, index: u32) -> u32 {
+        self.0.get_u32(FREE_LIST_CONTENT_OFFSET + index * 4)
+    }
+
+    #[inline]
+    pub(crate) fn set_content(&mut self, index: u32, pid: u32) {
+        self.0.seek(FREE_LIST_CONTENT_OFFSET + index * 4);
+        self.0.put_u32(pid);
+    }
+
+    pub(crate) fn push(&mut self, pid: u32) {
+        let size = self.size();
+        debug_assert!(size < self.capacity(), "free list page is full");
+        self.0.seek(FREE_LIST_CONTENT_OFFSET + size * 4);
+        self.0.put_u32(pid);
+        self.set_size(size + 1);
+    }
+
+    // the size is read from the page, a size past the capacity fails
+    pub(crate) fn pop(&mut self) -> DbResult<Option<u32>> {
+        let size = self.size();
+        if size == 0 {
+            return Ok(None);
+        }
+        let offset = (size - 1).saturating_mul(4).saturating_add(FREE_LIST_CONTENT_OFFSET);
+        let pid = self.0.read_u32(offset)?;
+        self.set_size(size - 1);
+        Ok(Some(pid))
+    }
+
+}
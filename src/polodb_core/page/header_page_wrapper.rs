@@ -1,27 +1,64 @@
-use super::page::RawPage;
+use std::io;
+use super::page::{RawPage, PageLayout};
+use super::page_store::PageStore;
+use crate::DbResult;
+use crate::error::DbErr;
+use crate::crc32::crc32;
+use super::page_cipher::{ENCRYPTION_SALT_SIZE, ENCRYPTION_CHECK_SIZE};
 
 static HEADER_DESP: &str          = "PipeappleDB Format v0.1";
+const TITLE_SIZE: usize           = 32;
+pub const FORMAT_VERSION: [u8; 4] = [0, 0, 0, 1];
+const PAGE_CHECKSUM_OFFSET: u32   = 36;
 const SECTOR_SIZE_OFFSET: u32     = 40;
 const PAGE_SIZE_OFFSET: u32       = 44;
 const NULL_PAGE_BAR_OFFSET: u32   = 48;
 const META_PAGE_ID: u32           = 52;
 const META_VERSION_OFFSET: u32    = 56;
 const META_ID_COUNTER_OFFSET: u32 = 60;
+const ENCRYPTION_SALT_OFFSET: u32 = 64;
+const ENCRYPTION_CHECK_OFFSET: u32 = 80;
+const JOURNAL_IN_USE_OFFSET: u32  = 96;
+const JOURNAL_EXTERNAL_OFFSET: u32 = 97;
+const RESERVED_COUNT_OFFSET: u32  = 98;
+const COMPRESSION_OFFSET: u32     = 99;
+const SHADOW_HEADER_PID_OFFSET: u32 = 100;
+const HEADER_SEQUENCE_OFFSET: u32 = 104;
+const HEADER_CRC_OFFSET: u32      = 112;
+const RESERVED_PIDS_OFFSET: u32   = 116;
 pub const FREE_LIST_OFFSET: u32   = 2048;
-const FREE_LIST_PAGE_LINK_OFFSET: u32 = 2048 + 4;
-pub const HEADER_FREE_LIST_MAX_SIZE: usize = (2048 - 8) / 4;
+
+// the pages not larger than 2KB put the free list after the fields
+const SMALL_PAGE_FREE_LIST_OFFSET: u32 = 128;
+
+// the copy of the header, reserved for the new databases
+pub(crate) const SHADOW_HEADER_PID: u32 = 2;
+
+// the room before the free list of the small pages
+pub(crate) const MAX_RESERVED_PAGES: usize = 3;
 
 /**
  * Offset 0 (32 bytes) : "PipeappleDB Format v0.1";
- * Offset 32 (8 bytes) : Version 0.0.0.0;
+ * Offset 32 (4 bytes) : Version 0.0.0.0;
+ * Offset 36 (1 byte)  : PageChecksum(the pages end with a CRC32, zero in the legacy files);
  * Offset 40 (4 bytes) : SectorSize;
  * Offset 44 (4 bytes) : PageSize;
  * Offset 48 (4 bytes) : NullPageBarId;
  * Offset 52 (4 bytes) : MetaPageId(usually 1);
  * Offset 56 (4 bytes) : MetaVersionId;
  * Offset 60 (4 bytes) : MetaIdCounter;
+ * Offset 64 (16 bytes): EncryptionSalt;
+ * Offset 80 (16 bytes): EncryptionCheck(zero if not encrypted);
+ * Offset 96 (1 byte)  : JournalInUse(the main file may be behind the journal);
+ * Offset 97 (1 byte)  : JournalExternal(the journal is not next to the main file);
+ * Offset 98 (1 byte)  : ReservedCount;
+ * Offset 99 (1 byte)  : Compression(the pages except the headers may be compressed);
+ * Offset 100 (4 bytes): ShadowHeaderPid(zero if there is no shadow);
+ * Offset 104 (8 bytes): HeaderSequence(increased by every write to the main file);
+ * Offset 112 (4 bytes): HeaderCrc(CRC32 of the page with the field zeroed);
+ * Offset 116 (12 bytes): ReservedPids(the pages kept for the higher layers, 3 at most);
  *
- * Free list offset: 2048;
+ * Free list offset: 2048(128 if the page is not larger than 2048);
  * | 4b   | 4b                  | 4b     | 4b    | ... |
  * | size | free list page link | free 1 | free2 | ... |
  */
@@ -29,15 +66,70 @@ pub(crate) struct HeaderPageWrapper(pub RawPage);
 
 impl HeaderPageWrapper {
 
+    // The title is the magic string of the format,
+    // it's checked before the other fields are trusted.
+    // The older versions pass, they are upgraded by the handler.
+    pub(crate) fn check_magic_and_version(file: &dyn PageStore) -> DbResult<()> {
+        let mut buffer = [0u8; TITLE_SIZE + 4];
+        file.read_at(0, &mut buffer)?;
+
+        let title_bytes = HEADER_DESP.as_bytes();
+        if buffer[0..title_bytes.len()] != *title_bytes || buffer[title_bytes.len()] != 0 {
+            return Err(DbErr::NotADatabaseFile);
+        }
+
+        let mut found = [0u8; 4];
+        found.copy_from_slice(&buffer[TITLE_SIZE..]);
+        if found > FORMAT_VERSION {
+            return Err(DbErr::UnsupportedVersion {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+
+        Ok(())
+    }
+
+    // the header page is at the beginning of the file
+    pub(crate) fn read_page_size(file: &dyn PageStore) -> io::Result<u32> {
+        let mut buffer = [0u8; 4];
+        file.read_at(PAGE_SIZE_OFFSET as u64, &mut buffer)?;
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    // read before the layout of the pages is known,
+    // the header can't be verified without it
+    pub(crate) fn read_page_checksum(file: &dyn PageStore, header_offset: u64) -> io::Result<bool> {
+        let mut buffer = [0u8; 1];
+        file.read_at(header_offset + (PAGE_CHECKSUM_OFFSET as u64), &mut buffer)?;
+        Ok(buffer[0] != 0)
+    }
+
+    #[inline]
+    fn free_list_offset(page_size: u32) -> u32 {
+        if page_size > FREE_LIST_OFFSET {
+            FREE_LIST_OFFSET
+        } else {
+            SMALL_PAGE_FREE_LIST_OFFSET
+        }
+    }
+
+    // the free list takes the remaining space of the header page
+    #[inline]
+    pub(crate) fn free_list_max_size(page_size: u32) -> usize {
+        ((page_size - HeaderPageWrapper::free_list_offset(page_size) - 8) / 4) as usize
+    }
+
     pub(crate) fn init(page_id: u32, page_size: u32) -> HeaderPageWrapper {
         let raw_page = RawPage::new(page_id, page_size);
         let mut wrapper = HeaderPageWrapper::from_raw_page(raw_page);
         wrapper.set_title(HEADER_DESP);
-        wrapper.set_version(&[0, 0, 0, 1]);
+        wrapper.set_version(&FORMAT_VERSION);
         wrapper.set_sector_size(4096);
         wrapper.set_page_size(4096);
         wrapper.set_meta_page_id(1);
-        wrapper.set_null_page_bar(2);
+        wrapper.set_shadow_header_pid(SHADOW_HEADER_PID);
+        wrapper.set_null_page_bar(SHADOW_HEADER_PID + 1);
         wrapper
     }
 
@@ -69,6 +161,11 @@ impl HeaderPageWrapper {
         title.to_string()
     }
 
+    pub(crate) fn is_title_valid(&self) -> bool {
+        let title_bytes = HEADER_DESP.as_bytes();
+        self.0.data[0..title_bytes.len()] == *title_bytes && self.0.data[title_bytes.len()] == 0
+    }
+
     pub(crate) fn set_version(&mut self, version: &[u8]) {
         self.0.seek(32);
         let _ = self.0.put(version);
@@ -151,40 +248,221 @@ impl HeaderPageWrapper {
         self.0.put_u32(data);
     }
 
+    #[inline]
+    fn own_free_list_offset(&self) -> u32 {
+        HeaderPageWrapper::free_list_offset(self.0.len())
+    }
+
     #[inline]
     pub(crate) fn get_free_list_size(&self) -> u32 {
-        self.0.get_u32(FREE_LIST_OFFSET)
+        self.0.get_u32(self.own_free_list_offset())
     }
 
     #[inline]
     pub(crate) fn set_free_list_size(&mut self, size: u32) {
-        self.0.seek(FREE_LIST_OFFSET);
+        self.0.seek(self.own_free_list_offset());
         self.0.put_u32(size)
     }
 
     #[inline]
     pub(crate) fn get_free_list_content(&self, index: u32) -> u32 {
-        let offset = index * 4 + FREE_LIST_OFFSET + 8;
+        let offset = index * 4 + self.own_free_list_offset() + 8;
         self.0.get_u32(offset)
     }
 
+    // the index may come from a corrupt size,
+    // so the offset saturates and the read is checked
+    pub(crate) fn read_free_list_content(&self, index: u32) -> DbResult<u32> {
+        let offset = index.saturating_mul(4).saturating_add(self.own_free_list_offset() + 8);
+        self.0.read_u32(offset)
+    }
+
     #[inline]
     pub(crate) fn set_free_list_content(&mut self, index: u32, pid: u32) {
-        let offset = index * 4 + FREE_LIST_OFFSET + 8;
+        let offset = index * 4 + self.own_free_list_offset() + 8;
         self.0.seek(offset);
         self.0.put_u32(pid);
     }
 
     #[inline]
-    #[allow(dead_code)]
     pub(crate) fn set_free_list_page_id(&mut self, pid: u32) {
-        self.0.seek(FREE_LIST_PAGE_LINK_OFFSET);
+        self.0.seek(self.own_free_list_offset() + 4);
         self.0.put_u32(pid);
     }
 
     #[inline]
     pub(crate) fn get_free_list_page_id(&self) -> u32 {
-        self.0.get_u32(FREE_LIST_PAGE_LINK_OFFSET)
+        self.0.get_u32(self.own_free_list_offset() + 4)
+    }
+
+    // only meaningful in the main file,
+    // set before the first commit to the journal, cleared by the checkpoint
+    #[inline]
+    pub(crate) fn is_journal_in_use(&self) -> bool {
+        self.0.get_u8(JOURNAL_IN_USE_OFFSET) != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_journal_in_use(&mut self, in_use: bool) {
+        self.0.seek(JOURNAL_IN_USE_OFFSET);
+        self.0.put_u8(in_use as u8);
+    }
+
+    #[inline]
+    #[allow(dead_code)]
+    pub(crate) fn has_page_checksum(&self) -> bool {
+        self.0.get_u8(PAGE_CHECKSUM_OFFSET) != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_page_checksum(&mut self, checksum: bool) {
+        self.0.seek(PAGE_CHECKSUM_OFFSET);
+        self.0.put_u8(checksum as u8);
+    }
+
+    #[inline]
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.0.get_u8(COMPRESSION_OFFSET) != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_compressed(&mut self, compressed: bool) {
+        self.0.seek(COMPRESSION_OFFSET);
+        self.0.put_u8(compressed as u8);
+    }
+
+    // recorded with the journal in use,
+    // the journal at a custom path must be given again to reopen
+    #[inline]
+    pub(crate) fn is_journal_external(&self) -> bool {
+        self.0.get_u8(JOURNAL_EXTERNAL_OFFSET) != 0
+    }
+
+    #[inline]
+    pub(crate) fn set_journal_external(&mut self, external: bool) {
+        self.0.seek(JOURNAL_EXTERNAL_OFFSET);
+        self.0.put_u8(external as u8);
+    }
+
+    pub(crate) fn get_reserved_pids(&self) -> Vec<u32> {
+        let count = (self.0.get_u8(RESERVED_COUNT_OFFSET) as usize).min(MAX_RESERVED_PAGES);
+        (0..count)
+            .map(|index| self.0.get_u32(RESERVED_PIDS_OFFSET + (index as u32) * 4))
+            .collect()
+    }
+
+    pub(crate) fn set_reserved_pids(&mut self, pids: &[u32]) {
+        debug_assert!(pids.len() <= MAX_RESERVED_PAGES);
+        self.0.seek(RESERVED_COUNT_OFFSET);
+        self.0.put_u8(pids.len() as u8);
+        for index in 0..MAX_RESERVED_PAGES {
+            self.0.seek(RESERVED_PIDS_OFFSET + (index as u32) * 4);
+            self.0.put_u32(pids.get(index).copied().unwrap_or(0));
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_shadow_header_pid(&self) -> u32 {
+        self.0.get_u32(SHADOW_HEADER_PID_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn set_shadow_header_pid(&mut self, pid: u32) {
+        self.0.seek(SHADOW_HEADER_PID_OFFSET);
+        self.0.put_u32(pid);
+    }
+
+    #[inline]
+    pub(crate) fn get_header_sequence(&self) -> u64 {
+        self.0.get_u64(HEADER_SEQUENCE_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn get_header_crc(&self) -> u32 {
+        self.0.get_u32(HEADER_CRC_OFFSET)
+    }
+
+    pub(crate) fn calc_header_crc(&self) -> u32 {
+        let mut data = self.0.data.clone();
+        let begin = HEADER_CRC_OFFSET as usize;
+        data[begin..(begin + 4)].fill(0);
+        crc32(&data)
+    }
+
+    // the headers written before the sequence was introduced
+    // have neither of them, they are trusted
+    pub(crate) fn is_crc_valid(&self) -> bool {
+        let crc = self.get_header_crc();
+        if crc == 0 && self.get_header_sequence() == 0 {
+            return true;
+        }
+        crc == self.calc_header_crc()
+    }
+
+    fn seal(&mut self, sequence: u64) {
+        self.0.seek(HEADER_SEQUENCE_OFFSET);
+        self.0.put_u64(sequence);
+        self.0.seek(HEADER_CRC_OFFSET);
+        self.0.put_u32(0);
+        let crc = self.calc_header_crc();
+        self.0.seek(HEADER_CRC_OFFSET);
+        self.0.put_u32(crc);
+    }
+
+    fn read_stored_sequence(file: &dyn PageStore) -> io::Result<u64> {
+        let mut buffer = [0u8; 8];
+        file.read_at(HEADER_SEQUENCE_OFFSET as u64, &mut buffer)?;
+        Ok(u64::from_be_bytes(buffer))
+    }
+
+    // The shadow is written before the primary,
+    // if either of the writes is torn, the other one is intact,
+    // the newer intact one is picked when the database is opened.
+    // The copies in the cache may be stale,
+    // so the sequence follows the one in the file.
+    pub(crate) fn write_to_store(&mut self, page_layout: PageLayout, file: &mut dyn PageStore, sync: bool) -> io::Result<()> {
+        let stored_sequence = HeaderPageWrapper::read_stored_sequence(file)?;
+        let sequence = self.get_header_sequence().max(stored_sequence) + 1;
+        self.seal(sequence);
+
+        let shadow_pid = self.get_shadow_header_pid();
+        if shadow_pid != 0 {
+            let mut shadow = RawPage::new(shadow_pid, self.0.len());
+            shadow.data.copy_from_slice(&self.0.data);
+            page_layout.without_cipher().write_page(&shadow, file)?;
+            if sync {
+                file.flush()?;
+                file.sync_data()?;
+            }
+        }
+
+        page_layout.write_page(&self.0, file)
+    }
+
+    pub(crate) fn get_encryption_salt(&self) -> [u8; ENCRYPTION_SALT_SIZE] {
+        let mut result = [0u8; ENCRYPTION_SALT_SIZE];
+        let begin = ENCRYPTION_SALT_OFFSET as usize;
+        result.copy_from_slice(&self.0.data[begin..(begin + ENCRYPTION_SALT_SIZE)]);
+        result
+    }
+
+    pub(crate) fn get_encryption_check(&self) -> [u8; ENCRYPTION_CHECK_SIZE] {
+        let mut result = [0u8; ENCRYPTION_CHECK_SIZE];
+        let begin = ENCRYPTION_CHECK_OFFSET as usize;
+        result.copy_from_slice(&self.0.data[begin..(begin + ENCRYPTION_CHECK_SIZE)]);
+        result
+    }
+
+    #[inline]
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.get_encryption_check() != [0u8; ENCRYPTION_CHECK_SIZE]
+    }
+
+    pub(crate) fn set_encryption(&mut self, salt: &[u8; ENCRYPTION_SALT_SIZE], check: &[u8; ENCRYPTION_CHECK_SIZE]) {
+        self.0.seek(ENCRYPTION_SALT_OFFSET);
+        self.0.put(salt);
+        self.0.seek(ENCRYPTION_CHECK_OFFSET);
+        self.0.put(check);
     }
 
 
@@ -216,4 +494,21 @@ mod tests {
         assert_eq!(wrapper.get_page_size(), test_page_size);
     }
 
+    #[test]
+    fn header_crc() {
+        let mut wrapper = HeaderPageWrapper::init(0, 4096);
+        assert_eq!(wrapper.get_shadow_header_pid(), SHADOW_HEADER_PID);
+        assert!(wrapper.is_crc_valid());
+
+        wrapper.seal(1);
+        assert_eq!(wrapper.get_header_sequence(), 1);
+        assert!(wrapper.is_crc_valid());
+
+        wrapper.set_null_page_bar(10);
+        assert!(!wrapper.is_crc_valid());
+
+        wrapper.seal(2);
+        assert!(wrapper.is_crc_valid());
+    }
+
 }
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::time::Duration;
+
+// a write transaction opened longer than this
+// is considered as stuck
+pub(crate) const STUCK_WRITE_TRANSACTION_THRESHOLD: Duration = Duration::from_secs(60);
+
+/**
+ * A compact summary of the database state,
+ * designed for liveness/readiness probes.
+ */
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    // frames found in the journal when the database was opened
+    pub recovered_on_open:        bool,
+
+    // frames in the journal which are not checkpointed
+    pub journal_lag:              u32,
+
+    // hits / total reads of the page cache, 0.0 if nothing is read
+    pub cache_hit_rate:           f64,
+
+    // free bytes / total bytes of the tracked partially-filled data pages
+    pub fragmentation:            f64,
+
+    pub write_transaction_open:   Option<Duration>,
+    pub write_transaction_stuck:  bool,
+}
+
+impl HealthReport {
+
+    #[inline]
+    pub fn is_healthy(&self) -> bool {
+        !self.write_transaction_stuck
+    }
+
+}
+
+impl fmt::Display for HealthReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HealthReport(recovered_on_open: {}, journal_lag: {}, cache_hit_rate: {:.3}, fragmentation: {:.3}, write_transaction_stuck: {})",
+               self.recovered_on_open, self.journal_lag, self.cache_hit_rate,
+               self.fragmentation, self.write_transaction_stuck)
+    }
+
+}
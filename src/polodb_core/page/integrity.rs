@@ -0,0 +1,391 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use polodb_bson::{Document, Value};
+use crate::DbResult;
+use crate::btree::{BTreeNode, HEADER_SIZE, ITEM_SIZE};
+use crate::data_ticket::DataTicket;
+use crate::meta_doc_helper::meta_doc_key;
+use super::page::{RawPage, PageType};
+use super::page_handler::PageHandler;
+use super::header_page_wrapper::HeaderPageWrapper;
+use super::data_page_wrapper::DataPageWrapper;
+use super::free_list_page_wrapper::FreeListPageWrapper;
+use super::overflow_page_wrapper::{OverflowPageWrapper, OverflowSlot};
+
+/**
+ * An inconsistency found by the integrity check
+ */
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IntegrityAnomaly {
+    // the pages are neither in the main file nor in the journal
+    NullPageBarBeyondFile { null_page_bar: u32, stored_pages: u32 },
+
+    // the size field of the header (pid 0) or a free list page
+    // is greater than the capacity
+    FreeListSizeOutOfRange { pid: u32, size: u32, capacity: u32 },
+
+    // the chain of the free list pages is not valid or a loop
+    BrokenFreeListChain { pid: u32 },
+
+    // zero or beyond the null page bar
+    InvalidFreePid { pid: u32 },
+
+    DuplicateFreePid { pid: u32 },
+
+    // referred by the trees, and in the free list
+    AllocatedAndFree { pid: u32 },
+
+    // a pid referred by the trees is zero or beyond the null page bar
+    DanglingReference { from_pid: u32, pid: u32 },
+
+    // the slot bars of the data page are not in order,
+    // or overlap the bars themselves
+    InvalidSlotBar { pid: u32, index: u32 },
+
+    // the data len doesn't equal the count of the occupied slots
+    DataLenMismatch { pid: u32, data_len: u32, occupied: u32 },
+
+    // the page can't be read or decoded
+    UnreadablePage { pid: u32 },
+}
+
+impl fmt::Display for IntegrityAnomaly {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityAnomaly::NullPageBarBeyondFile { null_page_bar, stored_pages } =>
+                write!(f, "null page bar {} is beyond the stored pages {}", null_page_bar, stored_pages),
+            IntegrityAnomaly::FreeListSizeOutOfRange { pid, size, capacity } =>
+                write!(f, "free list size {} of page {} is greater than the capacity {}", size, pid, capacity),
+            IntegrityAnomaly::BrokenFreeListChain { pid } => write!(f, "free list chain is broken at page {}", pid),
+            IntegrityAnomaly::InvalidFreePid { pid } => write!(f, "invalid pid {} in the free list", pid),
+            IntegrityAnomaly::DuplicateFreePid { pid } => write!(f, "pid {} is in the free list more than once", pid),
+            IntegrityAnomaly::AllocatedAndFree { pid } => write!(f, "page {} is in use and in the free list", pid),
+            IntegrityAnomaly::DanglingReference { from_pid, pid } =>
+                write!(f, "page {} refers to an invalid page {}", from_pid, pid),
+            IntegrityAnomaly::InvalidSlotBar { pid, index } => write!(f, "slot bar {} of data page {} is invalid", index, pid),
+            IntegrityAnomaly::DataLenMismatch { pid, data_len, occupied } =>
+                write!(f, "data len {} of data page {} is mismatched with the occupied slots {}", data_len, pid, occupied),
+            IntegrityAnomaly::UnreadablePage { pid } => write!(f, "page {} can not be read", pid),
+        }
+    }
+
+}
+
+/**
+ * The result of a full consistency check,
+ * nothing is written when checking.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    // pages below the null page bar, excluding the header
+    pub checked_pages: u32,
+
+    pub anomalies:     Vec<IntegrityAnomaly>,
+}
+
+impl IntegrityReport {
+
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+}
+
+impl fmt::Display for IntegrityReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IntegrityReport(checked_pages: {}, anomalies: [", self.checked_pages)?;
+        for (index, anomaly) in self.anomalies.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", anomaly)?;
+        }
+        write!(f, "])")
+    }
+
+}
+
+struct IntegrityChecker<'a> {
+    page_handler:  &'a mut PageHandler,
+    item_size:     u32,
+    null_page_bar: u32,
+    free_pids:     BTreeSet<u32>,
+    used_pids:     BTreeSet<u32>,
+    anomalies:     Vec<IntegrityAnomaly>,
+}
+
+impl<'a> IntegrityChecker<'a> {
+
+    #[inline]
+    fn is_valid_pid(&self, pid: u32) -> bool {
+        pid != 0 && pid < self.null_page_bar
+    }
+
+    fn read_page(&mut self, pid: u32) -> Option<RawPage> {
+        match self.page_handler.pipeline_read_page(pid) {
+            Ok(page) => Some(page),
+            Err(_) => {
+                self.anomalies.push(IntegrityAnomaly::UnreadablePage { pid });
+                None
+            }
+        }
+    }
+
+    fn add_free_pid(&mut self, pid: u32) {
+        if !self.is_valid_pid(pid) {
+            self.anomalies.push(IntegrityAnomaly::InvalidFreePid { pid });
+            return;
+        }
+        if !self.free_pids.insert(pid) {
+            self.anomalies.push(IntegrityAnomaly::DuplicateFreePid { pid });
+        }
+    }
+
+    fn check_free_list(&mut self, first_page_wrapper: &HeaderPageWrapper) {
+        let size = first_page_wrapper.get_free_list_size();
+        let capacity = HeaderPageWrapper::free_list_max_size(first_page_wrapper.0.len()) as u32;
+        if size > capacity {
+            self.anomalies.push(IntegrityAnomaly::FreeListSizeOutOfRange { pid: 0, size, capacity });
+        }
+        for index in 0..size.min(capacity) {
+            self.add_free_pid(first_page_wrapper.get_free_list_content(index));
+        }
+
+        let mut visited = BTreeSet::new();
+        let mut pid = first_page_wrapper.get_free_list_page_id();
+        while pid != 0 {
+            if !self.is_valid_pid(pid) || !visited.insert(pid) {
+                self.anomalies.push(IntegrityAnomaly::BrokenFreeListChain { pid });
+                return;
+            }
+
+            let page = match self.read_page(pid) {
+                Some(page) => page,
+                None => return,
+            };
+            if page.data[0..2] != PageType::FreeList.to_magic() {
+                self.anomalies.push(IntegrityAnomaly::BrokenFreeListChain { pid });
+                return;
+            }
+
+            // the free list page itself is free
+            self.add_free_pid(pid);
+
+            let wrapper = FreeListPageWrapper::from_raw_page(page);
+            let size = wrapper.size();
+            let capacity = wrapper.capacity();
+            if size > capacity {
+                self.anomalies.push(IntegrityAnomaly::FreeListSizeOutOfRange { pid, size, capacity });
+            }
+            for index in 0..size.min(capacity) {
+                self.add_free_pid(wrapper.get_content(index));
+            }
+
+            pid = wrapper.next_pid();
+        }
+    }
+
+    // false if the page is not valid or visited
+    fn use_pid(&mut self, from_pid: u32, pid: u32) -> bool {
+        if !self.is_valid_pid(pid) {
+            self.anomalies.push(IntegrityAnomaly::DanglingReference { from_pid, pid });
+            return false;
+        }
+        self.used_pids.insert(pid)
+    }
+
+    // the raw bytes of the ticket, None if it can't be read
+    fn use_data_ticket(&mut self, from_pid: u32, data_ticket: &DataTicket) -> Option<Vec<u8>> {
+        let pid = data_ticket.pid;
+        self.use_pid(from_pid, pid);
+        if !self.is_valid_pid(pid) {
+            return None;
+        }
+
+        let page = self.read_page(pid)?;
+        if page.data[0..2] != PageType::Data.to_magic() {
+            self.anomalies.push(IntegrityAnomaly::UnreadablePage { pid });
+            return None;
+        }
+
+        if DataPageWrapper::first_invalid_bar(&page).is_some() {
+            return None;  // reported when checking the data pages
+        }
+        let wrapper = DataPageWrapper::from_raw(page);
+        if (data_ticket.index as u32) >= wrapper.bar_len() {
+            self.anomalies.push(IntegrityAnomaly::DanglingReference { from_pid, pid });
+            return None;
+        }
+        let bytes = wrapper.get(data_ticket.index as u32)?.to_vec();
+
+        match OverflowSlot::from_bytes(&bytes) {
+            Some(slot) => {
+                self.use_overflow_chain(pid, slot.first_pid);
+                self.page_handler.get_raw_from_ticket(data_ticket).ok().flatten()
+            }
+            None => Some(bytes),
+        }
+    }
+
+    fn use_overflow_chain(&mut self, from_pid: u32, first_pid: u32) {
+        let mut from_pid = from_pid;
+        let mut pid = first_pid;
+        while pid != 0 {
+            if !self.use_pid(from_pid, pid) {
+                return;
+            }
+            let page = match self.read_page(pid) {
+                Some(page) => page,
+                None => return,
+            };
+            from_pid = pid;
+            pid = OverflowPageWrapper::from_raw_page(page).next_pid();
+        }
+    }
+
+    // the documents are decoded if docs is Some
+    fn use_btree(&mut self, parent_pid: u32, pid: u32, mut docs: Option<&mut Vec<Document>>) {
+        if !self.use_pid(parent_pid, pid) {
+            return;
+        }
+
+        let page = match self.read_page(pid) {
+            Some(page) => page,
+            None => return,
+        };
+        let btree_node = match BTreeNode::from_raw(&page, parent_pid, self.item_size, self.page_handler) {
+            Ok(btree_node) => btree_node,
+            Err(_) => {
+                self.anomalies.push(IntegrityAnomaly::UnreadablePage { pid });
+                return;
+            }
+        };
+
+        for item in &btree_node.content {
+            let bytes = self.use_data_ticket(pid, &item.data_ticket);
+            if let (Some(docs), Some(bytes)) = (docs.as_mut(), bytes) {
+                match Document::from_bytes(&bytes) {
+                    Ok(doc) => docs.push(doc),
+                    Err(_) => self.anomalies.push(IntegrityAnomaly::UnreadablePage { pid: item.data_ticket.pid }),
+                }
+            }
+        }
+
+        for child_pid in &btree_node.indexes {
+            if *child_pid != 0 {
+                self.use_btree(pid, *child_pid, docs.as_deref_mut());
+            }
+        }
+    }
+
+    // the meta tree, the collections and the indexes
+    fn collect_used_pids(&mut self, meta_pid: u32) {
+        if meta_pid == 0 {
+            return;
+        }
+
+        let mut meta_docs = vec![];
+        self.use_btree(0, meta_pid, Some(&mut meta_docs));
+
+        for meta_doc in &meta_docs {
+            if let Some(Value::Int(root_pid)) = meta_doc.get(meta_doc_key::ROOT_PID) {
+                self.use_btree(0, *root_pid as u32, None);
+            }
+
+            if let Some(Value::Document(indexes_doc)) = meta_doc.get(meta_doc_key::INDEXES) {
+                for (_, index_option) in indexes_doc.iter() {
+                    if let Value::Document(option_doc) = index_option {
+                        if let Some(Value::Int(root_pid)) = option_doc.get(meta_doc_key::index::ROOT_PID) {
+                            self.use_btree(0, *root_pid as u32, None);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_data_pages(&mut self) {
+        for pid in 1..self.null_page_bar {
+            if self.free_pids.contains(&pid) {
+                continue;
+            }
+
+            let page = match self.read_page(pid) {
+                Some(page) => page,
+                None => continue,
+            };
+            if page.data[0..2] != PageType::Data.to_magic() {
+                continue;
+            }
+
+            if let Some(index) = DataPageWrapper::first_invalid_bar(&page) {
+                self.anomalies.push(IntegrityAnomaly::InvalidSlotBar { pid, index });
+                continue;
+            }
+            let wrapper = DataPageWrapper::from_raw(page);
+
+            let occupied = (0..wrapper.bar_len()).filter(|index| wrapper.get(*index).is_some()).count() as u32;
+            if occupied != wrapper.data_len() {
+                self.anomalies.push(IntegrityAnomaly::DataLenMismatch { pid, data_len: wrapper.data_len(), occupied });
+            }
+        }
+    }
+
+}
+
+// nothing is written, it works on a read-only database
+#[inline]
+pub(crate) fn check_integrity(page_handler: &mut PageHandler) -> DbResult<IntegrityReport> {
+    let (report, _) = check_with_used_pids(page_handler)?;
+    Ok(report)
+}
+
+// the pids referred by the trees are returned with the report
+pub(crate) fn check_with_used_pids(page_handler: &mut PageHandler) -> DbResult<(IntegrityReport, BTreeSet<u32>)> {
+    let first_page = page_handler.get_first_page()?;
+    let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+    let null_page_bar = first_page_wrapper.get_null_page_bar();
+    let meta_pid = first_page_wrapper.get_meta_page_id();
+
+    let mut anomalies = vec![];
+    let stored_pages = page_handler.stored_page_count()?;
+    if null_page_bar > stored_pages {
+        anomalies.push(IntegrityAnomaly::NullPageBarBeyondFile { null_page_bar, stored_pages });
+    }
+
+    let item_size = (page_handler.page_size - HEADER_SIZE) / ITEM_SIZE;
+
+    // the pages are not inserted into the cache
+    page_handler.begin_scan();
+
+    let mut checker = IntegrityChecker {
+        page_handler,
+        item_size,
+        // the pages beyond the file can't be read
+        null_page_bar: null_page_bar.min(stored_pages),
+        free_pids: BTreeSet::new(),
+        used_pids: BTreeSet::new(),
+        anomalies,
+    };
+
+    checker.check_free_list(&first_page_wrapper);
+    checker.collect_used_pids(meta_pid);
+    checker.check_data_pages();
+
+    let mut anomalies = checker.anomalies;
+    anomalies.extend(
+        checker.used_pids.intersection(&checker.free_pids)
+            .map(|pid| IntegrityAnomaly::AllocatedAndFree { pid: *pid })
+    );
+
+    checker.page_handler.end_scan();
+
+    let report = IntegrityReport {
+        checked_pages: null_page_bar.saturating_sub(1),
+        anomalies,
+    };
+    Ok((report, checker.used_pids))
+}
@@ -0,0 +1,23 @@
+use super::page_handler::PageHandler;
+use crate::DbResult;
+
+pub(crate) type MigrationFn = Box<dyn FnMut(&mut PageHandler) -> DbResult<()>>;
+
+/**
+ * Transform the database of the version `from` to the version `to`,
+ * the pages are read and written through the handler,
+ * in the write transaction of the upgrade.
+ *
+ * The version in the header is updated by the handler after it runs.
+ */
+pub(crate) struct Migration {
+    pub from: [u8; 4],
+    pub to:   [u8; 4],
+    pub run:  MigrationFn,
+}
+
+// The steps to the current format version,
+// there is only one version of the format so far.
+pub(crate) fn registered_migrations() -> Vec<Migration> {
+    vec![]
+}
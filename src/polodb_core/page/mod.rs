@@ -2,8 +2,38 @@
 mod page;
 pub(crate) mod header_page_wrapper;
 mod page_handler;
+mod page_store;
+mod page_cipher;
 mod pagecache;
 mod data_page_wrapper;
+mod free_list_page_wrapper;
+mod overflow_page_wrapper;
+mod health;
+mod page_map_helper;
+mod quick_verify;
+mod integrity;
+mod page_iter;
+mod write_stats;
+mod vacuum;
+mod storage_stats;
+mod cache_metrics;
+mod file_lock;
+mod migration;
+mod blob_reader;
+mod salvage;
+#[cfg(feature = "compression")]
+mod compression;
 
-pub(crate) use page::{RawPage, PageType};
+pub(crate) use page::{RawPage, PageType, PageLayout};
 pub(crate) use page_handler::{PageHandler, TransactionState};
+pub use page_handler::TransactionStateKind;
+pub(crate) use page_store::{PageStore, MemoryPageStore};
+pub use health::HealthReport;
+pub use quick_verify::QuickVerifyReport;
+pub use integrity::{IntegrityReport, IntegrityAnomaly};
+pub use salvage::SalvageReport;
+pub use write_stats::WriteStats;
+pub use vacuum::VacuumStats;
+pub use storage_stats::StorageStats;
+pub use cache_metrics::CacheMetrics;
+pub use pagecache::CachePolicy;
@@ -0,0 +1,108 @@
+use super::page::{RawPage, PageType};
+
+const OVERFLOW_NEXT_OFFSET: u32 = 4;
+const OVERFLOW_DATA_LEN_OFFSET: u32 = 8;
+const OVERFLOW_DATA_OFFSET: u32 = 12;
+
+// never the first byte of a serialized document,
+// which is a type of value or 0
+const OVERFLOW_SLOT_MARK: u8 = 0xFF;
+pub(crate) const OVERFLOW_SLOT_SIZE: usize = 9;
+
+/**
+ * A piece of a document too large for a data page
+ *
+ * Offset 0 (2 bytes): magic number
+ * Offset 4 (4 bytes): next overflow page id, 0 if this is the last one
+ * Offset 8 (4 bytes): data len
+ * Offset 12: data
+ */
+pub(crate) struct OverflowPageWrapper(pub RawPage);
+
+impl OverflowPageWrapper {
+
+    pub(crate) fn init(page_id: u32, page_size: u32) -> OverflowPageWrapper {
+        let mut raw_page = RawPage::new(page_id, page_size);
+        let page_type = PageType::OverflowData;
+        raw_page.put(&page_type.to_magic());
+        OverflowPageWrapper(raw_page)
+    }
+
+    #[inline]
+    pub(crate) fn from_raw_page(page: RawPage) -> OverflowPageWrapper {
+        OverflowPageWrapper(page)
+    }
+
+    #[inline]
+    pub(crate) fn capacity_of(page_size: u32) -> usize {
+        (page_size - OVERFLOW_DATA_OFFSET) as usize
+    }
+
+    #[inline]
+    pub(crate) fn next_pid(&self) -> u32 {
+        self.0.get_u32(OVERFLOW_NEXT_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn set_next_pid(&mut self, pid: u32) {
+        self.0.seek(OVERFLOW_NEXT_OFFSET);
+        self.0.put_u32(pid);
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        let data_len = self.0.get_u32(OVERFLOW_DATA_LEN_OFFSET) as usize;
+        let begin = OVERFLOW_DATA_OFFSET as usize;
+        &self.0.data[begin..(begin + data_len)]
+    }
+
+    pub(crate) fn set_data(&mut self, data: &[u8]) {
+        debug_assert!(data.len() <= OverflowPageWrapper::capacity_of(self.0.len()), "overflow data is too large");
+        self.0.seek(OVERFLOW_DATA_LEN_OFFSET);
+        self.0.put_u32(data.len() as u32);
+        self.0.seek(OVERFLOW_DATA_OFFSET);
+        self.0.put(data);
+    }
+
+}
+
+/**
+ * Stored in the slot of the data page instead of the document
+ *
+ * | mark   | total len | first overflow pid |
+ * | 1 byte | 4 bytes   | 4 bytes            |
+ */
+pub(crate) struct OverflowSlot {
+    pub total_len: u32,
+    pub first_pid: u32,
+}
+
+impl OverflowSlot {
+
+    pub(crate) fn to_bytes(&self) -> [u8; OVERFLOW_SLOT_SIZE] {
+        let mut result = [0; OVERFLOW_SLOT_SIZE];
+        result[0] = OVERFLOW_SLOT_MARK;
+        result[1..5].copy_from_slice(&self.total_len.to_be_bytes());
+        result[5..9].copy_from_slice(&self.first_pid.to_be_bytes());
+        result
+    }
+
+    // None if the slot holds the document itself
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<OverflowSlot> {
+        if bytes.len() != OVERFLOW_SLOT_SIZE || bytes[0] != OVERFLOW_SLOT_MARK {
+            return None;
+        }
+
+        let mut buffer: [u8; 4] = [0; 4];
+        buffer.copy_from_slice(&bytes[1..5]);
+        let total_len = u32::from_be_bytes(buffer);
+
+        buffer.copy_from_slice(&bytes[5..9]);
+        let first_pid = u32::from_be_bytes(buffer);
+
+        Some(OverflowSlot {
+            total_len,
+            first_pid,
+        })
+    }
+
+}
@@ -1,8 +1,14 @@
-use std::fs::File;
-use std::io::{Seek, SeekFrom, Write, Read};
 use crate::DbResult;
+use crate::crc32::crc32;
+use super::page_store::PageStore;
+use super::page_cipher::PageCipher;
+#[cfg(feature = "compression")]
+use super::compression;
 use crate::error::{DbErr};
 
+// the CRC32 of the body is stored in the trailing bytes of a page
+pub(crate) const PAGE_CHECKSUM_SIZE: u32 = 4;
+
 #[repr(u8)]
 #[allow(dead_code)]
 pub(crate) enum PageType {
@@ -14,6 +20,8 @@ pub(crate) enum PageType {
 
     Data,
 
+    FreeList,
+
 }
 
 impl PageType {
@@ -22,7 +30,6 @@ impl PageType {
         [0xFF, self as u8]
     }
 
-    #[allow(dead_code)]
     pub fn from_magic(magic: [u8; 2]) -> DbResult<PageType> {
         if magic[0] != 0xFF {
             return Err(DbErr::UnexpectedPageHeader);
@@ -37,6 +44,8 @@ impl PageType {
 
             3 => Ok(PageType::Data),
 
+            4 => Ok(PageType::FreeList),
+
             _ => Err(DbErr::UnexpectedPageType)
         }
     }
@@ -62,16 +71,6 @@ impl RawPage {
         }
     }
 
-    pub unsafe fn copy_from_ptr(&mut self, ptr: *const u8) {
-        let target_ptr = self.data.as_mut_ptr();
-        target_ptr.copy_from_nonoverlapping(ptr, self.data.len());
-    }
-
-    pub unsafe fn copy_to_ptr(&self, ptr: *mut u8) {
-        let target_ptr = self.data.as_ptr();
-        target_ptr.copy_to_nonoverlapping(ptr, self.data.len());
-    }
-
     pub fn put(&mut self, data: &[u8]) {
         if data.len() + self.pos as usize > self.data.len() {
             panic!("space is not enough for page");
@@ -149,15 +148,88 @@ impl RawPage {
         u64::from_be_bytes(buffer)
     }
 
-    pub fn sync_to_file(&self, file: &mut File, offset: u64) -> std::io::Result<()> {
-        file.seek(SeekFrom::Start(offset))?;
-        file.write(self.data.as_slice())?;
+    // the checked accessors neither panic nor move the position
+    fn checked_range(&self, offset: u32, width: u32) -> DbResult<std::ops::Range<usize>> {
+        let page_size = self.data.len() as u32;
+        match offset.checked_add(width) {
+            Some(end) if end <= page_size => Ok((offset as usize)..(end as usize)),
+            _ => Err(DbErr::PageOffsetOutOfBounds { offset, width, page_size }),
+        }
+    }
+
+    pub fn read_u16(&self, offset: u32) -> DbResult<u16> {
+        let range = self.checked_range(offset, 2)?;
+        let mut buffer: [u8; 2] = [0; 2];
+        buffer.copy_from_slice(&self.data[range]);
+        Ok(u16::from_be_bytes(buffer))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_u16(&mut self, offset: u32, data: u16) -> DbResult<()> {
+        let range = self.checked_range(offset, 2)?;
+        self.data[range].copy_from_slice(&data.to_be_bytes());
         Ok(())
     }
 
-    pub fn read_from_file(&mut self, file: &mut File, offset: u64) -> std::io::Result<()> {
-        file.seek(SeekFrom::Start(offset))?;
-        file.read_exact(self.data.as_mut_slice())?;
+    pub fn read_u32(&self, offset: u32) -> DbResult<u32> {
+        let range = self.checked_range(offset, 4)?;
+        let mut buffer: [u8; 4] = [0; 4];
+        buffer.copy_from_slice(&self.data[range]);
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_u32(&mut self, offset: u32, data: u32) -> DbResult<()> {
+        let range = self.checked_range(offset, 4)?;
+        self.data[range].copy_from_slice(&data.to_be_bytes());
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn read_u64(&self, offset: u32) -> DbResult<u64> {
+        let range = self.checked_range(offset, 8)?;
+        let mut buffer: [u8; 8] = [0; 8];
+        buffer.copy_from_slice(&self.data[range]);
+        Ok(u64::from_be_bytes(buffer))
+    }
+
+    #[allow(dead_code)]
+    pub fn write_u64(&mut self, offset: u32, data: u64) -> DbResult<()> {
+        let range = self.checked_range(offset, 8)?;
+        self.data[range].copy_from_slice(&data.to_be_bytes());
+        Ok(())
+    }
+
+    pub fn read_from_file(&mut self, file: &dyn PageStore, offset: u64) -> std::io::Result<()> {
+        file.read_at(offset, self.data.as_mut_slice())
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u32 {
+        crc32(&self.data)
+    }
+
+    // a page which is never written is all zero, including the checksum,
+    // it's accepted
+    pub fn read_from_file_with_checksum(&mut self, file: &dyn PageStore, offset: u64) -> DbResult<()> {
+        file.read_at(offset, self.data.as_mut_slice())?;
+
+        let mut buffer: [u8; 4] = [0; 4];
+        file.read_at(offset + (self.data.len() as u64), &mut buffer)?;
+        self.verify_checksum(u32::from_be_bytes(buffer))
+    }
+
+    fn verify_checksum(&self, expected: u32) -> DbResult<()> {
+        let actual = self.checksum();
+
+        if expected != actual && !(expected == 0 && self.data.iter().all(|byte| *byte == 0)) {
+            return Err(DbErr::PageChecksumMismatch {
+                page_id: self.page_id,
+                expected,
+                actual,
+            });
+        }
+
         Ok(())
     }
 
@@ -173,3 +245,219 @@ impl RawPage {
     }
 
 }
+
+/**
+ * How the pages are laid out in the main file.
+ *
+ * With checksum, the last PAGE_CHECKSUM_SIZE bytes of a page
+ * hold the CRC32 of the body, the wrappers only see the body.
+ * Without checksum, it's the legacy layout.
+ *
+ * With cipher, the bodies are encrypted except the header page,
+ * the checksum is calculated on the encrypted body.
+ *
+ * With compression, the bodies are compressed before encrypted,
+ * the journal keeps them as they are.
+ */
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PageLayout {
+    physical_size: u32,
+    checksum:      bool,
+    cipher:        Option<PageCipher>,
+    #[cfg(feature = "compression")]
+    compression:   bool,
+}
+
+impl PageLayout {
+
+    pub fn new(physical_size: u32, checksum: bool) -> PageLayout {
+        PageLayout {
+            physical_size,
+            checksum,
+            cipher: None,
+            #[cfg(feature = "compression")]
+            compression: false,
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[inline]
+    pub fn with_compression(self) -> PageLayout {
+        PageLayout {
+            compression: true,
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn with_cipher(self, cipher: PageCipher) -> PageLayout {
+        PageLayout {
+            cipher: Some(cipher),
+            ..self
+        }
+    }
+
+    // for the copies of the header page
+    #[inline]
+    pub fn without_cipher(self) -> PageLayout {
+        PageLayout {
+            cipher: None,
+            ..self
+        }
+    }
+
+    // the header page carries the salt, it's never encrypted
+    pub fn sealed_copy(&self, page: &RawPage) -> Option<RawPage> {
+        match &self.cipher {
+            Some(cipher) if page.page_id != 0 => {
+                let mut result = RawPage::new(page.page_id, page.len());
+                result.data.copy_from_slice(&page.data);
+                cipher.encrypt(page.page_id, &mut result.data);
+                Some(result)
+            }
+
+            _ => None,
+        }
+    }
+
+    pub fn unseal(&self, page: &mut RawPage) {
+        if let Some(cipher) = &self.cipher {
+            if page.page_id != 0 {
+                cipher.decrypt(page.page_id, &mut page.data);
+            }
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    fn compressed_copy(&self, page: &RawPage) -> Option<RawPage> {
+        if self.compression {
+            compression::compressed_copy(page)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    fn decompress(&self, page: &mut RawPage) -> DbResult<()> {
+        if self.compression {
+            compression::decompress_page(page)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[inline]
+    fn decompress(&self, _page: &mut RawPage) -> DbResult<()> {
+        Ok(())
+    }
+
+    #[inline]
+    pub fn physical_size(&self) -> u32 {
+        self.physical_size
+    }
+
+    #[inline]
+    pub fn has_checksum(&self) -> bool {
+        self.checksum
+    }
+
+    #[inline]
+    pub fn body_size(&self) -> u32 {
+        if self.checksum {
+            self.physical_size - PAGE_CHECKSUM_SIZE
+        } else {
+            self.physical_size
+        }
+    }
+
+    #[inline]
+    pub fn offset_of(&self, page_id: u32) -> u64 {
+        (page_id as u64) * (self.physical_size as u64)
+    }
+
+    #[inline]
+    pub fn write_page(&self, page: &RawPage, file: &mut dyn PageStore) -> std::io::Result<()> {
+        file.write_at(self.offset_of(page.page_id), &self.encode_page(page))
+    }
+
+    // the bytes stored at the offset of the page,
+    // the pages in a row can be written at once
+    pub fn encode_page(&self, page: &RawPage) -> Vec<u8> {
+        #[cfg(feature = "compression")]
+        let compressed = self.compressed_copy(page);
+        #[cfg(feature = "compression")]
+        let page = compressed.as_ref().unwrap_or(page);
+
+        let sealed = self.sealed_copy(page);
+        let page = sealed.as_ref().unwrap_or(page);
+
+        let mut result = Vec::with_capacity(self.physical_size as usize);
+        result.extend_from_slice(&page.data);
+        if self.checksum {
+            result.extend_from_slice(&page.checksum().to_be_bytes());
+        }
+        result
+    }
+
+    // the page in the bytes read from its offset, verified and decrypted as read_page()
+    pub fn decode_page(&self, page_id: u32, bytes: &[u8]) -> DbResult<RawPage> {
+        let body_size = self.body_size() as usize;
+        let mut result = RawPage::new(page_id, self.body_size());
+        result.data.copy_from_slice(&bytes[0..body_size]);
+        if self.checksum {
+            let mut buffer: [u8; 4] = [0; 4];
+            buffer.copy_from_slice(&bytes[body_size..(body_size + PAGE_CHECKSUM_SIZE as usize)]);
+            result.verify_checksum(u32::from_be_bytes(buffer))?;
+        }
+        self.unseal(&mut result);
+        self.decompress(&mut result)?;
+        Ok(result)
+    }
+
+    pub fn read_page(&self, page_id: u32, file: &dyn PageStore) -> DbResult<RawPage> {
+        let offset = self.offset_of(page_id);
+        let mut result = RawPage::new(page_id, self.body_size());
+        if self.checksum {
+            result.read_from_file_with_checksum(file, offset)?;
+        } else {
+            result.read_from_file(file, offset)?;
+        }
+        self.unseal(&mut result);
+        self.decompress(&mut result)?;
+        Ok(result)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::RawPage;
+    use crate::DbErr;
+
+    #[test]
+    fn checked_accessors() {
+        let mut page = RawPage::new(0, 512);
+
+        page.write_u16(510, 0x1234).unwrap();
+        assert_eq!(page.read_u16(510).unwrap(), 0x1234);
+        assert_eq!(page.get_u16(510), 0x1234);
+
+        page.write_u32(508, 0x1234_5678).unwrap();
+        assert_eq!(page.read_u32(508).unwrap(), 0x1234_5678);
+
+        page.write_u64(504, 0x0102_0304_0506_0708).unwrap();
+        assert_eq!(page.read_u64(504).unwrap(), 0x0102_0304_0506_0708);
+        assert_eq!(page.get_u64(504), 0x0102_0304_0506_0708);
+
+        let err = page.read_u16(511).unwrap_err();
+        assert!(matches!(err, DbErr::PageOffsetOutOfBounds { offset: 511, width: 2, page_size: 512 }));
+        assert!(page.write_u32(509, 0).is_err());
+        assert!(page.read_u64(505).is_err());
+        assert!(page.write_u64(u32::MAX, 0).is_err());
+
+        // nothing is written when out of bounds
+        assert_eq!(page.read_u64(504).unwrap(), 0x0102_0304_0506_0708);
+    }
+
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use crate::aes::{Aes256, AesXts, AES_BLOCK_SIZE};
+use crate::DbResult;
+
+pub(crate) const ENCRYPTION_SALT_SIZE: usize = 16;
+pub(crate) const ENCRYPTION_CHECK_SIZE: usize = 16;
+
+/**
+ * Encrypt the page bodies with AES-256-XTS, the page id is the tweak.
+ *
+ * The keys of XTS are derived from the user's key and the salt
+ * in the header page, the check token is stored with the salt
+ * to detect a wrong key when opening.
+ */
+#[derive(Clone, Copy)]
+pub(crate) struct PageCipher {
+    xts:   AesXts,
+    check: [u8; ENCRYPTION_CHECK_SIZE],
+}
+
+impl PageCipher {
+
+    pub fn new(key: &[u8; 32], salt: &[u8; ENCRYPTION_SALT_SIZE]) -> PageCipher {
+        let master = Aes256::new(key);
+
+        let derive_block = |counter: u8| -> [u8; AES_BLOCK_SIZE] {
+            let mut block = *salt;
+            block[AES_BLOCK_SIZE - 1] ^= counter;
+            master.encrypt_block(&mut block);
+            block
+        };
+
+        let mut data_key = [0u8; 32];
+        data_key[0..16].copy_from_slice(&derive_block(1));
+        data_key[16..32].copy_from_slice(&derive_block(2));
+
+        let mut tweak_key = [0u8; 32];
+        tweak_key[0..16].copy_from_slice(&derive_block(3));
+        tweak_key[16..32].copy_from_slice(&derive_block(4));
+
+        let mut check = [0u8; ENCRYPTION_CHECK_SIZE];
+        Aes256::new(&data_key).encrypt_block(&mut check);
+
+        PageCipher {
+            xts: AesXts::new(&data_key, &tweak_key),
+            check,
+        }
+    }
+
+    // the salt is read from the random source of the OS
+    pub fn generate_salt() -> DbResult<[u8; ENCRYPTION_SALT_SIZE]> {
+        let mut salt = [0u8; ENCRYPTION_SALT_SIZE];
+        fill_random(&mut salt)?;
+        Ok(salt)
+    }
+
+    #[inline]
+    pub fn check(&self) -> [u8; ENCRYPTION_CHECK_SIZE] {
+        self.check
+    }
+
+    #[inline]
+    pub fn encrypt(&self, page_id: u32, data: &mut [u8]) {
+        self.xts.encrypt(page_id as u64, data)
+    }
+
+    // an all-zero body is a page never written
+    pub fn decrypt(&self, page_id: u32, data: &mut [u8]) {
+        if data.iter().all(|byte| *byte == 0) {
+            return;
+        }
+        self.xts.decrypt(page_id as u64, data)
+    }
+
+}
+
+// never print the keys
+impl fmt::Debug for PageCipher {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PageCipher")
+    }
+
+}
+
+#[cfg(target_os = "windows")]
+fn fill_random(buffer: &mut [u8]) -> std::io::Result<()> {
+    use winapi::shared::bcrypt::{BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG};
+
+    let status = unsafe {
+        BCryptGenRandom(std::ptr::null_mut(), buffer.as_mut_ptr(), buffer.len() as u32, BCRYPT_USE_SYSTEM_PREFERRED_RNG)
+    };
+    if status < 0 {
+        return Err(std::io::Error::other(format!("BCryptGenRandom failed: {:#x}", status)));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn fill_random(buffer: &mut [u8]) -> std::io::Result<()> {
+    use std::io::Read;
+
+    std::fs::File::open("/dev/urandom")?.read_exact(buffer)
+}
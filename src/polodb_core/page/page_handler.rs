@@ -1,21 +1,43 @@
-use std::fs::File;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Bound::{Included, Unbounded};
 use std::rc::Rc;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use polodb_bson::Document;
-use super::page::RawPage;
+use super::page::{RawPage, PageType, PageLayout};
+use super::page_cipher::PageCipher;
+use super::page_store::{PageStore, MemoryPageStore};
 use super::pagecache::PageCache;
-use super::header_page_wrapper;
-use super::header_page_wrapper::HeaderPageWrapper;
-use crate::journal::{JournalManager, TransactionType};
+use super::header_page_wrapper::{HeaderPageWrapper, SHADOW_HEADER_PID, MAX_RESERVED_PAGES, FORMAT_VERSION};
+use super::free_list_page_wrapper::FreeListPageWrapper;
+use super::overflow_page_wrapper::{OverflowPageWrapper, OverflowSlot, OVERFLOW_SLOT_SIZE};
+use crate::journal::{JournalManager, TransactionType, DurabilityMode, JournalMode, RecoveryReport};
 use crate::DbResult;
 use crate::error::DbErr;
 use crate::page::data_page_wrapper::DataPageWrapper;
 use crate::data_ticket::DataTicket;
+use crate::config::{Config, AllocationOrder};
+use crate::intent_log::{IntentLog, IntentRecord};
+use crate::change_map::{ChangeMap, BackupMarker};
+use super::health::{HealthReport, STUCK_WRITE_TRANSACTION_THRESHOLD};
+use super::page_map_helper;
+use super::page_iter::PageIter;
+use super::quick_verify::{self, QuickVerifyReport};
+use super::integrity::{self, IntegrityReport};
+use super::write_stats::{WriteStats, WriteStatsTracker};
+use super::vacuum::{self, VacuumStats};
+use super::storage_stats::StorageStats;
+use super::cache_metrics::CacheMetrics;
+use super::file_lock;
+use super::migration::{self, Migration};
+use super::blob_reader::BlobReader;
+use super::salvage::{self, SalvageReport};
 
-const DB_INIT_BLOCK_COUNT: u32 = 16;
-const PRESERVE_WRAPPER_MIN_REMAIN_SIZE: u32 = 16;
+// the bar of a slot in a data page
+const DATA_SLOT_BAR_SIZE: u32 = 2;
+const DEFAULT_JOURNAL_CHECKPOINT_THRESHOLD: u32 = 1000;
+const MIN_PAGE_SIZE: u32 = 512;
+const MAX_PAGE_SIZE: u32 = 65536;
 
 #[derive(Eq, PartialEq)]
 pub(crate) enum TransactionState {
@@ -25,52 +47,317 @@ pub(crate) enum TransactionState {
     DbAuto,
 }
 
+/// Who started the current transaction.
+///
+/// `UserAuto` is started by the user without a type,
+/// it's upgraded to write by the first write.
+/// `DbAuto` is started by an operation, it's committed by the operation,
+/// a write in a read transaction of `User` is one of its own.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransactionStateKind {
+    NoTrans,
+    User,
+    UserAuto,
+    DbAuto,
+}
+
 pub(crate) struct PageHandler {
-    file:                     File,
+    file:                     Box<dyn PageStore>,
+    // None if the handler is not opened from a file
+    db_path:                  Option<PathBuf>,
 
     pub last_commit_db_size:  u64,
 
+    // the size of the page body seen by the wrappers,
+    // the layout in the main file may reserve a trailing checksum
     pub page_size:            u32,
+    page_layout:              PageLayout,
     page_count:               u32,
     page_cache:               Box<PageCache>,
-    journal_manager:          Box<JournalManager>,
+    // None if the database is opened read-only
+    journal_manager:          Option<Box<JournalManager>>,
+    // the committed frames a writer left in the journal,
+    // loaded into memory when the database is opened read-only
+    replayed_journal:         Option<Box<JournalManager>>,
+    intent_log:               Option<Box<IntentLog>>,
+    // None if the handler is built from the parts,
+    // or the database opened read-only has no map
+    change_map:               Option<Box<ChangeMap>>,
 
     data_page_map:            BTreeMap<u32, Vec<u32>>,
 
     transaction_state:        TransactionState,
 
+    config:                   Config,
+
+    recovered_on_open:        bool,
+
+    // checked when opening if the journal is deleted
+    // while the main file is behind it
+    missing_journal_integrity: Option<IntegrityReport>,
+
+    // the journal is not next to the main file,
+    // recorded in the header with the journal in use
+    journal_external:         bool,
+
+    cache_metrics:            CacheMetrics,
+    write_transaction_begin:  Option<Instant>,
+
+    // pages allocated since the current transaction started
+    transaction_alloc_count:  u32,
+
+    // the pages read in scan mode are not inserted into cache,
+    // so a big scan doesn't evict the hot pages
+    scan_depth:               u32,
+
+    write_stats:              WriteStatsTracker,
+
+    // the tickets freed in the current transaction,
+    // the slots are released when committing,
+    // so they are still readable and can't be reused before that
+    tombstones:               Vec<DataTicket>,
+
+    // checkpoint when committing if the journal has this many frames
+    journal_checkpoint_threshold: u32,
+
+    durability_mode:          DurabilityMode,
+
+    journal_mode:             JournalMode,
+
+    // keep an emptied data page in data_page_map instead of freeing it,
+    // the empty pages are freed by vacuum
+    reuse_empty_data_pages:   bool,
+
+    // overwrite the freed pages with zeros
+    zero_freed_pages:         bool,
+
+    // the freed pages are recorded in the change map as written
+    freed_in_transaction:     Vec<u32>,
+
+    // the same as the journal manager's,
+    // updated when the transaction starts and ends
+    current_transaction_type: Option<TransactionType>,
+
+    // the commit of the current transaction is a rollback
+    dry_run:                  bool,
+
+    // the writes of a checkpoint never trigger another one
+    checkpoint_in_progress:   bool,
+
+    // the pages are restored by the journal,
+    // the in-memory state is restored by these
+    savepoints:               Vec<SavepointState>,
+
+    // the committed state seen by the read transaction of the user,
+    // captured when the first write interleaves it
+    read_snapshot:            Option<ReadSnapshot>,
+
+    write_observer:           Option<WriteObserver>,
+
+    transaction_observer:     Option<TransactionObserver>,
+
+    // the frames appended by a transaction, unlimited if None
+    max_transaction_pages:    Option<u32>,
+
+    // the bytes of the main file, unlimited if None
+    max_file_size:            Option<u64>,
+
+}
+
+// Invoked with every page appended to the journal, before it's cached.
+// The writes of a transaction are observed before it commits,
+// rolling back restores the pages without writing them again,
+// so the observer is not invoked by it.
+pub(crate) type WriteObserver = Box<dyn FnMut(u32, &RawPage)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransactionEvent {
+    Start,
+    // started by an operation out of a transaction of the user
+    AutoStart,
+    // a read transaction turns to write, explicitly or by an operation
+    Upgrade,
+    Commit,
+    AutoCommit,
+    Rollback,
+    AutoRollback,
+}
+
+// Invoked after the transaction is started or ended successfully,
+// with the type of the transaction and the time of the event.
+// A failed commit is not observed, the transaction goes on.
+pub(crate) type TransactionObserver = Box<dyn FnMut(TransactionEvent, TransactionType, Instant)>;
+
+// how the main file is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenMode {
+    CreateOrOpen,
+    CreateNew,
+    OpenExisting,
+}
+
+// The isolation of a read transaction of the user.
+//
+// An operation writing in the read transaction runs its own write transaction,
+// committed or rolled back by the operation, the read transaction goes on after it.
+// The reads of the read transaction never see the pages written meanwhile:
+// the journal is not checkpointed while the snapshot is kept,
+// so a page changed since is read from its frame when the snapshot is captured,
+// or from the main file, and the pages beyond the committed size are null pages.
+// Nothing else can write the database in the process,
+// another handle fails to lock the main file.
+// The snapshot is dropped when the read transaction ends.
+struct ReadSnapshot {
+    db_file_size:     u64,
+    journal_offsets:  BTreeMap<u32, u64>,
+}
+
+struct SavepointState {
+    name:                     String,
+    tombstones_len:           usize,
+    transaction_alloc_count:  u32,
+    data_page_map:            BTreeMap<u32, Vec<u32>>,
 }
 
 impl PageHandler {
 
-    fn read_first_block(file: &mut File, page_size: u32) -> std::io::Result<RawPage> {
-        let mut raw_page = RawPage::new(0, page_size);
-        raw_page.read_from_file(file, 0)?;
-        Ok(raw_page)
+    fn read_first_block(file: &mut dyn PageStore, page_layout: PageLayout) -> DbResult<RawPage> {
+        page_layout.read_page(0, file)
     }
 
-    fn force_write_first_block(file: &mut File, page_size: u32) -> std::io::Result<RawPage> {
-        let wrapper = HeaderPageWrapper::init(0, page_size);
-        wrapper.0.sync_to_file(file, 0)?;
+    fn force_write_first_block(file: &mut dyn PageStore, page_layout: PageLayout) -> std::io::Result<RawPage> {
+        let mut wrapper = HeaderPageWrapper::init(0, page_layout.body_size());
+        wrapper.set_page_size(page_layout.physical_size());
+        wrapper.set_page_checksum(page_layout.has_checksum());
+        wrapper.write_to_store(page_layout, file, false)?;
         Ok(wrapper.0)
     }
 
-    fn init_db(file: &mut File, page_size: u32) -> std::io::Result<(RawPage, u32, u64)> {
-        let meta = file.metadata()?;
-        let file_len = meta.len();
-        if file_len < page_size as u64 {
-            let expected_file_size: u64 = (page_size as u64) * (DB_INIT_BLOCK_COUNT as u64);
+    // the shadow is accepted only if it's a sealed header of the same page size
+    fn read_shadow_header(file: &dyn PageStore, page_layout: PageLayout) -> Option<HeaderPageWrapper> {
+        let shadow = page_layout.without_cipher().read_page(SHADOW_HEADER_PID, file).ok()?;
+        let mut wrapper = HeaderPageWrapper::from_raw_page(shadow);
+        let is_valid = wrapper.is_title_valid()
+            && wrapper.get_version() <= FORMAT_VERSION
+            && wrapper.get_page_size() == page_layout.physical_size()
+            && wrapper.get_shadow_header_pid() == SHADOW_HEADER_PID
+            && wrapper.get_header_sequence() != 0
+            && wrapper.is_crc_valid();
+        if is_valid {
+            Some(wrapper)
+        } else {
+            None
+        }
+    }
+
+    // The newer one of the intact headers is picked,
+    // the primary is repaired from the shadow if it's torn or behind.
+    fn read_newest_header(file: &mut dyn PageStore, page_layout: PageLayout, block_count: u64) -> DbResult<RawPage> {
+        let primary = PageHandler::check_header_page_size(file, page_layout)
+            .and_then(|_| PageHandler::read_first_block(file, page_layout))
+            .map(HeaderPageWrapper::from_raw_page);
+        let shadow = if block_count > (SHADOW_HEADER_PID as u64) {
+            PageHandler::read_shadow_header(file, page_layout)
+        } else {
+            None
+        };
+
+        let shadow = match (primary, shadow) {
+            (Ok(primary), Some(shadow)) if primary.is_crc_valid()
+                && primary.get_header_sequence() >= shadow.get_header_sequence() => return Ok(primary.0),
+
+            (Ok(primary), None) if primary.is_crc_valid() => return Ok(primary.0),
+
+            (Ok(primary), None) => return Err(DbErr::PageChecksumMismatch {
+                page_id: 0,
+                expected: primary.get_header_crc(),
+                actual: primary.calc_header_crc(),
+            }),
+
+            (Err(err), None) => return Err(err),
+
+            (_, Some(shadow)) => shadow,
+        };
+
+        let mut repaired = RawPage::new(0, shadow.0.len());
+        repaired.data.copy_from_slice(&shadow.0.data);
+        page_layout.write_page(&repaired, file)?;
+        file.flush()?;
+        file.sync_data()?;
+
+        Ok(repaired)
+    }
+
+    // A power of two, the offsets in a page are u16,
+    // so the body can't be larger than 65535 bytes.
+    // The header records if the pages have checksums, it's decided when the database is created,
+    // the flag of the config is ignored for an existing one.
+    fn detect_page_layout(file: &dyn PageStore, page_size: u32, checksum_new: bool) -> DbResult<PageLayout> {
+        let physical_size = page_size as u64;
+        if physical_size == 0 || file.len()? < physical_size {
+            return Ok(PageLayout::new(page_size, checksum_new));
+        }
+
+        let checksum = match HeaderPageWrapper::check_magic_and_version(file) {
+            Ok(()) => HeaderPageWrapper::read_page_checksum(file, 0)?,
+
+            // the shadow carries the flag if the primary is torn,
+            // a file without any header is rejected later
+            Err(_) => {
+                let shadow_offset = (SHADOW_HEADER_PID as u64) * physical_size;
+                HeaderPageWrapper::read_page_checksum(file, shadow_offset).unwrap_or(checksum_new)
+            }
+        };
+        Ok(PageLayout::new(page_size, checksum))
+    }
+
+    fn validate_page_layout(page_layout: PageLayout) -> DbResult<()> {
+        let page_size = page_layout.physical_size();
+        let is_valid = page_size.is_power_of_two()
+            && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+            && page_layout.body_size() <= (u16::MAX as u32);
+        if !is_valid {
+            return Err(DbErr::InvalidPageSize(page_size));
+        }
+        Ok(())
+    }
+
+    // The page size of an existing database must be the same as the header's,
+    // it's checked before the checksum, which is at the end of the page.
+    // A file without the magic and the version is rejected at first.
+    fn check_header_page_size(file: &dyn PageStore, page_layout: PageLayout) -> DbResult<()> {
+        HeaderPageWrapper::check_magic_and_version(file)?;
+
+        let stored = HeaderPageWrapper::read_page_size(file)?;
+        if stored != page_layout.physical_size() {
+            return Err(DbErr::PageSizeMismatch(page_layout.physical_size(), stored));
+        }
+        Ok(())
+    }
+
+    fn init_db(file: &mut dyn PageStore, page_layout: PageLayout, init_block_count: u32) -> DbResult<(RawPage, u32, u64)> {
+        PageHandler::validate_page_layout(page_layout)?;
+        if init_block_count == 0 {
+            return Err(DbErr::InvalidInitBlockCount(init_block_count));
+        }
+
+        let file_len = file.len()?;
+        let physical_size = page_layout.physical_size() as u64;
+        if file_len < physical_size {
+            // the shadow of the header is in the first block
+            let block_count = init_block_count.max(SHADOW_HEADER_PID + 1);
+            let expected_file_size: u64 = page_layout.offset_of(block_count);
             file.set_len(expected_file_size)?;
-            let first_page = PageHandler::force_write_first_block(file, page_size)?;
-            Ok((first_page, DB_INIT_BLOCK_COUNT as u32, expected_file_size))
+            let first_page = PageHandler::force_write_first_block(file, page_layout)?;
+            Ok((first_page, block_count, expected_file_size))
         } else {
-            let block_count = file_len / (page_size as u64);
-            let first_page = PageHandler::read_first_block(file, page_size)?;
+            let block_count = file_len / physical_size;
+            let first_page = PageHandler::read_newest_header(file, page_layout, block_count)?;
             Ok((first_page, block_count as u32, file_len))
         }
     }
 
-    fn mk_journal_path(db_path: &Path) -> PathBuf {
+    pub(crate) fn mk_journal_path(db_path: &Path) -> PathBuf {
         let mut buf = db_path.to_path_buf();
         let filename = buf.file_name().unwrap().to_str().unwrap();
         let new_filename = String::from(filename) + ".journal";
@@ -78,47 +365,493 @@ impl PageHandler {
         buf
     }
 
+    // the database is created if the file is missing
+    #[allow(dead_code)]
     pub fn new(path: &Path, page_size: u32) -> DbResult<PageHandler> {
+        PageHandler::new_with_config(path, page_size, Config::default())
+    }
+
+    #[inline]
+    pub fn new_with_config(path: &Path, page_size: u32, config: Config) -> DbResult<PageHandler> {
+        PageHandler::open_file(path, None, page_size, config, None, OpenMode::CreateOrOpen, &mut migration::registered_migrations())
+    }
+
+    // fails with AlreadyExists if the file is present, even empty
+    #[allow(dead_code)]
+    pub fn create(path: &Path, page_size: u32) -> DbResult<PageHandler> {
+        PageHandler::open_file(path, None, page_size, Config::default(), None, OpenMode::CreateNew, &mut migration::registered_migrations())
+    }
+
+    // fails with NotFound if the file is absent, nothing is created
+    #[allow(dead_code)]
+    pub fn open(path: &Path, page_size: u32) -> DbResult<PageHandler> {
+        PageHandler::open_file(path, None, page_size, Config::default(), None, OpenMode::OpenExisting, &mut migration::registered_migrations())
+    }
+
+    // the migrations are run if the database is of an older version
+    #[allow(dead_code)]
+    pub(crate) fn new_with_migrations(
+        path: &Path, page_size: u32, config: Config, migrations: &mut [Migration],
+    ) -> DbResult<PageHandler> {
+        PageHandler::open_file(path, None, page_size, config, None, OpenMode::CreateOrOpen, migrations)
+    }
+
+    // The journal is placed at the path instead of next to the main file,
+    // it's only opened, read and written by the path,
+    // never renamed or linked, so it can be on another filesystem.
+    // The header records that the journal in use is elsewhere,
+    // the same path must be given to reopen the database before it's checkpointed.
+    #[allow(dead_code)]
+    pub fn new_with_journal_path(path: &Path, journal_path: &Path, page_size: u32) -> DbResult<PageHandler> {
+        PageHandler::open_file(path, Some(journal_path), page_size, Config::default(), None, OpenMode::CreateOrOpen, &mut migration::registered_migrations())
+    }
+
+    #[allow(dead_code)]
+    pub fn new_encrypted(path: &Path, page_size: u32, key: &[u8; 32]) -> DbResult<PageHandler> {
+        PageHandler::new_encrypted_with_config(path, page_size, key, Config::default())
+    }
+
+    // The page bodies are encrypted in the main file and the journal,
+    // a new database is encrypted with the key,
+    // an existing one must be created with the same key.
+    #[inline]
+    pub fn new_encrypted_with_config(path: &Path, page_size: u32, key: &[u8; 32], config: Config) -> DbResult<PageHandler> {
+        PageHandler::open_file(path, None, page_size, config, Some(key), OpenMode::CreateOrOpen, &mut migration::registered_migrations())
+    }
+
+    fn open_file(
+        path: &Path, journal_path: Option<&Path>, page_size: u32,
+        config: Config, key: Option<&[u8; 32]>, mode: OpenMode, migrations: &mut [Migration],
+    ) -> DbResult<PageHandler> {
         let mut file = std::fs::OpenOptions::new()
-            .create(true)
+            .create(mode == OpenMode::CreateOrOpen)
+            .create_new(mode == OpenMode::CreateNew)
             .write(true)
+            .read(true)
+            .open(path)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::AlreadyExists if mode == OpenMode::CreateNew =>
+                    DbErr::AlreadyExists(path.to_string_lossy().into()),
+                std::io::ErrorKind::NotFound if mode == OpenMode::OpenExisting =>
+                    DbErr::NotFound(path.to_string_lossy().into()),
+                _ => DbErr::from(err),
+            })?;
+
+        // another handler writing the file would corrupt it
+        file_lock::lock_main_file(&file, path, true)?;
+
+        // the legacy databases have no checksums
+        let page_layout = PageHandler::detect_page_layout(&file, page_size, config.page_checksum)?;
+
+        let is_new = file.len()? < (page_layout.physical_size() as u64);
+        let (first_page, page_count, db_file_size) = PageHandler::init_db(&mut file, page_layout, config.init_block_count)?;
+
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let journal_in_use = first_page_wrapper.is_journal_in_use();
+
+        // the committed frames would be lost in another journal
+        if journal_in_use && first_page_wrapper.is_journal_external() != journal_path.is_some() {
+            return Err(DbErr::JournalPathMismatch);
+        }
+        let first_page = first_page_wrapper.0;
+
+        let page_layout = PageHandler::setup_cipher(&mut file, page_layout, first_page, key, is_new)?;
+        let page_layout = PageHandler::setup_compression(&mut file, page_layout, is_new && config.page_compression)?;
+
+        let journal_file_path: PathBuf = match journal_path {
+            Some(journal_path) => journal_path.to_path_buf(),
+            None => PageHandler::mk_journal_path(path),
+        };
+        let journal_missing = journal_in_use && !journal_file_path.exists();
+        let journal_manager = JournalManager::open_replaying(&journal_file_path, page_layout, db_file_size, &mut file)?;
+
+        let intent_log = IntentLog::open(&IntentLog::mk_path(path))?;
+
+        let mut page_handler = PageHandler::from_parts(
+            Box::new(file), page_layout, page_count,
+            Some(Box::new(journal_manager)), Some(Box::new(intent_log)),
+            config,
+        )?;
+
+        page_handler.db_path = Some(path.to_path_buf());
+        page_handler.journal_external = journal_path.is_some();
+        page_handler.change_map = Some(Box::new(ChangeMap::open(&ChangeMap::mk_path(path), is_new)?));
+
+        // the main file is not trusted without the journal,
+        // the flag is cleared by the next checkpoint
+        if journal_missing {
+            page_handler.missing_journal_integrity = Some(page_handler.check_integrity()?);
+        }
+
+        page_handler.upgrade_format(migrations)?;
+
+        Ok(page_handler)
+    }
+
+    // An older database is upgraded by the migrations from its version,
+    // step by step to the current one, in a single write transaction,
+    // a crash before the commit leaves the old version intact.
+    fn upgrade_format(&mut self, migrations: &mut [Migration]) -> DbResult<()> {
+        let found = HeaderPageWrapper::from_raw_page(self.peek_page(0)?).get_version();
+        if found == FORMAT_VERSION {
+            return Ok(());
+        }
+
+        self.with_write_transaction(|page_handler| page_handler.run_migrations(found, migrations))
+    }
+
+    fn run_migrations(&mut self, found: [u8; 4], migrations: &mut [Migration]) -> DbResult<()> {
+        let max_steps = migrations.len();
+        let mut version = found;
+        let mut steps = 0;
+        while version != FORMAT_VERSION {
+            // the path is broken, or runs in circles
+            let migration = match migrations.iter_mut().find(|migration| migration.from == version) {
+                Some(migration) if steps < max_steps => migration,
+                _ => return Err(DbErr::UnsupportedVersion {
+                    found,
+                    supported: FORMAT_VERSION,
+                }),
+            };
+
+            (migration.run)(self)?;
+            version = migration.to;
+            steps += 1;
+
+            let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(self.pipeline_read_page(0)?);
+            first_page_wrapper.set_version(&version);
+            self.pipeline_write_page(&first_page_wrapper.0)?;
+        }
+        Ok(())
+    }
+
+    // The salt and the check token are stored in the header page,
+    // which is never encrypted.
+    fn setup_cipher(
+        file: &mut dyn PageStore, page_layout: PageLayout, first_page: RawPage,
+        key: Option<&[u8; 32]>, is_new: bool,
+    ) -> DbResult<PageLayout> {
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+
+        let key = match (key, first_page_wrapper.is_encrypted()) {
+            (None, false) => return Ok(page_layout),
+            (Some(key), _) => key,
+            (None, true) => return Err(DbErr::InvalidEncryptionKey),
+        };
+
+        if first_page_wrapper.is_encrypted() {
+            let salt = first_page_wrapper.get_encryption_salt();
+            let cipher = PageCipher::new(key, &salt);
+            if cipher.check() != first_page_wrapper.get_encryption_check() {
+                return Err(DbErr::InvalidEncryptionKey);
+            }
+            return Ok(page_layout.with_cipher(cipher));
+        }
+
+        // an existing database in plain text
+        if !is_new {
+            return Err(DbErr::InvalidEncryptionKey);
+        }
+
+        let salt = PageCipher::generate_salt()?;
+        let cipher = PageCipher::new(key, &salt);
+        first_page_wrapper.set_encryption(&salt, &cipher.check());
+        first_page_wrapper.write_to_store(page_layout, file, false)?;
+
+        Ok(page_layout.with_cipher(cipher))
+    }
+
+    // A recovery tool for a partially corrupt database, not for the normal operation.
+    // An inconsistent free list is rebuilt from the pages below the null page bar,
+    // the unreadable and questionable pages are reported, and never reused.
+    // The header must be readable, nothing can be found without it.
+    #[allow(dead_code)]
+    pub fn open_salvage(path: &Path, page_size: u32) -> DbResult<(PageHandler, SalvageReport)> {
+        let mut page_handler = PageHandler::new(path, page_size)?;
+        let report = salvage::salvage(&mut page_handler)?;
+        Ok((page_handler, report))
+    }
+
+    // The header records if the pages are compressed,
+    // it's decided when the database is created.
+    fn setup_compression(file: &mut dyn PageStore, page_layout: PageLayout, compress_new: bool) -> DbResult<PageLayout> {
+        if compress_new && !cfg!(feature = "compression") {
+            return Err(DbErr::CompressionNotSupported);
+        }
+
+        let first_page = PageHandler::read_first_block(file, page_layout)?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        if compress_new {
+            first_page_wrapper.set_compressed(true);
+            first_page_wrapper.write_to_store(page_layout, file, false)?;
+        }
+
+        if !first_page_wrapper.is_compressed() {
+            return Ok(page_layout);
+        }
+
+        #[cfg(feature = "compression")]
+        return Ok(page_layout.with_compression());
+
+        #[cfg(not(feature = "compression"))]
+        return Err(DbErr::CompressionNotSupported);
+    }
+
+    #[allow(dead_code)]
+    pub fn new_in_memory(page_size: u32) -> DbResult<PageHandler> {
+        PageHandler::new_in_memory_with_config(page_size, Config::default())
+    }
+
+    // Nothing touches the filesystem,
+    // the data is lost when the handler is dropped.
+    pub fn new_in_memory_with_config(page_size: u32, config: Config) -> DbResult<PageHandler> {
+        PageHandler::from_store_with_config(
+            Box::new(MemoryPageStore::new()), page_size,
+            Box::new(MemoryPageStore::new()), config,
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn from_store(store: Box<dyn PageStore>, page_size: u32, journal_store: Box<dyn PageStore>) -> DbResult<PageHandler> {
+        PageHandler::from_store_with_config(store, page_size, journal_store, Config::default())
+    }
+
+    // The pages and the journal are kept in the stores supplied by the embedder,
+    // an empty store is initialized, the frames committed in the journal store are replayed.
+    // The intent log and the change map are kept in memory.
+    pub fn from_store_with_config(
+        mut store: Box<dyn PageStore>, page_size: u32,
+        journal_store: Box<dyn PageStore>, config: Config,
+    ) -> DbResult<PageHandler> {
+        let page_layout = PageHandler::detect_page_layout(store.as_ref(), page_size, config.page_checksum)?;
+
+        let is_new = store.len()? < (page_layout.physical_size() as u64);
+        let (_, page_count, db_file_size) = PageHandler::init_db(store.as_mut(), page_layout, config.init_block_count)?;
+        let page_layout = PageHandler::setup_compression(store.as_mut(), page_layout, is_new && config.page_compression)?;
+
+        let journal_manager = JournalManager::open_store(None, journal_store, page_layout, db_file_size, Some(store.as_mut()))?;
+
+        let intent_log = IntentLog::open_in_memory()?;
+
+        let mut page_handler = PageHandler::from_parts(
+            store, page_layout, page_count,
+            Some(Box::new(journal_manager)), Some(Box::new(intent_log)),
+            config,
+        )?;
+        page_handler.change_map = Some(Box::new(ChangeMap::open_in_memory()?));
+
+        page_handler.upgrade_format(&mut migration::registered_migrations())?;
+
+        Ok(page_handler)
+    }
+
+    #[allow(dead_code)]
+    pub fn open_read_only(path: &Path, page_size: u32) -> DbResult<PageHandler> {
+        PageHandler::open_read_only_with_config(path, page_size, Config::default())
+    }
+
+    // The file is opened without write permission,
+    // no journal or intent log is created,
+    // the committed frames of a journal left behind are read from the memory,
+    // all the mutations return DbErr::ReadOnly.
+    pub fn open_read_only_with_config(path: &Path, page_size: u32, config: Config) -> DbResult<PageHandler> {
+        let mut file = std::fs::OpenOptions::new()
             .read(true)
             .open(path)?;
 
-        let (_, page_count, db_file_size) = PageHandler::init_db(&mut file, page_size)?;
+        // the readers share the lock, a writer can't open the file
+        file_lock::lock_main_file(&file, path, false)?;
+
+        let page_layout = PageHandler::detect_page_layout(&file, page_size, config.page_checksum)?;
+        PageHandler::validate_page_layout(page_layout)?;
+
+        // check the header is readable
+        PageHandler::check_header_page_size(&file, page_layout)?;
+        let first_page = PageHandler::read_first_block(&mut file, page_layout)?;
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        if first_page_wrapper.is_encrypted() {
+            return Err(DbErr::InvalidEncryptionKey);
+        }
+
+        // an older database can't be upgraded without writing
+        let found = first_page_wrapper.get_version();
+        if found != FORMAT_VERSION {
+            return Err(DbErr::UnsupportedVersion {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+
+        let page_layout = PageHandler::setup_compression(&mut file, page_layout, false)?;
+
+        let file_len = file.metadata()?.len();
+        let page_count = (file_len / (page_layout.physical_size() as u64)) as u32;
+
+        let replayed_journal = if first_page_wrapper.is_journal_in_use() {
+            PageHandler::load_journal_read_only(path, &first_page_wrapper, page_layout, file_len)?
+        } else {
+            None
+        };
+
+        let mut page_handler = PageHandler::from_parts(Box::new(file), page_layout, page_count, None, None, config)?;
+        page_handler.db_path = Some(path.to_path_buf());
+        page_handler.replayed_journal = replayed_journal.map(Box::new);
+        page_handler.change_map = ChangeMap::open_read_only(&ChangeMap::mk_path(path))?.map(Box::new);
+
+        Ok(page_handler)
+    }
+
+    // The main file is behind the journal, the committed frames are
+    // replayed into the memory instead, neither file is written.
+    // The path of an external journal is unknown here.
+    // Nothing is replayed if the journal is missing, as the writer would find.
+    fn load_journal_read_only(
+        path: &Path, first_page_wrapper: &HeaderPageWrapper, page_layout: PageLayout, db_file_size: u64,
+    ) -> DbResult<Option<JournalManager>> {
+        if first_page_wrapper.is_journal_external() {
+            return Err(DbErr::JournalPathMismatch);
+        }
+
+        let journal_bytes = match std::fs::read(PageHandler::mk_journal_path(path)) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let journal_store = Box::new(MemoryPageStore::from_bytes(journal_bytes));
+        let journal_manager = JournalManager::open_store(None, journal_store, page_layout, db_file_size, None)?;
+        Ok(Some(journal_manager))
+    }
 
-        let journal_file_path: PathBuf = PageHandler::mk_journal_path(path);
-        let journal_manager = JournalManager::open(&journal_file_path, page_size, db_file_size)?;
+    fn from_parts(
+        file: Box<dyn PageStore>, page_layout: PageLayout, page_count: u32,
+        journal_manager: Option<Box<JournalManager>>, intent_log: Option<Box<IntentLog>>,
+        config: Config,
+    ) -> DbResult<PageHandler> {
+        let page_size = page_layout.body_size();
 
-        let page_cache = PageCache::new_default(page_size);
+        let recovered_on_open = match &journal_manager {
+            Some(journal_manager) => journal_manager.is_recovered(),
+            None => false,
+        };
 
-        let last_commit_db_size = {
-            let meta = file.metadata()?;
-            meta.len()
+        let mut page_cache = match config.cache_capacity_bytes {
+            Some(capacity_bytes) => PageCache::with_capacity(capacity_bytes, page_size, config.cache_policy),
+            None => PageCache::new_default(page_size, config.cache_policy),
         };
+        // the header is read by almost every operation
+        page_cache.pin(0);
+
+        let last_commit_db_size = file.len()?;
 
         Ok(PageHandler {
             file,
+            db_path: None,
 
             last_commit_db_size,
 
             page_size,
+            page_layout,
             page_count,
             page_cache: Box::new(page_cache),
-            journal_manager: Box::new(journal_manager),
+            journal_manager,
+            replayed_journal: None,
+            intent_log,
+            change_map: None,
 
             data_page_map: BTreeMap::new(),
 
             transaction_state: TransactionState::NoTrans,
 
+            config,
+
+            recovered_on_open,
+            missing_journal_integrity: None,
+
+            journal_external: false,
+            cache_metrics: CacheMetrics::default(),
+            write_transaction_begin: None,
+
+            transaction_alloc_count: 0,
+
+            scan_depth: 0,
+
+            write_stats: WriteStatsTracker::new(),
+
+            tombstones: vec![],
+
+            journal_checkpoint_threshold: DEFAULT_JOURNAL_CHECKPOINT_THRESHOLD,
+
+            durability_mode: DurabilityMode::default(),
+
+            journal_mode: JournalMode::default(),
+
+            reuse_empty_data_pages: false,
+
+            zero_freed_pages: false,
+
+            freed_in_transaction: vec![],
+
+            current_transaction_type: None,
+
+            dry_run: false,
+
+            checkpoint_in_progress: false,
+
+            savepoints: vec![],
+
+            read_snapshot: None,
+
+            write_observer: None,
+            transaction_observer: None,
+
+            max_transaction_pages: None,
+            max_file_size: None,
+
         })
     }
 
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.journal_manager.is_none()
+    }
+
+    #[inline]
+    fn check_writable(&self) -> DbResult<()> {
+        if self.is_read_only() {
+            return Err(DbErr::ReadOnly);
+        }
+        Ok(())
+    }
+
+    // the journal the pages are read from
+    #[inline]
+    fn read_journal(&self) -> Option<&JournalManager> {
+        self.journal_manager.as_deref().or(self.replayed_journal.as_deref())
+    }
+
+    #[inline]
+    fn journal_mut(&mut self) -> DbResult<&mut JournalManager> {
+        self.journal_manager.as_deref_mut().ok_or(DbErr::ReadOnly)
+    }
+
+    #[inline]
+    fn intent_log_mut(&mut self) -> DbResult<&mut IntentLog> {
+        self.intent_log.as_deref_mut().ok_or(DbErr::ReadOnly)
+    }
+
     pub(crate) fn auto_start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
         match self.transaction_state {
             TransactionState::NoTrans => {
-                self.start_transaction(ty)?;
+                self.begin_transaction(ty)?;
+                self.transaction_state = TransactionState::DbAuto;
+                self.notify_transaction(TransactionEvent::AutoStart, ty);
+            }
+
+            // the write runs by its own, the read transaction goes on after it
+            TransactionState::User if ty == TransactionType::Write && self.current_transaction_type == Some(TransactionType::Read) => {
+                self.suspend_read_transaction()?;
                 self.transaction_state = TransactionState::DbAuto;
+                self.notify_transaction(TransactionEvent::AutoStart, ty);
             }
 
             // current is auto-read, but going to write
@@ -139,31 +872,110 @@ impl PageHandler {
 
     pub(crate) fn auto_rollback(&mut self) -> DbResult<()> {
         if self.transaction_state == TransactionState::DbAuto {
-            self.rollback()?;
+            let ty = self.current_transaction_type;
+            self.rollback_transaction()?;
             self.transaction_state = TransactionState::NoTrans;
+            if let Some(ty) = ty {
+                self.notify_transaction(TransactionEvent::AutoRollback, ty);
+            }
+            self.resume_read_transaction()?;
         }
         Ok(())
     }
 
     pub(crate) fn auto_commit(&mut self) -> DbResult<()> {
         if self.transaction_state == TransactionState::DbAuto {
-            self.commit()?;
+            let ty = self.current_transaction_type;
+            self.commit_transaction()?;
             self.transaction_state = TransactionState::NoTrans;
+            if let Some(ty) = ty {
+                self.notify_transaction(TransactionEvent::AutoCommit, ty);
+            }
+            self.resume_read_transaction()?;
+        }
+        Ok(())
+    }
+
+    // The read transaction of the user is ended in the journal,
+    // the committed state is the same as when it starts,
+    // nothing is written in it.
+    fn suspend_read_transaction(&mut self) -> DbResult<()> {
+        if self.read_snapshot.is_none() {
+            let journal_offsets = self.read_journal()
+                .map_or_else(BTreeMap::new, |journal_manager| journal_manager.committed_offsets());
+            self.read_snapshot = Some(ReadSnapshot {
+                db_file_size: self.last_commit_db_size,
+                journal_offsets,
+            });
         }
+        self.journal_mut()?.commit()?;
+        self.current_transaction_type = None;
+        self.begin_transaction(TransactionType::Write)
+    }
+
+    // after the write interleaving the read transaction of the user
+    fn resume_read_transaction(&mut self) -> DbResult<()> {
+        if self.read_snapshot.is_none() {
+            return Ok(());
+        }
+        self.begin_transaction(TransactionType::Read)?;
+        self.transaction_state = TransactionState::User;
         Ok(())
     }
 
+    // Run the closure in a write transaction started automatically,
+    // committed if it returns Ok, rolled back if it returns Err,
+    // the writes before the error are discarded.
+    // In a write transaction of the user, it's committed or rolled back by the user,
+    // in a read one, it's committed by its own.
+    pub(crate) fn with_write_transaction<F, T>(&mut self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut PageHandler) -> DbResult<T>,
+    {
+        self.auto_start_transaction(TransactionType::Write)?;
+
+        match f(self) {
+            Ok(ret) => {
+                self.auto_commit()?;
+                Ok(ret)
+            }
+
+            Err(err) => {
+                self.auto_rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    // the bytes a data page should have besides the document
+    #[inline]
+    fn preserve_bytes(&self) -> u32 {
+        std::cmp::max(self.config.data_page_policy.preserve_bytes, DATA_SLOT_BAR_SIZE)
+    }
+
+    // the room a data page must have to take the slot
+    #[inline]
+    fn required_room(&self, slot_size: u32) -> u32 {
+        slot_size + self.preserve_bytes()
+    }
+
+    // A document larger than max_item_size is chained in the overflow pages,
+    // only its slot is stored in the data page.
+    #[inline]
+    fn is_overflow_size(&self, bytes_len: usize) -> bool {
+        bytes_len as u32 > DataPageWrapper::max_item_size(self.page_size)
+    }
+
     pub(crate) fn distribute_data_page_wrapper(&mut self, data_size: u32) -> DbResult<DataPageWrapper> {
-        let data_size = data_size + 2;  // preserve 2 bytes
+        let data_size = self.required_room(data_size);
         let (wrapper, removed_key) = {
             let mut range = self.data_page_map.range_mut((Included(data_size), Unbounded));
             match range.next() {
                 Some((key, value)) => {
-                    if value.is_empty() {
-                        panic!("unexpected: distributed vector is empty");
-                    }
-                    let last_index = value[value.len() - 1];
-                    value.remove(value.len() - 1);
+                    let last_index = match value.pop() {
+                        Some(pid) => pid,
+                        None => return Err(DbErr::Internal(format!("distributed vector of {} is empty", key))),
+                    };
 
                     let mut removed_key = None;
 
@@ -199,7 +1011,7 @@ impl PageHandler {
 
     pub(crate) fn return_data_page_wrapper(&mut self, wrapper: DataPageWrapper) {
         let remain_size = wrapper.remain_size();
-        if remain_size < PRESERVE_WRAPPER_MIN_REMAIN_SIZE {
+        if remain_size < self.config.data_page_policy.min_remain_size {
             return;
         }
 
@@ -219,39 +1031,123 @@ impl PageHandler {
         }
     }
 
+    // the IO error carries the page and its offset in the main file
+    fn page_io_err(&self, page_id: u32, err: DbErr) -> DbErr {
+        match err {
+            DbErr::IOErr(source) => DbErr::PageIo {
+                page_id,
+                offset: self.page_layout.offset_of(page_id),
+                source,
+            },
+            err => err,
+        }
+    }
+
     // 1. write to journal, if success
     //    - 2. checkpoint journal, if full
     // 3. write to page_cache
+    //
+    // a page is in the journal before it's in the cache,
+    // so the cache never holds a dirty page, evicting is always safe
     pub fn pipeline_write_page(&mut self, page: &RawPage) -> Result<(), DbErr> {
-        self.journal_manager.as_mut().append_raw_page(page)?;
+        self.check_transaction_page_limit()?;
+        if self.is_unchanged_write(page)? {
+            return Ok(());
+        }
+        if let Err(err) = self.journal_mut()?.append_raw_page(page) {
+            return Err(self.page_io_err(page.page_id, err));
+        }
+
+        if let Some(write_observer) = self.write_observer.as_mut() {
+            write_observer(page.page_id, page);
+        }
 
-        self.page_cache.insert_to_cache(page);
+        self.page_cache.insert_to_cache(page)?;
         Ok(())
     }
 
-    // 1. read from page_cache, if none
-    // 2. read from journal, if none
-    // 3. read from main db
+    // The cache holds the latest image of the page, committed or in the transaction,
+    // rewriting the same bytes appends nothing to the journal.
+    // The checksum trails the body in the file, it's never in the compared bytes,
+    // any difference in the body is written.
+    fn is_unchanged_write(&mut self, page: &RawPage) -> DbResult<bool> {
+        if self.journal_mut()?.transaction_type() != Some(TransactionType::Write) {
+            return Ok(false);
+        }
+        Ok(self.page_cache.holds_image(page))
+    }
+
+    // the page seen by the read snapshot if it's changed since,
+    // None if the current one is the same
+    fn read_snapshot_page(&self, page_id: u32) -> DbResult<Option<RawPage>> {
+        let snapshot = match &self.read_snapshot {
+            Some(snapshot) if self.current_transaction_type == Some(TransactionType::Read) => snapshot,
+            _ => return Ok(None),
+        };
+        if self.page_layout.offset_of(page_id) >= snapshot.db_file_size {
+            return Ok(Some(RawPage::new(page_id, self.page_size)));
+        }
+
+        let journal_manager = match self.read_journal() {
+            Some(journal_manager) => journal_manager,
+            None => return Ok(None),
+        };
+        let snapshot_offset = snapshot.journal_offsets.get(&page_id).copied();
+        if journal_manager.frame_position(page_id) == snapshot_offset {
+            return Ok(None);
+        }
+
+        let result = match snapshot_offset {
+            Some(offset) => journal_manager.read_page_at(page_id, offset)
+                .map_err(|err| self.page_io_err(page_id, err.into()))?,
+            None => self.page_layout.read_page(page_id, self.file.as_ref())?,
+        };
+        Ok(Some(result))
+    }
+
+    // 1. read from the read snapshot, if it's changed since
+    // 2. read from page_cache, if none
+    // 3. read from journal, if none
+    // 4. read from main db
     pub fn pipeline_read_page(&mut self, page_id: u32) -> Result<RawPage, DbErr> {
+        if let Some(page) = self.read_snapshot_page(page_id)? {
+            return Ok(page);
+        }
+
         if let Some(page) = self.page_cache.get_from_cache(page_id) {
             #[cfg(feature = "log")]
             eprintln!("read page from cache, page_id: {}", page_id);
 
+            self.cache_metrics.hits += 1;
             return Ok(page);
         }
 
-        if let Some(page) = self.journal_manager.read_page(page_id)? {
+        let journal_page = match self.read_journal().map(|journal_manager| journal_manager.read_page(page_id)) {
+            Some(Ok(page)) => page,
+            Some(Err(err)) => return Err(self.page_io_err(page_id, err.into())),
+            None => None,
+        };
+        if let Some(page) = journal_page {
+            self.cache_metrics.journal_hits += 1;
+
             // find in journal, insert to cache
-            self.page_cache.insert_to_cache(&page);
+            if !self.is_scan_mode() {
+                self.page_cache.insert_to_cache(&page)?;
+            }
 
             return Ok(page);
         }
 
-        let offset = (page_id as u64) * (self.page_size as u64);
-        let mut result = RawPage::new(page_id, self.page_size);
-        result.read_from_file(&mut self.file, offset)?;
+        // the checksum is verified if the layout has it
+        self.cache_metrics.disk_reads += 1;
+        let result = match self.page_layout.read_page(page_id, self.file.as_ref()) {
+            Ok(result) => result,
+            Err(err) => return Err(self.page_io_err(page_id, err)),
+        };
 
-        self.page_cache.insert_to_cache(&result);
+        if !self.is_scan_mode() {
+            self.page_cache.insert_to_cache(&result)?;
+        }
 
         #[cfg(feature = "log")]
         eprintln!("read page from main file, id: {}", page_id);
@@ -259,23 +1155,285 @@ impl PageHandler {
         Ok(result)
     }
 
+    // Read the page from the journal or the main file,
+    // the cache is neither looked up nor filled,
+    // so a one-off scan doesn't evict the hot pages.
+    // The cache is never newer than the journal, it's safe to skip it.
+    #[allow(dead_code)]
+    pub(crate) fn read_page_uncached(&mut self, page_id: u32) -> DbResult<RawPage> {
+        if let Some(journal_manager) = self.read_journal() {
+            if let Some(page) = journal_manager.read_page(page_id)? {
+                self.cache_metrics.journal_hits += 1;
+                return Ok(page);
+            }
+        }
+
+        self.cache_metrics.disk_reads += 1;
+        self.page_layout.read_page(page_id, self.file.as_ref())
+    }
+
+    // Read a run of pages from the main file at once, and put them into the cache,
+    // so the sequential reads after hit the cache.
+    // The pages in the journal are skipped, their images in the main file are stale,
+    // and the pages in the cache are not replaced.
+    // The run is cut at the capacity of the cache,
+    // the pages beyond it would evict the ones read before.
+    //
+    // return the count of the pages put into the cache
+    #[allow(dead_code)]
+    pub fn prefetch(&mut self, start_pid: u32, count: u32) -> DbResult<u32> {
+        let physical_size = self.page_layout.physical_size() as u64;
+        let file_pages = (self.file.len()? / physical_size) as u32;
+        let count = std::cmp::min(count as usize, self.page_cache.capacity()) as u32;
+        let end_pid = start_pid.saturating_add(count).min(file_pages);
+        if start_pid >= end_pid {
+            return Ok(0);
+        }
+
+        let mut bytes = vec![0; ((end_pid - start_pid) as usize) * (physical_size as usize)];
+        self.file.read_at(self.page_layout.offset_of(start_pid), &mut bytes)?;
+        self.cache_metrics.disk_reads += 1;
+
+        let mut cached = 0;
+        for (index, chunk) in bytes.chunks(physical_size as usize).enumerate() {
+            let pid = start_pid + (index as u32);
+            let in_journal = self.read_journal()
+                .is_some_and(|journal_manager| journal_manager.contains_page(pid));
+            if in_journal || self.page_cache.peek_from_cache(pid).is_some() {
+                continue;
+            }
+
+            let page = self.page_layout.decode_page(pid, chunk)?;
+            self.page_cache.insert_to_cache(&page)?;
+            cached += 1;
+        }
+
+        Ok(cached)
+    }
+
+    // read a page without touching the LRU order and the counters
+    fn peek_page(&self, page_id: u32) -> DbResult<RawPage> {
+        if let Some(page) = self.page_cache.peek_from_cache(page_id) {
+            return Ok(page);
+        }
+
+        if let Some(journal_manager) = self.read_journal() {
+            if let Some(page) = journal_manager.read_page(page_id)? {
+                return Ok(page);
+            }
+        }
+
+        self.page_layout.read_page(page_id, self.file.as_ref())
+    }
+
+    // release the cache memory gradually under memory pressure,
+    // return the count of evicted pages
+    #[inline]
+    pub fn shrink_cache_to(&mut self, target: usize) -> usize {
+        self.page_cache.shrink_to(target)
+    }
+
+    #[inline]
+    pub fn set_cache_soft_limit(&mut self, bytes: usize) {
+        self.page_cache.set_soft_limit(bytes)
+    }
+
+    // scan mode can be nested,
+    // every begin_scan() should be paired with an end_scan()
+    #[inline]
+    pub(crate) fn begin_scan(&mut self) {
+        self.scan_depth += 1;
+    }
+
+    #[inline]
+    pub(crate) fn end_scan(&mut self) {
+        debug_assert!(self.scan_depth > 0, "end_scan() without begin_scan()");
+        self.scan_depth -= 1;
+    }
+
+    #[inline]
+    pub(crate) fn is_scan_mode(&self) -> bool {
+        self.scan_depth > 0
+    }
+
     pub(crate) fn get_doc_from_ticket(&mut self, data_ticket: &DataTicket) -> DbResult<Option<Rc<Document>>> {
-        let page = self.pipeline_read_page(data_ticket.pid)?;
-        let wrapper = DataPageWrapper::from_raw(page);
-        let bytes = wrapper.get(data_ticket.index as u32);
+        let bytes = self.get_raw_from_ticket(data_ticket)?;
         if let Some(bytes) = bytes {
-            let doc = Document::from_bytes(bytes)?;
+            let doc = Document::from_bytes(&bytes)?;
             return Ok(Some(Rc::new(doc)));
         }
         return Ok(None);
     }
 
+    // the bytes stored by store_blob(), a ticket of a document returns its BSON bytes
+    #[allow(dead_code)]
+    pub(crate) fn read_blob(&mut self, data_ticket: &DataTicket) -> DbResult<Vec<u8>> {
+        match self.get_raw_from_ticket(data_ticket)? {
+            Some(bytes) => Ok(bytes),
+            None => Err(DbErr::DataTicketNotFound {
+                pid: data_ticket.pid,
+                index: data_ticket.index,
+            }),
+        }
+    }
+
+    // the blob is yielded page by page, the overflow pages are never joined,
+    // a missing ticket fails before anything is read
+    #[allow(dead_code)]
+    pub(crate) fn read_blob_stream(&mut self, data_ticket: &DataTicket) -> DbResult<impl std::io::Read + '_> {
+        let bytes = match self.get_slot_bytes(data_ticket)? {
+            Some(bytes) => bytes,
+            None => return Err(DbErr::DataTicketNotFound {
+                pid: data_ticket.pid,
+                index: data_ticket.index,
+            }),
+        };
+
+        let reader = match OverflowSlot::from_bytes(&bytes) {
+            Some(slot) => BlobReader::overflow(self, slot.first_pid),
+            None => BlobReader::inline(self, bytes),
+        };
+        Ok(reader)
+    }
+
+    // read the bytes of the document without decoding,
+    // the overflow pages are joined
+    pub(crate) fn get_raw_from_ticket(&mut self, data_ticket: &DataTicket) -> DbResult<Option<Vec<u8>>> {
+        let bytes = match self.get_slot_bytes(data_ticket)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+
+        match OverflowSlot::from_bytes(&bytes) {
+            Some(slot) => {
+                let result = self.read_overflow_chain(&slot)?;
+                Ok(Some(result))
+            }
+
+            None => Ok(Some(bytes)),
+        }
+    }
+
+    // the bytes in the data page, an overflow slot is not followed
+    fn get_slot_bytes(&mut self, data_ticket: &DataTicket) -> DbResult<Option<Vec<u8>>> {
+        let page = self.pipeline_read_page(data_ticket.pid)?;
+        let wrapper = DataPageWrapper::from_raw(page);
+
+        // the removed items at the end are truncated
+        if (data_ticket.index as u32) >= wrapper.bar_len() {
+            return Ok(None);
+        }
+
+        Ok(wrapper.get(data_ticket.index as u32).map(|bytes| bytes.to_vec()))
+    }
+
+    fn read_overflow_chain(&mut self, slot: &OverflowSlot) -> DbResult<Vec<u8>> {
+        let mut result = Vec::with_capacity(slot.total_len as usize);
+        let mut pid = slot.first_pid;
+        while pid != 0 {
+            let page = self.pipeline_read_page(pid)?;
+            let wrapper = OverflowPageWrapper::from_raw_page(page);
+            result.extend_from_slice(wrapper.data());
+            pid = wrapper.next_pid();
+        }
+        Ok(result)
+    }
+
+    // split the bytes into a chain of overflow pages,
+    // return the pid of the first one
+    fn write_overflow_chain(&mut self, bytes: &[u8]) -> DbResult<u32> {
+        let chunks: Vec<&[u8]> = bytes.chunks(OverflowPageWrapper::capacity_of(self.page_size)).collect();
+        let mut pids = Vec::with_capacity(chunks.len());
+        for _ in 0..chunks.len() {
+            pids.push(self.alloc_page_id()?);
+        }
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let mut wrapper = OverflowPageWrapper::init(pids[index], self.page_size);
+            wrapper.set_next_pid(pids.get(index + 1).copied().unwrap_or(0));
+            wrapper.set_data(chunk);
+            self.pipeline_write_page(&wrapper.0)?;
+        }
+
+        Ok(pids[0])
+    }
+
+    fn free_overflow_chain(&mut self, slot: &OverflowSlot) -> DbResult<()> {
+        let pids = self.collect_overflow_chain(slot)?;
+        self.free_pages(&pids)?;
+        Ok(())
+    }
+
+    fn collect_overflow_chain(&mut self, slot: &OverflowSlot) -> DbResult<Vec<u32>> {
+        let mut pids = vec![];
+        let mut pid = slot.first_pid;
+        while pid != 0 {
+            let page = self.pipeline_read_page(pid)?;
+            let wrapper = OverflowPageWrapper::from_raw_page(page);
+            pids.push(pid);
+            pid = wrapper.next_pid();
+        }
+        Ok(pids)
+    }
+
+    #[inline]
+    // Serialize the document and decode it back, nothing is allocated or written,
+    // a batch can be checked before the transaction begins.
+    // A document which is read back differently is rejected,
+    // e.g. a key with a NUL byte.
+    #[allow(dead_code)]
+    pub(crate) fn validate_doc(doc: &Document) -> DbResult<()> {
+        let bytes = doc.to_bytes()?;
+        let decoded = Document::from_bytes(&bytes)
+            .map_err(|err| DbErr::ValidationError(format!("the document can't be read back: {}", err)))?;
+        if decoded.to_bytes()? != bytes {
+            return Err(DbErr::ValidationError("the document is not read back as it's stored".into()));
+        }
+        Ok(())
+    }
+
     pub(crate) fn store_doc(&mut self, doc: &Document) -> DbResult<DataTicket> {
+        self.check_writable()?;
+        let bytes = doc.to_bytes()?;
+        self.store_bytes(&bytes)
+    }
+
+    // the bytes are stored as they are, without BSON,
+    // they are chained in the overflow pages if they are large
+    #[allow(dead_code)]
+    #[inline]
+    pub(crate) fn store_blob(&mut self, bytes: &[u8]) -> DbResult<DataTicket> {
+        self.check_writable()?;
+        self.store_bytes(bytes)
+    }
+
+    // The room a data page must have if the document is stored,
+    // it's the space taken with the default policy, nothing is allocated.
+    #[allow(dead_code)]
+    pub(crate) fn estimate_stored_size(&self, doc: &Document) -> DbResult<usize> {
         let bytes = doc.to_bytes()?;
+        let slot_size = if self.is_overflow_size(bytes.len()) {
+            OVERFLOW_SLOT_SIZE
+        } else {
+            bytes.len()
+        };
+        Ok(self.required_room(slot_size as u32) as usize)
+    }
+
+    pub(crate) fn store_bytes(&mut self, bytes: &[u8]) -> DbResult<DataTicket> {
+        if self.is_overflow_size(bytes.len()) {
+            let first_pid = self.write_overflow_chain(bytes)?;
+            let slot = OverflowSlot {
+                total_len: bytes.len() as u32,
+                first_pid,
+            };
+            return self.store_bytes(&slot.to_bytes());
+        }
+
         let mut wrapper = self.distribute_data_page_wrapper(bytes.len() as u32)?;
         let index = wrapper.bar_len() as u16;
         let pid = wrapper.pid();
-        wrapper.put(&bytes);
+        wrapper.put(bytes);
 
         self.pipeline_write_page(wrapper.borrow_page())?;
 
@@ -287,171 +1445,5659 @@ impl PageHandler {
         })
     }
 
-    pub(crate) fn free_data_ticket(&mut self, data_ticket: &DataTicket) -> DbResult<Vec<u8>> {
-        #[cfg(feature = "log")]
-        eprintln!("free data ticket: {}", data_ticket);
+    // The documents are packed into the current data page until it's full,
+    // so a page is appended to the journal once per batch
+    // instead of once per document.
+    //
+    // The tickets are in the order of the input.
+    #[allow(dead_code)]
+    pub(crate) fn store_docs(&mut self, docs: &[Document]) -> DbResult<Vec<DataTicket>> {
+        self.check_writable()?;
 
-        let page = self.pipeline_read_page(data_ticket.pid)?;
-        let mut wrapper = DataPageWrapper::from_raw(page);
-        let bytes = wrapper.get(data_ticket.index as u32).unwrap().to_vec();
-        wrapper.remove(data_ticket.index as u32);
-        if wrapper.is_empty() {
-            self.free_page(data_ticket.pid)?;
+        let mut tickets = Vec::with_capacity(docs.len());
+        let mut current: Option<DataPageWrapper> = None;
+
+        for doc in docs {
+            let mut bytes = doc.to_bytes()?;
+            if self.is_overflow_size(bytes.len()) {
+                let first_pid = self.write_overflow_chain(&bytes)?;
+                let slot = OverflowSlot {
+                    total_len: bytes.len() as u32,
+                    first_pid,
+                };
+                bytes = slot.to_bytes().to_vec();
+            }
+
+            let data_size = bytes.len() as u32;
+            let fits = match &current {
+                Some(wrapper) => wrapper.remain_size() >= self.required_room(data_size),
+                None => false,
+            };
+            if !fits {
+                if let Some(wrapper) = current.take() {
+                    self.pipeline_write_page(wrapper.borrow_page())?;
+                    self.return_data_page_wrapper(wrapper);
+                }
+                current = Some(self.distribute_data_page_wrapper(data_size)?);
+            }
+
+            let wrapper = current.as_mut().unwrap();
+            let index = wrapper.bar_len() as u16;
+            let pid = wrapper.pid();
+            wrapper.put(&bytes);
+
+            tickets.push(DataTicket {
+                pid,
+                index,
+            });
         }
-        let page = wrapper.consume_page();
-        self.pipeline_write_page(&page)?;
-        Ok(bytes)
-    }
 
-    #[inline]
-    pub fn free_page(&mut self, pid: u32) -> DbResult<()> {
-        self.free_pages(&[pid])
+        if let Some(wrapper) = current {
+            self.pipeline_write_page(wrapper.borrow_page())?;
+            self.return_data_page_wrapper(wrapper);
+        }
+
+        Ok(tickets)
     }
 
-    pub fn free_pages(&mut self, pages: &[u32]) -> DbResult<()> {
+    // In a transaction, the ticket is tombstoned
+    // and released when committing.
+    pub(crate) fn free_data_ticket(&mut self, data_ticket: &DataTicket) -> DbResult<Vec<u8>> {
         #[cfg(feature = "log")]
-        for pid in pages {
-            eprintln!("free page, id: {}", *pid);
-        }
+        eprintln!("free data ticket: {}", data_ticket);
 
-        let first_page = self.pipeline_read_page(0)?;
-        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
-        let free_list_pid = first_page_wrapper.get_free_list_page_id();
-        if free_list_pid != 0 {
-            unimplemented!();
+        if self.transaction_type() != Some(TransactionType::Write) {
+            return self.release_data_ticket(data_ticket);
         }
 
-        let current_size = first_page_wrapper.get_free_list_size();
-        if (current_size as usize) + pages.len() >= header_page_wrapper::HEADER_FREE_LIST_MAX_SIZE {
-            unimplemented!();
+        let is_tombstoned = self.tombstones.iter()
+            .any(|ticket| ticket.pid == data_ticket.pid && ticket.index == data_ticket.index);
+        if is_tombstoned {
+            return Err(DbErr::DataTicketNotFound {
+                pid: data_ticket.pid,
+                index: data_ticket.index,
+            });
         }
 
-        first_page_wrapper.set_free_list_size(current_size + (pages.len() as u32));
-        let mut counter = 0;
-        for pid in pages {
-            first_page_wrapper.set_free_list_content(current_size + counter, *pid);
-            counter += 1;
+        let wrapper = self.read_occupied_data_page(data_ticket.pid, &[data_ticket.index as u32])?;
+        let mut bytes = PageHandler::occupied_slot(&wrapper, data_ticket.index as u32)?.to_vec();
+        if let Some(slot) = OverflowSlot::from_bytes(&bytes) {
+            bytes = self.read_overflow_chain(&slot)?;
         }
 
-        self.pipeline_write_page(&first_page_wrapper.0)?;
+        self.tombstones.push(data_ticket.clone());
+        Ok(bytes)
+    }
 
-        self.page_count -= pages.len() as u32;
+    // Free the tickets sharing a page together, every page is read
+    // and written once. Nothing is freed if any of them is not found.
+    // In a write transaction, the slots are released when committing,
+    // the same as free_data_ticket().
+    pub(crate) fn free_data_tickets(&mut self, data_tickets: &[DataTicket]) -> DbResult<()> {
+        if self.transaction_type() != Some(TransactionType::Write) {
+            return self.release_data_tickets(data_tickets);
+        }
+
+        for data_ticket in data_tickets {
+            let is_tombstoned = self.tombstones.iter()
+                .any(|ticket| ticket.pid == data_ticket.pid && ticket.index == data_ticket.index);
+            if is_tombstoned {
+                return Err(DbErr::DataTicketNotFound {
+                    pid: data_ticket.pid,
+                    index: data_ticket.index,
+                });
+            }
+        }
+        self.read_data_pages_of_tickets(data_tickets)?;
 
+        self.tombstones.extend_from_slice(data_tickets);
         Ok(())
     }
 
-    pub fn is_journal_full(&self) -> bool {
-        self.journal_manager.len() >= 1000
+    // the pages of the tickets grouped by pid, with the indexes of the slots,
+    // every slot must be occupied, and appear once
+    fn read_data_pages_of_tickets(&mut self, data_tickets: &[DataTicket]) -> DbResult<Vec<(DataPageWrapper, Vec<u32>)>> {
+        let mut groups: BTreeMap<u32, BTreeSet<u16>> = BTreeMap::new();
+        for data_ticket in data_tickets {
+            if !groups.entry(data_ticket.pid).or_default().insert(data_ticket.index) {
+                return Err(DbErr::DataTicketNotFound {
+                    pid: data_ticket.pid,
+                    index: data_ticket.index,
+                });
+            }
+        }
+
+        let mut result = Vec::with_capacity(groups.len());
+        for (pid, indexes) in groups {
+            let indexes: Vec<u32> = indexes.into_iter().map(|index| index as u32).collect();
+            let wrapper = self.read_occupied_data_page(pid, &indexes)?;
+            result.push((wrapper, indexes));
+        }
+        Ok(result)
     }
 
-    pub fn checkpoint_journal(&mut self) -> DbResult<()> {
-        self.journal_manager.checkpoint_journal(&mut self.file)
+    // the tickets of the items on the data page,
+    // to rebuild the indexes from the pages
+    #[allow(dead_code)]
+    pub(crate) fn tickets_on_page(&mut self, pid: u32) -> DbResult<Vec<DataTicket>> {
+        let wrapper = self.read_data_page_checked(pid)?;
+        let tickets = wrapper.occupied_indices()
+            .into_iter()
+            .map(|index| DataTicket {
+                pid,
+                index: index as u16,
+            })
+            .collect();
+
+        Ok(tickets)
     }
 
-    fn try_get_free_page_id(&mut self) -> DbResult<Option<u32>> {
-        let first_page = self.get_first_page()?;
-        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+    // fails with NotADataPage if the magic is not a data page
+    fn read_data_page_checked(&mut self, pid: u32) -> DbResult<DataPageWrapper> {
+        let page = self.pipeline_read_page(pid)?;
 
-        let free_list_size = first_page_wrapper.get_free_list_size();
-        if free_list_size == 0 {
-            return Ok(None);
+        let mut magic: [u8; 2] = [0; 2];
+        magic.copy_from_slice(&page.data[0..2]);
+        if !matches!(PageType::from_magic(magic), Ok(PageType::Data)) {
+            return Err(DbErr::NotADataPage(pid));
         }
 
-        let result = first_page_wrapper.get_free_list_content(free_list_size - 1);
-        first_page_wrapper.set_free_list_size(free_list_size - 1);
+        Ok(DataPageWrapper::from_raw(page))
+    }
 
-        self.pipeline_write_page(&first_page_wrapper.0)?;
+    // the page of the tickets, if the slots are occupied,
+    // a freed ticket is an error instead of a panic
+    fn read_occupied_data_page(&mut self, pid: u32, indexes: &[u32]) -> DbResult<DataPageWrapper> {
+        let wrapper = self.read_data_page_checked(pid)?;
+        for index in indexes {
+            if *index >= wrapper.bar_len() || wrapper.get(*index).is_none() {
+                return Err(DbErr::DataTicketNotFound {
+                    pid,
+                    index: *index as u16,
+                });
+            }
+        }
 
-        Ok(Some(result))
+        Ok(wrapper)
     }
 
     #[inline]
-    pub fn get_first_page(&mut self) -> Result<RawPage, DbErr> {
-        self.pipeline_read_page(0)
+    fn release_tombstones(&mut self) -> DbResult<()> {
+        let tombstones = std::mem::take(&mut self.tombstones);
+        self.release_data_tickets(&tombstones)
     }
 
-    pub fn alloc_page_id(&mut self) -> DbResult<u32> {
-        let page_id = match self.try_get_free_page_id()? {
-            Some(page_id) =>  {
+    // the slots are checked by read_occupied_data_page()
+    fn occupied_slot(wrapper: &DataPageWrapper, index: u32) -> DbResult<&[u8]> {
+        wrapper.get(index).ok_or_else(|| {
+            DbErr::Internal(format!("slot {} of page {} is not occupied", index, wrapper.pid()))
+        })
+    }
 
-                #[cfg(feature = "log")]
-                eprintln!("get new page_id from free list: {}", page_id);
+    fn release_data_tickets(&mut self, data_tickets: &[DataTicket]) -> DbResult<()> {
+        let pages = self.read_data_pages_of_tickets(data_tickets)?;
 
-                Ok(page_id)
+        let mut freed_pids = vec![];
+        for (mut wrapper, indexes) in pages {
+            let pid = wrapper.pid();
+            for index in indexes {
+                if let Some(slot) = OverflowSlot::from_bytes(PageHandler::occupied_slot(&wrapper, index)?) {
+                    freed_pids.extend(self.collect_overflow_chain(&slot)?);
+                }
+                wrapper.remove(index);
             }
 
-            None =>  {
-                self.actual_alloc_page_id()
+            // compact without updating the tickets
+            wrapper.truncate_removed_tail();
+
+            if wrapper.is_empty() && self.reuse_empty_data_pages {
+                self.reuse_empty_data_page(pid)?;
+                continue;
             }
-        }?;
 
-        self.page_count += 1;
-        Ok(page_id)
+            if wrapper.is_empty() {
+                freed_pids.push(pid);
+            }
+            let page = wrapper.consume_page();
+            self.pipeline_write_page(&page)?;
+        }
+
+        // write before freeing, a freed page may become a free list page
+        if !freed_pids.is_empty() {
+            self.free_pages(&freed_pids)?;
+        }
+
+        Ok(())
     }
 
-    fn actual_alloc_page_id(&mut self) -> DbResult<u32> {
-        let first_page = self.get_first_page()?;
-        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+    pub(crate) fn release_data_ticket(&mut self, data_ticket: &DataTicket) -> DbResult<Vec<u8>> {
+        let mut wrapper = self.read_occupied_data_page(data_ticket.pid, &[data_ticket.index as u32])?;
+        let mut bytes = PageHandler::occupied_slot(&wrapper, data_ticket.index as u32)?.to_vec();
 
-        let null_page_bar = first_page_wrapper.get_null_page_bar();
-        first_page_wrapper.set_null_page_bar(null_page_bar + 1);
+        if let Some(slot) = OverflowSlot::from_bytes(&bytes) {
+            bytes = self.read_overflow_chain(&slot)?;
+            self.free_overflow_chain(&slot)?;
+        }
 
-        if (null_page_bar as u64) >= self.last_commit_db_size {  // truncate file
-            let expected_size = self.last_commit_db_size + (DB_INIT_BLOCK_COUNT * self.page_size) as u64;
+        wrapper.remove(data_ticket.index as u32);
 
-            self.last_commit_db_size = expected_size;
+        // compact without updating the tickets
+        wrapper.truncate_removed_tail();
+
+        if wrapper.is_empty() && self.reuse_empty_data_pages {
+            self.reuse_empty_data_page(data_ticket.pid)?;
+            return Ok(bytes);
         }
 
-        self.pipeline_write_page(&first_page_wrapper.0)?;
+        let is_empty = wrapper.is_empty();
+        let page = wrapper.consume_page();
+        self.pipeline_write_page(&page)?;
 
-        #[cfg(feature = "log")]
-        eprintln!("alloc new page_id : {}", null_page_bar);
+        // write before freeing, a freed page may become a free list page
+        if is_empty {
+            self.free_page(data_ticket.pid)?;
+        }
 
-        Ok(null_page_bar)
+        Ok(bytes)
     }
 
-    #[inline]
-    pub fn journal_file_path(&self) -> &Path {
-        self.journal_manager.path()
+    // All the tickets of the page are released, so the bars are reset.
+    // The old entries of the page are removed before it's returned
+    // with the full remaining size, a page is in the map once.
+    fn reuse_empty_data_page(&mut self, pid: u32) -> DbResult<()> {
+        let wrapper = DataPageWrapper::init(pid, self.page_size);
+        self.pipeline_write_page(wrapper.borrow_page())?;
+
+        self.remove_from_data_page_map(pid);
+        self.return_data_page_wrapper(wrapper);
+
+        Ok(())
     }
 
-    #[inline]
-    pub fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
-        self.journal_manager.start_transaction(ty)
+    fn remove_from_data_page_map(&mut self, pid: u32) {
+        self.data_page_map.retain(|_, pids| {
+            pids.retain(|item| *item != pid);
+            !pids.is_empty()
+        });
     }
 
-    #[inline]
-    pub fn transaction_type(&mut self) -> Option<TransactionType> {
-        self.journal_manager.transaction_type()
+    // Move a document to another data page, and free the old slot
+    // as free_data_ticket() does.
+    // The source page is taken out of the candidates,
+    // so it's neither the destination nor refilled later.
+    #[allow(dead_code)]
+    pub(crate) fn relocate_doc(&mut self, data_ticket: &DataTicket) -> DbResult<DataTicket> {
+        self.check_writable()?;
+
+        let bytes = match self.get_raw_from_ticket(data_ticket)? {
+            Some(bytes) => bytes,
+            None => return Err(DbErr::DataTicketNotFound {
+                pid: data_ticket.pid,
+                index: data_ticket.index,
+            }),
+        };
+
+        self.remove_from_data_page_map(data_ticket.pid);
+        let new_ticket = self.store_bytes(&bytes)?;
+        self.free_data_ticket(data_ticket)?;
+
+        Ok(new_ticket)
     }
 
     #[inline]
-    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
-        self.journal_manager.upgrade_read_transaction_to_write()
+    pub fn set_reuse_empty_data_pages(&mut self, reuse: bool) {
+        self.reuse_empty_data_pages = reuse;
     }
 
+    // A freed page keeps the old content until it's reused.
+    // If it's set, every freed page is written as zeros through the journal,
+    // freeing costs a page write per page, as much as writing it.
     #[inline]
-    pub fn set_transaction_state(&mut self, state: TransactionState) {
-        self.transaction_state = state;
+    pub fn set_zero_freed_pages(&mut self, zero: bool) {
+        self.zero_freed_pages = zero;
     }
 
-    pub fn commit(&mut self) -> DbResult<()> {
-        self.journal_manager.commit()?;
-        if self.is_journal_full() {
-            self.checkpoint_journal()?;
-            #[cfg(feature = "log")]
-            eprintln!("checkpoint journal finished");
+    // The empty data pages kept for reuse are in the memory only,
+    // they are not referred by any ticket after reopening,
+    // so all the empty data pages not in the free list are freed.
+    fn free_empty_data_pages(&mut self) -> DbResult<u32> {
+        let first_page = self.get_first_page()?;
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let null_page_bar = first_page_wrapper.get_null_page_bar();
+        let free_pids = self.collect_free_pids(&first_page_wrapper)?;
+
+        let reserved_pids = first_page_wrapper.get_reserved_pids();
+
+        let mut empty_pids = vec![];
+        for pid in 1..null_page_bar {
+            if free_pids.contains(&pid) || reserved_pids.contains(&pid) {
+                continue;
+            }
+            match self.read_data_page_checked(pid) {
+                Ok(wrapper) if wrapper.is_empty() => empty_pids.push(pid),
+                Ok(_) | Err(DbErr::NotADataPage(_)) => (),
+                Err(err) => return Err(err),
+            }
         }
-        Ok(())
+
+        if empty_pids.is_empty() {
+            return Ok(0);
+        }
+
+        self.free_pages(&empty_pids)
     }
 
-    // after the rollback
-    // all the cache are wrong
-    // cleat it
-    pub fn rollback(&mut self) -> DbResult<()> {
-        self.journal_manager.rollback()?;
-        self.page_cache = Box::new(PageCache::new_default(self.page_size));
-        Ok(())
+    // A page allocated for the metadata of the higher layers,
+    // it's recorded in the header, so it's kept across reopening,
+    // and it can't be freed until it's released.
+    #[allow(dead_code)]
+    pub fn reserve_page(&mut self) -> DbResult<u32> {
+        self.check_writable()?;
+        let first_page = self.get_first_page()?;
+        if HeaderPageWrapper::from_raw_page(first_page).get_reserved_pids().len() >= MAX_RESERVED_PAGES {
+            return Err(DbErr::ReservedPagesFull(MAX_RESERVED_PAGES as u32));
+        }
+
+        let pid = self.alloc_page_id()?;
+
+        // the header is changed by the allocation
+        let first_page = self.get_first_page()?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let mut reserved_pids = first_page_wrapper.get_reserved_pids();
+        reserved_pids.push(pid);
+        first_page_wrapper.set_reserved_pids(&reserved_pids);
+        self.pipeline_write_page(&first_page_wrapper.0)?;
+
+        Ok(pid)
+    }
+
+    // the page is no longer reserved, and it's freed
+    #[allow(dead_code)]
+    pub fn release_reserved_page(&mut self, pid: u32) -> DbResult<()> {
+        self.check_writable()?;
+        let first_page = self.get_first_page()?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let mut reserved_pids = first_page_wrapper.get_reserved_pids();
+        let index = match reserved_pids.iter().position(|reserved| *reserved == pid) {
+            Some(index) => index,
+            None => return Err(DbErr::PageNotReserved(pid)),
+        };
+
+        reserved_pids.remove(index);
+        first_page_wrapper.set_reserved_pids(&reserved_pids);
+        self.pipeline_write_page(&first_page_wrapper.0)?;
+
+        self.free_page(pid)?;
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn reserved_pages(&mut self) -> DbResult<Vec<u32>> {
+        let first_page = self.get_first_page()?;
+        Ok(HeaderPageWrapper::from_raw_page(first_page).get_reserved_pids())
+    }
+
+    #[inline]
+    pub fn free_page(&mut self, pid: u32) -> DbResult<u32> {
+        self.free_pages(&[pid])
+    }
+
+    // returns the count of the pages added to the free list,
+    // a pid repeated in the batch is freed once
+    pub fn free_pages(&mut self, pages: &[u32]) -> DbResult<u32> {
+        self.check_writable()?;
+
+        let mut seen = BTreeSet::new();
+        let pages: Vec<u32> = pages.iter().copied().filter(|pid| seen.insert(*pid)).collect();
+
+        #[cfg(feature = "log")]
+        for pid in &pages {
+            eprintln!("free page, id: {}", *pid);
+        }
+
+        let first_page = self.pipeline_read_page(0)?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+
+        // validate the whole batch before any mutation,
+        // a page freed twice would be handed out twice
+        let null_page_bar = first_page_wrapper.get_null_page_bar();
+        let reserved_pids = first_page_wrapper.get_reserved_pids();
+        for pid in &pages {
+            if *pid == 0 {
+                return Err(DbErr::CannotFreeHeaderPage);
+            }
+            if reserved_pids.contains(pid) {
+                return Err(DbErr::PageReserved(*pid));
+            }
+            if *pid >= null_page_bar {
+                return Err(DbErr::PageIdOutOfRange { pid: *pid, max: null_page_bar - 1 });
+            }
+        }
+        if let Some(pid) = self.walk_free_list(&first_page_wrapper)?.into_iter().find(|pid| seen.contains(pid)) {
+            return Err(DbErr::PageAlreadyFree(pid));
+        }
+        let freed_count = pages.len() as u32;
+        if freed_count > self.page_count {
+            return Err(DbErr::Internal(format!("freeing {} pages of {}", freed_count, self.page_count)));
+        }
+        let pages = pages.as_slice();
+
+        if self.zero_freed_pages {
+            for pid in pages {
+                self.pipeline_write_page(&RawPage::new(*pid, self.page_size))?;
+            }
+        }
+        self.freed_in_transaction.extend_from_slice(pages);
+
+        match self.config.allocation_order {
+            AllocationOrder::Lifo => {
+                let current_size = first_page_wrapper.get_free_list_size();
+                let header_remain = HeaderPageWrapper::free_list_max_size(self.page_size).saturating_sub(current_size as usize);
+                let (header_pages, spilled_pages) = pages.split_at(std::cmp::min(header_remain, pages.len()));
+
+                first_page_wrapper.set_free_list_size(current_size + (header_pages.len() as u32));
+                for (counter, pid) in header_pages.iter().enumerate() {
+                    first_page_wrapper.set_free_list_content(current_size + (counter as u32), *pid);
+                }
+
+                if !spilled_pages.is_empty() {
+                    self.spill_free_pages(&mut first_page_wrapper, spilled_pages)?;
+                }
+            }
+
+            AllocationOrder::LowestFirst => {
+                self.push_free_pages_sorted(&mut first_page_wrapper, pages)?;
+            }
+
+        }
+
+        self.pipeline_write_page(&first_page_wrapper.0)?;
+
+        self.page_count -= freed_count;
+
+        Ok(freed_count)
+    }
+
+    // The header free list is kept in descending order,
+    // the tail popped by the allocator is the smallest pid.
+    // When it's full, the largest pids are spilled to the free list pages.
+    fn push_free_pages_sorted(&mut self, first_page_wrapper: &mut HeaderPageWrapper, pages: &[u32]) -> DbResult<()> {
+        let current_size = first_page_wrapper.get_free_list_size();
+        let mut free_list: Vec<u32> = (0..current_size)
+            .map(|index| first_page_wrapper.get_free_list_content(index))
+            .collect();
+        free_list.extend_from_slice(pages);
+        free_list.sort_unstable_by(|a, b| b.cmp(a));
+
+        let spilled_count = free_list.len().saturating_sub(HeaderPageWrapper::free_list_max_size(self.page_size));
+        let (spilled_pages, header_pages) = free_list.split_at(spilled_count);
+
+        first_page_wrapper.set_free_list_size(header_pages.len() as u32);
+        for (index, pid) in header_pages.iter().enumerate() {
+            first_page_wrapper.set_free_list_content(index as u32, *pid);
+        }
+
+        if !spilled_pages.is_empty() {
+            self.spill_free_pages(first_page_wrapper, spilled_pages)?;
+        }
+
+        Ok(())
+    }
+
+    // The header free list is full, push the pids to the free list pages.
+    // When the head of the chain is full, the freed page itself
+    // becomes the new head, so no allocation is needed.
+    fn spill_free_pages(&mut self, first_page_wrapper: &mut HeaderPageWrapper, pages: &[u32]) -> DbResult<()> {
+        let mut free_list_page = match first_page_wrapper.get_free_list_page_id() {
+            0 => None,
+            pid => {
+                let page = self.pipeline_read_page(pid)?;
+                Some(FreeListPageWrapper::from_raw_page(page))
+            }
+        };
+
+        for pid in pages {
+            match &mut free_list_page {
+                Some(wrapper) if !wrapper.is_full() => wrapper.push(*pid),
+
+                _ => {
+                    if let Some(wrapper) = free_list_page.take() {
+                        self.pipeline_write_page(&wrapper.0)?;
+                    }
+
+                    let mut wrapper = FreeListPageWrapper::init(*pid, self.page_size);
+                    wrapper.set_next_pid(first_page_wrapper.get_free_list_page_id());
+                    first_page_wrapper.set_free_list_page_id(*pid);
+                    free_list_page = Some(wrapper);
+                }
+            }
+        }
+
+        if let Some(wrapper) = free_list_page {
+            self.pipeline_write_page(&wrapper.0)?;
+        }
+
+        Ok(())
+    }
+
+    // Relocate the live documents into densely packed pages,
+    // then truncate the free pages at the tail of the file.
+    //
+    // The documents are relocated in a transaction,
+    // a crash in the middle is rolled back when reopening.
+    // It can't join the transaction of the user, which may be rolled back.
+    pub fn vacuum(&mut self) -> DbResult<VacuumStats> {
+        if self.transaction_type().is_some() {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+
+        let origin_file_size = self.file.len()?;
+
+        self.start_transaction(TransactionType::Write)?;
+
+        // put the documents to the new pages only
+        self.data_page_map.clear();
+
+        // the pages emptied by relocating are freed
+        let reuse_empty_data_pages = std::mem::replace(&mut self.reuse_empty_data_pages, false);
+        let result = self.free_empty_data_pages().and_then(|empty_pages_freed| {
+            let mut stats = vacuum::relocate_all_docs(self)?;
+            stats.pages_freed += empty_pages_freed;
+            self.trim_free_tail()?;
+            Ok(stats)
+        });
+        self.reuse_empty_data_pages = reuse_empty_data_pages;
+        let mut stats = match result {
+            Ok(stats) => stats,
+            Err(err) => {
+                self.rollback()?;
+                return Err(err);
+            }
+        };
+        self.commit()?;
+        self.checkpoint()?;
+
+        let file_size = self.shrink_file_to_null_page_bar()?;
+        stats.bytes_reclaimed = origin_file_size.saturating_sub(file_size);
+
+        Ok(stats)
+    }
+
+    // The largest pid allocated, the one below the null page bar,
+    // it doesn't fall when the pages are freed, only truncating moves it down.
+    // Read through the pipeline, the allocations of the transaction are counted.
+    pub fn high_water_pid(&mut self) -> DbResult<u32> {
+        let first_page = self.get_first_page()?;
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+        Ok(null_page_bar.saturating_sub(1))
+    }
+
+    // Remove the free pages at the tail from the free list,
+    // and return the space to the OS, the new size of the file is returned.
+    // Nothing is changed if the last page is in use.
+    pub fn truncate_to_high_water(&mut self) -> DbResult<u64> {
+        if self.transaction_type().is_some() {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+
+        self.start_transaction(TransactionType::Write)?;
+        match self.trim_free_tail() {
+            Ok(true) => self.commit()?,
+            Ok(false) => {
+                self.rollback()?;
+                return Ok(self.file.len()?);
+            }
+            Err(err) => {
+                self.rollback()?;
+                return Err(err);
+            }
+        }
+        self.checkpoint()?;
+
+        self.shrink_file_to_null_page_bar()
+    }
+
+    // after checkpoint, the pages after the null page bar are not used
+    fn shrink_file_to_null_page_bar(&mut self) -> DbResult<u64> {
+        let first_page = self.get_first_page()?;
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+        let expected_size = self.page_layout.offset_of(std::cmp::max(null_page_bar, self.config.init_block_count));
+        let file_size = self.file.len()?;
+        if expected_size < file_size {
+            self.file.set_len(expected_size)?;
+            self.last_commit_db_size = expected_size;
+            return Ok(expected_size);
+        }
+
+        Ok(file_size)
+    }
+
+    // Only the header in the main file is updated,
+    // the one in the cache and the journal is untouched.
+    fn mark_journal_in_use(&mut self, in_use: bool) -> DbResult<()> {
+        let first_page = self.page_layout.read_page(0, self.file.as_ref())?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        if first_page_wrapper.is_journal_in_use() == in_use
+            && first_page_wrapper.is_journal_external() == self.journal_external {
+            return Ok(());
+        }
+
+        first_page_wrapper.set_journal_in_use(in_use);
+        first_page_wrapper.set_journal_external(self.journal_external);
+        let sync = self.durability_mode == DurabilityMode::FullSync;
+        first_page_wrapper.write_to_store(self.page_layout, self.file.as_mut(), sync)?;
+        self.file.flush()?;
+        if self.durability_mode == DurabilityMode::FullSync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    // the pages in the main file or in the journal,
+    // a page beyond them can not be read
+    pub(crate) fn stored_page_count(&self) -> DbResult<u32> {
+        let file_pages = (self.file.len()? / (self.page_layout.physical_size() as u64)) as u32;
+        let journal_pages = self.read_journal()
+            .and_then(|journal_manager| journal_manager.max_page_id())
+            .map_or(0, |page_id| page_id + 1);
+        Ok(file_pages.max(journal_pages))
+    }
+
+    // all the pids in the free list, including the free list pages
+    #[inline]
+    pub(crate) fn collect_free_pids(&mut self, first_page_wrapper: &HeaderPageWrapper) -> DbResult<BTreeSet<u32>> {
+        let free_list = self.walk_free_list(first_page_wrapper)?;
+        Ok(free_list.into_iter().collect())
+    }
+
+    // The free pids in the order of the list, nothing is written.
+    // The pids in the header come first, then the chain from its head,
+    // every free list page is followed by the pids it holds.
+    pub fn collect_free_list(&mut self) -> DbResult<Vec<u32>> {
+        let first_page = self.get_first_page()?;
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        self.walk_free_list(&first_page_wrapper)
+    }
+
+    // a size out of the page, a loop or a page of another type
+    // fails with BrokenFreeList instead of panicking
+    fn walk_free_list(&mut self, first_page_wrapper: &HeaderPageWrapper) -> DbResult<Vec<u32>> {
+        let free_list_size = PageHandler::header_free_list_size(first_page_wrapper, self.page_size)?;
+        let mut result: Vec<u32> = (0..free_list_size)
+            .map(|index| first_page_wrapper.get_free_list_content(index))
+            .collect();
+
+        PageHandler::walk_free_list_pages(
+            first_page_wrapper,
+            |pid| self.pipeline_read_page(pid),
+            |pid, wrapper| {
+                result.push(pid);
+                result.extend((0..wrapper.size()).map(|index| wrapper.get_content(index)));
+            },
+        )?;
+
+        Ok(result)
+    }
+
+    #[inline]
+    fn header_free_list_size(first_page_wrapper: &HeaderPageWrapper, page_size: u32) -> DbResult<u32> {
+        let free_list_size = first_page_wrapper.get_free_list_size();
+        if free_list_size as usize > HeaderPageWrapper::free_list_max_size(page_size) {
+            return Err(DbErr::BrokenFreeList(0));
+        }
+        Ok(free_list_size)
+    }
+
+    // Visit the free list pages from the head of the chain,
+    // the pages are read by read_page, so it's shared by the readers
+    // through the pipeline and the ones peeking without a transaction.
+    fn walk_free_list_pages<R, V>(first_page_wrapper: &HeaderPageWrapper, mut read_page: R, mut visit: V) -> DbResult<()>
+    where
+        R: FnMut(u32) -> DbResult<RawPage>,
+        V: FnMut(u32, &FreeListPageWrapper),
+    {
+        let null_page_bar = first_page_wrapper.get_null_page_bar();
+        let mut visited = BTreeSet::new();
+        let mut free_list_pid = first_page_wrapper.get_free_list_page_id();
+        while free_list_pid != 0 {
+            if free_list_pid >= null_page_bar || !visited.insert(free_list_pid) {
+                return Err(DbErr::BrokenFreeList(free_list_pid));
+            }
+
+            let page = read_page(free_list_pid)?;
+            if page.data[0..2] != PageType::FreeList.to_magic() {
+                return Err(DbErr::BrokenFreeList(free_list_pid));
+            }
+            let wrapper = FreeListPageWrapper::from_raw_page(page);
+            if wrapper.size() > wrapper.capacity() {
+                return Err(DbErr::BrokenFreeList(free_list_pid));
+            }
+
+            visit(free_list_pid, &wrapper);
+            free_list_pid = wrapper.next_pid();
+        }
+
+        Ok(())
+    }
+
+    // move the null page bar down over the free pages at the tail,
+    // and rebuild the free list with the rest
+    // returns false if the last page is in use
+    fn trim_free_tail(&mut self) -> DbResult<bool> {
+        let first_page = self.get_first_page()?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let mut free_pids = self.collect_free_pids(&first_page_wrapper)?;
+
+        let null_page_bar = first_page_wrapper.get_null_page_bar();
+        let mut new_null_page_bar = null_page_bar;
+        while new_null_page_bar > 2 && free_pids.remove(&(new_null_page_bar - 1)) {
+            new_null_page_bar -= 1;
+        }
+
+        if new_null_page_bar == null_page_bar {
+            return Ok(false);
+        }
+
+        first_page_wrapper.set_null_page_bar(new_null_page_bar);
+        first_page_wrapper.set_free_list_size(0);
+        first_page_wrapper.set_free_list_page_id(0);
+        self.pipeline_write_page(&first_page_wrapper.0)?;
+
+        // descending, the smallest pids are handed out first
+        let rest: Vec<u32> = free_pids.into_iter().rev().collect();
+        if !rest.is_empty() {
+            self.page_count += rest.len() as u32;  // they are counted when freed
+            self.free_pages(&rest)?;
+        }
+
+        Ok(true)
+    }
+
+    pub fn is_journal_full(&self) -> bool {
+        match &self.journal_manager {
+            Some(journal_manager) => journal_manager.len() >= self.journal_checkpoint_threshold,
+            None => false,
+        }
+    }
+
+    // the frames in the journal, checkpointed by commit when it reaches the threshold
+    pub fn journal_len(&self) -> usize {
+        match &self.journal_manager {
+            Some(journal_manager) => journal_manager.len() as usize,
+            None => 0,
+        }
+    }
+
+    #[inline]
+    pub fn journal_checkpoint_threshold(&self) -> u32 {
+        self.journal_checkpoint_threshold
+    }
+
+    // 0 is rejected, it would checkpoint on every commit
+    pub fn set_journal_checkpoint_threshold(&mut self, pages: u32) -> DbResult<()> {
+        if pages == 0 {
+            return Err(DbErr::InvalidCheckpointThreshold(pages));
+        }
+        self.journal_checkpoint_threshold = pages;
+        Ok(())
+    }
+
+    pub fn set_durability_mode(&mut self, mode: DurabilityMode) {
+        self.durability_mode = mode;
+        if let Some(journal_manager) = self.journal_manager.as_mut() {
+            journal_manager.set_durability_mode(mode);
+        }
+    }
+
+    #[inline]
+    pub fn durability_mode(&self) -> DurabilityMode {
+        self.durability_mode
+    }
+
+    // Sync the journal without checkpointing,
+    // the main file and the free list are untouched.
+    // The frames of the transaction in progress are synced too,
+    // they are still discarded if it's not committed.
+    pub fn flush_journal(&mut self) -> DbResult<()> {
+        self.journal_mut()?.sync()
+    }
+
+    // the frames written before are kept as they are
+    pub fn set_journal_mode(&mut self, mode: JournalMode) {
+        self.journal_mode = mode;
+        if let Some(journal_manager) = self.journal_manager.as_mut() {
+            journal_manager.set_journal_mode(mode);
+        }
+    }
+
+    #[inline]
+    pub fn journal_mode(&self) -> JournalMode {
+        self.journal_mode
+    }
+
+    // Write all the frames of the journal to the main file and truncate the journal,
+    // regardless of the length of the journal.
+    // It's also called by commit when the journal is full.
+    pub fn checkpoint(&mut self) -> DbResult<()> {
+        if self.checkpoint_in_progress {
+            return Err(DbErr::CheckpointInProgress);
+        }
+        if self.transaction_type().is_some() {
+            return Err(DbErr::CheckpointDuringTransaction);
+        }
+
+        self.checkpoint_in_progress = true;
+        let result = self.checkpoint_without_guard();
+        self.checkpoint_in_progress = false;
+        result
+    }
+
+    // Commit the automatic transaction, checkpoint the journal and sync the main file.
+    // Nothing is done when it's dropped, the errors are returned here instead.
+    // The journal is empty if it returns Ok, so it's safe to delete.
+    #[allow(dead_code)]
+    pub fn close(mut self) -> DbResult<()> {
+        self.auto_commit()?;
+        if self.is_read_only() {
+            return Ok(());
+        }
+
+        self.checkpoint()?;
+
+        self.file.flush()?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    // Swap the database at other_path in place of this one, and reopen it.
+    // Both are checkpointed and closed at first, so their journals carry nothing.
+    // The rename of the main file is the commit point, a crash before it leaves
+    // the old database, after it the new one, the sidecars are replaced after it.
+    //
+    // The sidecars of this database are removed before the rename,
+    // the stale ones never sit beside the new main file.
+    // An encrypted database can't be reopened without the key, it's rejected.
+    //
+    // A failure before this database is closed leaves the handler as it is.
+    // After it, the handler is reopened over the path, whichever database is in it,
+    // and the error of the swap is returned.
+    #[allow(dead_code)]
+    pub fn replace_with(&mut self, other_path: &Path) -> DbResult<()> {
+        self.check_writable()?;
+        let path = match self.db_path.clone() {
+            Some(path) => path,
+            None => return Err(DbErr::NoDatabaseFile),
+        };
+        if HeaderPageWrapper::from_raw_page(self.get_first_page()?).is_encrypted() {
+            return Err(DbErr::CannotReplaceEncrypted);
+        }
+
+        let page_size = self.page_layout.physical_size();
+        let config = self.config.clone();
+        let reopen = |path: &Path| PageHandler::open_file(
+            path, None, page_size, config.clone(), None,
+            OpenMode::OpenExisting, &mut migration::registered_migrations(),
+        );
+
+        // the other database is complete in its main file after it's closed
+        reopen(other_path)?.close()?;
+
+        // the files are released, the handler holds an empty database in memory
+        let placeholder = PageHandler::new_in_memory(page_size)?;
+        if let Err(err) = std::mem::replace(self, placeholder).close() {
+            *self = reopen(&path)?;
+            return Err(err);
+        }
+
+        let swapped = PageHandler::swap_main_file(other_path, &path);
+        *self = reopen(&path)?;
+        swapped
+    }
+
+    // the rename of the main file is the commit point
+    fn swap_main_file(other_path: &Path, path: &Path) -> DbResult<()> {
+        let sidecars: [fn(&Path) -> PathBuf; 3] = [
+            PageHandler::mk_journal_path,
+            IntentLog::mk_path,
+            ChangeMap::mk_path,
+        ];
+        for mk_path in &sidecars {
+            PageHandler::remove_if_exists(&mk_path(path))?;
+        }
+
+        std::fs::rename(other_path, path)?;
+        PageHandler::sync_parent_dir(path)?;
+
+        for mk_path in &sidecars {
+            let other_sidecar = mk_path(other_path);
+            if other_sidecar.exists() {
+                std::fs::rename(&other_sidecar, mk_path(path))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_if_exists(path: &Path) -> DbResult<()> {
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // the rename is durable once the directory is synced,
+    // a directory can't be opened as a file on Windows
+    #[cfg(not(target_os = "windows"))]
+    fn sync_parent_dir(path: &Path) -> DbResult<()> {
+        if let Some(parent) = path.parent() {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn sync_parent_dir(_path: &Path) -> DbResult<()> {
+        Ok(())
+    }
+
+    fn checkpoint_without_guard(&mut self) -> DbResult<()> {
+        if self.config.coalesce_free_list_on_checkpoint {
+            self.coalesce_free_list()?;
+        }
+        let journal_manager = match self.journal_manager.as_mut() {
+            Some(journal_manager) => journal_manager,
+            None => return Err(DbErr::ReadOnly),
+        };
+        let main_file_pages = journal_manager.checkpoint_journal(self.file.as_mut())?;
+        let journal_on_disk = journal_manager.path().is_some();
+        self.write_stats.record_checkpoint(main_file_pages);
+
+        if journal_on_disk {
+            self.mark_journal_in_use(false)?;
+        }
+
+        // preallocate the rest of the block
+        if self.file.len()? < self.last_commit_db_size {
+            self.file.set_len(self.last_commit_db_size)?;
+        }
+
+        Ok(())
+    }
+
+    // return false if the pids are in descending order already
+    fn sort_descending(pids: &mut [u32]) -> bool {
+        if pids.windows(2).all(|pair| pair[0] >= pair[1]) {
+            return false;
+        }
+        pids.sort_unstable_by(|a, b| b.cmp(a));
+        true
+    }
+
+    // Sort the free list in descending order, because the allocator
+    // pops from the tail, the smallest pids are handed out first,
+    // and continuous pids are handed out as a run.
+    // The pages spilled to the chain are popped the same way,
+    // each of them is sorted as well.
+    //
+    // Writes are quiesced when checkpointing,
+    // so the new order is committed as a standalone transaction.
+    fn coalesce_free_list(&mut self) -> DbResult<()> {
+        let first_page = self.get_first_page()?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let mut changed_pages = vec![];
+
+        let free_list_size = first_page_wrapper.get_free_list_size();
+        let mut free_list: Vec<u32> = (0..free_list_size)
+            .map(|index| first_page_wrapper.get_free_list_content(index))
+            .collect();
+        let header_changed = PageHandler::sort_descending(&mut free_list);
+        for (index, pid) in free_list.iter().enumerate() {
+            first_page_wrapper.set_free_list_content(index as u32, *pid);
+        }
+
+        let mut free_list_pid = first_page_wrapper.get_free_list_page_id();
+        while free_list_pid != 0 {
+            let page = self.pipeline_read_page(free_list_pid)?;
+            let mut free_list_page = FreeListPageWrapper::from_raw_page(page);
+            let mut entries: Vec<u32> = (0..free_list_page.size())
+                .map(|index| free_list_page.get_content(index))
+                .collect();
+            free_list_pid = free_list_page.next_pid();
+            if PageHandler::sort_descending(&mut entries) {
+                for (index, pid) in entries.iter().enumerate() {
+                    free_list_page.set_content(index as u32, *pid);
+                }
+                changed_pages.push(free_list_page.0);
+            }
+        }
+        if header_changed {
+            changed_pages.push(first_page_wrapper.0);
+        }
+
+        if changed_pages.is_empty() {
+            return Ok(());
+        }
+
+        self.begin_transaction(TransactionType::Write)?;
+        let result = changed_pages.iter().try_for_each(|page| self.pipeline_write_page(page));
+        match result {
+            Ok(()) => self.commit_transaction(),
+            Err(err) => {
+                self.rollback_transaction()?;
+                Err(err)
+            }
+        }
+    }
+
+    // Nothing of the free list is read when opening,
+    // the chain is loaded one page at a time when it's popped,
+    // the pages behind the head are not read until they become the head.
+    fn try_get_free_page_id(&mut self) -> DbResult<Option<u32>> {
+        let first_page = self.get_first_page()?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+
+        let free_list_pid = first_page_wrapper.get_free_list_page_id();
+        let free_list_size = first_page_wrapper.get_free_list_size();
+
+        // the pids spilled to the chain are larger than the ones in the header
+        let header_first = self.config.allocation_order == AllocationOrder::LowestFirst && free_list_size > 0;
+        if free_list_pid != 0 && !header_first {
+            return self.try_get_free_page_id_from_free_list_page(first_page_wrapper, free_list_pid);
+        }
+
+        if free_list_size == 0 {
+            return Ok(None);
+        }
+
+        let result = first_page_wrapper.read_free_list_content(free_list_size - 1)
+            .map_err(|_| DbErr::BrokenFreeList(0))?;
+        first_page_wrapper.set_free_list_size(free_list_size - 1);
+
+        self.pipeline_write_page(&first_page_wrapper.0)?;
+
+        Ok(Some(result))
+    }
+
+    // pop from the head of the chain,
+    // the head page itself is reclaimed once it's empty
+    //
+    // If the header free list has enough room for the entries of the head page,
+    // they are merged back into the header, and the head page is handed out,
+    // so a sparse free list page doesn't stay allocated.
+    fn try_get_free_page_id_from_free_list_page(&mut self, mut first_page_wrapper: HeaderPageWrapper, free_list_pid: u32) -> DbResult<Option<u32>> {
+        let page = self.pipeline_read_page(free_list_pid)?;
+        let mut free_list_page = FreeListPageWrapper::from_raw_page(page);
+
+        let header_size = first_page_wrapper.get_free_list_size();
+        let header_remain = HeaderPageWrapper::free_list_max_size(self.page_size).saturating_sub(header_size as usize);
+        let page_entries = free_list_page.size();
+        if page_entries > 0 && (page_entries as usize) <= header_remain {
+            let mut entries: Vec<u32> = (0..page_entries)
+                .map(|index| free_list_page.get_content(index))
+                .collect();
+            if self.config.allocation_order == AllocationOrder::LowestFirst {
+                entries.sort_unstable_by(|a, b| b.cmp(a));
+            }
+            for (index, pid) in entries.iter().enumerate() {
+                first_page_wrapper.set_free_list_content(header_size + (index as u32), *pid);
+            }
+            first_page_wrapper.set_free_list_size(header_size + page_entries);
+            first_page_wrapper.set_free_list_page_id(free_list_page.next_pid());
+            self.pipeline_write_page(&first_page_wrapper.0)?;
+            return Ok(Some(free_list_page.pid()));
+        }
+
+        let popped = free_list_page.pop().map_err(|_| DbErr::BrokenFreeList(free_list_pid))?;
+        match popped {
+            Some(pid) => {
+                self.pipeline_write_page(&free_list_page.0)?;
+                Ok(Some(pid))
+            }
+
+            None => {
+                first_page_wrapper.set_free_list_page_id(free_list_page.next_pid());
+                self.pipeline_write_page(&first_page_wrapper.0)?;
+                Ok(Some(free_list_page.pid()))
+            }
+
+        }
+    }
+
+    #[inline]
+    pub fn get_first_page(&mut self) -> Result<RawPage, DbErr> {
+        self.pipeline_read_page(0)
+    }
+
+    pub fn alloc_page_id(&mut self) -> DbResult<u32> {
+        self.check_writable()?;
+        self.check_transaction_alloc_limit(1)?;
+
+        let page_id = match self.try_get_free_page_id()? {
+            Some(page_id) =>  {
+
+                #[cfg(feature = "log")]
+                eprintln!("get new page_id from free list: {}", page_id);
+
+                Ok(page_id)
+            }
+
+            None =>  {
+                self.actual_alloc_page_id()
+            }
+        }?;
+
+        self.page_count += 1;
+        self.transaction_alloc_count += 1;
+        Ok(page_id)
+    }
+
+    // Unlike the alloc limit, the transaction is kept,
+    // the pages written are still readable and it can be rolled back.
+    fn check_transaction_page_limit(&self) -> DbResult<()> {
+        let max = match self.max_transaction_pages {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+
+        let frame_count = self.journal_manager.as_ref()
+            .and_then(|journal_manager| journal_manager.transaction_write_counts())
+            .map_or(0, |(frame_count, _)| frame_count);
+        if frame_count < max {
+            return Ok(());
+        }
+
+        Err(DbErr::TransactionTooLarge(max))
+    }
+
+    #[inline]
+    #[allow(dead_code)]
+    pub fn set_max_transaction_pages(&mut self, max: Option<u32>) {
+        self.max_transaction_pages = max;
+    }
+
+    // The pages beyond the limit are not allocated,
+    // the pages in the free list are still handed out.
+    // A file already larger than it is never shrunk.
+    #[inline]
+    #[allow(dead_code)]
+    pub fn set_max_file_size(&mut self, max: Option<u64>) {
+        self.max_file_size = max;
+    }
+
+    // a safety valve for a single runaway operation,
+    // the transaction is rolled back once the limit is exceeded
+    fn check_transaction_alloc_limit(&mut self, count: u32) -> DbResult<()> {
+        let limit = match self.config.max_alloc_pages_per_txn {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        if self.transaction_alloc_count + count <= limit {
+            return Ok(());
+        }
+
+        if self.transaction_type().is_some() {
+            self.rollback()?;
+        }
+        self.transaction_state = TransactionState::NoTrans;
+
+        Err(DbErr::TransactionAllocLimitExceeded(limit))
+    }
+
+    // Allocate count fresh pages beyond the null page bar at once,
+    // the free list is not used, the header is written once.
+    // The pages are returned by rolling back the transaction.
+    #[allow(dead_code)]
+    pub fn preallocate(&mut self, count: u32) -> DbResult<Vec<u32>> {
+        self.check_writable()?;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        self.check_transaction_alloc_limit(count)?;
+
+        let first_pid = self.actual_alloc_page_ids(count)?;
+
+        self.page_count += count;
+        self.transaction_alloc_count += count;
+        Ok((first_pid..(first_pid + count)).collect())
+    }
+
+    #[inline]
+    fn actual_alloc_page_id(&mut self) -> DbResult<u32> {
+        self.actual_alloc_page_ids(1)
+    }
+
+    // return the first pid of the continuous pages
+    fn actual_alloc_page_ids(&mut self, count: u32) -> DbResult<u32> {
+        let first_page = self.get_first_page()?;
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+
+        let null_page_bar = first_page_wrapper.get_null_page_bar();
+        first_page_wrapper.set_null_page_bar(null_page_bar + count);
+
+        // grow by blocks
+        let mut expected_size = self.last_commit_db_size;
+        while self.page_layout.offset_of(null_page_bar + count) > expected_size {
+            let current_pages = expected_size / (self.page_layout.physical_size() as u64);
+            let grow_pages = self.config.growth_strategy.grow_pages(current_pages as u32);
+            expected_size += self.page_layout.offset_of(grow_pages);
+        }
+
+        // the last block is cut at the limit
+        if let Some(limit) = self.max_file_size {
+            if self.page_layout.offset_of(null_page_bar + count) > limit {
+                return Err(DbErr::FileSizeLimitExceeded { limit });
+            }
+            expected_size = expected_size.min(limit.max(self.last_commit_db_size));
+        }
+
+        // The space is taken when the pages are allocated,
+        // so a full disk fails the allocation instead of the checkpoint.
+        // The pages are written to the file by the checkpoint.
+        let file_size = self.file.len()?;
+        if expected_size > file_size {
+            self.file.set_len(expected_size).map_err(|_| DbErr::OutOfSpace {
+                requested_bytes: expected_size - file_size,
+            })?;
+        }
+        let prev_db_size = self.last_commit_db_size;
+        self.last_commit_db_size = expected_size;
+
+        // a header write refused (read transaction, size limits)
+        // gives the grown space back
+        if let Err(err) = self.pipeline_write_page(&first_page_wrapper.0) {
+            self.last_commit_db_size = prev_db_size;
+            if expected_size > file_size {
+                self.file.set_len(file_size)?;
+            }
+            return Err(err);
+        }
+
+        #[cfg(feature = "log")]
+        eprintln!("alloc new page_id : {}, count: {}", null_page_bar, count);
+
+        Ok(null_page_bar)
+    }
+
+    // None if the database is opened read-only
+    #[inline]
+    pub fn journal_file_path(&self) -> Option<&Path> {
+        self.journal_manager.as_ref().and_then(|journal_manager| journal_manager.path())
+    }
+
+    // bracket a logical operation spanning multiple transactions,
+    // the intent is durable before this function returns
+    #[inline]
+    pub fn begin_op(&mut self, intent: &[u8]) -> DbResult<u64> {
+        self.intent_log_mut()?.begin_op(intent)
+    }
+
+    #[inline]
+    pub fn end_op(&mut self) -> DbResult<()> {
+        self.intent_log_mut()?.end_op()
+    }
+
+    // the operations began but not ended before the last crash
+    #[inline]
+    pub fn incomplete_ops(&self) -> &[IntentRecord] {
+        match &self.intent_log {
+            Some(intent_log) => intent_log.incomplete_ops(),
+            None => &[],
+        }
+    }
+
+    // call it after the operation is replayed or discarded
+    #[inline]
+    pub fn resolve_incomplete_op(&mut self, op_id: u64) -> DbResult<()> {
+        self.intent_log_mut()?.resolve_incomplete_op(op_id)
+    }
+
+    #[inline]
+    pub fn is_intent_log_clean(&self) -> bool {
+        match &self.intent_log {
+            Some(intent_log) => intent_log.is_clean(),
+            None => true,
+        }
+    }
+
+    // None if the database is opened read-only
+    #[inline]
+    pub fn intent_log_path(&self) -> Option<&Path> {
+        self.intent_log.as_ref().and_then(|intent_log| intent_log.path())
+    }
+
+    #[inline]
+    pub fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        self.begin_transaction(ty)?;
+        self.notify_transaction(TransactionEvent::Start, ty);
+        Ok(())
+    }
+
+    fn begin_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        self.journal_mut()?.start_transaction(ty)?;
+        self.current_transaction_type = Some(ty);
+        self.dry_run = false;
+        self.transaction_alloc_count = 0;
+        self.savepoints.clear();
+        if ty == TransactionType::Write {
+            self.write_transaction_begin = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    // Start a write transaction that never persists,
+    // the same code path as a real one can be run safely,
+    // committing it discards the changes as rolling back.
+    #[allow(dead_code)]
+    pub fn begin_dry_run(&mut self) -> DbResult<()> {
+        self.start_transaction(TransactionType::Write)?;
+        self.dry_run = true;
+        Ok(())
+    }
+
+    #[inline]
+    #[allow(dead_code)]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    #[inline]
+    pub fn transaction_type(&mut self) -> Option<TransactionType> {
+        self.journal_manager.as_ref().and_then(|journal_manager| journal_manager.transaction_type())
+    }
+
+    // it doesn't borrow the journal manager mutably
+    #[inline]
+    #[allow(dead_code)]
+    pub fn current_transaction_type(&self) -> Option<TransactionType> {
+        self.current_transaction_type
+    }
+
+    // the pages read in the transaction stay valid,
+    // nothing is written by another handler in between
+    pub fn upgrade_transaction_to_write(&mut self) -> DbResult<()> {
+        self.check_writable()?;
+        if self.transaction_type() != Some(TransactionType::Read) {
+            return Err(DbErr::NotInReadTransaction);
+        }
+        self.upgrade_read_transaction_to_write()
+    }
+
+    #[inline]
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        self.journal_mut()?.upgrade_read_transaction_to_write()?;
+        self.current_transaction_type = Some(TransactionType::Write);
+        self.write_transaction_begin = Some(Instant::now());
+        self.notify_transaction(TransactionEvent::Upgrade, TransactionType::Write);
+        Ok(())
+    }
+
+    #[inline]
+    fn notify_transaction(&mut self, event: TransactionEvent, ty: TransactionType) {
+        if let Some(transaction_observer) = self.transaction_observer.as_mut() {
+            transaction_observer(event, ty, Instant::now());
+        }
+    }
+
+    #[inline]
+    pub fn set_transaction_state(&mut self, state: TransactionState) {
+        self.transaction_state = state;
+    }
+
+    pub fn transaction_state_kind(&self) -> TransactionStateKind {
+        match self.transaction_state {
+            TransactionState::NoTrans => TransactionStateKind::NoTrans,
+            TransactionState::User => TransactionStateKind::User,
+            TransactionState::UserAuto => TransactionStateKind::UserAuto,
+            TransactionState::DbAuto => TransactionStateKind::DbAuto,
+        }
+    }
+
+    pub fn commit(&mut self) -> DbResult<()> {
+        let ty = self.current_transaction_type;
+        self.commit_transaction()?;
+        if let Some(ty) = ty {
+            self.notify_transaction(TransactionEvent::Commit, ty);
+        }
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> DbResult<()> {
+        if self.dry_run {
+            // the pages allocated in the dry run are not allocated any more
+            self.data_page_map.clear();
+            return self.rollback_transaction();
+        }
+
+        self.release_tombstones()?;
+        let journal_manager = self.journal_mut()?;
+        let write_counts = journal_manager.transaction_write_counts();
+
+        // the first frames after the checkpoint,
+        // the main file is behind the journal from now on
+        let first_frames = journal_manager.len() == 0
+            && journal_manager.path().is_some()
+            && write_counts.is_some_and(|(journal_frames, _)| journal_frames > 0);
+        if first_frames {
+            self.mark_journal_in_use(true)?;
+        }
+
+        self.record_changed_pages()?;
+
+        let journal_manager = self.journal_mut()?;
+        journal_manager.commit()?;
+        if self.current_transaction_type == Some(TransactionType::Read) {
+            self.read_snapshot = None;
+        }
+        self.current_transaction_type = None;
+        self.savepoints.clear();
+        if let Some((journal_frames, logical_pages)) = write_counts {
+            self.write_stats.record_commit(journal_frames, logical_pages);
+        }
+        self.write_transaction_begin = None;
+
+        // the journal is truncated by the checkpoint in progress,
+        // the frames of the read snapshot must be kept
+        if !self.checkpoint_in_progress && self.read_snapshot.is_none() && self.is_journal_full() {
+            self.checkpoint()?;
+            #[cfg(feature = "log")]
+            eprintln!("checkpoint journal finished");
+        }
+        Ok(())
+    }
+
+    // after the rollback
+    // all the cache are wrong
+    // cleat it
+    pub fn rollback(&mut self) -> DbResult<()> {
+        let ty = self.current_transaction_type;
+        self.rollback_transaction()?;
+        if let Some(ty) = ty {
+            self.notify_transaction(TransactionEvent::Rollback, ty);
+        }
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> DbResult<()> {
+        self.journal_mut()?.rollback()?;
+        if self.current_transaction_type == Some(TransactionType::Read) {
+            self.read_snapshot = None;
+        }
+        self.current_transaction_type = None;
+        self.dry_run = false;
+        self.write_transaction_begin = None;
+        self.tombstones.clear();
+        self.savepoints.clear();
+        self.freed_in_transaction.clear();
+        self.page_cache.clear();
+        Ok(())
+    }
+
+    // before the journal commits, see ChangeMap
+    fn record_changed_pages(&mut self) -> DbResult<()> {
+        let mut page_ids = match &self.journal_manager {
+            Some(journal_manager) => journal_manager.transaction_page_ids(),
+            None => return Ok(()),
+        };
+        page_ids.append(&mut self.freed_in_transaction);
+
+        let sync = self.durability_mode == DurabilityMode::FullSync;
+        if let Some(change_map) = self.change_map.as_mut() {
+            change_map.record_commit(&page_ids, sync)?;
+        }
+        Ok(())
+    }
+
+    // the marker of the last commit,
+    // the default marker if there is no change map
+    pub fn current_backup_marker(&self) -> BackupMarker {
+        match &self.change_map {
+            Some(change_map) => change_map.marker(),
+            None => BackupMarker::default(),
+        }
+    }
+
+    // The pages to copy for an incremental backup, in ascending order.
+    //
+    // A freed page is reported as written by the commit freeing it,
+    // it's in the free list of the header, which is written by the same commit,
+    // so the backup can tell it's free, its content is meaningless.
+    // The pages beyond the null page bar are not reported,
+    // the backup should be cut to the same count of pages.
+    //
+    // All the pages are reported if the marker can't be compared,
+    // it's of another map or taken before the first commit recorded.
+    pub fn changed_pages_since(&mut self, marker: BackupMarker) -> DbResult<Vec<u32>> {
+        let first_page = self.pipeline_read_page(0)?;
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+
+        match &self.change_map {
+            Some(change_map) if change_map.marker().map_id == marker.map_id && marker.sequence > 0 =>
+                change_map.changed_since(marker.sequence, null_page_bar),
+
+            _ => Ok((0..null_page_bar).collect()),
+        }
+    }
+
+    // Mark the current position of the write transaction,
+    // a savepoint with the same name shadows the previous one.
+    pub fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        self.journal_mut()?.savepoint(name)?;
+        self.savepoints.push(SavepointState {
+            name: name.into(),
+            tombstones_len: self.tombstones.len(),
+            transaction_alloc_count: self.transaction_alloc_count,
+            data_page_map: self.data_page_map.clone(),
+        });
+        Ok(())
+    }
+
+    // Discard the changes after the savepoint without ending the transaction,
+    // the savepoint is kept so it can be rolled back to again.
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> DbResult<()> {
+        let affected_pages = self.journal_mut()?.rollback_to_savepoint(name)?;
+
+        // the journal has found it
+        let index = match self.savepoints.iter().rposition(|savepoint| savepoint.name == name) {
+            Some(index) => index,
+            None => return Err(DbErr::Internal(format!("savepoint \"{}\" is not found in page handler", name))),
+        };
+        self.savepoints.truncate(index + 1);
+
+        let savepoint = &self.savepoints[index];
+        self.tombstones.truncate(savepoint.tombstones_len);
+        self.transaction_alloc_count = savepoint.transaction_alloc_count;
+        self.data_page_map = savepoint.data_page_map.clone();
+
+        for page_id in affected_pages {
+            self.page_cache.invalidate(page_id);
+        }
+
+        Ok(())
+    }
+
+    // replace the previous one, None to remove it
+    #[inline]
+    #[allow(dead_code)]
+    pub fn set_transaction_observer(&mut self, transaction_observer: Option<TransactionObserver>) {
+        self.transaction_observer = transaction_observer;
+    }
+
+    // replace the previous one, None to remove it
+    #[inline]
+    #[allow(dead_code)]
+    pub fn set_write_observer(&mut self, write_observer: Option<WriteObserver>) {
+        self.write_observer = write_observer;
+    }
+
+    #[inline]
+    pub fn cache_metrics(&self) -> CacheMetrics {
+        self.cache_metrics.clone()
+    }
+
+    // start a new window of measurement
+    #[inline]
+    pub fn reset_cache_metrics(&mut self) {
+        self.cache_metrics = CacheMetrics::default();
+    }
+
+    // The pages in the free list, including the free list pages,
+    // the pids are not collected.
+    // Read through the pipeline, the frees of the transaction are counted.
+    pub fn free_page_count(&mut self) -> DbResult<u32> {
+        let first_page = self.pipeline_read_page(0)?;
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+
+        let mut result = PageHandler::header_free_list_size(&first_page_wrapper, self.page_size)?;
+        PageHandler::walk_free_list_pages(
+            &first_page_wrapper,
+            |pid| self.pipeline_read_page(pid),
+            |_, wrapper| result += 1 + wrapper.size(),
+        )?;
+
+        Ok(result)
+    }
+
+    // no transaction is needed, nothing is mutated
+    pub fn stats(&self) -> DbResult<StorageStats> {
+        let first_page = self.peek_page(0)?;
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+
+        let mut free_pages = PageHandler::header_free_list_size(&first_page_wrapper, self.page_size)?;
+        PageHandler::walk_free_list_pages(
+            &first_page_wrapper,
+            |pid| self.peek_page(pid),
+            |_, wrapper| free_pages += 1 + wrapper.size(),
+        )?;
+
+        let (tracked_data_pages, free_bytes_estimate) = self.tracked_data_pages();
+
+        Ok(StorageStats {
+            page_count: self.page_count,
+            free_pages,
+            tracked_data_pages,
+            free_bytes_estimate,
+            last_commit_db_size: self.last_commit_db_size,
+        })
+    }
+
+    // the count of the pages tracked by data_page_map and their remaining bytes,
+    // shared by the stats and the health report
+    fn tracked_data_pages(&self) -> (u32, u64) {
+        let mut page_num: u32 = 0;
+        let mut free_bytes: u64 = 0;
+        for (remain_size, pids) in &self.data_page_map {
+            page_num += pids.len() as u32;
+            free_bytes += (*remain_size as u64) * (pids.len() as u64);
+        }
+        (page_num, free_bytes)
+    }
+
+    // estimated by the pages tracked by data_page_map
+    fn fragmentation_estimate(&self) -> f64 {
+        let (page_num, free_bytes) = self.tracked_data_pages();
+        if page_num == 0 {
+            return 0.0;
+        }
+        (free_bytes as f64) / (((page_num as u64) * (self.page_size as u64)) as f64)
+    }
+
+    // The bytes not used on the data pages, relative to the size of them,
+    // all the allocated pages are read, unlike the estimate of the health report.
+    // Near 1.0, the documents are sparse, vacuum packs them into fewer pages.
+    pub fn fragmentation_ratio(&mut self) -> DbResult<f32> {
+        let page_size = self.page_size as u64;
+        let mut data_pages: u64 = 0;
+        let mut unused_bytes: u64 = 0;
+        for page in self.iter_pages(false) {
+            let page = page?;
+            if page.data[0..2] != PageType::Data.to_magic() {
+                continue;
+            }
+
+            let wrapper = DataPageWrapper::from_raw(page);
+            data_pages += 1;
+            unused_bytes += wrapper.remain_size() as u64;
+        }
+
+        if data_pages == 0 {
+            return Ok(0.0);
+        }
+        Ok(((unused_bytes as f64) / ((data_pages * page_size) as f64)) as f32)
+    }
+
+    // The pages marked with the data magic by DataPageWrapper::init,
+    // the freed pages keep their bytes, they are skipped by the free list.
+    #[allow(dead_code)]
+    pub fn data_page_ids(&mut self) -> DbResult<Vec<u32>> {
+        self.begin_scan();
+        let result = self.iter_pages(false)
+            .filter_map(|page| match page {
+                Ok(page) if page.data[0..2] == PageType::Data.to_magic() => Some(Ok(page.page_id)),
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect();
+        self.end_scan();
+
+        result
+    }
+
+    // a row per page, for offline analysis of the fragmentation and utilization
+    #[inline]
+    pub fn export_page_map_csv(&mut self, writer: &mut dyn std::io::Write) -> DbResult<()> {
+        page_map_helper::export_page_map_csv(self, writer)
+    }
+
+    // A consistent copy of the committed state,
+    // the journal is checkpointed at first, so the copy has no journal.
+    // The pages are copied in a read transaction, writes wait for it only.
+    //
+    // The copy has the same layout, an encrypted copy is opened with the same key.
+    pub fn backup_to(&mut self, dest: &Path, overwrite: bool) -> DbResult<()> {
+        if self.transaction_type().is_some() {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+
+        if dest.exists() && !overwrite {
+            return Err(DbErr::DestinationExists(dest.to_string_lossy().to_string()));
+        }
+
+        let mut dest_file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .truncate(true)
+            .open(dest)?;
+
+        // nothing is written to a read-only database
+        if self.is_read_only() {
+            self.begin_scan();
+            let result = self.copy_pages_to(&mut dest_file);
+            self.end_scan();
+            result?;
+        } else {
+            self.checkpoint()?;
+
+            self.start_transaction(TransactionType::Read)?;
+            self.begin_scan();
+            let result = self.copy_pages_to(&mut dest_file);
+            self.end_scan();
+            self.commit()?;
+            result?;
+        }
+
+        dest_file.sync_data()?;
+
+        Ok(())
+    }
+
+    fn copy_pages_to(&mut self, dest_file: &mut dyn PageStore) -> DbResult<()> {
+        // the pages beyond the main file may be replayed from the journal
+        let page_count = self.stored_page_count()?;
+        dest_file.set_len(self.page_layout.offset_of(page_count))?;
+
+        for pid in 0..page_count {
+            let page = self.pipeline_read_page(pid)?;
+            self.page_layout.write_page(&page, dest_file)?;
+        }
+
+        dest_file.flush()?;
+
+        Ok(())
+    }
+
+    // every allocated page, the pages in the free list are skipped
+    // unless `include_freed` is set, for forensic inspection
+    #[inline]
+    #[allow(dead_code)]
+    pub(crate) fn iter_pages(&mut self, include_freed: bool) -> impl Iterator<Item = DbResult<RawPage>> + '_ {
+        PageIter::new(self, include_freed)
+    }
+
+    // check the header and a strided sample of pages,
+    // much faster than a full integrity scan
+    #[inline]
+    pub fn quick_verify(&mut self, sample_rate: f64) -> DbResult<QuickVerifyReport> {
+        quick_verify::quick_verify(self, sample_rate)
+    }
+
+    // nothing is written, the anomalies are reported instead of failing
+    #[inline]
+    pub fn check_integrity(&mut self) -> DbResult<IntegrityReport> {
+        integrity::check_integrity(self)
+    }
+
+    #[inline]
+    pub fn write_stats(&self) -> WriteStats {
+        self.write_stats.stats()
+    }
+
+    // the journal is replayed when opening,
+    // nothing is recovered if it's opened read-only
+    pub fn recovery_report(&self) -> RecoveryReport {
+        let mut report = match &self.journal_manager {
+            Some(journal_manager) => journal_manager.recovery_report().clone(),
+            None => RecoveryReport::default(),
+        };
+        report.journal_missing = self.missing_journal_integrity.is_some();
+        report.integrity = self.missing_journal_integrity.clone();
+        report
+    }
+
+    pub fn health(&self) -> HealthReport {
+        let write_transaction_open = self.write_transaction_begin.map(|begin| begin.elapsed());
+        let write_transaction_stuck = match write_transaction_open {
+            Some(duration) => duration >= STUCK_WRITE_TRANSACTION_THRESHOLD,
+            None => false,
+        };
+
+        HealthReport {
+            recovered_on_open: self.recovered_on_open,
+            journal_lag: self.journal_manager.as_ref().map_or(0, |journal_manager| journal_manager.len()),
+            cache_hit_rate: self.cache_metrics.hit_rate(),
+            fragmentation: self.fragmentation_estimate(),
+            write_transaction_open,
+            write_transaction_stuck,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use crate::page::{PageHandler, RawPage, PageType, TransactionState, TransactionStateKind, IntegrityAnomaly};
+    use crate::page::data_page_wrapper::DataPageWrapper;
+    use crate::page::header_page_wrapper::HeaderPageWrapper;
+    use crate::page::free_list_page_wrapper::FreeListPageWrapper;
+    use crate::config::{Config, GrowthStrategy, AllocationOrder};
+    use crate::{TransactionType, DbErr, DbResult, BackupMarker};
+    use crate::data_ticket::DataTicket;
+    use crate::journal::{JournalManager, DurabilityMode, REPLAY_BOUND_PAGES};
+    use super::{DEFAULT_JOURNAL_CHECKPOINT_THRESHOLD, FORMAT_VERSION, SHADOW_HEADER_PID, Migration, TransactionEvent};
+    use crate::intent_log::IntentLog;
+    use crate::page::{PageLayout, PageStore, MemoryPageStore};
+    use std::rc::Rc;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use polodb_bson::{Document, Value, mk_document};
+
+    fn mk_db_path(db_name: &str) -> PathBuf {
+        let mut db_path = env::temp_dir();
+        let mut journal_path = env::temp_dir();
+
+        let db_filename = String::from(db_name) + ".db";
+        let journal_filename = String::from(db_name) + ".db.journal";
+
+        db_path.push(db_filename);
+        journal_path.push(journal_filename);
+
+        let _ = std::fs::remove_file(db_path.as_path());
+        let _ = std::fs::remove_file(journal_path);
+
+        db_path
+    }
+
+    fn prepare_page_handler(db_name: &str, config: Config) -> PageHandler {
+        let db_path = mk_db_path(db_name);
+        PageHandler::new_with_config(db_path.as_path(), 4096, config).unwrap()
+    }
+
+    #[test]
+    fn test_coalesce_free_list_on_checkpoint() {
+        let config = Config {
+            coalesce_free_list_on_checkpoint: true,
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-coalesce-free-list", config);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut pids = vec![];
+        for _ in 0..10 {
+            pids.push(page_handler.alloc_page_id().unwrap());
+        }
+
+        // free in a scattered order
+        for index in [3, 7, 0, 9, 5, 1, 8, 2, 6, 4].iter() {
+            page_handler.free_page(pids[*index]).unwrap();
+        }
+        page_handler.commit().unwrap();
+
+        page_handler.checkpoint().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut reallocated = vec![];
+        for _ in 0..10 {
+            reallocated.push(page_handler.alloc_page_id().unwrap());
+        }
+        page_handler.commit().unwrap();
+
+        assert_eq!(reallocated, pids);
+    }
+
+    #[test]
+    fn test_coalesce_spilled_free_list() {
+        let config = Config {
+            coalesce_free_list_on_checkpoint: true,
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-coalesce-spilled-free-list", config);
+        let pids = alloc_pages(&mut page_handler, 800);
+
+        // scattered, more than the header holds
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for i in 0..800 {
+            page_handler.free_page(pids[(i * 7) % 800]).unwrap();
+        }
+        page_handler.commit().unwrap();
+
+        page_handler.checkpoint().unwrap();
+        assert!(page_handler.transaction_type().is_none());
+
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.get_first_page().unwrap());
+        let header_list: Vec<u32> = (0..first_page_wrapper.get_free_list_size())
+            .map(|index| first_page_wrapper.get_free_list_content(index))
+            .collect();
+        assert!(header_list.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        let mut free_list_pid = first_page_wrapper.get_free_list_page_id();
+        assert_ne!(free_list_pid, 0);
+        while free_list_pid != 0 {
+            let free_list_page = FreeListPageWrapper::from_raw_page(page_handler.pipeline_read_page(free_list_pid).unwrap());
+            let entries: Vec<u32> = (0..free_list_page.size())
+                .map(|index| free_list_page.get_content(index))
+                .collect();
+            assert!(entries.windows(2).all(|pair| pair[0] >= pair[1]));
+            free_list_pid = free_list_page.next_pid();
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_in_progress() {
+        let config = Config {
+            coalesce_free_list_on_checkpoint: true,
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-checkpoint-in-progress", config);
+
+        let pids = alloc_pages(&mut page_handler, 300);
+
+        // ascending, checkpoint writes the sorted one
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[0..150]).unwrap();
+        page_handler.commit().unwrap();
+
+        // every commit in the checkpoint fills the journal
+        page_handler.set_journal_checkpoint_threshold(1).unwrap();
+        assert!(page_handler.is_journal_full());
+
+        page_handler.checkpoint().unwrap();
+        assert!(!page_handler.checkpoint_in_progress);
+        assert_eq!(page_handler.journal_manager.as_ref().unwrap().len(), 0);
+
+        // a commit in the checkpoint doesn't checkpoint again
+        page_handler.checkpoint_in_progress = true;
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[150..]).unwrap();
+        page_handler.commit().unwrap();
+        assert!(page_handler.journal_manager.as_ref().unwrap().len() > 0);
+
+        assert!(matches!(page_handler.checkpoint(), Err(DbErr::CheckpointInProgress)));
+
+        page_handler.checkpoint_in_progress = false;
+        page_handler.checkpoint().unwrap();
+        assert_eq!(page_handler.journal_manager.as_ref().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_truncate_to_high_water() {
+        let mut page_handler = prepare_page_handler("test-truncate-to-high-water", Config::default());
+        let pids = alloc_pages(&mut page_handler, 100);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids {
+            page_handler.pipeline_write_page(&RawPage::new(*pid, page_handler.page_size)).unwrap();
+        }
+        page_handler.commit().unwrap();
+        page_handler.checkpoint().unwrap();
+        let origin_file_size = page_handler.file.len().unwrap();
+
+        // the last page is in use
+        assert_eq!(page_handler.truncate_to_high_water().unwrap(), origin_file_size);
+
+        // free the tail and a page in the middle
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[50..]).unwrap();
+        page_handler.free_page(pids[10]).unwrap();
+        page_handler.commit().unwrap();
+
+        let file_size = page_handler.truncate_to_high_water().unwrap();
+        assert_eq!(file_size, page_handler.file.len().unwrap());
+        assert_eq!(file_size, page_handler.page_layout.offset_of(pids[49] + 1));
+        assert!(file_size < origin_file_size);
+
+        // only the page in the middle is left in the free list
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_null_page_bar(), pids[49] + 1);
+        let free_pids = page_handler.collect_free_pids(&first_page_wrapper).unwrap();
+        assert_eq!(free_pids.into_iter().collect::<Vec<u32>>(), vec![pids[10]]);
+
+        assert_eq!(page_handler.truncate_to_high_water().unwrap(), file_size);
+    }
+
+    #[test]
+    fn test_init_block_count() {
+        let config = Config {
+            init_block_count: 0,
+            ..Config::default()
+        };
+        let db_path = mk_db_path("test-init-block-count");
+        let result = PageHandler::new_with_config(db_path.as_path(), 4096, config);
+        assert!(matches!(result, Err(DbErr::InvalidInitBlockCount(0))));
+
+        let config = Config {
+            init_block_count: 256,
+            growth_strategy: GrowthStrategy::Fixed(256),
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-init-block-count", config);
+        let block_size = page_handler.page_layout.offset_of(256);
+        assert_eq!(page_handler.file.len().unwrap(), block_size);
+
+        let write_pages = |page_handler: &mut PageHandler, count: usize| {
+            let pids = alloc_pages(page_handler, count);
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for pid in &pids {
+                page_handler.pipeline_write_page(&RawPage::new(*pid, page_handler.page_size)).unwrap();
+            }
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+        };
+
+        write_pages(&mut page_handler, 100);
+        assert_eq!(page_handler.file.len().unwrap(), block_size);
+
+        write_pages(&mut page_handler, 200);
+        assert_eq!(page_handler.file.len().unwrap(), block_size * 2);
+
+        write_pages(&mut page_handler, 300);
+        assert_eq!(page_handler.file.len().unwrap(), block_size * 3);
+    }
+
+    #[test]
+    fn test_write_stats() {
+        let mut page_handler = prepare_page_handler("test-write-stats", Config::default());
+        assert_eq!(page_handler.write_stats().write_amplification, 0.0);
+
+        // the same data page is rewritten by every store
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for i in 0..10 {
+            let doc = mk_document! {
+                "content": i,
+            };
+            page_handler.store_doc(&doc).unwrap();
+        }
+        page_handler.commit().unwrap();
+
+        let stats = page_handler.write_stats();
+        assert!(stats.last_commit_journal_frames >= 10);
+        assert!(stats.last_commit_logical_pages < stats.last_commit_journal_frames);
+        assert!(stats.write_amplification > 1.0);
+
+        page_handler.checkpoint().unwrap();
+        let after_checkpoint = page_handler.write_stats();
+        assert_eq!(after_checkpoint.last_checkpoint_pages, stats.last_commit_logical_pages);
+        assert!(after_checkpoint.write_amplification > stats.write_amplification);
+    }
+
+    #[test]
+    fn test_deferred_free_data_ticket() {
+        let mut page_handler = prepare_page_handler("test-deferred-free-data-ticket", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let doc = mk_document! {
+            "content": "a".repeat(3000),
+        };
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+
+        let free_list_size = |page_handler: &mut PageHandler| {
+            let first_page = page_handler.get_first_page().unwrap();
+            HeaderPageWrapper::from_raw_page(first_page).get_free_list_size()
+        };
+        let origin_free_list_size = free_list_size(&mut page_handler);
+
+        // discarded by rollback
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_data_ticket(&ticket).unwrap();
+        page_handler.rollback().unwrap();
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_data_ticket(&ticket).unwrap();
+
+        // still readable, and the page is not released
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+        assert_eq!(free_list_size(&mut page_handler), origin_free_list_size);
+
+        page_handler.commit().unwrap();
+
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_none());
+        assert_eq!(free_list_size(&mut page_handler), origin_free_list_size + 1);
+    }
+
+    #[test]
+    fn test_store_large_doc() {
+        let mut page_handler = prepare_page_handler("test-store-large-doc", Config::default());
+
+        let content: String = (0..(200 * 1024)).map(|i| ((i % 26) as u8 + b'a') as char).collect();
+        let doc = mk_document! {
+            "content": content.clone(),
+        };
+        let origin_bytes = doc.to_bytes().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+
+        let bytes = page_handler.get_raw_from_ticket(&ticket).unwrap().unwrap();
+        assert_eq!(bytes, origin_bytes);
+        let read_doc = page_handler.get_doc_from_ticket(&ticket).unwrap().unwrap();
+        assert_eq!(read_doc.get("content").unwrap().unwrap_string(), content);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let origin_free_list_size = HeaderPageWrapper::from_raw_page(first_page).get_free_list_size();
+
+        // the whole chain is freed
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let freed_bytes = page_handler.free_data_ticket(&ticket).unwrap();
+        page_handler.commit().unwrap();
+        assert_eq!(freed_bytes, origin_bytes);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let free_list_size = HeaderPageWrapper::from_raw_page(first_page).get_free_list_size();
+        let chain_len = (origin_bytes.len() + 4096 - 12 - 1) / (4096 - 12);
+        assert_eq!((free_list_size - origin_free_list_size) as usize, chain_len + 1);
+    }
+
+    #[test]
+    fn test_quick_verify() {
+        let db_path = mk_db_path("test-quick-verify");
+        let ticket = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let mut ticket = None;
+            for i in 0..50 {
+                let doc = mk_document! {
+                    "content": i.to_string().repeat(100),
+                };
+                ticket = Some(page_handler.store_doc(&doc).unwrap());
+            }
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+
+            let report = page_handler.quick_verify(1.0).unwrap();
+            assert!(report.is_ok());
+            assert_eq!(report.checked_pages, report.total_pages);
+
+            let report = page_handler.quick_verify(0.0).unwrap();
+            assert!(report.header_ok);
+            assert_eq!(report.checked_pages, 0);
+
+            ticket.unwrap()
+        };
+
+        // break the magic of a data page
+        {
+            use std::io::{Seek, SeekFrom, Write};
+            let mut file = std::fs::OpenOptions::new().write(true).open(db_path.as_path()).unwrap();
+            file.seek(SeekFrom::Start((ticket.pid as u64) * 4096)).unwrap();
+            file.write_all(&[0xFF, 0x77]).unwrap();
+        }
+
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        let report = page_handler.quick_verify(1.0).unwrap();
+        assert!(report.header_ok);
+        assert_eq!(report.mismatched_pages, vec![ticket.pid]);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn test_page_checksum() {
+        for page_checksum in [true, false] {
+            let db_path = mk_db_path(&format!("test-page-checksum-{}", page_checksum));
+            let mk_config = || {
+                Config {
+                    page_checksum,
+                    ..Config::default()
+                }
+            };
+
+            let ticket = {
+                let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, mk_config()).unwrap();
+                page_handler.start_transaction(TransactionType::Write).unwrap();
+                let doc = mk_document! {
+                    "content": "hello",
+                };
+                let ticket = page_handler.store_doc(&doc).unwrap();
+                page_handler.commit().unwrap();
+                page_handler.checkpoint().unwrap();
+                ticket
+            };
+
+            {
+                let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, mk_config()).unwrap();
+                page_handler.pipeline_read_page(ticket.pid).unwrap();
+            }
+
+            // flip a byte of the data page on the disk
+            {
+                use std::io::{Seek, SeekFrom, Write};
+                let mut file = std::fs::OpenOptions::new().write(true).open(db_path.as_path()).unwrap();
+                file.seek(SeekFrom::Start((ticket.pid as u64) * 4096 + 100)).unwrap();
+                file.write_all(&[0x55]).unwrap();
+            }
+
+            let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, mk_config()).unwrap();
+            let result = page_handler.pipeline_read_page(ticket.pid);
+            if page_checksum {
+                match result {
+                    Err(DbErr::PageChecksumMismatch { page_id, expected, actual }) => {
+                        assert_eq!(page_id, ticket.pid);
+                        assert_ne!(expected, actual);
+                    }
+                    _ => panic!("the corrupt page should be detected"),
+                }
+            } else {
+                // the legacy layout has no checksum
+                assert_eq!(result.unwrap().len(), 4096);
+            }
+        }
+    }
+
+    #[test]
+    fn test_page_checksum_detected() {
+        for page_checksum in [true, false] {
+            let db_path = mk_db_path(&format!("test-page-checksum-detected-{}", page_checksum));
+            let mk_config = |page_checksum| {
+                Config {
+                    page_checksum,
+                    ..Config::default()
+                }
+            };
+
+            let ticket = {
+                let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, mk_config(page_checksum)).unwrap();
+                page_handler.start_transaction(TransactionType::Write).unwrap();
+                let ticket = page_handler.store_doc(&mk_document! { "content": "hello" }).unwrap();
+                page_handler.commit().unwrap();
+                page_handler.checkpoint().unwrap();
+                ticket
+            };
+
+            // the header decides, not the config
+            let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, mk_config(!page_checksum)).unwrap();
+            assert_eq!(page_handler.page_layout.has_checksum(), page_checksum);
+            let doc = page_handler.get_doc_from_ticket(&ticket).unwrap().unwrap();
+            assert_eq!(doc.get("content").unwrap().unwrap_string(), "hello");
+            drop(page_handler);
+
+            let page_handler = PageHandler::open_read_only_with_config(db_path.as_path(), 4096, mk_config(!page_checksum)).unwrap();
+            assert_eq!(page_handler.page_layout.has_checksum(), page_checksum);
+        }
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let db_path = mk_db_path("test-open-read-only");
+
+        let tickets: Vec<DataTicket> = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let tickets = (0..10).map(|i| {
+                let doc = mk_document! {
+                    "_id": i,
+                    "content": i.to_string().repeat(100),
+                };
+                page_handler.store_doc(&doc).unwrap()
+            }).collect();
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+            tickets
+        };
+
+        let mut page_handler = PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+        assert!(page_handler.is_read_only());
+
+        for (i, ticket) in tickets.iter().enumerate() {
+            let doc = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+            assert_eq!(doc.get("_id").unwrap().unwrap_int(), i as i64);
+        }
+
+        let doc = mk_document! {
+            "content": "hello",
+        };
+        assert!(matches!(page_handler.start_transaction(TransactionType::Write), Err(DbErr::ReadOnly)));
+        assert!(matches!(page_handler.start_transaction(TransactionType::Read), Err(DbErr::ReadOnly)));
+        assert!(matches!(page_handler.store_doc(&doc), Err(DbErr::ReadOnly)));
+        assert!(matches!(page_handler.alloc_page_id(), Err(DbErr::ReadOnly)));
+        assert!(matches!(page_handler.free_pages(&[tickets[0].pid]), Err(DbErr::ReadOnly)));
+
+        let first_page = page_handler.get_first_page().unwrap();
+        assert!(matches!(page_handler.pipeline_write_page(&first_page), Err(DbErr::ReadOnly)));
+        assert!(page_handler.journal_file_path().is_none());
+    }
+
+    #[test]
+    fn test_read_snapshot() {
+        let mut page_handler = prepare_page_handler("test-read-snapshot", Config::default());
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let old_ticket = page_handler.store_doc(&mk_document! { "content": "old" }).unwrap();
+        page_handler.commit().unwrap();
+        page_handler.journal_checkpoint_threshold = 1;
+
+        page_handler.start_transaction(TransactionType::Read).unwrap();
+        page_handler.set_transaction_state(TransactionState::User);
+        let null_page_bar = HeaderPageWrapper::from_raw_page(page_handler.get_first_page().unwrap()).get_null_page_bar();
+
+        // interleaved with the read transaction, committed by its own
+        let new_ticket = page_handler.with_write_transaction(|page_handler| {
+            page_handler.free_data_ticket(&old_ticket)?;
+            let new_ticket = page_handler.store_doc(&mk_document! { "content": "new" })?;
+            page_handler.alloc_page_id()?;
+            Ok(new_ticket)
+        }).unwrap();
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::User);
+        assert!(page_handler.transaction_type() == Some(TransactionType::Read));
+
+        // the journal is kept for the snapshot
+        assert!(page_handler.journal_len() > 0);
+
+        let doc = page_handler.get_doc_from_ticket(&old_ticket).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "old");
+        let first_page = page_handler.get_first_page().unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar(), null_page_bar);
+        let beyond_pid = (page_handler.last_commit_db_size / (page_handler.page_layout.physical_size() as u64)) as u32;
+        assert_eq!(page_handler.pipeline_read_page(beyond_pid).unwrap().data[0..2], [0, 0]);
+
+        page_handler.commit().unwrap();
+        page_handler.set_transaction_state(TransactionState::NoTrans);
+        assert!(page_handler.read_snapshot.is_none());
+
+        let doc = page_handler.get_doc_from_ticket(&new_ticket).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "new");
+        let first_page = page_handler.get_first_page().unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar(), null_page_bar + 1);
+    }
+
+    #[test]
+    fn test_open_read_only_with_journal() {
+        let db_path = mk_db_path("test-open-read-only-with-journal");
+        let journal_path = PageHandler::mk_journal_path(db_path.as_path());
+
+        // dropped without the checkpoint, the main file is behind the journal
+        let tickets: Vec<DataTicket> = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let tickets = (0..10).map(|i| {
+                let doc = mk_document! {
+                    "_id": i,
+                    "content": i.to_string().repeat(100),
+                };
+                page_handler.store_doc(&doc).unwrap()
+            }).collect();
+            page_handler.commit().unwrap();
+            tickets
+        };
+        let db_bytes = std::fs::read(db_path.as_path()).unwrap();
+        let journal_bytes = std::fs::read(journal_path.as_path()).unwrap();
+
+        {
+            let mut page_handler = PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+            assert!(page_handler.is_read_only());
+            for (i, ticket) in tickets.iter().enumerate() {
+                let doc = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+                assert_eq!(doc.get("_id").unwrap().unwrap_int(), i as i64);
+            }
+            assert!(matches!(page_handler.start_transaction(TransactionType::Read), Err(DbErr::ReadOnly)));
+        }
+
+        // neither file is written
+        assert_eq!(std::fs::read(db_path.as_path()).unwrap(), db_bytes);
+        assert_eq!(std::fs::read(journal_path.as_path()).unwrap(), journal_bytes);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut page_handler = PageHandler::new_in_memory(4096).unwrap();
+        assert!(page_handler.journal_file_path().is_none());
+        assert!(page_handler.intent_log_path().is_none());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let tickets: Vec<DataTicket> = (0..100).map(|i| {
+            let doc = mk_document! {
+                "_id": i,
+                "content": i.to_string().repeat(100),
+            };
+            page_handler.store_doc(&doc).unwrap()
+        }).collect();
+        page_handler.commit().unwrap();
+        page_handler.checkpoint().unwrap();
+
+        for (i, ticket) in tickets.iter().enumerate() {
+            let doc = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+            assert_eq!(doc.get("_id").unwrap().unwrap_int(), i as i64);
+        }
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pid = page_handler.alloc_page_id().unwrap();
+        page_handler.free_page(pid).unwrap();
+        page_handler.commit().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.alloc_page_id().unwrap(), pid);
+        page_handler.rollback().unwrap();
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_free_list_size(), 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut page_handler = prepare_page_handler("test-stats", Config::default());
+
+        let pids = alloc_pages(&mut page_handler, 600);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let doc = mk_document! {
+            "content": "hello",
+        };
+        page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+
+        let stats = page_handler.stats().unwrap();
+        assert_eq!(stats.free_pages, 0);
+        assert_eq!(stats.tracked_data_pages, 1);
+        assert!(stats.free_bytes_estimate > 0);
+
+        // spill to the free list pages
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids).unwrap();
+        page_handler.commit().unwrap();
+
+        let stats = page_handler.stats().unwrap();
+        assert_eq!(stats.free_pages, 600);
+        assert_eq!(stats.last_commit_db_size, page_handler.last_commit_db_size);
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let mut page_handler = prepare_page_handler("test-savepoint", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let first = page_handler.store_doc(&mk_document! {
+            "content": "first",
+        }).unwrap();
+
+        page_handler.savepoint("a").unwrap();
+        let second = page_handler.store_doc(&mk_document! {
+            "content": "second",
+        }).unwrap();
+        let allocated = page_handler.alloc_page_id().unwrap();
+
+        // shadows the first "a"
+        page_handler.savepoint("a").unwrap();
+        page_handler.store_doc(&mk_document! {
+            "content": "third",
+        }).unwrap();
+
+        page_handler.rollback_to_savepoint("a").unwrap();
+        assert!(page_handler.get_doc_from_ticket(&second).unwrap().is_some());
+        assert_eq!(page_handler.alloc_page_id().unwrap(), allocated + 1);
+
+        page_handler.rollback_to_savepoint("a").unwrap();
+        page_handler.rollback_to_savepoint("a").unwrap();
+
+        match page_handler.rollback_to_savepoint("unknown") {
+            Err(DbErr::SavepointNotFound(name)) => assert_eq!(name, "unknown"),
+            _ => panic!("the savepoint should not be found"),
+        }
+        page_handler.commit().unwrap();
+
+        let doc = page_handler.get_doc_from_ticket(&first).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "first");
+        let doc = page_handler.get_doc_from_ticket(&second).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "second");
+
+        // the page allocated after the second "a" is rolled back
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_null_page_bar(), allocated + 1);
+
+        // savepoints are released with the transaction
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert!(matches!(page_handler.rollback_to_savepoint("a"), Err(DbErr::SavepointNotFound(_))));
+        page_handler.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_store_docs() {
+        let docs: Vec<Document> = (0..1000).map(|i| {
+            mk_document! {
+                "_id": i,
+                "content": i.to_string(),
+            }
+        }).collect();
+
+        let mut page_handler = prepare_page_handler("test-store-docs-one-by-one", Config::default());
+        page_handler.set_journal_checkpoint_threshold(u32::MAX).unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for doc in &docs {
+            page_handler.store_doc(doc).unwrap();
+        }
+        page_handler.commit().unwrap();
+        let one_by_one_frames = page_handler.write_stats().last_commit_journal_frames;
+
+        let mut page_handler = prepare_page_handler("test-store-docs", Config::default());
+        page_handler.set_journal_checkpoint_threshold(u32::MAX).unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+
+        let large_doc = mk_document! {
+            "content": "x".repeat(10000),
+        };
+        let mut batch = docs.clone();
+        batch.insert(500, large_doc);
+
+        let tickets = page_handler.store_docs(&batch).unwrap();
+        page_handler.commit().unwrap();
+        let batch_frames = page_handler.write_stats().last_commit_journal_frames;
+
+        assert!(batch_frames * 10 < one_by_one_frames);
+
+        assert_eq!(tickets.len(), batch.len());
+        for (ticket, doc) in tickets.iter().zip(batch.iter()) {
+            let stored = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+            assert_eq!(
+                stored.get("content").unwrap().unwrap_string(),
+                doc.get("content").unwrap().unwrap_string(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_page_map_csv() {
+        let mut page_handler = prepare_page_handler("test-export-page-map-csv", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let doc = mk_document! {
+            "content": "hello",
+        };
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        let free_pid = page_handler.alloc_page_id().unwrap();
+        page_handler.free_page(free_pid).unwrap();
+        page_handler.commit().unwrap();
+
+        let mut buffer: Vec<u8> = vec![];
+        page_handler.export_page_map_csv(&mut buffer).unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], "pid,kind,live_entries,used_bytes,remaining_bytes,is_free");
+        assert!(lines[1].starts_with("0,header,"));
+
+        let data_row = lines[(ticket.pid + 1) as usize];
+        assert!(data_row.starts_with(&format!("{},data,1,", ticket.pid)));
+        assert!(data_row.ends_with(",false"));
+
+        let free_row = lines[(free_pid + 1) as usize];
+        assert!(free_row.ends_with(",true"));
+    }
+
+    #[test]
+    fn test_free_data_ticket_twice() {
+        let mut page_handler = prepare_page_handler("test-free-data-ticket-twice", Config::default());
+
+        let doc = mk_document! {
+            "content": "hello",
+        };
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+
+        // tombstoned in the transaction
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_data_ticket(&ticket).unwrap();
+        let err = page_handler.free_data_ticket(&ticket).unwrap_err();
+        assert!(matches!(err, DbErr::DataTicketNotFound { pid, index } if pid == ticket.pid && index == ticket.index));
+        page_handler.commit().unwrap();
+
+        // released by the last commit
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.free_data_ticket(&ticket).unwrap_err();
+        assert!(matches!(err, DbErr::DataTicketNotFound { .. }));
+
+        let header_ticket = DataTicket {
+            pid: 0,
+            index: 0,
+        };
+        let err = page_handler.free_data_ticket(&header_ticket).unwrap_err();
+        assert!(matches!(err, DbErr::NotADataPage(0)));
+        page_handler.commit().unwrap();
+    }
+
+    #[test]
+    fn test_release_truncates_removed_tail() {
+        let mut page_handler = prepare_page_handler("test-release-truncates-removed-tail", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut tickets = vec![];
+        for i in 0..3 {
+            let doc = mk_document! {
+                "content": i.to_string(),
+            };
+            tickets.push(page_handler.store_doc(&doc).unwrap());
+        }
+        page_handler.commit().unwrap();
+        assert!(tickets.iter().all(|ticket| ticket.pid == tickets[0].pid));
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_data_ticket(&tickets[1]).unwrap();
+        page_handler.free_data_ticket(&tickets[2]).unwrap();
+        page_handler.commit().unwrap();
+
+        let page = page_handler.pipeline_read_page(tickets[0].pid).unwrap();
+        let wrapper = DataPageWrapper::from_raw(page);
+        assert_eq!(wrapper.bar_len(), 1);
+
+        let doc = page_handler.get_doc_from_ticket(&tickets[0]).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "0");
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let db_path = mk_db_path("test-encrypted");
+        let key = [7u8; 32];
+        let marker = "plain-text-marker";
+
+        let mut tickets = vec![];
+        {
+            let mut page_handler = PageHandler::new_encrypted(db_path.as_path(), 4096, &key).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for i in 0..10 {
+                let doc = mk_document! {
+                    "content": format!("{}-{}", marker, i),
+                };
+                tickets.push(page_handler.store_doc(&doc).unwrap());
+            }
+            page_handler.commit().unwrap();
+
+            let journal_path = page_handler.journal_file_path().unwrap().to_path_buf();
+            let journal_bytes = std::fs::read(journal_path).unwrap();
+            assert!(!contains_bytes(&journal_bytes, marker.as_bytes()));
+
+            page_handler.checkpoint().unwrap();
+        }
+
+        let db_bytes = std::fs::read(db_path.as_path()).unwrap();
+        assert!(!contains_bytes(&db_bytes, marker.as_bytes()));
+
+        let err = PageHandler::new_encrypted(db_path.as_path(), 4096, &[8u8; 32]).err().unwrap();
+        assert!(matches!(err, DbErr::InvalidEncryptionKey));
+
+        let err = PageHandler::new(db_path.as_path(), 4096).err().unwrap();
+        assert!(matches!(err, DbErr::InvalidEncryptionKey));
+
+        let mut page_handler = PageHandler::new_encrypted(db_path.as_path(), 4096, &key).unwrap();
+        for (i, ticket) in tickets.iter().enumerate() {
+            let doc = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+            let content = doc.get("content").unwrap().unwrap_string();
+            assert_eq!(content, format!("{}-{}", marker, i));
+        }
+    }
+
+    fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    struct CountingStore {
+        inner:   MemoryPageStore,
+        reads:   Rc<Cell<u32>>,
+        flushes: Rc<Cell<u32>>,
+        syncs:   Rc<Cell<u32>>,
+        // the offsets and the lengths
+        writes:  Rc<RefCell<Vec<(u64, usize)>>>,
+    }
+
+    impl PageStore for CountingStore {
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<()> {
+            self.reads.set(self.reads.get() + 1);
+            self.inner.read_at(offset, buffer)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+            self.writes.borrow_mut().push((offset, data.len()));
+            self.inner.write_at(offset, data)
+        }
+
+        fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+            self.inner.set_len(len)
+        }
+
+        fn len(&self) -> std::io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes.set(self.flushes.get() + 1);
+            Ok(())
+        }
+
+        fn sync_data(&mut self) -> std::io::Result<()> {
+            self.syncs.set(self.syncs.get() + 1);
+            Ok(())
+        }
+
+    }
+
+    // the same bytes are seen by the handlers reopened over it
+    #[derive(Clone, Default)]
+    struct SharedStore(Rc<RefCell<MemoryPageStore>>);
+
+    impl PageStore for SharedStore {
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<()> {
+            self.0.borrow().read_at(offset, buffer)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+            self.0.borrow_mut().write_at(offset, data)
+        }
+
+        fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+            self.0.borrow_mut().set_len(len)
+        }
+
+        fn len(&self) -> std::io::Result<u64> {
+            self.0.borrow().len()
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.borrow_mut().flush()
+        }
+
+        fn sync_data(&mut self) -> std::io::Result<()> {
+            self.0.borrow_mut().sync_data()
+        }
+
+    }
+
+    // fails every read and write once it's broken
+    struct FailingStore {
+        inner:  MemoryPageStore,
+        broken: Rc<Cell<bool>>,
+    }
+
+    impl FailingStore {
+
+        fn check(&self) -> std::io::Result<()> {
+            if self.broken.get() {
+                return Err(std::io::Error::other("injected"));
+            }
+            Ok(())
+        }
+
+    }
+
+    impl PageStore for FailingStore {
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<()> {
+            self.check()?;
+            self.inner.read_at(offset, buffer)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+            self.check()?;
+            self.inner.write_at(offset, data)
+        }
+
+        fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+            self.inner.set_len(len)
+        }
+
+        fn len(&self) -> std::io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn sync_data(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+    }
+
+    // the file can't grow beyond the limit, as a full disk
+    struct FullDiskStore {
+        inner: MemoryPageStore,
+        limit: u64,
+    }
+
+    impl PageStore for FullDiskStore {
+
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> std::io::Result<()> {
+            self.inner.read_at(offset, buffer)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+            self.inner.write_at(offset, data)
+        }
+
+        fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+            if len > self.limit {
+                return Err(std::io::Error::other("no space left on device"));
+            }
+            self.inner.set_len(len)
+        }
+
+        fn len(&self) -> std::io::Result<u64> {
+            self.inner.len()
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn sync_data(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+    }
+
+    #[test]
+    fn test_out_of_space() {
+        let page_layout = PageLayout::new(4096, true);
+        let mut store = FullDiskStore {
+            inner: MemoryPageStore::new(),
+            limit: u64::MAX,
+        };
+        let (_, page_count, db_file_size) = PageHandler::init_db(&mut store, page_layout, Config::default().init_block_count).unwrap();
+        store.limit = db_file_size;
+        let journal_manager = JournalManager::open_in_memory(page_layout, db_file_size).unwrap();
+        let mut page_handler = PageHandler::from_parts(
+            Box::new(store), page_layout, page_count,
+            Some(Box::new(journal_manager)), None,
+            Config::default(),
+        ).unwrap();
+
+        // the pages in the file are allocated without growing
+        let null_page_bar = HeaderPageWrapper::from_raw_page(page_handler.get_first_page().unwrap()).get_null_page_bar();
+        let room = (db_file_size / 4096) as u32 - null_page_bar;
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for _ in 0..room {
+            page_handler.alloc_page_id().unwrap();
+        }
+
+        let err = page_handler.alloc_page_id().unwrap_err();
+        assert!(matches!(err, DbErr::OutOfSpace { requested_bytes } if requested_bytes > 0));
+        page_handler.commit().unwrap();
+        page_handler.checkpoint().unwrap();
+        assert_eq!(page_handler.file.len().unwrap(), db_file_size);
+
+        // the freed pages are still allocated when the disk is full
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_page(null_page_bar).unwrap();
+        page_handler.commit().unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.alloc_page_id().unwrap(), null_page_bar);
+        page_handler.commit().unwrap();
+    }
+
+    #[test]
+    fn test_page_io_error() {
+        let main_broken = Rc::new(Cell::new(false));
+        let journal_broken = Rc::new(Cell::new(false));
+        let page_layout = PageLayout::new(4096, true);
+        let mut store = FailingStore {
+            inner: MemoryPageStore::new(),
+            broken: main_broken.clone(),
+        };
+        let (_, page_count, db_file_size) = PageHandler::init_db(&mut store, page_layout, Config::default().init_block_count).unwrap();
+        let journal_store = FailingStore {
+            inner: MemoryPageStore::new(),
+            broken: journal_broken.clone(),
+        };
+        let journal_manager = JournalManager::open_store(None, Box::new(journal_store), page_layout, db_file_size, None).unwrap();
+        let mut page_handler = PageHandler::from_parts(
+            Box::new(store), page_layout, page_count,
+            Some(Box::new(journal_manager)), None,
+            Config::default(),
+        ).unwrap();
+
+        let pid = alloc_pages(&mut page_handler, 1)[0];
+        page_handler.checkpoint().unwrap();
+        page_handler.page_cache.clear();
+
+        main_broken.set(true);
+        let err = page_handler.pipeline_read_page(pid).unwrap_err();
+        assert!(matches!(err, DbErr::PageIo { page_id, offset, .. } if page_id == pid && offset == page_layout.offset_of(pid)));
+        assert!(err.to_string().starts_with(&format!("io error of page {} at offset {}: injected", pid, page_layout.offset_of(pid))));
+        main_broken.set(false);
+
+        journal_broken.set(true);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.pipeline_write_page(&RawPage::new(pid, page_layout.body_size())).unwrap_err();
+        assert!(matches!(err, DbErr::PageIo { page_id, .. } if page_id == pid));
+    }
+
+    #[test]
+    fn test_durability_mode() {
+        let run = |mode: DurabilityMode| -> (u32, u32) {
+            let flushes = Rc::new(Cell::new(0));
+            let syncs = Rc::new(Cell::new(0));
+            let mk_store = || CountingStore {
+                inner: MemoryPageStore::new(),
+                reads: Rc::new(Cell::new(0)),
+                flushes: flushes.clone(),
+                syncs: syncs.clone(),
+                writes: Rc::default(),
+            };
+
+            let page_layout = PageLayout::new(4096, true);
+            let mut store = mk_store();
+            let (_, page_count, db_file_size) = PageHandler::init_db(&mut store, page_layout, Config::default().init_block_count).unwrap();
+            let journal_manager = JournalManager::open_store(None, Box::new(mk_store()), page_layout, db_file_size, None).unwrap();
+            let intent_log = IntentLog::open_in_memory().unwrap();
+            let mut page_handler = PageHandler::from_parts(
+                Box::new(store), page_layout, page_count,
+                Some(Box::new(journal_manager)), Some(Box::new(intent_log)),
+                Config::default(),
+            ).unwrap();
+
+            assert_eq!(page_handler.durability_mode(), DurabilityMode::FullSync);
+            page_handler.set_durability_mode(mode);
+
+            for i in 0..3 {
+                page_handler.start_transaction(TransactionType::Write).unwrap();
+                let doc = mk_document! {
+                    "content": i,
+                };
+                page_handler.store_doc(&doc).unwrap();
+                page_handler.commit().unwrap();
+            }
+            page_handler.checkpoint().unwrap();
+
+            (flushes.get(), syncs.get())
+        };
+
+        // a sync per commit, and the main file when checkpointing,
+        // the shadow of the header is synced before the primary
+        assert_eq!(run(DurabilityMode::FullSync), (2, 5));
+        // only the checkpoint is synced
+        assert_eq!(run(DurabilityMode::AsyncFlush), (5, 2));
+        assert_eq!(run(DurabilityMode::NoSync), (1, 0));
+    }
+
+    #[test]
+    fn test_flush_journal() {
+        let main_syncs = Rc::new(Cell::new(0));
+        let journal_syncs = Rc::new(Cell::new(0));
+        let mk_store = |syncs: &Rc<Cell<u32>>| CountingStore {
+            inner: MemoryPageStore::new(),
+            reads: Rc::new(Cell::new(0)),
+            flushes: Rc::new(Cell::new(0)),
+            syncs: syncs.clone(),
+            writes: Rc::default(),
+        };
+
+        let page_layout = PageLayout::new(4096, true);
+        let mut store = mk_store(&main_syncs);
+        let (_, page_count, db_file_size) = PageHandler::init_db(&mut store, page_layout, Config::default().init_block_count).unwrap();
+        let journal_manager = JournalManager::open_store(None, Box::new(mk_store(&journal_syncs)), page_layout, db_file_size, None).unwrap();
+        let mut page_handler = PageHandler::from_parts(
+            Box::new(store), page_layout, page_count,
+            Some(Box::new(journal_manager)), None,
+            Config::default(),
+        ).unwrap();
+        page_handler.set_durability_mode(DurabilityMode::NoSync);
+
+        alloc_pages(&mut page_handler, 20);
+        let main_size = page_handler.file.len().unwrap();
+        let (main_count, journal_count) = (main_syncs.get(), journal_syncs.get());
+
+        page_handler.flush_journal().unwrap();
+        assert_eq!(journal_syncs.get(), journal_count + 1);
+        assert_eq!(main_syncs.get(), main_count);
+        assert_eq!(page_handler.file.len().unwrap(), main_size);
+        assert!(page_handler.journal_len() > 0);
+
+        // no journal to sync
+        let db_path = mk_db_path("test-flush-journal");
+        PageHandler::new(db_path.as_path(), 4096).unwrap().close().unwrap();
+        let mut page_handler = PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+        assert!(matches!(page_handler.flush_journal(), Err(DbErr::ReadOnly)));
+    }
+
+    #[test]
+    fn test_read_your_writes_in_auto_transaction() {
+        let mut page_handler = prepare_page_handler("test-read-your-writes", Config::default());
+
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::DbAuto);
+        let first = page_handler.store_doc(&mk_document! { "_id": 1 }).unwrap();
+        assert_eq!(page_handler.get_doc_from_ticket(&first).unwrap().unwrap().get("_id").unwrap().unwrap_int(), 1);
+
+        // the page written again is read from the journal, not the cache
+        let second = page_handler.store_doc(&mk_document! { "_id": 2 }).unwrap();
+        assert_eq!(first.pid, second.pid);
+        page_handler.page_cache.clear();
+        assert!(page_handler.get_doc_from_ticket(&first).unwrap().is_some());
+        assert!(page_handler.get_doc_from_ticket(&second).unwrap().is_some());
+        page_handler.auto_commit().unwrap();
+
+        assert!(page_handler.get_doc_from_ticket(&second).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_read_your_writes_after_upgrade() {
+        let mut page_handler = prepare_page_handler("test-read-your-writes-after-upgrade", Config::default());
+        let ticket = page_handler.with_write_transaction(|page_handler| {
+            page_handler.store_doc(&mk_document! { "_id": 1 })
+        }).unwrap();
+
+        // the page is cached by the read
+        page_handler.auto_start_transaction(TransactionType::Read).unwrap();
+        page_handler.set_transaction_state(TransactionState::UserAuto);
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+        assert!(page_handler.page_cache.peek_from_cache(ticket.pid).is_some());
+
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        assert!(page_handler.current_transaction_type() == Some(TransactionType::Write));
+
+        // written to the same page, over the cached copy
+        let stored = page_handler.store_doc(&mk_document! { "_id": 2 }).unwrap();
+        assert_eq!(stored.pid, ticket.pid);
+        let doc = page_handler.get_doc_from_ticket(&stored).unwrap().unwrap();
+        assert_eq!(doc.get("_id").unwrap().unwrap_int(), 2);
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+
+        page_handler.page_cache.clear();
+        assert!(page_handler.get_doc_from_ticket(&stored).unwrap().is_some());
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+
+        page_handler.commit().unwrap();
+        page_handler.set_transaction_state(TransactionState::NoTrans);
+        assert!(page_handler.get_doc_from_ticket(&stored).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_current_transaction_type() {
+        let mut page_handler = prepare_page_handler("test-current-transaction-type", Config::default());
+        assert!(page_handler.current_transaction_type().is_none());
+
+        page_handler.start_transaction(TransactionType::Read).unwrap();
+        assert!(page_handler.current_transaction_type() == Some(TransactionType::Read));
+        page_handler.commit().unwrap();
+        assert!(page_handler.current_transaction_type().is_none());
+
+        page_handler.auto_start_transaction(TransactionType::Read).unwrap();
+        page_handler.set_transaction_state(TransactionState::UserAuto);
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        assert!(page_handler.current_transaction_type() == Some(TransactionType::Write));
+        assert!(page_handler.current_transaction_type() == page_handler.transaction_type());
+
+        page_handler.rollback().unwrap();
+        assert!(page_handler.current_transaction_type().is_none());
+        assert!(page_handler.transaction_type().is_none());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut page_handler = prepare_page_handler("test-dry-run", Config::default());
+        let doc = mk_document! {
+            "content": "hello",
+        };
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+        let frame_count = page_handler.journal_manager.as_ref().unwrap().len();
+
+        page_handler.begin_dry_run().unwrap();
+        assert!(page_handler.is_dry_run());
+        page_handler.free_data_ticket(&ticket).unwrap();
+        for _ in 0..10 {
+            page_handler.alloc_page_id().unwrap();
+        }
+        page_handler.commit().unwrap();
+        assert!(!page_handler.is_dry_run());
+        assert!(page_handler.transaction_type().is_none());
+
+        // nothing is persisted
+        assert_eq!(page_handler.journal_manager.as_ref().unwrap().len(), frame_count);
+        let first_page = page_handler.get_first_page().unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar(), null_page_bar);
+        let stored = page_handler.get_doc_from_ticket(&ticket).unwrap().unwrap();
+        assert_eq!(stored.get("content").unwrap().unwrap_string(), "hello");
+
+        // a normal transaction commits again
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert!(!page_handler.is_dry_run());
+        page_handler.free_data_ticket(&ticket).unwrap();
+        page_handler.commit().unwrap();
+        assert!(page_handler.journal_manager.as_ref().unwrap().len() > frame_count);
+    }
+
+    #[test]
+    fn test_cache_metrics() {
+        let db_path = mk_db_path("test-cache-metrics");
+        let doc = mk_document! {
+            "content": "hello",
+        };
+        let (checkpointed, pending) = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let checkpointed = page_handler.store_doc(&doc).unwrap();
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+
+            // left in the journal
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let pending = page_handler.alloc_page_id().unwrap();
+            let page = RawPage::new(pending, page_handler.page_size);
+            page_handler.pipeline_write_page(&page).unwrap();
+            page_handler.commit().unwrap();
+            (checkpointed, pending)
+        };
+
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        page_handler.reset_cache_metrics();
+
+        page_handler.get_doc_from_ticket(&checkpointed).unwrap().unwrap();
+        page_handler.get_doc_from_ticket(&checkpointed).unwrap().unwrap();
+        let metrics = page_handler.cache_metrics();
+        assert_eq!(metrics.disk_reads, 1);
+        assert_eq!(metrics.hits, 1);
+        assert_eq!(metrics.journal_hits, 0);
+
+        page_handler.reset_cache_metrics();
+        page_handler.pipeline_read_page(pending).unwrap();
+        let metrics = page_handler.cache_metrics();
+        assert_eq!(metrics.journal_hits, 1);
+        assert_eq!(metrics.misses(), 1);
+        assert_eq!(metrics.hits, 0);
+    }
+
+    #[test]
+    fn test_invalid_page_size() {
+        let db_path = mk_db_path("test-invalid-page-size");
+        for page_size in &[0u32, 256, 1000, 4095, 131072] {
+            let err = PageHandler::new(db_path.as_path(), *page_size).err().unwrap();
+            assert!(matches!(err, DbErr::InvalidPageSize(size) if size == *page_size));
+        }
+
+        // the offsets in a page are u16
+        let config = Config {
+            page_checksum: false,
+            ..Config::default()
+        };
+        let err = PageHandler::new_in_memory_with_config(65536, config).err().unwrap();
+        assert!(matches!(err, DbErr::InvalidPageSize(65536)));
+    }
+
+    #[test]
+    fn test_page_size_mismatch() {
+        let db_path = mk_db_path("test-page-size-mismatch");
+        {
+            PageHandler::new(db_path.as_path(), 4096).unwrap();
+        }
+
+        let err = PageHandler::new(db_path.as_path(), 8192).err().unwrap();
+        assert!(matches!(err, DbErr::PageSizeMismatch(8192, 4096)));
+
+        let err = PageHandler::open_read_only(db_path.as_path(), 1024).err().unwrap();
+        assert!(matches!(err, DbErr::PageSizeMismatch(1024, 4096)));
+
+        PageHandler::new(db_path.as_path(), 4096).unwrap();
+    }
+
+    #[test]
+    fn test_not_a_database_file() {
+        let db_path = mk_db_path("test-not-a-database-file");
+        let content: Vec<u8> = (0..(4096 * 4)).map(|_| unsafe { libc::rand() } as u8).collect();
+        std::fs::write(db_path.as_path(), &content).unwrap();
+
+        let err = PageHandler::new(db_path.as_path(), 4096).err().unwrap();
+        assert!(matches!(err, DbErr::NotADatabaseFile));
+
+        let err = PageHandler::open_read_only(db_path.as_path(), 4096).err().unwrap();
+        assert!(matches!(err, DbErr::NotADatabaseFile));
+
+        // a newer format
+        let db_path = mk_db_path("test-unsupported-version");
+        {
+            PageHandler::new(db_path.as_path(), 4096).unwrap();
+        }
+        let mut content = std::fs::read(db_path.as_path()).unwrap();
+        content[32] = 9;
+        content[4096 * 2 + 32] = 9;
+        std::fs::write(db_path.as_path(), &content).unwrap();
+
+        let err = PageHandler::new(db_path.as_path(), 4096).err().unwrap();
+        assert!(matches!(err, DbErr::UnsupportedVersion { found: [9, 0, 0, 1], supported: [0, 0, 0, 1] }));
+    }
+
+    #[test]
+    fn test_migration() {
+        const OLD_VERSION: [u8; 4] = [0, 0, 0, 0];
+
+        let db_path = mk_db_path("test-migration");
+        let pid = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            let pid = alloc_pages(&mut page_handler, 1)[0];
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.pipeline_read_page(0).unwrap());
+            first_page_wrapper.set_version(&OLD_VERSION);
+            page_handler.pipeline_write_page(&first_page_wrapper.0).unwrap();
+            page_handler.commit().unwrap();
+            page_handler.close().unwrap();
+            pid
+        };
+
+        let is_old_version = |result: DbResult<PageHandler>| matches!(
+            result,
+            Err(DbErr::UnsupportedVersion { found: OLD_VERSION, supported: FORMAT_VERSION })
+        );
+        assert!(is_old_version(PageHandler::new(db_path.as_path(), 4096)));
+        assert!(is_old_version(PageHandler::open_read_only(db_path.as_path(), 4096)));
+
+        let runs = Rc::new(Cell::new(0));
+        let mk_migrations = |fails: bool| {
+            let runs = runs.clone();
+            vec![Migration {
+                from: OLD_VERSION,
+                to: FORMAT_VERSION,
+                run: Box::new(move |page_handler: &mut PageHandler| {
+                    runs.set(runs.get() + 1);
+                    let mut page = RawPage::new(pid, page_handler.page_size);
+                    page.put_u32(0xDEAD);
+                    page_handler.pipeline_write_page(&page)?;
+                    if fails {
+                        return Err(DbErr::Busy);
+                    }
+                    Ok(())
+                }),
+            }]
+        };
+
+        // the old version is intact after the failure
+        let result = PageHandler::new_with_migrations(db_path.as_path(), 4096, Config::default(), &mut mk_migrations(true));
+        assert!(matches!(result, Err(DbErr::Busy)));
+        assert!(is_old_version(PageHandler::new(db_path.as_path(), 4096)));
+
+        runs.set(0);
+        let mut page_handler = PageHandler::new_with_migrations(db_path.as_path(), 4096, Config::default(), &mut mk_migrations(false)).unwrap();
+        assert_eq!(runs.get(), 1);
+        assert_eq!(page_handler.pipeline_read_page(pid).unwrap().get_u32(0), 0xDEAD);
+        drop(page_handler);
+
+        // upgraded once
+        let mut page_handler = PageHandler::new_with_migrations(db_path.as_path(), 4096, Config::default(), &mut mk_migrations(false)).unwrap();
+        assert_eq!(runs.get(), 1);
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.pipeline_read_page(0).unwrap());
+        assert_eq!(first_page_wrapper.get_version(), FORMAT_VERSION);
+        page_handler.close().unwrap();
+        PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+    }
+
+    #[test]
+    fn test_custom_page_size() {
+        for page_size in &[512u32, 2048, 65536] {
+            let db_path = mk_db_path(&format!("test-custom-page-size-{}", page_size));
+            let mut tickets = vec![];
+            {
+                let mut page_handler = PageHandler::new(db_path.as_path(), *page_size).unwrap();
+                // spill the free list of the small pages
+                let max_size = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+                let pids = alloc_pages(&mut page_handler, std::cmp::min(max_size + 10, 1000));
+
+                page_handler.start_transaction(TransactionType::Write).unwrap();
+                page_handler.free_pages(&pids).unwrap();
+                for i in 0..50 {
+                    let doc = mk_document! {
+                        "content": i.to_string().repeat(20),
+                    };
+                    tickets.push(page_handler.store_doc(&doc).unwrap());
+                }
+                page_handler.commit().unwrap();
+                page_handler.checkpoint().unwrap();
+            }
+
+            let mut page_handler = PageHandler::new(db_path.as_path(), *page_size).unwrap();
+            for (i, ticket) in tickets.iter().enumerate() {
+                let doc = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+                assert_eq!(doc.get("content").unwrap().unwrap_string(), i.to_string().repeat(20));
+            }
+        }
+    }
+
+    #[test]
+    fn test_backup_to() {
+        let mut page_handler = prepare_page_handler("test-backup-to", Config::default());
+        let dest_path = mk_db_path("test-backup-to-dest");
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut tickets = vec![];
+        for i in 0..20 {
+            let doc = mk_document! {
+                "content": i.to_string().repeat(50),
+            };
+            tickets.push(page_handler.store_doc(&doc).unwrap());
+        }
+        page_handler.commit().unwrap();
+
+        page_handler.backup_to(dest_path.as_path(), false).unwrap();
+
+        let err = page_handler.backup_to(dest_path.as_path(), false).unwrap_err();
+        assert!(matches!(err, DbErr::DestinationExists(_)));
+
+        // pending in the journal
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let doc = mk_document! {
+            "content": "last",
+        };
+        tickets.push(page_handler.store_doc(&doc).unwrap());
+        page_handler.commit().unwrap();
+
+        page_handler.backup_to(dest_path.as_path(), true).unwrap();
+
+        let mut backup = PageHandler::new(dest_path.as_path(), 4096).unwrap();
+        for ticket in &tickets {
+            let expected = page_handler.get_doc_from_ticket(ticket).unwrap().unwrap();
+            let actual = backup.get_doc_from_ticket(ticket).unwrap().unwrap();
+            assert_eq!(
+                actual.get("content").unwrap().unwrap_string(),
+                expected.get("content").unwrap().unwrap_string(),
+            );
+        }
+    }
+
+    fn churn_alloc_count(page_handler: &mut PageHandler) -> u32 {
+        let mut alloc_count = 0;
+        for _ in 0..5 {
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let mut tickets = vec![];
+            for i in 0..20 {
+                let doc = mk_document! {
+                    "content": i.to_string().repeat(1500),
+                };
+                tickets.push(page_handler.store_doc(&doc).unwrap());
+            }
+            alloc_count += page_handler.transaction_alloc_count;
+            page_handler.commit().unwrap();
+
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for ticket in &tickets {
+                page_handler.free_data_ticket(ticket).unwrap();
+            }
+            page_handler.commit().unwrap();
+        }
+        alloc_count
+    }
+
+    fn count_empty_data_pages(page_handler: &mut PageHandler) -> usize {
+        page_handler.iter_pages(false)
+            .map(|page| page.unwrap())
+            .filter(|page| page.data[0..2] == PageType::Data.to_magic())
+            .map(DataPageWrapper::from_raw)
+            .filter(|wrapper| wrapper.is_empty())
+            .count()
+    }
+
+    #[test]
+    fn test_reuse_empty_data_pages() {
+        let mut page_handler = prepare_page_handler("test-free-empty-data-pages", Config::default());
+        let free_alloc_count = churn_alloc_count(&mut page_handler);
+        assert_eq!(count_empty_data_pages(&mut page_handler), 0);
+
+        let mut page_handler = prepare_page_handler("test-reuse-empty-data-pages", Config::default());
+        page_handler.set_reuse_empty_data_pages(true);
+        let reuse_alloc_count = churn_alloc_count(&mut page_handler);
+        assert!(reuse_alloc_count * 2 < free_alloc_count);
+        assert!(count_empty_data_pages(&mut page_handler) > 0);
+    }
+
+    #[test]
+    fn test_iter_pages() {
+        let mut page_handler = prepare_page_handler("test-iter-pages", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let doc = mk_document! {
+            "content": "hello",
+        };
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        let free_pid = page_handler.alloc_page_id().unwrap();
+        page_handler.free_page(free_pid).unwrap();
+
+        // the pending pages in the journal are visible
+        let pids: Vec<u32> = page_handler.iter_pages(false)
+            .map(|page| page.unwrap().page_id)
+            .collect();
+        assert_eq!(pids[0], 0);
+        assert!(pids.contains(&ticket.pid));
+        assert!(!pids.contains(&free_pid));
+
+        page_handler.commit().unwrap();
+
+        let all_pids: Vec<u32> = page_handler.iter_pages(true)
+            .map(|page| page.unwrap().page_id)
+            .collect();
+        assert!(all_pids.contains(&free_pid));
+        assert_eq!(all_pids.len(), pids.len() + 1);
+    }
+
+    #[test]
+    fn test_scan_mode_does_not_fill_cache() {
+        let db_path = mk_db_path("test-scan-mode");
+        let mut tickets = vec![];
+        {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for i in 0..100 {
+                let doc = mk_document! {
+                    "content": i.to_string().repeat(100),
+                };
+                tickets.push(page_handler.store_doc(&doc).unwrap());
+            }
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+        }
+
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+
+        page_handler.begin_scan();
+        for ticket in &tickets {
+            assert!(page_handler.get_doc_from_ticket(ticket).unwrap().is_some());
+        }
+        page_handler.end_scan();
+        assert_eq!(page_handler.page_cache.len(), 0);
+
+        for ticket in &tickets {
+            assert!(page_handler.get_doc_from_ticket(ticket).unwrap().is_some());
+        }
+        assert!(page_handler.page_cache.len() > 0);
+    }
+
+    #[test]
+    fn test_max_alloc_pages_per_txn() {
+        let config = Config {
+            max_alloc_pages_per_txn: Some(5),
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-max-alloc-pages-per-txn", config);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for _ in 0..5 {
+            page_handler.alloc_page_id().unwrap();
+        }
+        match page_handler.alloc_page_id() {
+            Err(DbErr::TransactionAllocLimitExceeded(5)) => (),
+            _ => panic!("the limit should be exceeded"),
+        }
+        assert!(page_handler.transaction_type().is_none());
+
+        // the counter is reset by a new transaction
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for _ in 0..5 {
+            page_handler.alloc_page_id().unwrap();
+        }
+        page_handler.commit().unwrap();
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let config = Config {
+            cache_capacity_bytes: Some(16 * 4096),
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-cache-capacity", config);
+        assert_eq!(page_handler.page_cache.capacity(), 16);
+
+        let pids = alloc_pages(&mut page_handler, 2000);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids {
+            let mut page = RawPage::new(*pid, 4096);
+            page.put_u32(*pid);
+            page_handler.pipeline_write_page(&page).unwrap();
+        }
+        page_handler.commit().unwrap();
+        assert!(page_handler.page_cache.len() <= 16);
+
+        for pid in &pids {
+            let page = page_handler.pipeline_read_page(*pid).unwrap();
+            assert_eq!(page.get_u32(0), *pid);
+        }
+        assert!(page_handler.page_cache.len() <= 16);
+
+        // the header is pinned on open
+        assert!(page_handler.page_cache.is_pinned(0));
+        page_handler.pipeline_read_page(0).unwrap();
+        for pid in &pids {
+            page_handler.pipeline_read_page(*pid).unwrap();
+        }
+        assert!(page_handler.page_cache.peek_from_cache(0).is_some());
+
+        // the capacity is kept after rollback
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.rollback().unwrap();
+        assert_eq!(page_handler.page_cache.capacity(), 16);
+    }
+
+    #[test]
+    fn test_cache_soft_limit() {
+        let mut page_handler = prepare_page_handler("test-cache-soft-limit", Config::default());
+        let pids = alloc_pages(&mut page_handler, 64);
+        for pid in &pids {
+            page_handler.pipeline_read_page(*pid).unwrap();
+        }
+        assert!(page_handler.page_cache.allocated_bytes() > 16 * 4096);
+
+        // the bytes held follow the limit
+        page_handler.set_cache_soft_limit(16 * 4096);
+        for _ in 0..2 {
+            for pid in &pids {
+                page_handler.pipeline_read_page(*pid).unwrap();
+            }
+        }
+        assert!(page_handler.page_cache.allocated_bytes() <= 16 * 4096);
+    }
+
+    #[test]
+    fn test_journal_checkpoint_threshold() {
+        let mut page_handler = prepare_page_handler("test-journal-checkpoint-threshold", Config::default());
+
+        match page_handler.set_journal_checkpoint_threshold(0) {
+            Err(DbErr::InvalidCheckpointThreshold(0)) => (),
+            _ => panic!("0 should be rejected"),
+        }
+
+        assert_eq!(page_handler.journal_checkpoint_threshold(), DEFAULT_JOURNAL_CHECKPOINT_THRESHOLD);
+        page_handler.set_journal_checkpoint_threshold(10).unwrap();
+        assert_eq!(page_handler.journal_checkpoint_threshold(), 10);
+
+        alloc_pages(&mut page_handler, 5);
+        assert!(page_handler.journal_len() > 0);
+
+        // the journal reaches the threshold, checkpointed by commit
+        alloc_pages(&mut page_handler, 5);
+        assert_eq!(page_handler.journal_len(), 0);
+    }
+
+    fn alloc_pages(page_handler: &mut PageHandler, count: usize) -> Vec<u32> {
+        let mut pids = Vec::with_capacity(count);
+        for chunk_begin in (0..count).step_by(1000) {
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for _ in chunk_begin..std::cmp::min(chunk_begin + 1000, count) {
+                pids.push(page_handler.alloc_page_id().unwrap());
+            }
+            page_handler.commit().unwrap();
+        }
+        pids
+    }
+
+    #[test]
+    fn test_free_invalid_pids() {
+        let mut page_handler = prepare_page_handler("test-free-invalid-pids", Config::default());
+        let pids = alloc_pages(&mut page_handler, 3);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert!(matches!(page_handler.free_pages(&[pids[0], 0]), Err(DbErr::CannotFreeHeaderPage)));
+
+        let result = page_handler.free_pages(&[pids[0], null_page_bar]);
+        match result {
+            Err(DbErr::PageIdOutOfRange { pid, max }) => {
+                assert_eq!(pid, null_page_bar);
+                assert_eq!(max, null_page_bar - 1);
+            }
+            _ => panic!("unexpected result: {:?}", result),
+        }
+
+        // the rejected batches are not applied
+        let first_page = page_handler.pipeline_read_page(0).unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_free_list_size(), 0);
+
+        assert_eq!(page_handler.free_pages(&pids).unwrap(), 3);
+        page_handler.commit().unwrap();
+
+        let first_page = page_handler.get_first_page().unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_free_list_size(), 3);
+    }
+
+    #[test]
+    fn test_free_pids_twice() {
+        let mut page_handler = prepare_page_handler("test-free-pids-twice", Config::default());
+        let max_size = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+        let pids = alloc_pages(&mut page_handler, max_size + 10);
+
+        // a repeated pid is freed once
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.free_pages(&[pids[0], pids[1], pids[0]]).unwrap(), 2);
+
+        // in the header
+        assert!(matches!(page_handler.free_page(pids[1]), Err(DbErr::PageAlreadyFree(pid)) if pid == pids[1]));
+        page_handler.commit().unwrap();
+
+        // spilled to the chain
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[2..]).unwrap();
+        let last_pid = *pids.last().unwrap();
+        let result = page_handler.free_pages(&[last_pid]);
+        assert!(matches!(result, Err(DbErr::PageAlreadyFree(pid)) if pid == last_pid), "{:?}", result);
+        page_handler.commit().unwrap();
+
+        // the count of the pages never underflows
+        let mut page_handler = prepare_page_handler("test-free-pids-underflow", Config::default());
+        let pids = alloc_pages(&mut page_handler, 2);
+        page_handler.page_count = 1;
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert!(matches!(page_handler.free_pages(&pids), Err(DbErr::Internal(_))));
+        assert_eq!(page_handler.page_count, 1);
+        page_handler.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_free_list_boundary() {
+        let mut page_handler = prepare_page_handler("test-free-list-boundary", Config::default());
+        let max_size = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+        let mut pids = alloc_pages(&mut page_handler, max_size + 1);
+
+        // fill the header free list exactly
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.free_pages(&pids[0..max_size]).unwrap(), max_size as u32);
+        page_handler.commit().unwrap();
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_free_list_size() as usize, max_size);
+        assert_eq!(first_page_wrapper.get_free_list_page_id(), 0);
+
+        // the next one spills
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.free_page(pids[max_size]).unwrap(), 1);
+        page_handler.commit().unwrap();
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_free_list_page_id(), pids[max_size]);
+
+        let mut reallocated = alloc_pages(&mut page_handler, max_size + 1);
+        pids.sort_unstable();
+        reallocated.sort_unstable();
+        assert_eq!(reallocated, pids);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_free_list_size(), 0);
+        assert_eq!(first_page_wrapper.get_free_list_page_id(), 0);
+    }
+
+    fn count_free_list_pages(page_handler: &mut PageHandler) -> u32 {
+        let first_page = page_handler.get_first_page().unwrap();
+        let mut pid = HeaderPageWrapper::from_raw_page(first_page).get_free_list_page_id();
+        let mut count = 0;
+        while pid != 0 {
+            let page = page_handler.pipeline_read_page(pid).unwrap();
+            pid = FreeListPageWrapper::from_raw_page(page).next_pid();
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn test_merge_free_list_page() {
+        let mut page_handler = prepare_page_handler("test-merge-free-list-page", Config::default());
+        let max_size = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+        let pids = alloc_pages(&mut page_handler, 2000);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids).unwrap();
+        page_handler.commit().unwrap();
+        assert!(count_free_list_pages(&mut page_handler) > 0);
+
+        alloc_pages(&mut page_handler, 1900);
+        assert_eq!(count_free_list_pages(&mut page_handler), 0);
+
+        // the header has room for a sparse free list page
+        let mut page_handler = prepare_page_handler("test-merge-free-list-page-sparse", Config::default());
+        let pids = alloc_pages(&mut page_handler, max_size + 100);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids).unwrap();
+        let first_page = page_handler.get_first_page().unwrap();
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let free_list_pid = first_page_wrapper.get_free_list_page_id();
+        first_page_wrapper.set_free_list_size((max_size - 200) as u32);
+        page_handler.pipeline_write_page(&first_page_wrapper.0).unwrap();
+        page_handler.commit().unwrap();
+        assert_eq!(count_free_list_pages(&mut page_handler), 1);
+
+        let pid = alloc_pages(&mut page_handler, 1)[0];
+        assert_eq!(pid, free_list_pid);
+        assert_eq!(count_free_list_pages(&mut page_handler), 0);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert_eq!(first_page_wrapper.get_free_list_size() as usize, max_size - 200 + 99);
+    }
+
+    #[test]
+    fn test_lazy_free_list() {
+        let db_path = mk_db_path("test-lazy-free-list");
+        let copy_path = mk_db_path("test-lazy-free-list-copy");
+
+        // the pids in the order of popping, the chain is walked from the head
+        let (expected, chain) = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            let max_size = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+            let capacity = FreeListPageWrapper::init(0, page_handler.page_size).capacity() as usize;
+            let pids = alloc_pages(&mut page_handler, max_size + capacity * 3 + 10);
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            page_handler.free_pages(&pids).unwrap();
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+
+            let first_page = page_handler.get_first_page().unwrap();
+            let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+            let mut expected = vec![];
+            let mut chain = vec![];
+            let mut free_list_pid = first_page_wrapper.get_free_list_page_id();
+            while free_list_pid != 0 {
+                let wrapper = FreeListPageWrapper::from_raw_page(page_handler.pipeline_read_page(free_list_pid).unwrap());
+                expected.extend((0..wrapper.size()).rev().map(|index| wrapper.get_content(index)));
+                expected.push(free_list_pid);
+                chain.push((free_list_pid, wrapper.size()));
+                free_list_pid = wrapper.next_pid();
+            }
+            let header_size = first_page_wrapper.get_free_list_size();
+            expected.extend((0..header_size).rev().map(|index| first_page_wrapper.get_free_list_content(index)));
+            assert_eq!(expected.len(), pids.len());
+            (expected, chain)
+        };
+        assert_eq!(chain.len(), 4);
+        std::fs::copy(db_path.as_path(), copy_path.as_path()).unwrap();
+
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let allocated: Vec<u32> = (0..expected.len()).map(|_| page_handler.alloc_page_id().unwrap()).collect();
+        page_handler.commit().unwrap();
+        assert_eq!(allocated, expected);
+        drop(page_handler);
+
+        // the tail of the chain is broken, it's not read until it's popped
+        let (tail_pid, _) = *chain.last().unwrap();
+        let mut content = std::fs::read(copy_path.as_path()).unwrap();
+        content[(tail_pid as usize) * 4096 + 100] ^= 0xFF;
+        std::fs::write(copy_path.as_path(), &content).unwrap();
+
+        let mut page_handler = PageHandler::new(copy_path.as_path(), 4096).unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let before_tail: u32 = chain[0..(chain.len() - 1)].iter().map(|(_, size)| size + 1).sum();
+        for pid in &expected[0..(before_tail as usize)] {
+            assert_eq!(page_handler.alloc_page_id().unwrap(), *pid);
+        }
+        let err = page_handler.alloc_page_id().unwrap_err();
+        assert!(matches!(err, DbErr::PageChecksumMismatch { page_id, .. } if page_id == tail_pid));
+        page_handler.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_open_skips_free_list_chain() {
+        let page_size = 4096;
+        let max_size = HeaderPageWrapper::free_list_max_size(page_size);
+        let capacity = FreeListPageWrapper::init(0, page_size).capacity() as usize;
+
+        // the file of a database with count pages freed
+        let build = |count: usize| {
+            let store = SharedStore::default();
+            let mut page_handler = PageHandler::from_store(
+                Box::new(store.clone()), page_size, Box::new(MemoryPageStore::new()),
+            ).unwrap();
+            let pids = alloc_pages(&mut page_handler, count);
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            page_handler.free_pages(&pids).unwrap();
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+            let chained = page_handler.collect_free_list().unwrap().len() > max_size;
+            drop(page_handler);
+
+            let mut content = vec![0; store.len().unwrap() as usize];
+            store.read_at(0, &mut content).unwrap();
+            (content, chained)
+        };
+
+        // the reads of the main file when it's opened
+        let open = |content: &[u8]| {
+            let reads = Rc::new(Cell::new(0));
+            let mut store = CountingStore {
+                inner: MemoryPageStore::new(),
+                reads: reads.clone(),
+                flushes: Rc::new(Cell::new(0)),
+                syncs: Rc::new(Cell::new(0)),
+                writes: Rc::default(),
+            };
+            store.write_at(0, content).unwrap();
+            let page_handler = PageHandler::from_store(
+                Box::new(store), page_size, Box::new(MemoryPageStore::new()),
+            ).unwrap();
+            (page_handler, reads)
+        };
+
+        let (small, small_chained) = build(10);
+        let (large, large_chained) = build(max_size + capacity * 8);
+        assert!(!small_chained);
+        assert!(large_chained);
+
+        let (_, small_reads) = open(&small);
+        let (mut page_handler, large_reads) = open(&large);
+        assert_eq!(large_reads.get(), small_reads.get());
+
+        // the chain is read when the header list is used up
+        let opened_reads = large_reads.get();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for _ in 0..=max_size {
+            page_handler.alloc_page_id().unwrap();
+        }
+        page_handler.commit().unwrap();
+        assert!(large_reads.get() > opened_reads);
+    }
+
+    #[test]
+    fn test_health_fragmentation_matches_stats() {
+        let mut page_handler = prepare_page_handler("test-health-fragmentation-matches-stats", Config::default());
+        assert_eq!(page_handler.health().fragmentation, 0.0);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for id in 0..20 {
+            let doc = mk_document! {
+                "_id": id,
+                "content": "a".repeat(300),
+            };
+            page_handler.store_doc(&doc).unwrap();
+        }
+        page_handler.commit().unwrap();
+
+        // the estimate is made of the pages counted by the stats
+        let stats = page_handler.stats().unwrap();
+        let tracked_bytes = (stats.tracked_data_pages as f64) * (page_handler.page_size as f64);
+        assert!(stats.free_bytes_estimate > 0);
+        assert_eq!(page_handler.health().fragmentation, (stats.free_bytes_estimate as f64) / tracked_bytes);
+    }
+
+    #[test]
+    fn test_relocate_doc() {
+        let mut page_handler = prepare_page_handler("test-relocate-doc", Config::default());
+        let doc = mk_document! {
+            "_id": 1,
+            "content": "a".repeat(100),
+        };
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+
+        // the source page has enough room, but it's not picked
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let new_ticket = page_handler.relocate_doc(&ticket).unwrap();
+        assert_ne!(new_ticket.pid, ticket.pid);
+        page_handler.commit().unwrap();
+
+        let read_doc = page_handler.get_doc_from_ticket(&new_ticket).unwrap().unwrap();
+        assert_eq!(read_doc.get("content").unwrap().unwrap_string(), "a".repeat(100));
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let free_pids = page_handler.collect_free_pids(&first_page_wrapper).unwrap();
+        assert!(free_pids.contains(&ticket.pid));
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.relocate_doc(&ticket).unwrap_err();
+        assert!(matches!(err, DbErr::DataTicketNotFound { .. }));
+        page_handler.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_blob() {
+        let mut page_handler = prepare_page_handler("test-blob", Config::default());
+        let blob: Vec<u8> = (0..(1024 * 1024)).map(|i| (i % 251) as u8).collect();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_blob(&blob).unwrap();
+        let small_ticket = page_handler.store_blob(b"not a document").unwrap();
+        page_handler.commit().unwrap();
+
+        assert_eq!(page_handler.read_blob(&ticket).unwrap(), blob);
+        assert_eq!(page_handler.read_blob(&small_ticket).unwrap(), b"not a document");
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_data_ticket(&small_ticket).unwrap();
+        page_handler.commit().unwrap();
+
+        let err = page_handler.read_blob(&small_ticket).unwrap_err();
+        assert!(matches!(err, DbErr::DataTicketNotFound { .. }));
+    }
+
+    #[test]
+    fn test_read_blob_stream() {
+        use std::io::Read;
+
+        let mut page_handler = prepare_page_handler("test-read-blob-stream", Config::default());
+        let blob: Vec<u8> = (0..(300 * 1024)).map(|i| (i % 253) as u8).collect();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_blob(&blob).unwrap();
+        let small_ticket = page_handler.store_blob(b"small blob").unwrap();
+        page_handler.commit().unwrap();
+
+        let page_size = page_handler.page_size as usize;
+        let mut streamed = vec![];
+        let mut buffer = vec![0u8; 64 * 1024];
+        {
+            let mut stream = page_handler.read_blob_stream(&ticket).unwrap();
+            loop {
+                let len = stream.read(&mut buffer).unwrap();
+                if len == 0 {
+                    break;
+                }
+                // never more than a page at a time
+                assert!(len <= page_size);
+                streamed.extend_from_slice(&buffer[0..len]);
+            }
+        }
+        assert_eq!(streamed, blob);
+
+        let mut small = vec![];
+        page_handler.read_blob_stream(&small_ticket).unwrap().read_to_end(&mut small).unwrap();
+        assert_eq!(small, b"small blob");
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_data_ticket(&small_ticket).unwrap();
+        page_handler.commit().unwrap();
+
+        let err = page_handler.read_blob_stream(&small_ticket).err().unwrap();
+        assert!(matches!(err, DbErr::DataTicketNotFound { .. }));
+    }
+
+    #[test]
+    fn test_tickets_on_page() {
+        let mut page_handler = prepare_page_handler("test-tickets-on-page", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut tickets = vec![];
+        for i in 0..5 {
+            let doc = mk_document! {
+                "_id": i,
+            };
+            tickets.push(page_handler.store_doc(&doc).unwrap());
+        }
+        page_handler.free_data_ticket(&tickets[1]).unwrap();
+        page_handler.commit().unwrap();
+
+        let pid = tickets[0].pid;
+        assert!(tickets.iter().all(|ticket| ticket.pid == pid));
+
+        let listed = page_handler.tickets_on_page(pid).unwrap();
+        let expected: Vec<DataTicket> = tickets.iter()
+            .enumerate()
+            .filter(|(index, _)| *index != 1)
+            .map(|(_, ticket)| ticket.clone())
+            .collect();
+        assert_eq!(listed, expected);
+
+        let err = page_handler.tickets_on_page(0).unwrap_err();
+        assert!(matches!(err, DbErr::NotADataPage(0)));
+    }
+
+    #[test]
+    fn test_with_write_transaction() {
+        let mut page_handler = prepare_page_handler("test-with-write-transaction", Config::default());
+        let doc = mk_document! {
+            "_id": 1,
+        };
+
+        let ticket = page_handler.with_write_transaction(|page_handler| {
+            page_handler.store_doc(&doc)
+        }).unwrap();
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::NoTrans);
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+
+        // the page allocated before the error is rolled back
+        let mut allocated = 0;
+        let err = page_handler.with_write_transaction(|page_handler| -> DbResult<()> {
+            allocated = page_handler.alloc_page_id()?;
+            Err(DbErr::Busy)
+        }).unwrap_err();
+        assert!(matches!(err, DbErr::Busy));
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::NoTrans);
+
+        let pids = alloc_pages(&mut page_handler, 1);
+        assert_eq!(pids[0], allocated);
+    }
+
+    #[test]
+    fn test_zero_freed_pages() {
+        let config = Config {
+            page_checksum: false,
+            ..Config::default()
+        };
+        let db_path = mk_db_path("test-zero-freed-pages");
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, config).unwrap();
+        page_handler.set_zero_freed_pages(true);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pid = page_handler.alloc_page_id().unwrap();
+        let mut page = RawPage::new(pid, 4096);
+        page.data.fill(0xAB);
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.commit().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_page(pid).unwrap();
+        page_handler.commit().unwrap();
+        page_handler.checkpoint().unwrap();
+
+        let bytes = std::fs::read(db_path.as_path()).unwrap();
+        let offset = (pid as usize) * 4096;
+        assert!(bytes[offset..(offset + 4096)].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_read_page_uncached() {
+        let db_path = mk_db_path("test-read-page-uncached");
+        let pids = {
+            let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let mut pids = vec![];
+            for i in 0..10u8 {
+                let pid = page_handler.alloc_page_id().unwrap();
+                let mut page = RawPage::new(pid, page_handler.page_size);
+                page.data.fill(i + 1);
+                page_handler.pipeline_write_page(&page).unwrap();
+                pids.push(pid);
+            }
+            page_handler.commit().unwrap();
+            pids
+        };
+
+        // the pages are pending in the journal
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        let hot_page = page_handler.pipeline_read_page(pids[0]).unwrap();
+        let cached_len = page_handler.page_cache.len();
+
+        for (i, pid) in pids.iter().enumerate() {
+            let page = page_handler.read_page_uncached(*pid).unwrap();
+            assert!(page.data.iter().all(|byte| *byte == (i as u8) + 1));
+        }
+
+        assert_eq!(page_handler.page_cache.len(), cached_len);
+        assert_eq!(page_handler.page_cache.peek_from_cache(pids[0]).unwrap().data, hot_page.data);
+        assert!(pids[1..].iter().all(|pid| page_handler.page_cache.peek_from_cache(*pid).is_none()));
+    }
+
+    #[test]
+    fn test_close() {
+        let db_path = mk_db_path("test-close");
+        let doc = mk_document! {
+            "_id": 1,
+        };
+
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        let journal_path = page_handler.journal_file_path().unwrap().to_path_buf();
+        page_handler.close().unwrap();
+
+        std::fs::remove_file(journal_path).unwrap();
+
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        assert!(page_handler.recovery_report().integrity.is_none());
+        let read_doc = page_handler.get_doc_from_ticket(&ticket).unwrap().unwrap();
+        assert_eq!(read_doc.get("_id").unwrap().unwrap_int(), 1);
+    }
+
+    #[test]
+    fn test_journal_path() {
+        let db_path = mk_db_path("test-journal-path");
+        let mut journal_dir = env::temp_dir();
+        journal_dir.push("test-journal-path-dir");
+        let _ = std::fs::create_dir(journal_dir.as_path());
+        let journal_path = journal_dir.join("custom.journal");
+        let _ = std::fs::remove_file(journal_path.as_path());
+
+        let doc = mk_document! {
+            "_id": 1,
+        };
+        let ticket = {
+            let mut page_handler = PageHandler::new_with_journal_path(db_path.as_path(), journal_path.as_path(), 4096).unwrap();
+            assert_eq!(page_handler.journal_file_path(), Some(journal_path.as_path()));
+
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let ticket = page_handler.store_doc(&doc).unwrap();
+            page_handler.commit().unwrap();
+            ticket
+        };
+        assert!(journal_path.exists());
+        assert!(!PageHandler::mk_journal_path(db_path.as_path()).exists());
+
+        // the committed frames are only in the custom journal
+        let err = PageHandler::new(db_path.as_path(), 4096).err().unwrap();
+        assert!(matches!(err, DbErr::JournalPathMismatch));
+
+        let mut page_handler = PageHandler::new_with_journal_path(db_path.as_path(), journal_path.as_path(), 4096).unwrap();
+        let read_doc = page_handler.get_doc_from_ticket(&ticket).unwrap().unwrap();
+        assert_eq!(read_doc.get("_id").unwrap().unwrap_int(), 1);
+
+        // nothing is pending after the checkpoint
+        page_handler.checkpoint().unwrap();
+        drop(page_handler);
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_fragmentation_ratio() {
+        let mut page_handler = prepare_page_handler("test-fragmentation-ratio", Config::default());
+        assert_eq!(page_handler.fragmentation_ratio().unwrap(), 0.0);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut tickets = vec![];
+        for i in 0..100 {
+            let doc = mk_document! {
+                "_id": i,
+                "content": "a".repeat(200),
+            };
+            tickets.push(page_handler.store_doc(&doc).unwrap());
+        }
+        page_handler.commit().unwrap();
+        let packed = page_handler.fragmentation_ratio().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for ticket in tickets.iter().step_by(2) {
+            page_handler.free_data_ticket(ticket).unwrap();
+        }
+        page_handler.commit().unwrap();
+        let sparse = page_handler.fragmentation_ratio().unwrap();
+
+        assert!(sparse > packed);
+        assert!(sparse > 0.4 && sparse < 1.0);
+    }
+
+    #[test]
+    fn test_reserve_page() {
+        let db_path = mk_db_path("test-reserve-page");
+        let reserved = {
+            let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let reserved = vec![
+                page_handler.reserve_page().unwrap(),
+                page_handler.reserve_page().unwrap(),
+            ];
+            page_handler.commit().unwrap();
+            reserved
+        };
+
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        assert_eq!(page_handler.reserved_pages().unwrap(), reserved);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.free_page(reserved[0]).unwrap_err();
+        assert!(matches!(err, DbErr::PageReserved(pid) if pid == reserved[0]));
+
+        page_handler.reserve_page().unwrap();
+        let err = page_handler.reserve_page().unwrap_err();
+        assert!(matches!(err, DbErr::ReservedPagesFull(3)));
+
+        page_handler.release_reserved_page(reserved[0]).unwrap();
+        let err = page_handler.release_reserved_page(reserved[0]).unwrap_err();
+        assert!(matches!(err, DbErr::PageNotReserved(_)));
+        page_handler.commit().unwrap();
+
+        let reserved_pages = page_handler.reserved_pages().unwrap();
+        assert_eq!(reserved_pages.len(), 2);
+        assert_eq!(reserved_pages[0], reserved[1]);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        assert!(page_handler.collect_free_pids(&first_page_wrapper).unwrap().contains(&reserved[0]));
+    }
+
+    #[test]
+    fn test_database_locked() {
+        let db_path = mk_db_path("test-database-locked");
+
+        let page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        match PageHandler::new(db_path.as_path(), 4096) {
+            Err(DbErr::DatabaseLocked { path }) => assert_eq!(path, db_path.to_string_lossy()),
+            _ => panic!("the second writer should be rejected"),
+        }
+        assert!(matches!(
+            PageHandler::open_read_only(db_path.as_path(), 4096),
+            Err(DbErr::DatabaseLocked { .. })
+        ));
+
+        // released on close
+        page_handler.close().unwrap();
+        let reader = PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+        let another_reader = PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+        assert!(matches!(
+            PageHandler::new(db_path.as_path(), 4096),
+            Err(DbErr::DatabaseLocked { .. })
+        ));
+
+        // released on drop
+        drop(reader);
+        drop(another_reader);
+        PageHandler::new(db_path.as_path(), 4096).unwrap();
+    }
+
+    #[test]
+    fn test_changed_pages_since() {
+        let db_path = mk_db_path("test-changed-pages-since");
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        let pids = alloc_pages(&mut page_handler, 10);
+
+        // everything is copied by the first backup
+        let null_page_bar = pids[9] + 1;
+        let all_pages: Vec<u32> = (0..null_page_bar).collect();
+        assert_eq!(page_handler.changed_pages_since(BackupMarker::default()).unwrap(), all_pages);
+        let marker = page_handler.current_backup_marker();
+        assert!(marker.sequence > 0);
+        assert!(page_handler.changed_pages_since(marker).unwrap().is_empty());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in [pids[2], pids[7]] {
+            let mut page = RawPage::new(pid, 4096);
+            page.put_u32(pid);
+            page_handler.pipeline_write_page(&page).unwrap();
+        }
+        page_handler.commit().unwrap();
+        assert_eq!(page_handler.changed_pages_since(marker).unwrap(), vec![pids[2], pids[7]]);
+
+        // the rolled back writes are not changes
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&RawPage::new(pids[4], 4096)).unwrap();
+        page_handler.free_pages(&[pids[5]]).unwrap();
+        page_handler.rollback().unwrap();
+        assert_eq!(page_handler.changed_pages_since(marker).unwrap(), vec![pids[2], pids[7]]);
+
+        // the freed page comes with the header holding the free list
+        let marker = page_handler.current_backup_marker();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&[pids[5]]).unwrap();
+        page_handler.commit().unwrap();
+        assert_eq!(page_handler.changed_pages_since(marker).unwrap(), vec![0, pids[5]]);
+
+        // kept after reopening
+        page_handler.close().unwrap();
+        let mut page_handler = PageHandler::open_read_only(db_path.as_path(), 4096).unwrap();
+        assert_eq!(page_handler.current_backup_marker().map_id, marker.map_id);
+        assert_eq!(page_handler.changed_pages_since(marker).unwrap(), vec![0, pids[5]]);
+        drop(page_handler);
+
+        // a marker of the database used to be at the path
+        let db_path = mk_db_path("test-changed-pages-since");
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        assert_ne!(page_handler.current_backup_marker().map_id, marker.map_id);
+        let changed = page_handler.changed_pages_since(marker).unwrap();
+        assert_eq!(changed.len() as u32, HeaderPageWrapper::from_raw_page(page_handler.pipeline_read_page(0).unwrap()).get_null_page_bar());
+    }
+
+    #[test]
+    fn test_data_page_policy() {
+        fn kept_pages(name: &str, min_remain_size: u32) -> usize {
+            let mut config = Config::default();
+            config.data_page_policy.min_remain_size = min_remain_size;
+            let mut page_handler = prepare_page_handler(name, config);
+
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for i in 0..200 {
+                let doc = mk_document! {
+                    "_id": i,
+                    "content": "a".repeat(300 + (i as usize) * 7 % 50),
+                };
+                page_handler.store_doc(&doc).unwrap();
+            }
+            page_handler.commit().unwrap();
+
+            page_handler.data_page_map.values().map(|pids| pids.len()).sum()
+        }
+
+        let kept_by_default = kept_pages("test-data-page-policy-default", 16);
+        assert_eq!(Config::default().data_page_policy.min_remain_size, 16);
+        assert!(kept_pages("test-data-page-policy-tiny", 1) > kept_by_default);
+    }
+
+    #[test]
+    fn test_free_page_count() {
+        let mut page_handler = prepare_page_handler("test-free-page-count", Config::default());
+        assert_eq!(page_handler.free_page_count().unwrap(), 0);
+
+        // more than the header holds
+        let max_size = HeaderPageWrapper::free_list_max_size(4096);
+        let pids = alloc_pages(&mut page_handler, max_size + 100);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[0..10]).unwrap();
+        assert_eq!(page_handler.free_page_count().unwrap(), 10);
+        page_handler.free_pages(&pids[10..]).unwrap();
+        assert_eq!(page_handler.free_page_count().unwrap() as usize, pids.len());
+        page_handler.rollback().unwrap();
+        assert_eq!(page_handler.free_page_count().unwrap(), 0);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[0..10]).unwrap();
+        page_handler.commit().unwrap();
+        assert_eq!(page_handler.free_page_count().unwrap(), 10);
+        assert_eq!(page_handler.free_page_count().unwrap(), page_handler.stats().unwrap().free_pages);
+    }
+
+    #[test]
+    fn test_open_salvage() {
+        let db_path = mk_db_path("test-open-salvage");
+
+        let pids = {
+            let mut page_handler = PageHandler::new(&db_path, 4096).unwrap();
+            let pids = alloc_pages(&mut page_handler, 10);
+
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            page_handler.free_pages(&pids[5..]).unwrap();
+
+            // not referred by the trees, but not blank
+            let mut page = RawPage::new(pids[0], page_handler.page_size);
+            page.data[0..2].copy_from_slice(&PageType::BTreeNode.to_magic());
+            page_handler.pipeline_write_page(&page).unwrap();
+
+            // the free list refers to a page beyond the null page bar
+            let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.get_first_page().unwrap());
+            first_page_wrapper.set_free_list_content(0, 9999);
+            page_handler.pipeline_write_page(&first_page_wrapper.0).unwrap();
+            page_handler.commit().unwrap();
+
+            page_handler.close().unwrap();
+            pids
+        };
+
+        let (mut page_handler, report) = PageHandler::open_salvage(&db_path, 4096).unwrap();
+        assert!(!report.is_clean());
+        assert!(report.anomalies.contains(&IntegrityAnomaly::InvalidFreePid { pid: 9999 }));
+        assert!(report.free_list_rebuilt);
+        assert!(report.recovered_free_pages >= 9);
+        assert_eq!(report.questionable_pages, vec![pids[0]]);
+        assert!(report.unreadable_pages.is_empty());
+
+        assert!(page_handler.check_integrity().unwrap().is_ok());
+
+        let null_page_bar = HeaderPageWrapper::from_raw_page(page_handler.get_first_page().unwrap()).get_null_page_bar();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for _ in 0..report.recovered_free_pages {
+            let pid = page_handler.alloc_page_id().unwrap();
+            assert!(pid < null_page_bar);
+            assert_ne!(pid, pids[0]);
+        }
+        page_handler.commit().unwrap();
+        page_handler.close().unwrap();
+
+        // nothing to salvage the second time
+        let (_, report) = PageHandler::open_salvage(&db_path, 4096).unwrap();
+        assert!(!report.free_list_rebuilt);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_page_compression() {
+        let db_path = mk_db_path("test-page-compression");
+
+        let mut config = Config::default();
+        config.page_compression = true;
+        let mut page_handler = PageHandler::new_with_config(&db_path, 4096, config).unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pid = page_handler.alloc_page_id().unwrap();
+        let mut page = RawPage::new(pid, page_handler.page_size);
+        page.data[0..2].copy_from_slice(&PageType::Data.to_magic());
+        let text = b"{\"content\": \"a text-heavy document\"} ".repeat(90);
+        page.data[8..(8 + text.len())].copy_from_slice(&text);
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.commit().unwrap();
+        page_handler.checkpoint().unwrap();
+
+        // compressed in the main file
+        let mut stored = RawPage::new(pid, 4096);
+        stored.read_from_file(page_handler.file.as_ref(), (pid as u64) * 4096).unwrap();
+        assert_eq!(stored.data[0], 0xFE);
+        assert_eq!(page_handler.read_page_uncached(pid).unwrap().data, page.data);
+        page_handler.close().unwrap();
+
+        // the header records it
+        let mut page_handler = PageHandler::new(&db_path, 4096).unwrap();
+        assert_eq!(page_handler.pipeline_read_page(pid).unwrap().data, page.data);
+        page_handler.close().unwrap();
+
+        let mut page_handler = PageHandler::open_read_only(&db_path, 4096).unwrap();
+        assert_eq!(page_handler.pipeline_read_page(pid).unwrap().data, page.data);
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn test_page_compression_not_supported() {
+        let config = Config {
+            page_compression: true,
+            ..Config::default()
+        };
+        let err = PageHandler::new_in_memory_with_config(4096, config).err().unwrap();
+        assert!(matches!(err, DbErr::CompressionNotSupported));
+    }
+
+    #[test]
+    fn test_collect_free_list() {
+        let mut page_handler = prepare_page_handler("test-collect-free-list", Config::default());
+        assert!(page_handler.collect_free_list().unwrap().is_empty());
+
+        let header_capacity = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+        let pids = alloc_pages(&mut page_handler, 2000);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids).unwrap();
+        page_handler.commit().unwrap();
+
+        // the first spilled page is filled, then the next one becomes the head
+        let first_list_page = FreeListPageWrapper::from_raw_page(page_handler.pipeline_read_page(pids[header_capacity]).unwrap());
+        let first_end = header_capacity + 1 + (first_list_page.capacity() as usize);
+        let mut expected: Vec<u32> = pids[0..header_capacity].to_vec();
+        expected.extend_from_slice(&pids[first_end..]);
+        expected.extend_from_slice(&pids[header_capacity..first_end]);
+
+        let free_list = page_handler.collect_free_list().unwrap();
+        assert_eq!(free_list, expected);
+        assert_eq!(free_list.len() as u32, page_handler.free_page_count().unwrap());
+
+        // a loop in the chain
+        let mut head = FreeListPageWrapper::from_raw_page(page_handler.pipeline_read_page(pids[first_end]).unwrap());
+        head.set_next_pid(pids[first_end]);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&head.0).unwrap();
+        page_handler.commit().unwrap();
+
+        let err = page_handler.collect_free_list().unwrap_err();
+        assert!(matches!(err, DbErr::BrokenFreeList(pid) if pid == pids[first_end]));
+        let err = page_handler.free_page_count().unwrap_err();
+        assert!(matches!(err, DbErr::BrokenFreeList(pid) if pid == pids[first_end]));
+        let err = page_handler.stats().unwrap_err();
+        assert!(matches!(err, DbErr::BrokenFreeList(pid) if pid == pids[first_end]));
+
+        // a page of another type in the chain
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&RawPage::new(pids[first_end], page_handler.page_size)).unwrap();
+        page_handler.commit().unwrap();
+        let err = page_handler.stats().unwrap_err();
+        assert!(matches!(err, DbErr::BrokenFreeList(pid) if pid == pids[first_end]));
+    }
+
+    #[test]
+    fn test_alloc_with_corrupt_free_list_size() {
+        let mut page_handler = prepare_page_handler("test-alloc-corrupt-free-list-size", Config::default());
+        let pids = alloc_pages(&mut page_handler, 2);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[0..1]).unwrap();
+        page_handler.commit().unwrap();
+
+        // the popped slot of the header lies past the page
+        update_header(&mut page_handler, |wrapper| wrapper.set_free_list_size(u32::MAX));
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.alloc_page_id().unwrap_err();
+        assert!(matches!(err, DbErr::BrokenFreeList(0)));
+        page_handler.rollback().unwrap();
+
+        // the popped slot of the spilled page lies past the page
+        update_header(&mut page_handler, |wrapper| {
+            wrapper.set_free_list_size(0);
+            wrapper.set_free_list_page_id(pids[1]);
+        });
+        let mut free_list_page = FreeListPageWrapper::init(pids[1], page_handler.page_size);
+        free_list_page.0.seek(4);
+        free_list_page.0.put_u32(u32::MAX);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&free_list_page.0).unwrap();
+        page_handler.commit().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.alloc_page_id().unwrap_err();
+        assert!(matches!(err, DbErr::BrokenFreeList(pid) if pid == pids[1]));
+        page_handler.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_create_and_open() {
+        let db_path = mk_db_path("test-create-and-open");
+
+        // absent
+        let err = PageHandler::open(&db_path, 4096).err().unwrap();
+        assert!(matches!(err, DbErr::NotFound(_)));
+        assert!(!db_path.exists());
+
+        let mut page_handler = PageHandler::create(&db_path, 4096).unwrap();
+        let pids = alloc_pages(&mut page_handler, 3);
+        page_handler.close().unwrap();
+
+        // present
+        let err = PageHandler::create(&db_path, 4096).err().unwrap();
+        assert!(matches!(err, DbErr::AlreadyExists(_)));
+
+        let mut page_handler = PageHandler::open(&db_path, 4096).unwrap();
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.get_first_page().unwrap());
+        assert_eq!(first_page_wrapper.get_null_page_bar(), pids[2] + 1);
+        page_handler.close().unwrap();
+
+        // new creates or opens
+        PageHandler::new(&db_path, 4096).unwrap().close().unwrap();
+        let db_path = mk_db_path("test-create-and-open-new");
+        PageHandler::new(&db_path, 4096).unwrap().close().unwrap();
+        assert!(db_path.exists());
+    }
+
+    #[test]
+    fn test_high_water_pid() {
+        let mut page_handler = prepare_page_handler("test-high-water-pid", Config::default());
+        let origin = page_handler.high_water_pid().unwrap();
+
+        let pids = alloc_pages(&mut page_handler, 10);
+        assert_eq!(page_handler.high_water_pid().unwrap(), pids[9]);
+        assert!(pids[9] > origin);
+
+        // the allocation is not committed
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pid = page_handler.alloc_page_id().unwrap();
+        assert_eq!(page_handler.high_water_pid().unwrap(), pid);
+        page_handler.commit().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids).unwrap();
+        page_handler.free_page(pid).unwrap();
+        page_handler.commit().unwrap();
+        assert_eq!(page_handler.high_water_pid().unwrap(), pid);
+
+        // the freed pages are reused below it
+        let reused = alloc_pages(&mut page_handler, 5);
+        assert!(reused.iter().all(|reused_pid| *reused_pid <= pid));
+        assert_eq!(page_handler.high_water_pid().unwrap(), pid);
+    }
+
+    #[test]
+    fn test_checkpoint_write_batching() {
+        let writes: Rc<RefCell<Vec<(u64, usize)>>> = Rc::default();
+        let page_layout = PageLayout::new(4096, true);
+        let mut store = CountingStore {
+            inner: MemoryPageStore::new(),
+            reads: Rc::new(Cell::new(0)),
+            flushes: Rc::new(Cell::new(0)),
+            syncs: Rc::new(Cell::new(0)),
+            writes: writes.clone(),
+        };
+        let (_, page_count, db_file_size) = PageHandler::init_db(&mut store, page_layout, Config::default().init_block_count).unwrap();
+        let journal_manager = JournalManager::open_in_memory(page_layout, db_file_size).unwrap();
+        let mut page_handler = PageHandler::from_parts(
+            Box::new(store), page_layout, page_count,
+            Some(Box::new(journal_manager)), None,
+            Config::default(),
+        ).unwrap();
+
+        let pids = alloc_pages(&mut page_handler, 3);
+        page_handler.checkpoint().unwrap();
+
+        let mut page = RawPage::new(pids[0], page_layout.body_size());
+        for round in 0..50u32 {
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            page.data[0..4].copy_from_slice(&round.to_be_bytes());
+            page_handler.pipeline_write_page(&page).unwrap();
+            page_handler.commit().unwrap();
+        }
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids[1..] {
+            page_handler.pipeline_write_page(&RawPage::new(*pid, page_layout.body_size())).unwrap();
+        }
+        page_handler.commit().unwrap();
+
+        writes.borrow_mut().clear();
+        page_handler.checkpoint().unwrap();
+
+        // the pages in a row are written at once, the last frame wins
+        let offset = page_layout.offset_of(pids[0]);
+        let page_writes: Vec<(u64, usize)> = writes.borrow().iter()
+            .filter(|(write_offset, _)| *write_offset != 0 && *write_offset != page_layout.offset_of(SHADOW_HEADER_PID))
+            .copied()
+            .collect();
+        assert_eq!(page_writes, vec![(offset, 3 * 4096)]);
+
+        let stored = page_handler.read_page_uncached(pids[0]).unwrap();
+        assert_eq!(stored.data[0..4], 49u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_validate_doc() {
+        let valid = mk_document! {
+            "_id": 1,
+            "name": "PoloDB",
+            "tags": mk_document! {
+                "embedded": "yes",
+            },
+        };
+        PageHandler::validate_doc(&valid).unwrap();
+
+        let mut invalid = Document::new_without_id();
+        invalid.insert("na\0me".into(), Value::from(1));
+        let err = PageHandler::validate_doc(&invalid).unwrap_err();
+        assert!(matches!(err, DbErr::ValidationError(_)));
+    }
+
+    #[test]
+    fn test_from_store() {
+        let store = SharedStore::default();
+        let journal_store = SharedStore::default();
+
+        let mut page_handler = PageHandler::from_store(
+            Box::new(store.clone()), 4096, Box::new(journal_store.clone()),
+        ).unwrap();
+        let pids = alloc_pages(&mut page_handler, 1);
+
+        let mut page = RawPage::new(pids[0], page_handler.page_size);
+        page.data[0..4].copy_from_slice(&[1, 2, 3, 4]);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.commit().unwrap();
+
+        // the commit is only in the journal store
+        assert!(journal_store.len().unwrap() > 0);
+        drop(page_handler);
+
+        let mut page_handler = PageHandler::from_store(
+            Box::new(store.clone()), 4096, Box::new(journal_store.clone()),
+        ).unwrap();
+        let read = page_handler.pipeline_read_page(pids[0]).unwrap();
+        assert_eq!(read.data, page.data);
+
+        page_handler.checkpoint().unwrap();
+        drop(page_handler);
+
+        let mut page_handler = PageHandler::from_store(
+            Box::new(store), 4096, Box::new(MemoryPageStore::new()),
+        ).unwrap();
+        let read = page_handler.pipeline_read_page(pids[0]).unwrap();
+        assert_eq!(read.data, page.data);
+    }
+
+    #[test]
+    fn test_allocation_order_lowest_first() {
+        let config = Config {
+            allocation_order: AllocationOrder::LowestFirst,
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-allocation-order-lowest-first", config);
+
+        let free_list_max_size = HeaderPageWrapper::free_list_max_size(page_handler.page_size);
+        let pids = alloc_pages(&mut page_handler, free_list_max_size + 20);
+
+        // free in a scattered order, some are spilled to the chain
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for index in [3, 7, 0, 9, 5, 1, 8, 2, 6, 4].iter() {
+            page_handler.free_page(pids[*index]).unwrap();
+        }
+        page_handler.free_pages(&pids[10..]).unwrap();
+        page_handler.commit().unwrap();
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut reallocated = vec![];
+        for _ in 0..5 {
+            reallocated.push(page_handler.alloc_page_id().unwrap());
+        }
+        page_handler.commit().unwrap();
+        assert_eq!(reallocated, pids[0..5].to_vec());
+
+        // the smallest pid is reused first, not the last freed
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_page(pids[4]).unwrap();
+        page_handler.free_page(pids[2]).unwrap();
+        assert_eq!(page_handler.alloc_page_id().unwrap(), pids[2]);
+        page_handler.commit().unwrap();
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let db_path = mk_db_path("test-replace-with");
+        let other_path = mk_db_path("test-replace-with-other");
+
+        let write_marker = |page_handler: &mut PageHandler, marker: u8| -> u32 {
+            let pid = alloc_pages(page_handler, 1)[0];
+            let mut page = RawPage::new(pid, page_handler.page_size);
+            page.data[0] = marker;
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            page_handler.pipeline_write_page(&page).unwrap();
+            page_handler.commit().unwrap();
+            pid
+        };
+
+        let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+        write_marker(&mut page_handler, 1);
+
+        // the new contents are only in the journal of the other database
+        let mut other = PageHandler::new(other_path.as_path(), 4096).unwrap();
+        let mut new_pids = vec![];
+        for _ in 0..3 {
+            new_pids.push(write_marker(&mut other, 2));
+        }
+        drop(other);
+
+        page_handler.replace_with(other_path.as_path()).unwrap();
+        assert!(!other_path.exists());
+        assert!(!PageHandler::mk_journal_path(other_path.as_path()).exists());
+
+        for pid in &new_pids {
+            assert_eq!(page_handler.pipeline_read_page(*pid).unwrap().data[0], 2);
+        }
+        assert_eq!(page_handler.high_water_pid().unwrap(), *new_pids.last().unwrap());
+
+        // a missing database is not swapped in, the handler is kept
+        let result = page_handler.replace_with(other_path.as_path());
+        assert!(matches!(result, Err(DbErr::NotFound(_))));
+        assert_eq!(page_handler.pipeline_read_page(new_pids[0]).unwrap().data[0], 2);
+        write_marker(&mut page_handler, 3);
+        drop(page_handler);
+
+        let mut in_memory = PageHandler::new_in_memory(4096).unwrap();
+        assert!(matches!(in_memory.replace_with(db_path.as_path()), Err(DbErr::NoDatabaseFile)));
+
+        let encrypted_path = mk_db_path("test-replace-with-encrypted");
+        let mut encrypted = PageHandler::new_encrypted(encrypted_path.as_path(), 4096, &[7; 32]).unwrap();
+        let result = encrypted.replace_with(db_path.as_path());
+        assert!(matches!(result, Err(DbErr::CannotReplaceEncrypted)));
+        write_marker(&mut encrypted, 4);
+        assert!(db_path.exists());
+    }
+
+    #[test]
+    fn test_skip_unchanged_write() {
+        let mut page_handler = prepare_page_handler("test-skip-unchanged-write", Config::default());
+        let pid = alloc_pages(&mut page_handler, 1)[0];
+
+        let mut page = RawPage::new(pid, page_handler.page_size);
+        page.data[0..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        let frame_count = |page_handler: &PageHandler| {
+            page_handler.journal_manager.as_ref().unwrap().transaction_write_counts().unwrap().0
+        };
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        assert_eq!(frame_count(&page_handler), 1);
+
+        // a single byte differs
+        let last = page.data.len() - 1;
+        page.data[last] = 1;
+        page_handler.pipeline_write_page(&page).unwrap();
+        assert_eq!(frame_count(&page_handler), 2);
+        page_handler.commit().unwrap();
+
+        // the committed image is unchanged too
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        assert_eq!(frame_count(&page_handler), 0);
+        page_handler.commit().unwrap();
+
+        assert_eq!(page_handler.read_page_uncached(pid).unwrap().data, page.data);
+
+        // still no write outside of a transaction
+        assert!(matches!(page_handler.pipeline_write_page(&page), Err(DbErr::CannotWriteDbWithoutTransaction)));
+    }
+
+    #[test]
+    fn test_data_page_ids() {
+        let mut page_handler = prepare_page_handler("test-data-page-ids", Config::default());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut data_pids = std::collections::BTreeSet::new();
+        for index in 0..200 {
+            let doc = mk_document! {
+                "_id": index,
+                "content": "x".repeat(100),
+            };
+            data_pids.insert(page_handler.store_doc(&doc).unwrap().pid);
+        }
+        let blob = mk_document! {
+            "content": "y".repeat(10000),
+        };
+        page_handler.store_doc(&blob).unwrap();
+        page_handler.commit().unwrap();
+        let other_pids = alloc_pages(&mut page_handler, 2);
+        assert!(data_pids.len() > 2);
+
+        // a freed data page keeps its magic
+        let freed_pid = *data_pids.iter().next().unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_page(freed_pid).unwrap();
+        page_handler.commit().unwrap();
+        data_pids.remove(&freed_pid);
+
+        let found = page_handler.data_page_ids().unwrap();
+        for pid in &data_pids {
+            assert!(found.contains(pid));
+        }
+        assert!(!found.contains(&freed_pid));
+        for pid in &other_pids {
+            assert!(!found.contains(pid));
+        }
+
+        let overflow_pids: Vec<u32> = page_handler.iter_pages(false)
+            .map(|page| page.unwrap())
+            .filter(|page| page.data[0..2] == PageType::OverflowData.to_magic())
+            .map(|page| page.page_id)
+            .collect();
+        assert!(!overflow_pids.is_empty());
+        assert!(overflow_pids.iter().all(|pid| !found.contains(pid)));
+    }
+
+    #[test]
+    fn test_upgrade_transaction_to_write() {
+        let mut page_handler = prepare_page_handler("test-upgrade-transaction-to-write", Config::default());
+        let pid = alloc_pages(&mut page_handler, 1)[0];
+
+        assert!(matches!(page_handler.upgrade_transaction_to_write(), Err(DbErr::NotInReadTransaction)));
+
+        page_handler.start_transaction(TransactionType::Read).unwrap();
+        let mut page = page_handler.pipeline_read_page(pid).unwrap();
+        assert!(matches!(page_handler.pipeline_write_page(&page), Err(DbErr::CannotWriteDbWithoutTransaction)));
+
+        page_handler.upgrade_transaction_to_write().unwrap();
+        assert!(page_handler.transaction_type() == Some(TransactionType::Write));
+        page.data[0..3].copy_from_slice(&[1, 2, 3]);
+        page_handler.pipeline_write_page(&page).unwrap();
+
+        // already writing
+        assert!(matches!(page_handler.upgrade_transaction_to_write(), Err(DbErr::NotInReadTransaction)));
+        page_handler.commit().unwrap();
+
+        assert_eq!(page_handler.read_page_uncached(pid).unwrap().data[0..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_bounded_replay() {
+        let store = SharedStore::default();
+        let journal_store = SharedStore::default();
+        let open = |journal_store: SharedStore| PageHandler::from_store(
+            Box::new(store.clone()), 4096, Box::new(journal_store),
+        ).unwrap();
+
+        let page_count = REPLAY_BOUND_PAGES * 3;
+        let mut page_handler = open(journal_store.clone());
+        page_handler.set_journal_checkpoint_threshold(u32::MAX).unwrap();
+        let pids = alloc_pages(&mut page_handler, page_count);
+        for chunk in pids.chunks(100) {
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for pid in chunk {
+                let mut page = RawPage::new(*pid, page_handler.page_size);
+                page.data[0..4].copy_from_slice(&pid.to_be_bytes());
+                page_handler.pipeline_write_page(&page).unwrap();
+            }
+            page_handler.commit().unwrap();
+        }
+        // a crash, nothing is checkpointed
+        drop(page_handler);
+
+        let check_pages = |page_handler: &mut PageHandler| {
+            for pid in &pids {
+                let page = page_handler.pipeline_read_page(*pid).unwrap();
+                assert_eq!(page.data[0..4], pid.to_be_bytes());
+            }
+        };
+
+        let mut page_handler = open(journal_store.clone());
+        let report = page_handler.recovery_report();
+        assert!(report.frames_recovered as usize > page_count);
+        assert!(report.pages_replayed as usize >= page_count - REPLAY_BOUND_PAGES);
+        let offset_map_len = page_handler.journal_manager.as_ref().unwrap().offset_map.len();
+        assert!(offset_map_len <= REPLAY_BOUND_PAGES + 100);
+        let replayed = page_handler.page_layout.read_page(pids[0], &store).unwrap();
+        assert_eq!(replayed.data[0..4], pids[0].to_be_bytes());
+        check_pages(&mut page_handler);
+
+        // the replay is interrupted, it's replayed again
+        drop(page_handler);
+        let mut page_handler = open(journal_store.clone());
+        check_pages(&mut page_handler);
+
+        page_handler.checkpoint().unwrap();
+        drop(page_handler);
+        let mut page_handler = open(SharedStore::default());
+        check_pages(&mut page_handler);
+    }
+
+    #[test]
+    fn test_transaction_state_kind() {
+        let mut page_handler = prepare_page_handler("test-transaction-state-kind", Config::default());
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::NoTrans);
+
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::DbAuto);
+        page_handler.alloc_page_id().unwrap();
+        page_handler.auto_commit().unwrap();
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::NoTrans);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.set_transaction_state(TransactionState::User);
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::User);
+        page_handler.alloc_page_id().unwrap();
+
+        // not committed by an operation in a user transaction
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        page_handler.auto_commit().unwrap();
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::User);
+        page_handler.commit().unwrap();
+        page_handler.set_transaction_state(TransactionState::NoTrans);
+
+        page_handler.start_transaction(TransactionType::Read).unwrap();
+        page_handler.set_transaction_state(TransactionState::UserAuto);
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::UserAuto);
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        assert!(page_handler.transaction_type() == Some(TransactionType::Write));
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::UserAuto);
+        page_handler.rollback().unwrap();
+        page_handler.set_transaction_state(TransactionState::NoTrans);
+        assert_eq!(page_handler.transaction_state_kind(), TransactionStateKind::NoTrans);
+    }
+
+    #[test]
+    fn test_growth_strategy() {
+        let count_grows = |name: &str, growth_strategy: GrowthStrategy| -> (u32, u64) {
+            let config = Config {
+                growth_strategy,
+                ..Config::default()
+            };
+            let mut page_handler = prepare_page_handler(name, config);
+
+            let mut grows = 0;
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for _ in 0..10000 {
+                let db_size = page_handler.last_commit_db_size;
+                page_handler.alloc_page_id().unwrap();
+                if page_handler.last_commit_db_size != db_size {
+                    grows += 1;
+                }
+            }
+            page_handler.commit().unwrap();
+            page_handler.checkpoint().unwrap();
+
+            (grows, page_handler.file.len().unwrap())
+        };
+
+        let (fixed_grows, _) = count_grows("test-growth-strategy-fixed", GrowthStrategy::default());
+        assert_eq!(fixed_grows, 625);
+
+        // 16 pages are doubled to 16384 pages
+        let (geometric_grows, file_size) = count_grows("test-growth-strategy-geometric", GrowthStrategy::Geometric {
+            factor: 2,
+            cap: u32::MAX,
+        });
+        assert_eq!(geometric_grows, 10);
+        assert_eq!(file_size, 16384 * 4096);
+
+        // linear by the cap after reaching it
+        let (capped_grows, _) = count_grows("test-growth-strategy-capped", GrowthStrategy::Geometric {
+            factor: 2,
+            cap: 1024,
+        });
+        assert_eq!(capped_grows, 6 + 9);
+
+        assert_eq!(GrowthStrategy::Geometric { factor: 1, cap: 1024 }.grow_pages(16), 1);
+        assert_eq!(GrowthStrategy::Fixed(0).grow_pages(16), 1);
+    }
+
+    #[test]
+    fn test_broken_data_page_map() {
+        let mut page_handler = prepare_page_handler("test-broken-data-page-map", Config::default());
+        page_handler.data_page_map.insert(1024, vec![]);
+
+        let doc = mk_document! {
+            "content": "a".repeat(100),
+        };
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.store_doc(&doc).unwrap_err();
+        assert!(matches!(err, DbErr::Internal(_)));
+        page_handler.rollback().unwrap();
+
+        // it goes on after the map is reset
+        page_handler.data_page_map.clear();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let ticket = page_handler.store_doc(&doc).unwrap();
+        page_handler.commit().unwrap();
+        assert!(page_handler.get_doc_from_ticket(&ticket).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_free_10k_pages() {
+        let mut page_handler = prepare_page_handler("test-free-10k-pages", Config::default());
+        let mut pids = alloc_pages(&mut page_handler, 10000);
+
+        for chunk in pids.chunks(1000) {
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            for pid in chunk {
+                page_handler.free_page(*pid).unwrap();
+            }
+            page_handler.commit().unwrap();
+        }
+
+        let mut reallocated = alloc_pages(&mut page_handler, 10000);
+        pids.sort_unstable();
+        reallocated.sort_unstable();
+        assert_eq!(reallocated, pids);
+
+        // the free list is drained, allocate from the null page bar
+        let first_page = page_handler.get_first_page().unwrap();
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+        let next = alloc_pages(&mut page_handler, 1);
+        assert_eq!(next, vec![null_page_bar]);
+    }
+
+    fn update_header<T: FnOnce(&mut HeaderPageWrapper)>(page_handler: &mut PageHandler, update: T) {
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let first_page = page_handler.get_first_page().unwrap();
+        let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        update(&mut first_page_wrapper);
+        page_handler.pipeline_write_page(&first_page_wrapper.0).unwrap();
+        page_handler.commit().unwrap();
+    }
+
+    #[test]
+    fn test_check_integrity_free_list() {
+        let mut page_handler = prepare_page_handler("test-check-integrity-free-list", Config::default());
+        let pids = alloc_pages(&mut page_handler, 4);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_pages(&pids[0..2]).unwrap();
+        page_handler.commit().unwrap();
+
+        let report = page_handler.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.checked_pages, pids[3]);
+
+        let first_page = page_handler.get_first_page().unwrap();
+        let null_page_bar = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+
+        // the same pid twice, and a pid beyond the null page bar
+        update_header(&mut page_handler, |wrapper| {
+            wrapper.set_free_list_content(1, pids[0]);
+            wrapper.set_free_list_content(2, null_page_bar);
+            wrapper.set_free_list_size(3);
+        });
+        let report = page_handler.check_integrity().unwrap();
+        assert!(report.anomalies.contains(&IntegrityAnomaly::DuplicateFreePid { pid: pids[0] }), "{}", report);
+        assert!(report.anomalies.contains(&IntegrityAnomaly::InvalidFreePid { pid: null_page_bar }), "{}", report);
+
+        let capacity = HeaderPageWrapper::free_list_max_size(page_handler.page_size) as u32;
+        update_header(&mut page_handler, |wrapper| wrapper.set_free_list_size(capacity + 1));
+        let report = page_handler.check_integrity().unwrap();
+        assert!(report.anomalies.contains(&IntegrityAnomaly::FreeListSizeOutOfRange {
+            pid: 0,
+            size: capacity + 1,
+            capacity,
+        }), "{}", report);
+
+        // a loop in the chain of the free list pages
+        update_header(&mut page_handler, |wrapper| {
+            wrapper.set_free_list_size(0);
+            wrapper.set_free_list_page_id(pids[2]);
+        });
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut free_list_page = FreeListPageWrapper::init(pids[2], page_handler.page_size);
+        free_list_page.set_next_pid(pids[2]);
+        page_handler.pipeline_write_page(&free_list_page.0).unwrap();
+        page_handler.commit().unwrap();
+        let report = page_handler.check_integrity().unwrap();
+        assert!(report.anomalies.contains(&IntegrityAnomaly::BrokenFreeListChain { pid: pids[2] }), "{}", report);
+
+        let stored_pages = page_handler.stored_page_count().unwrap();
+        update_header(&mut page_handler, |wrapper| {
+            wrapper.set_free_list_page_id(0);
+            wrapper.set_null_page_bar(stored_pages + 100);
+        });
+        let report = page_handler.check_integrity().unwrap();
+        assert_eq!(report.anomalies, vec![IntegrityAnomaly::NullPageBarBeyondFile {
+            null_page_bar: stored_pages + 100,
+            stored_pages,
+        }]);
+    }
+
+    #[test]
+    fn test_check_integrity_data_page() {
+        let mut page_handler = prepare_page_handler("test-check-integrity-data-page", Config::default());
+        let pids = alloc_pages(&mut page_handler, 2);
+
+        let mut wrapper = DataPageWrapper::init(pids[0], page_handler.page_size);
+        wrapper.put(&[1, 2, 3]);
+        wrapper.put(&[4, 5, 6]);
+        wrapper.remove(0);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(wrapper.borrow_page()).unwrap();
+        page_handler.commit().unwrap();
+        let report = page_handler.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+
+        wrapper.set_data_len(2);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(wrapper.borrow_page()).unwrap();
+        page_handler.commit().unwrap();
+        let report = page_handler.check_integrity().unwrap();
+        assert_eq!(report.anomalies, vec![IntegrityAnomaly::DataLenMismatch {
+            pid: pids[0],
+            data_len: 2,
+            occupied: 1,
+        }]);
+
+        // the second bar is behind the first one
+        let mut page = wrapper.consume_page();
+        let first_bar = page.get_u16(16);
+        page.seek(18);
+        page.put_u16(first_bar + 1);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.commit().unwrap();
+        let report = page_handler.check_integrity().unwrap();
+        assert_eq!(report.anomalies, vec![IntegrityAnomaly::InvalidSlotBar { pid: pids[0], index: 1 }]);
+
+        // the bars overflow the page
+        page.seek(6);
+        page.put_u16(u16::MAX);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.commit().unwrap();
+        let report = page_handler.check_integrity().unwrap();
+        assert_eq!(report.anomalies, vec![IntegrityAnomaly::InvalidSlotBar { pid: pids[0], index: 0 }]);
+    }
+
+    #[test]
+    fn test_missing_journal() {
+        let db_path = mk_db_path("test-missing-journal");
+        let journal_path = PageHandler::mk_journal_path(db_path.as_path());
+
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        alloc_pages(&mut page_handler, 3);
+        assert!(!page_handler.recovery_report().journal_missing);
+        drop(page_handler);
+
+        // the committed pages are only in the journal
+        std::fs::remove_file(journal_path.as_path()).unwrap();
+        let mut page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        let report = page_handler.recovery_report();
+        assert!(report.journal_missing);
+        assert!(report.integrity.is_some());
+
+        // the main file is up to date after the checkpoint
+        alloc_pages(&mut page_handler, 1);
+        page_handler.checkpoint().unwrap();
+        drop(page_handler);
+
+        std::fs::remove_file(journal_path.as_path()).unwrap();
+        let page_handler = PageHandler::new_with_config(db_path.as_path(), 4096, Config::default()).unwrap();
+        let report = page_handler.recovery_report();
+        assert!(!report.journal_missing);
+        assert!(report.integrity.is_none());
+    }
+
+    #[test]
+    fn test_torn_header() {
+        let db_path = mk_db_path("test-torn-header");
+        let null_page_bar = {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            alloc_pages(&mut page_handler, 10);
+            page_handler.checkpoint().unwrap();
+            let first_page = page_handler.get_first_page().unwrap();
+            HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar()
+        };
+        let content = std::fs::read(db_path.as_path()).unwrap();
+        assert_eq!(content[0..4096], content[(4096 * 2)..(4096 * 3)]);
+
+        // the beginning of the primary is not written
+        let mut torn = content.clone();
+        torn[0..2048].fill(0);
+        std::fs::write(db_path.as_path(), &torn).unwrap();
+        {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            let first_page = page_handler.get_first_page().unwrap();
+            assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar(), null_page_bar);
+        }
+        let repaired = std::fs::read(db_path.as_path()).unwrap();
+        assert_eq!(repaired[0..4096], content[0..4096]);
+
+        // the primary is intact if the shadow is torn
+        let mut torn = content.clone();
+        torn[(4096 * 2 + 2048)..(4096 * 3)].fill(0xFF);
+        std::fs::write(db_path.as_path(), &torn).unwrap();
+        {
+            let mut page_handler = PageHandler::new(db_path.as_path(), 4096).unwrap();
+            let first_page = page_handler.get_first_page().unwrap();
+            assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar(), null_page_bar);
+        }
+
+        // neither of them is intact
+        let mut torn = content;
+        torn[48] ^= 0xFF;
+        torn[4096 * 2 + 48] ^= 0xFF;
+        std::fs::write(db_path.as_path(), &torn).unwrap();
+        let err = PageHandler::new(db_path.as_path(), 4096).err().unwrap();
+        assert!(matches!(err, DbErr::PageChecksumMismatch { page_id: 0, .. }));
+    }
+
+    #[test]
+    fn test_estimate_stored_size() {
+        let mut page_handler = prepare_page_handler("test-estimate-stored-size", Config::default());
+        let small_doc = mk_document! {
+            "_id": 1,
+            "content": "a".repeat(100),
+        };
+        let overflow_doc = mk_document! {
+            "_id": 2,
+            "content": "a".repeat(20000),
+        };
+
+        let null_page_bar = {
+            let first_page = page_handler.get_first_page().unwrap();
+            HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar()
+        };
+        let estimated = page_handler.estimate_stored_size(&small_doc).unwrap();
+        assert_eq!(estimated, small_doc.to_bytes().unwrap().len() + 2);
+        let first_page = page_handler.get_first_page().unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar(), null_page_bar);
+        assert!(page_handler.data_page_map.is_empty());
+
+        // only the slot of the overflow document is in the data page
+        let estimated = page_handler.estimate_stored_size(&overflow_doc).unwrap();
+        assert_eq!(estimated, super::OVERFLOW_SLOT_SIZE + 2);
+
+        // the pages are returned to the map with the remaining size
+        let remain_size_of = |page_handler: &PageHandler, pid: u32| {
+            page_handler.data_page_map.iter()
+                .find(|(_, pids)| pids.contains(&pid))
+                .map(|(remain_size, _)| *remain_size)
+        };
+        let fresh_remain_size = DataPageWrapper::init(0, page_handler.page_size).remain_size();
+        for doc in [&small_doc, &overflow_doc] {
+            let estimated = page_handler.estimate_stored_size(doc).unwrap();
+
+            page_handler.start_transaction(TransactionType::Write).unwrap();
+            let before: HashMap<u32, u32> = page_handler.data_page_map.iter()
+                .flat_map(|(remain_size, pids)| pids.iter().map(move |pid| (*pid, *remain_size)))
+                .collect();
+            let ticket = page_handler.store_doc(doc).unwrap();
+            page_handler.commit().unwrap();
+
+            let before = before.get(&ticket.pid).copied().unwrap_or(fresh_remain_size);
+            let after = remain_size_of(&page_handler, ticket.pid).unwrap();
+            assert_eq!((before - after) as usize, estimated);
+        }
+    }
+
+    #[test]
+    fn test_max_transaction_pages() {
+        let mut page_handler = prepare_page_handler("test-max-transaction-pages", Config::default());
+        let pids = alloc_pages(&mut page_handler, 20);
+        page_handler.set_max_transaction_pages(Some(10));
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut written = 0;
+        let err = loop {
+            let mut page = RawPage::new(pids[written], page_handler.page_size);
+            page.put(&[1, 2, 3]);
+            match page_handler.pipeline_write_page(&page) {
+                Ok(()) => written += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, DbErr::TransactionTooLarge(10)));
+        assert_eq!(written, 10);
+
+        // still in the transaction
+        assert!(page_handler.transaction_type() == Some(TransactionType::Write));
+        assert_eq!(page_handler.pipeline_read_page(pids[0]).unwrap().data[0..3], [1, 2, 3]);
+        page_handler.rollback().unwrap();
+        assert_eq!(page_handler.pipeline_read_page(pids[0]).unwrap().data[0..3], [0, 0, 0]);
+
+        // the frames are counted per transaction
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids[0..10] {
+            page_handler.pipeline_write_page(&RawPage::new(*pid, page_handler.page_size)).unwrap();
+        }
+        page_handler.commit().unwrap();
+
+        page_handler.set_max_transaction_pages(None);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids {
+            page_handler.pipeline_write_page(&RawPage::new(*pid, page_handler.page_size)).unwrap();
+        }
+        page_handler.commit().unwrap();
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let mut page_handler = prepare_page_handler("test-max-file-size", Config::default());
+        alloc_pages(&mut page_handler, 1);
+        page_handler.checkpoint().unwrap();
+
+        let limit = page_handler.file.len().unwrap() + 40 * 4096;
+        page_handler.set_max_file_size(Some(limit));
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut pids = vec![];
+        let err = loop {
+            match page_handler.alloc_page_id() {
+                Ok(pid) => pids.push(pid),
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, DbErr::FileSizeLimitExceeded { limit: found } if found == limit));
+        page_handler.commit().unwrap();
+        assert_eq!(page_handler.file.len().unwrap(), limit);
+        assert_eq!(page_handler.high_water_pid().unwrap(), (limit / 4096) as u32 - 1);
+
+        // the free list is reused at the limit
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.free_page(pids[3]).unwrap();
+        page_handler.commit().unwrap();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.alloc_page_id().unwrap(), pids[3]);
+        assert!(matches!(page_handler.alloc_page_id(), Err(DbErr::FileSizeLimitExceeded { .. })));
+        page_handler.commit().unwrap();
+
+        page_handler.set_max_file_size(None);
+        alloc_pages(&mut page_handler, 1);
+        page_handler.checkpoint().unwrap();
+        assert!(page_handler.file.len().unwrap() > limit);
+    }
+
+    #[test]
+    fn test_free_data_tickets() {
+        let mut page_handler = prepare_page_handler("test-free-data-tickets", Config::default());
+
+        // 10 documents in a page
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let mut tickets = vec![];
+        for i in 0..100 {
+            let doc = mk_document! {
+                "_id": i,
+                "content": "a".repeat(360),
+            };
+            tickets.push(page_handler.store_doc(&doc).unwrap());
+        }
+        page_handler.commit().unwrap();
+
+        let pids: std::collections::BTreeSet<u32> = tickets.iter().map(|ticket| ticket.pid).collect();
+        assert_eq!(pids.len(), 10);
+
+        // rejected as a whole
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let err = page_handler.free_data_tickets(&[tickets[0].clone(), tickets[0].clone()]).unwrap_err();
+        assert!(matches!(err, DbErr::DataTicketNotFound { .. }));
+        let err = page_handler.free_data_tickets(&[tickets[1].clone(), DataTicket { pid: tickets[1].pid, index: 100 }]).unwrap_err();
+        assert!(matches!(err, DbErr::DataTicketNotFound { .. }));
+
+        page_handler.free_data_tickets(&tickets).unwrap();
+        page_handler.commit().unwrap();
+
+        // every data page once, and the header with the free list
+        let stats = page_handler.write_stats();
+        assert_eq!(stats.last_commit_journal_frames, 11);
+        assert_eq!(stats.last_commit_logical_pages, 11);
+
+        for ticket in &tickets {
+            assert!(page_handler.get_doc_from_ticket(ticket).unwrap().is_none());
+        }
+        let first_page = page_handler.get_first_page().unwrap();
+        assert_eq!(HeaderPageWrapper::from_raw_page(first_page).get_free_list_size(), 10);
+    }
+
+    #[test]
+    fn test_preallocate() {
+        let mut page_handler = prepare_page_handler("test-preallocate", Config::default());
+        let null_page_bar = |page_handler: &mut PageHandler| {
+            let first_page = page_handler.get_first_page().unwrap();
+            HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar()
+        };
+        let origin_null_page_bar = null_page_bar(&mut page_handler);
+
+        // returned by rollback
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pids = page_handler.preallocate(100).unwrap();
+        assert_eq!(pids, (origin_null_page_bar..(origin_null_page_bar + 100)).collect::<Vec<u32>>());
+        assert_eq!(null_page_bar(&mut page_handler), origin_null_page_bar + 100);
+        page_handler.rollback().unwrap();
+        assert_eq!(null_page_bar(&mut page_handler), origin_null_page_bar);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert!(page_handler.preallocate(0).unwrap().is_empty());
+        let pids = page_handler.preallocate(100).unwrap();
+        assert_eq!(pids[0], origin_null_page_bar);
+        page_handler.commit().unwrap();
+        assert_eq!(null_page_bar(&mut page_handler), origin_null_page_bar + 100);
+
+        // the file grows to hold them
+        page_handler.checkpoint().unwrap();
+        assert!(page_handler.file.len().unwrap() >= page_handler.page_layout.offset_of(origin_null_page_bar + 100));
+
+        // not in a transaction
+        assert!(page_handler.preallocate(1).is_err());
+    }
+
+    #[test]
+    fn test_alloc_refused_keeps_file_size() {
+        let mut page_handler = prepare_page_handler("test-alloc-refused-keeps-file-size", Config::default());
+        let pids = alloc_pages(&mut page_handler, 10);
+        page_handler.checkpoint().unwrap();
+        let file_len = page_handler.file.len().unwrap();
+        let db_size = page_handler.last_commit_db_size;
+        page_handler.set_max_transaction_pages(Some(10));
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids {
+            page_handler.pipeline_write_page(&RawPage::new(*pid, page_handler.page_size)).unwrap();
+        }
+
+        // far beyond the file, the header write is refused
+        let count = (file_len / (page_handler.page_size as u64)) as u32 * 4;
+        let err = page_handler.preallocate(count).unwrap_err();
+        assert!(matches!(err, DbErr::TransactionTooLarge(10)));
+        assert_eq!(page_handler.file.len().unwrap(), file_len);
+        assert_eq!(page_handler.last_commit_db_size, db_size);
+
+        page_handler.rollback().unwrap();
+    }
+
+    #[test]
+    fn test_preallocate_limit() {
+        let config = Config {
+            max_alloc_pages_per_txn: Some(10),
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-preallocate-limit", config);
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        assert_eq!(page_handler.preallocate(10).unwrap().len(), 10);
+        let err = page_handler.preallocate(1).unwrap_err();
+        assert!(matches!(err, DbErr::TransactionAllocLimitExceeded(10)));
+        assert!(page_handler.transaction_type().is_none());
+    }
+
+    // 1000 individual allocations against one preallocation of 1000
+    #[test]
+    fn bench_preallocate() {
+        let mut page_handler = prepare_page_handler("bench-alloc-page-id", Config::default());
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let individual_pids: Vec<u32> = (0..1000).map(|_| page_handler.alloc_page_id().unwrap()).collect();
+        page_handler.commit().unwrap();
+        let individual_stats = page_handler.write_stats();
+
+        let mut page_handler = prepare_page_handler("bench-preallocate", Config::default());
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let preallocated_pids = page_handler.preallocate(1000).unwrap();
+        page_handler.commit().unwrap();
+        let preallocated_stats = page_handler.write_stats();
+
+        assert_eq!(individual_pids, preallocated_pids);
+
+        // a frame of the header for every allocation
+        assert_eq!(individual_stats.last_commit_journal_frames, 1000);
+        assert_eq!(preallocated_stats.last_commit_journal_frames, 1);
+    }
+
+    // a 10k-page scan, page by page against a prefetch of every 100 pages
+    #[test]
+    fn bench_prefetch() {
+        const PAGE_COUNT: u32 = 10000;
+
+        let reads = Rc::new(Cell::new(0));
+        let page_layout = PageLayout::new(4096, true);
+        let mut store = CountingStore {
+            inner: MemoryPageStore::new(),
+            reads: reads.clone(),
+            flushes: Rc::new(Cell::new(0)),
+            syncs: Rc::new(Cell::new(0)),
+            writes: Rc::default(),
+        };
+        let (_, page_count, db_file_size) = PageHandler::init_db(&mut store, page_layout, Config::default().init_block_count).unwrap();
+        let journal_manager = JournalManager::open_in_memory(page_layout, db_file_size).unwrap();
+        let mut page_handler = PageHandler::from_parts(
+            Box::new(store), page_layout, page_count,
+            Some(Box::new(journal_manager)), None,
+            Config::default(),
+        ).unwrap();
+
+        let pids = alloc_pages(&mut page_handler, PAGE_COUNT as usize);
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        for pid in &pids {
+            let mut page = RawPage::new(*pid, page_layout.body_size());
+            page.data[0..4].copy_from_slice(&pid.to_be_bytes());
+            page_handler.pipeline_write_page(&page).unwrap();
+        }
+        page_handler.commit().unwrap();
+        page_handler.checkpoint().unwrap();
+
+        // the last page is pending in the journal
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let last_pid = *pids.last().unwrap();
+        let mut page = RawPage::new(last_pid, page_layout.body_size());
+        page.data[0..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.commit().unwrap();
+
+        let scan = |page_handler: &mut PageHandler, prefetch: bool| -> u32 {
+            page_handler.page_cache.clear();
+            let reads_before = reads.get();
+            for pid in &pids {
+                if prefetch && (*pid - pids[0]).is_multiple_of(100) {
+                    page_handler.prefetch(*pid, 100).unwrap();
+                }
+                let page = page_handler.pipeline_read_page(*pid).unwrap();
+                let expected = if *pid == last_pid { u32::MAX } else { *pid };
+                assert_eq!(page.data[0..4], expected.to_be_bytes());
+            }
+            reads.get() - reads_before
+        };
+
+        let page_reads = scan(&mut page_handler, false);
+        let prefetch_reads = scan(&mut page_handler, true);
+
+        // a read of the page and one of the checksum for every page not in the journal
+        assert_eq!(page_reads, (PAGE_COUNT - 1) * 2);
+        assert_eq!(prefetch_reads, PAGE_COUNT / 100);
+    }
+
+    #[test]
+    fn test_prefetch_cut_at_capacity() {
+        let config = Config {
+            cache_capacity_bytes: Some(16 * 4096),
+            ..Config::default()
+        };
+        let mut page_handler = prepare_page_handler("test-prefetch-cut-at-capacity", config);
+        let pids = alloc_pages(&mut page_handler, 100);
+        page_handler.checkpoint().unwrap();
+
+        page_handler.page_cache.clear();
+        let cached = page_handler.prefetch(pids[0], u32::MAX).unwrap();
+        assert_eq!(cached, 16);
+        assert!(page_handler.page_cache.len() <= 16);
+        assert!(page_handler.page_cache.peek_from_cache(pids[0]).is_some());
+    }
+
+    #[test]
+    fn test_transaction_observer() {
+        let mut page_handler = prepare_page_handler("test-transaction-observer", Config::default());
+        let pid = alloc_pages(&mut page_handler, 1)[0];
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let observed = events.clone();
+        page_handler.set_transaction_observer(Some(Box::new(move |event, ty, at| {
+            observed.borrow_mut().push((event, ty == TransactionType::Write, at));
+        })));
+
+        let page = RawPage::new(pid, page_handler.page_size);
+        page_handler.auto_start_transaction(TransactionType::Write).unwrap();
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.auto_commit().unwrap();
+
+        // the automatic transaction is not started again in the transaction of the user
+        page_handler.start_transaction(TransactionType::Read).unwrap();
+        page_handler.set_transaction_state(TransactionState::User);
+        page_handler.auto_start_transaction(TransactionType::Read).unwrap();
+        page_handler.upgrade_transaction_to_write().unwrap();
+        page_handler.rollback().unwrap();
+        page_handler.set_transaction_state(TransactionState::NoTrans);
+
+        let result: DbResult<()> = page_handler.with_write_transaction(|_| Err(DbErr::Busy));
+        assert!(result.is_err());
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        page_handler.commit().unwrap();
+
+        let sequence: Vec<(TransactionEvent, bool)> = events.borrow().iter()
+            .map(|(event, is_write, _)| (*event, *is_write))
+            .collect();
+        assert_eq!(sequence, vec![
+            (TransactionEvent::AutoStart, true),
+            (TransactionEvent::AutoCommit, true),
+            (TransactionEvent::Start, false),
+            (TransactionEvent::Upgrade, true),
+            (TransactionEvent::Rollback, true),
+            (TransactionEvent::AutoStart, true),
+            (TransactionEvent::AutoRollback, true),
+            (TransactionEvent::Start, true),
+            (TransactionEvent::Commit, true),
+        ]);
+        assert!(events.borrow().windows(2).all(|pair| pair[0].2 <= pair[1].2));
+
+        page_handler.set_transaction_observer(None);
+        alloc_pages(&mut page_handler, 1);
+        assert_eq!(events.borrow().len(), sequence.len());
+    }
+
+    #[test]
+    fn test_write_observer() {
+        let mut page_handler = prepare_page_handler("test-write-observer", Config::default());
+
+        let written = Rc::new(std::cell::RefCell::new(vec![]));
+        let observed = written.clone();
+        page_handler.set_write_observer(Some(Box::new(move |page_id, page: &RawPage| {
+            assert_eq!(page.page_id, page_id);
+            observed.borrow_mut().push(page_id);
+        })));
+
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pid = page_handler.alloc_page_id().unwrap();
+        let mut page = RawPage::new(pid, page_handler.page_size);
+        page.put(&[1, 2, 3]);
+        page_handler.pipeline_write_page(&page).unwrap();
+        page_handler.free_page(pid).unwrap();
+        page_handler.commit().unwrap();
+        assert_eq!(*written.borrow(), vec![0, pid, 0]);
+
+        // nothing is written again by rolling back
+        written.borrow_mut().clear();
+        page_handler.start_transaction(TransactionType::Write).unwrap();
+        let pid = page_handler.alloc_page_id().unwrap();
+        page_handler.rollback().unwrap();
+        assert_eq!(*written.borrow(), vec![0]);
+
+        // not observed if the journal rejects it
+        written.borrow_mut().clear();
+        let page = RawPage::new(pid, page_handler.page_size);
+        assert!(page_handler.pipeline_write_page(&page).is_err());
+        assert!(written.borrow().is_empty());
+
+        page_handler.set_write_observer(None);
+        alloc_pages(&mut page_handler, 1);
+        assert!(written.borrow().is_empty());
     }
 
 }
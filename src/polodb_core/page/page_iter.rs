@@ -0,0 +1,77 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use crate::DbResult;
+use super::page::RawPage;
+use super::page_handler::PageHandler;
+use super::header_page_wrapper::HeaderPageWrapper;
+
+/**
+ * Walk every page up to the null page bar
+ * through the pipeline, the pages pending in the journal are reflected.
+ *
+ * The header and the free list are read at the first call of `next`.
+ * If it fails, the error is yielded and the iteration stops.
+ */
+pub(crate) struct PageIter<'a> {
+    page_handler:  &'a mut PageHandler,
+    include_freed: bool,
+    state:         Option<(Range<u32>, BTreeSet<u32>)>,
+    finished:      bool,
+}
+
+impl<'a> PageIter<'a> {
+
+    pub(crate) fn new(page_handler: &'a mut PageHandler, include_freed: bool) -> PageIter<'a> {
+        PageIter {
+            page_handler,
+            include_freed,
+            state: None,
+            finished: false,
+        }
+    }
+
+    fn init_state(&mut self) -> DbResult<()> {
+        let first_page = self.page_handler.get_first_page()?;
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+        let null_page_bar = first_page_wrapper.get_null_page_bar();
+
+        let free_pids = if self.include_freed {
+            BTreeSet::new()
+        } else {
+            self.page_handler.collect_free_pids(&first_page_wrapper)?
+        };
+
+        self.state = Some((0..null_page_bar, free_pids));
+        Ok(())
+    }
+
+}
+
+impl<'a> Iterator for PageIter<'a> {
+    type Item = DbResult<RawPage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        if self.state.is_none() {
+            if let Err(err) = self.init_state() {
+                self.finished = true;
+                return Some(Err(err));
+            }
+        }
+
+        let (pids, free_pids) = self.state.as_mut().unwrap();
+        let pid = pids.find(|pid| !free_pids.contains(pid));
+
+        match pid {
+            Some(pid) => Some(self.page_handler.pipeline_read_page(pid)),
+            None => {
+                self.finished = true;
+                None
+            }
+        }
+    }
+
+}
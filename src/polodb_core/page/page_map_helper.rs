@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::collections::BTreeSet;
+use crate::DbResult;
+use crate::btree::{HEADER_SIZE, ITEM_SIZE};
+use super::page::{RawPage, PageType};
+use super::page_handler::PageHandler;
+use super::header_page_wrapper::HeaderPageWrapper;
+use super::data_page_wrapper::DataPageWrapper;
+use super::free_list_page_wrapper::FreeListPageWrapper;
+
+struct PageMapRow {
+    pid:             u32,
+    kind:            &'static str,
+    live_entries:    u32,
+    used_bytes:      u32,
+    remaining_bytes: u32,
+    is_free:         bool,
+}
+
+fn mk_row(page: RawPage, is_free: bool) -> PageMapRow {
+    let pid = page.page_id;
+    let page_size = page.len();
+
+    if pid == 0 {
+        return PageMapRow {
+            pid,
+            kind: "header",
+            live_entries: 0,
+            used_bytes: page_size,
+            remaining_bytes: 0,
+            is_free,
+        };
+    }
+
+    if page.data[0..2] == [0, 0] {
+        return PageMapRow {
+            pid,
+            kind: "empty",
+            live_entries: 0,
+            used_bytes: 0,
+            remaining_bytes: page_size,
+            is_free,
+        };
+    }
+
+    let mut magic: [u8; 2] = [0; 2];
+    magic.copy_from_slice(&page.data[0..2]);
+
+    match PageType::from_magic(magic) {
+        Ok(PageType::Data) => {
+            let wrapper = DataPageWrapper::from_raw(page);
+            let remaining_bytes = wrapper.remain_size();
+            PageMapRow {
+                pid,
+                kind: "data",
+                live_entries: wrapper.data_len(),
+                used_bytes: page_size - remaining_bytes,
+                remaining_bytes,
+                is_free,
+            }
+        }
+
+        Ok(PageType::BTreeNode) => {
+            let live_entries = page.get_u16(2) as u32;
+            let used_bytes = std::cmp::min(HEADER_SIZE + live_entries * ITEM_SIZE, page_size);
+            PageMapRow {
+                pid,
+                kind: "btree",
+                live_entries,
+                used_bytes,
+                remaining_bytes: page_size - used_bytes,
+                is_free,
+            }
+        }
+
+        Ok(PageType::FreeList) => PageMapRow {
+            pid,
+            kind: "free_list",
+            live_entries: FreeListPageWrapper::from_raw_page(page).size(),
+            used_bytes: page_size,
+            remaining_bytes: 0,
+            is_free,
+        },
+
+        Ok(PageType::OverflowData) => PageMapRow {
+            pid,
+            kind: "overflow",
+            live_entries: 0,
+            used_bytes: page_size,
+            remaining_bytes: 0,
+            is_free,
+        },
+
+        _ => PageMapRow {
+            pid,
+            kind: "unknown",
+            live_entries: 0,
+            used_bytes: 0,
+            remaining_bytes: 0,
+            is_free,
+        },
+
+    }
+}
+
+// emit a row for every page up to null_page_bar
+pub(crate) fn export_page_map_csv(page_handler: &mut PageHandler, writer: &mut dyn Write) -> DbResult<()> {
+    let first_page = page_handler.get_first_page()?;
+    let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+    let null_page_bar = first_page_wrapper.get_null_page_bar();
+
+    let free_pids: BTreeSet<u32> = page_handler.collect_free_pids(&first_page_wrapper)?;
+
+    writeln!(writer, "pid,kind,live_entries,used_bytes,remaining_bytes,is_free")?;
+
+    for pid in 0..null_page_bar {
+        let page = page_handler.pipeline_read_page(pid)?;
+        let row = mk_row(page, free_pids.contains(&pid));
+        writeln!(writer, "{},{},{},{},{},{}",
+                 row.pid, row.kind, row.live_entries,
+                 row.used_bytes, row.remaining_bytes, row.is_free)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
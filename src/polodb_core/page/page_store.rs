@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write, Read};
+
+/**
+ * The storage under the main database, the journal and the intent log.
+ *
+ * It's a file usually, the in-memory store is used to
+ * run the database without touching the filesystem.
+ */
+pub(crate) trait PageStore {
+
+    // fill the whole buffer, an error is returned if it's beyond the end
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> io::Result<()>;
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+
+    fn len(&self) -> io::Result<u64>;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    // durable on the disk after it returns
+    fn sync_data(&mut self) -> io::Result<()>;
+
+    // the file to lock, a store without file can't be shared
+    fn as_file(&self) -> Option<&File> {
+        None
+    }
+
+}
+
+impl PageStore for File {
+
+    // reading moves the cursor only, a shared file is enough
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        let mut file: &File = self;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buffer)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+
+    #[inline]
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self)
+    }
+
+    #[inline]
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    #[inline]
+    fn as_file(&self) -> Option<&File> {
+        Some(self)
+    }
+
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryPageStore {
+    data: Vec<u8>,
+}
+
+impl MemoryPageStore {
+
+    pub fn new() -> MemoryPageStore {
+        MemoryPageStore::default()
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> MemoryPageStore {
+        MemoryPageStore { data }
+    }
+
+}
+
+impl PageStore for MemoryPageStore {
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        let begin = offset as usize;
+        let end = begin + buffer.len();
+        if end > self.data.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read beyond the end of memory store"));
+        }
+        buffer.copy_from_slice(&self.data[begin..end]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let begin = offset as usize;
+        let end = begin + data.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[begin..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    #[inline]
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.data.resize(len as usize, 0);
+        Ok(())
+    }
+
+    #[inline]
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    fn sync_data(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::page_store::{PageStore, MemoryPageStore};
+
+    #[test]
+    fn test_memory_page_store() {
+        let mut store = MemoryPageStore::new();
+        store.write_at(4, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(store.len().unwrap(), 8);
+
+        let mut buffer = [0u8; 6];
+        store.read_at(2, &mut buffer).unwrap();
+        assert_eq!(buffer, [0, 0, 1, 2, 3, 4]);
+
+        assert!(store.read_at(4, &mut buffer).is_err());
+
+        store.set_len(5).unwrap();
+        let mut buffer = [0u8; 1];
+        store.read_at(4, &mut buffer).unwrap();
+        assert_eq!(buffer, [1]);
+    }
+
+}
@@ -1,7 +1,11 @@
-use libc::{ malloc, free };
 use std::collections::HashMap;
 use std::ptr::null_mut;
 use crate::page::RawPage;
+use crate::{DbErr, DbResult};
+
+// the max count of pages evicted by an insertion at the soft limit,
+// the pinned pages may keep the cache above it for a while
+const SOFT_LIMIT_EVICTION_BATCH: usize = 2;
 
 struct LruNode {
     prev:      *mut LruNode,
@@ -192,7 +196,6 @@ impl LruMap {
         Some(result)
     }
 
-    #[allow(dead_code)]
     pub fn tail(&self) -> Option<(u32, u32)> {
         unsafe {
             if self.end.is_null() {
@@ -208,94 +211,385 @@ impl LruMap {
 
 }
 
+// the least recently used is the victim,
+// the tail of the node list
+#[inline]
+fn remove_lru_tail(lru_map: &mut LruMap) -> Option<u32> {
+    let (key, _) = lru_map.tail()?;
+    lru_map.remove(key);
+    Some(key)
+}
+
+/**
+ * Decide which page is evicted when the cache is full,
+ * only the page ids are tracked, the cache holds the pages.
+ */
+pub(crate) trait EvictionPolicy {
+
+    // a cached page is read or overridden
+    fn on_access(&mut self, page_id: u32);
+
+    fn on_insert(&mut self, page_id: u32);
+
+    // the page is dropped by the cache, not by the policy
+    fn on_remove(&mut self, page_id: u32);
+
+    // the victim is not tracked anymore after it's returned
+    fn choose_victim(&mut self) -> Option<u32>;
+
+}
+
+struct LruPolicy {
+    lru_map: LruMap,
+}
+
+impl LruPolicy {
+
+    fn new() -> LruPolicy {
+        LruPolicy {
+            lru_map: LruMap::new(usize::MAX),
+        }
+    }
+
+}
+
+impl EvictionPolicy for LruPolicy {
+
+    #[inline]
+    fn on_access(&mut self, page_id: u32) {
+        let _ = self.lru_map.find(page_id);
+    }
+
+    #[inline]
+    fn on_insert(&mut self, page_id: u32) {
+        let _ = self.lru_map.insert(page_id, 0);
+    }
+
+    #[inline]
+    fn on_remove(&mut self, page_id: u32) {
+        let _ = self.lru_map.remove(page_id);
+    }
+
+    #[inline]
+    fn choose_victim(&mut self) -> Option<u32> {
+        remove_lru_tail(&mut self.lru_map)
+    }
+
+}
+
+// A simplified 2Q:
+// the pages read once are in a FIFO queue, and are evicted firstly,
+// the pages read again are promoted to a LRU queue.
+// So a long scan can't flush the hot pages.
+struct TwoQueuePolicy {
+    recent_capacity: usize,
+    recent:          LruMap,  // only inserted and removed, so it's FIFO
+    frequent:        LruMap,
+}
+
+impl TwoQueuePolicy {
+
+    fn new(capacity: usize) -> TwoQueuePolicy {
+        TwoQueuePolicy {
+            recent_capacity: std::cmp::max(capacity / 4, 1),
+            recent: LruMap::new(usize::MAX),
+            frequent: LruMap::new(usize::MAX),
+        }
+    }
+
+}
+
+impl EvictionPolicy for TwoQueuePolicy {
+
+    fn on_access(&mut self, page_id: u32) {
+        if self.recent.remove(page_id).is_some() {
+            let _ = self.frequent.insert(page_id, 0);
+        } else {
+            let _ = self.frequent.find(page_id);
+        }
+    }
+
+    #[inline]
+    fn on_insert(&mut self, page_id: u32) {
+        let _ = self.recent.insert(page_id, 0);
+    }
+
+    fn on_remove(&mut self, page_id: u32) {
+        if self.recent.remove(page_id).is_none() {
+            let _ = self.frequent.remove(page_id);
+        }
+    }
+
+    fn choose_victim(&mut self) -> Option<u32> {
+        if self.recent.len() > self.recent_capacity || self.frequent.len() == 0 {
+            if let Some(page_id) = remove_lru_tail(&mut self.recent) {
+                return Some(page_id);
+            }
+        }
+        remove_lru_tail(&mut self.frequent)
+    }
+
+}
+
+/**
+ * The eviction policy of the page cache
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CachePolicy {
+    // least recently used
+    #[default]
+    Lru,
+
+    // resists the scans, the pages read only once are evicted firstly
+    TwoQueue,
+}
+
+impl CachePolicy {
+
+    fn create(self, capacity: usize) -> Box<dyn EvictionPolicy> {
+        match self {
+            CachePolicy::Lru => Box::new(LruPolicy::new()),
+            CachePolicy::TwoQueue => Box::new(TwoQueuePolicy::new(capacity)),
+        }
+    }
+
+}
+
 pub(crate) struct PageCache {
-    page_count: usize,
-    page_size:  u32,
-    data:       *mut u8,
-    lru_map:    LruMap,
+    page_count:   usize,
+    page_size:    u32,
+
+    // page id -> the bytes of the page,
+    // every slot is allocated on insertion and freed on eviction,
+    // so the memory held follows the count of pages
+    slots:        HashMap<u32, Box<[u8]>>,
+    policy_kind:  CachePolicy,
+    policy:       Box<dyn EvictionPolicy>,
+
+    // the count of pages the insertions evict down to,
+    // it's the capacity if no soft limit is set
+    soft_limit:   usize,
+
+    // page id -> the count of pins,
+    // a pinned page is never chosen as a victim
+    pinned:       HashMap<u32, u32>,
 }
 
 impl PageCache {
 
-    pub fn new_default(page_size: u32) -> PageCache {
-        Self::new(1024, page_size)
+    pub fn new_default(page_size: u32, policy_kind: CachePolicy) -> PageCache {
+        Self::new(1024, page_size, policy_kind)
     }
 
-    pub fn new(page_count: usize, page_size: u32) -> PageCache {
-        let cache_size = page_count * (page_size as usize);
-
-        let data: *mut u8 = unsafe {
-            malloc(cache_size).cast()
-        };
+    // capacity_bytes / page_size pages, at least one page
+    pub fn with_capacity(capacity_bytes: usize, page_size: u32, policy_kind: CachePolicy) -> PageCache {
+        let page_count = std::cmp::max(capacity_bytes / (page_size as usize), 1);
+        Self::new(page_count, page_size, policy_kind)
+    }
 
+    pub fn new(page_count: usize, page_size: u32, policy_kind: CachePolicy) -> PageCache {
         PageCache {
             page_count,
             page_size,
-            data,
-            lru_map: LruMap::new(page_count),
+            slots: HashMap::new(),
+            policy_kind,
+            policy: policy_kind.create(page_count),
+            soft_limit: page_count,
+            pinned: HashMap::new(),
         }
     }
 
-    pub(crate) fn get_from_cache(&mut self, page_id: u32) -> Option<RawPage> {
-        let index = match self.lru_map.find(page_id) {
-            Some(index) => index,
-            None => return None,
-        };
-        let offset: usize = (index as usize) * (self.page_size as usize);
-        let mut result = RawPage::new(page_id, self.page_size);
-        unsafe {
-            result.copy_from_ptr(self.data.add(offset as usize));
+    // The pins are nested, the page can be evicted
+    // after it's unpinned as many times as pinned.
+    // A page not in the cache can be pinned before it's inserted.
+    pub(crate) fn pin(&mut self, page_id: u32) {
+        *self.pinned.entry(page_id).or_insert(0) += 1;
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn unpin(&mut self, page_id: u32) {
+        if let Some(count) = self.pinned.get_mut(&page_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.pinned.remove(&page_id);
+            }
         }
-        Some(result)
     }
 
     #[inline]
-    fn distribute_new_index(&mut self) -> u32 {
-        if self.lru_map.len() < self.page_count {  // is not full
-            self.lru_map.len() as u32
-        } else {
-            let (_, tail_value) = self.lru_map.remove_tail().expect("data error");
-            tail_value
-        }
+    #[allow(dead_code)]
+    pub(crate) fn is_pinned(&self, page_id: u32) -> bool {
+        self.pinned.contains_key(&page_id)
     }
 
-    pub(crate) fn insert_to_cache(&mut self, page: &RawPage) {
-        match self.lru_map.find(page.page_id) {
-            Some(index) => {  // override
-                let offset = (index as usize) * (self.page_size as usize);
-                unsafe {
-                    page.copy_to_ptr(self.data.add(offset));
-                }
+    // The policy knows nothing about the pins,
+    // the pinned victims are given back to it as accessed.
+    fn choose_unpinned_victim(&mut self) -> Option<u32> {
+        let mut skipped = vec![];
+        let mut result = None;
+
+        while let Some(victim) = self.policy.choose_victim() {
+            if !self.pinned.contains_key(&victim) {
+                result = Some(victim);
+                break;
             }
+            skipped.push(victim);
+        }
 
-            None => {
-                let index = self.distribute_new_index();
-                let offset = (index as usize) * (self.page_size as usize);
-                unsafe {
-                    page.copy_to_ptr(self.data.add(offset));
-                }
-                let _ = self.lru_map.insert(page.page_id, index);
-            },
-        };
+        for page_id in skipped {
+            self.policy.on_insert(page_id);
+            self.policy.on_access(page_id);
+        }
+
+        result
     }
 
-}
+    // count of pages can be held
+    #[inline]
+    #[allow(dead_code)]
+    pub(crate) fn capacity(&self) -> usize {
+        self.page_count
+    }
 
-impl Drop for PageCache {
+    #[inline]
+    #[allow(dead_code)]
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
 
-    fn drop (&mut self) {
-        unsafe {
-            free(self.data.cast())
+    // the bytes of the pages currently allocated
+    #[allow(dead_code)]
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.slots.values().map(|slot| slot.len()).sum()
+    }
+
+    // The cache never holds more than bytes, it's below the capacity,
+    // at least one page is held. The pages above the limit are evicted at once.
+    pub(crate) fn set_soft_limit(&mut self, bytes: usize) {
+        let pages = std::cmp::max(bytes / (self.page_size as usize), 1);
+        self.soft_limit = std::cmp::min(pages, self.page_count);
+        self.shrink_to(self.soft_limit);
+    }
+
+    // evict a few pages until there is room under the soft limit
+    fn evict_toward_soft_limit(&mut self) {
+        let mut evicted = 0;
+        while self.slots.len() >= self.soft_limit && evicted < SOFT_LIMIT_EVICTION_BATCH {
+            let victim = match self.choose_unpinned_victim() {
+                Some(victim) => victim,
+                None => break,
+            };
+            self.slots.remove(&victim).expect("data error");
+            evicted += 1;
+        }
+    }
+
+    // drop all the pages and free the memory
+    pub(crate) fn clear(&mut self) {
+        self.slots = HashMap::new();
+        self.policy = self.policy_kind.create(self.page_count);
+    }
+
+    pub(crate) fn get_from_cache(&mut self, page_id: u32) -> Option<RawPage> {
+        let slot = self.slots.get(&page_id)?;
+        let mut result = RawPage::new(page_id, slot.len() as u32);
+        result.data.copy_from_slice(slot);
+        self.policy.on_access(page_id);
+        Some(result)
+    }
+
+    // the eviction policy is untouched
+    pub(crate) fn peek_from_cache(&self, page_id: u32) -> Option<RawPage> {
+        let slot = self.slots.get(&page_id)?;
+        let mut result = RawPage::new(page_id, slot.len() as u32);
+        result.data.copy_from_slice(slot);
+        Some(result)
+    }
+
+    // the cached page has the same bytes, the eviction policy is untouched
+    pub(crate) fn holds_image(&self, page: &RawPage) -> bool {
+        match self.slots.get(&page.page_id) {
+            Some(slot) => **slot == *page.data.as_slice(),
+            None => false,
+        }
+    }
+
+    // Evict the coldest pages until at most target_entries remain,
+    // return the count of evicted pages.
+    // The pinned pages are kept, so more may remain.
+    //
+    // All the pages in the cache are clean,
+    // because a page is written to the journal before the cache,
+    // so evicting them never loses data.
+    pub(crate) fn shrink_to(&mut self, target_entries: usize) -> usize {
+        let mut evicted = 0;
+
+        while self.slots.len() > target_entries {
+            let victim = match self.choose_unpinned_victim() {
+                Some(victim) => victim,
+                None => break,
+            };
+            self.slots.remove(&victim).expect("data error");
+            evicted += 1;
+        }
+
+        if evicted > 0 {
+            self.slots.shrink_to_fit();
+        }
+
+        evicted
+    }
+
+    // drop the page if it's cached
+    pub(crate) fn invalidate(&mut self, page_id: u32) {
+        if self.slots.remove(&page_id).is_some() {
+            self.policy.on_remove(page_id);
         }
     }
 
+    pub(crate) fn insert_to_cache(&mut self, page: &RawPage) -> DbResult<()> {
+        if let Some(slot) = self.slots.get_mut(&page.page_id) {  // override
+            if slot.len() == page.data.len() {
+                slot.copy_from_slice(&page.data);
+            } else {
+                *slot = page.data.clone().into_boxed_slice();
+            }
+            self.policy.on_access(page.page_id);
+            return Ok(());
+        }
+
+        if self.soft_limit < self.page_count {
+            self.evict_toward_soft_limit();
+        }
+
+        // exceeding the capacity is never allowed,
+        // the slot of the victim is reused
+        let slot = if self.slots.len() < self.page_count {
+            page.data.clone().into_boxed_slice()
+        } else {
+            let victim = self.choose_unpinned_victim().ok_or(DbErr::CacheFull)?;
+            let mut slot = self.slots.remove(&victim).expect("data error");
+            if slot.len() == page.data.len() {
+                slot.copy_from_slice(&page.data);
+                slot
+            } else {
+                page.data.clone().into_boxed_slice()
+            }
+        };
+
+        self.slots.insert(page.page_id, slot);
+        self.policy.on_insert(page.page_id);
+        Ok(())
+    }
+
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::page::pagecache::{LruMap, PageCache};
+    use crate::page::pagecache::{LruMap, PageCache, CachePolicy, EvictionPolicy, LruPolicy, TwoQueuePolicy};
     use crate::page::RawPage;
+    use crate::DbErr;
 
     fn make_raw_page(page_id: u32) -> RawPage {
         let mut page = RawPage::new(page_id, 4096);
@@ -332,7 +626,7 @@ mod tests {
 
     #[test]
     fn page_cache() {
-        let mut page_cache = PageCache::new(3, 4096);
+        let mut page_cache = PageCache::new(3, 4096, CachePolicy::Lru);
 
         let mut ten_pages = Vec::with_capacity(TEST_PAGE_LEN as usize);
 
@@ -341,7 +635,7 @@ mod tests {
         }
 
         for i in 0..3 {
-            page_cache.insert_to_cache(&ten_pages[i as usize]);
+            page_cache.insert_to_cache(&ten_pages[i as usize]).unwrap();
         }
 
         for i in 0..3 {
@@ -354,7 +648,7 @@ mod tests {
 
 
         for i in 3..6 {
-            page_cache.insert_to_cache(&ten_pages[i as usize]);
+            page_cache.insert_to_cache(&ten_pages[i as usize]).unwrap();
         }
 
         for i in 0..3 {
@@ -372,4 +666,201 @@ mod tests {
         }
     }
 
+    #[test]
+    fn shrink_to() {
+        let mut page_cache = PageCache::new(5, 4096, CachePolicy::Lru);
+
+        let mut ten_pages = Vec::with_capacity(TEST_PAGE_LEN as usize);
+
+        for i in 0..TEST_PAGE_LEN {
+            ten_pages.push(make_raw_page(i))
+        }
+
+        for i in 0..5 {
+            page_cache.insert_to_cache(&ten_pages[i as usize]).unwrap();
+        }
+
+        // page 0 becomes the hottest
+        assert!(page_cache.get_from_cache(0).is_some());
+
+        assert_eq!(page_cache.allocated_bytes(), 5 * 4096);
+        assert_eq!(page_cache.shrink_to(2), 3);
+        assert_eq!(page_cache.len(), 2);
+        assert_eq!(page_cache.allocated_bytes(), 2 * 4096);
+        assert!(page_cache.get_from_cache(0).is_some());
+        assert!(page_cache.get_from_cache(4).is_some());
+        for i in 1..4 {
+            assert!(page_cache.get_from_cache(i).is_none());
+        }
+
+        // the new slots don't override the alive pages
+        for i in 5..8 {
+            page_cache.insert_to_cache(&ten_pages[i as usize]).unwrap();
+        }
+        assert_eq!(page_cache.len(), 5);
+
+        for i in [0, 4, 5, 6, 7].iter() {
+            let page = page_cache.get_from_cache(*i).unwrap();
+            assert_eq!(page.data, ten_pages[*i as usize].data);
+        }
+
+        assert_eq!(page_cache.allocated_bytes(), 5 * 4096);
+
+        assert_eq!(page_cache.shrink_to(0), 5);
+        assert_eq!(page_cache.len(), 0);
+        assert_eq!(page_cache.allocated_bytes(), 0);
+        assert_eq!(page_cache.shrink_to(0), 0);
+    }
+
+    // two hot pages, then a scan of the pages read once
+    fn replay_scan(policy: &mut dyn EvictionPolicy) -> Vec<u32> {
+        for page_id in 0..4 {
+            policy.on_insert(page_id);
+        }
+        policy.on_access(0);
+        policy.on_access(1);
+
+        let mut victims = vec![];
+        for page_id in 4..8 {
+            victims.push(policy.choose_victim().unwrap());
+            policy.on_insert(page_id);
+        }
+        victims
+    }
+
+    #[test]
+    fn victims_of_policies() {
+        let mut lru_policy = LruPolicy::new();
+        assert_eq!(replay_scan(&mut lru_policy), vec![2, 3, 0, 1]);
+
+        // the hot pages survive the scan
+        let mut two_queue_policy = TwoQueuePolicy::new(4);
+        assert_eq!(replay_scan(&mut two_queue_policy), vec![2, 3, 4, 5]);
+
+        // the FIFO queue is not drained below its share
+        two_queue_policy.on_remove(6);
+        assert_eq!(two_queue_policy.choose_victim(), Some(0));
+        assert_eq!(two_queue_policy.choose_victim(), Some(1));
+        assert_eq!(two_queue_policy.choose_victim(), Some(7));
+        assert_eq!(two_queue_policy.choose_victim(), None);
+    }
+
+    #[test]
+    fn two_queue_page_cache() {
+        let mut page_cache = PageCache::new(4, 4096, CachePolicy::TwoQueue);
+
+        let pages: Vec<RawPage> = (0..TEST_PAGE_LEN).map(make_raw_page).collect();
+        for page in &pages[0..2] {
+            page_cache.insert_to_cache(page).unwrap();
+        }
+        assert!(page_cache.get_from_cache(0).is_some());
+        assert!(page_cache.get_from_cache(1).is_some());
+
+        for page in &pages[2..] {
+            page_cache.insert_to_cache(page).unwrap();
+        }
+        assert_eq!(page_cache.len(), 4);
+
+        let page = page_cache.get_from_cache(0).unwrap();
+        assert_eq!(page.data, pages[0].data);
+        let page = page_cache.get_from_cache(1).unwrap();
+        assert_eq!(page.data, pages[1].data);
+        for page_id in 2..8 {
+            assert!(page_cache.get_from_cache(page_id).is_none());
+        }
+
+        page_cache.clear();
+        assert_eq!(page_cache.len(), 0);
+        assert!(page_cache.get_from_cache(0).is_none());
+    }
+
+    #[test]
+    fn soft_limit() {
+        let mut page_cache = PageCache::new(16, 4096, CachePolicy::Lru);
+        let pages: Vec<RawPage> = (0..64).map(make_raw_page).collect();
+
+        for page in &pages[0..16] {
+            page_cache.insert_to_cache(page).unwrap();
+        }
+        assert_eq!(page_cache.len(), 16);
+        assert_eq!(page_cache.allocated_bytes(), 16 * 4096);
+
+        // shrinks at once
+        page_cache.set_soft_limit(8 * 4096);
+        assert_eq!(page_cache.len(), 8);
+        assert_eq!(page_cache.allocated_bytes(), 8 * 4096);
+        page_cache.insert_to_cache(&pages[16]).unwrap();
+        assert_eq!(page_cache.len(), 8);
+
+        // continuous reads
+        for _ in 0..4 {
+            for (page_id, page) in pages.iter().enumerate() {
+                if page_cache.get_from_cache(page_id as u32).is_none() {
+                    page_cache.insert_to_cache(page).unwrap();
+                }
+                assert_eq!(page_cache.len(), 8);
+                assert_eq!(page_cache.allocated_bytes(), 8 * 4096);
+            }
+        }
+
+        for page_id in 56..64 {
+            let page = page_cache.get_from_cache(page_id).unwrap();
+            assert_eq!(page.data, pages[page_id as usize].data);
+        }
+
+        // at least one page
+        page_cache.set_soft_limit(0);
+        assert_eq!(page_cache.allocated_bytes(), 4096);
+        for page in &pages[0..8] {
+            page_cache.insert_to_cache(page).unwrap();
+            assert_eq!(page_cache.len(), 1);
+        }
+        assert_eq!(page_cache.allocated_bytes(), 4096);
+        assert!(page_cache.get_from_cache(7).is_some());
+    }
+
+    #[test]
+    fn pin() {
+        let mut page_cache = PageCache::new(4, 4096, CachePolicy::Lru);
+        let pages: Vec<RawPage> = (0..64).map(make_raw_page).collect();
+
+        page_cache.pin(0);
+        page_cache.pin(0);
+        page_cache.insert_to_cache(&pages[0]).unwrap();
+
+        // the coldest page survives the churn
+        for page in &pages[1..] {
+            page_cache.insert_to_cache(page).unwrap();
+        }
+        assert_eq!(page_cache.len(), 4);
+        assert_eq!(page_cache.peek_from_cache(0).unwrap().data, pages[0].data);
+        assert_eq!(page_cache.shrink_to(0), 3);
+        assert_eq!(page_cache.len(), 1);
+
+        // nested
+        page_cache.unpin(0);
+        assert!(page_cache.is_pinned(0));
+        page_cache.unpin(0);
+        assert!(!page_cache.is_pinned(0));
+        assert_eq!(page_cache.shrink_to(0), 1);
+
+        for page in &pages[0..4] {
+            page_cache.pin(page.page_id);
+            page_cache.insert_to_cache(page).unwrap();
+        }
+        match page_cache.insert_to_cache(&pages[4]) {
+            Err(DbErr::CacheFull) => (),
+            _ => panic!("should be full"),
+        }
+        assert_eq!(page_cache.len(), 4);
+
+        // overriding a pinned page needs no room
+        page_cache.insert_to_cache(&pages[1]).unwrap();
+
+        page_cache.unpin(2);
+        page_cache.insert_to_cache(&pages[4]).unwrap();
+        assert!(page_cache.peek_from_cache(2).is_none());
+        assert!(page_cache.peek_from_cache(4).is_some());
+    }
+
 }
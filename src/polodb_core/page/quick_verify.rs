@@ -0,0 +1,140 @@
+use std::fmt;
+use crate::DbResult;
+use crate::error::DbErr;
+use super::page::{RawPage, PageType};
+use super::page_handler::PageHandler;
+use super::header_page_wrapper::HeaderPageWrapper;
+
+/**
+ * The result of a quick verification,
+ * only the header and a strided sample of pages are checked.
+ */
+#[derive(Debug, Clone)]
+pub struct QuickVerifyReport {
+    pub header_ok:        bool,
+
+    // pages checked, excluding the header
+    pub checked_pages:    u32,
+
+    // pages below the null page bar, excluding the header
+    pub total_pages:      u32,
+
+    pub mismatched_pages: Vec<u32>,
+}
+
+impl QuickVerifyReport {
+
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.header_ok && self.mismatched_pages.is_empty()
+    }
+
+    // checked / total pages, 1.0 if there is no page to check
+    pub fn confidence(&self) -> f64 {
+        if self.total_pages == 0 {
+            return 1.0;
+        }
+        (self.checked_pages as f64) / (self.total_pages as f64)
+    }
+
+}
+
+impl fmt::Display for QuickVerifyReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "QuickVerifyReport(header_ok: {}, checked_pages: {}/{}, mismatched_pages: {:?})",
+               self.header_ok, self.checked_pages, self.total_pages, self.mismatched_pages)
+    }
+
+}
+
+fn verify_header(wrapper: &HeaderPageWrapper) -> bool {
+    if !wrapper.is_title_valid() {
+        return false;
+    }
+
+    let null_page_bar = wrapper.get_null_page_bar();
+    if null_page_bar < 2 {
+        return false;
+    }
+
+    let free_list_size = wrapper.get_free_list_size();
+    if free_list_size as usize > HeaderPageWrapper::free_list_max_size(wrapper.0.len()) {
+        return false;
+    }
+
+    (0..free_list_size).all(|index| {
+        let pid = wrapper.get_free_list_content(index);
+        pid != 0 && pid < null_page_bar
+    })
+}
+
+// an empty page, or a page with a known magic
+fn verify_page(page: &RawPage) -> bool {
+    if page.data[0..2] == [0, 0] {
+        return true;
+    }
+
+    let mut magic: [u8; 2] = [0; 2];
+    magic.copy_from_slice(&page.data[0..2]);
+
+    PageType::from_magic(magic).is_ok()
+}
+
+// sample_rate is clamped to [0.0, 1.0],
+// 0.0 checks the header only, 1.0 checks every page
+pub(crate) fn quick_verify(page_handler: &mut PageHandler, sample_rate: f64) -> DbResult<QuickVerifyReport> {
+    let first_page = page_handler.get_first_page()?;
+    let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
+    let header_ok = verify_header(&first_page_wrapper);
+
+    let null_page_bar = first_page_wrapper.get_null_page_bar();
+    let total_pages = null_page_bar.saturating_sub(1);
+    let shadow_header_pid = first_page_wrapper.get_shadow_header_pid();
+
+    let mut report = QuickVerifyReport {
+        header_ok,
+        checked_pages: 0,
+        total_pages,
+        mismatched_pages: vec![],
+    };
+
+    let sample_rate = sample_rate.clamp(0.0, 1.0);
+    if !header_ok || sample_rate == 0.0 || total_pages == 0 {
+        return Ok(report);
+    }
+
+    let stride = ((1.0 / sample_rate).ceil() as u32).max(1);
+
+    // the sampled pages are not inserted into the cache
+    page_handler.begin_scan();
+
+    let mut pid = 1;
+    while pid < null_page_bar {
+        let page = match page_handler.pipeline_read_page(pid) {
+            Ok(page) => page,
+            Err(DbErr::PageChecksumMismatch { .. }) => {
+                report.mismatched_pages.push(pid);
+                report.checked_pages += 1;
+                pid += stride;
+                continue;
+            }
+            Err(err) => {
+                page_handler.end_scan();
+                return Err(err);
+            }
+        };
+
+        // the shadow is a copy of the header
+        if pid != shadow_header_pid && !verify_page(&page) {
+            report.mismatched_pages.push(pid);
+        }
+        report.checked_pages += 1;
+
+        pid += stride;
+    }
+
+    page_handler.end_scan();
+
+    Ok(report)
+}
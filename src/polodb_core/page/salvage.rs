@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::DbResult;
+use super::page::PageType;
+use super::page_handler::PageHandler;
+use super::header_page_wrapper::{HeaderPageWrapper, SHADOW_HEADER_PID};
+use super::integrity::{self, IntegrityAnomaly};
+
+/**
+ * What is skipped or reconstructed when opening a corrupt database
+ */
+#[derive(Debug, Clone, Default)]
+pub struct SalvageReport {
+    // found by the integrity check before anything is repaired
+    pub anomalies:           Vec<IntegrityAnomaly>,
+
+    pub free_list_rebuilt:   bool,
+
+    // the pages in the rebuilt free list
+    pub recovered_free_pages: u32,
+
+    // the pages can't be read, they are never reused
+    pub unreadable_pages:    Vec<u32>,
+
+    // not referred by the trees, but not blank either,
+    // or an invalid data page, they are kept allocated
+    pub questionable_pages:  Vec<u32>,
+}
+
+impl SalvageReport {
+
+    #[inline]
+    pub fn is_clean(&self) -> bool {
+        self.anomalies.is_empty()
+    }
+
+}
+
+impl fmt::Display for SalvageReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SalvageReport(anomalies: {}, free_list_rebuilt: {}, recovered_free_pages: {}, unreadable_pages: {:?}, questionable_pages: {:?})",
+               self.anomalies.len(), self.free_list_rebuilt, self.recovered_free_pages,
+               self.unreadable_pages, self.questionable_pages)
+    }
+
+}
+
+fn is_free_list_anomaly(anomaly: &IntegrityAnomaly) -> bool {
+    matches!(
+        anomaly,
+        IntegrityAnomaly::FreeListSizeOutOfRange { .. } |
+        IntegrityAnomaly::BrokenFreeListChain { .. } |
+        IntegrityAnomaly::InvalidFreePid { .. } |
+        IntegrityAnomaly::DuplicateFreePid { .. } |
+        IntegrityAnomaly::AllocatedAndFree { .. }
+    )
+}
+
+// a page that holds nothing worth keeping
+fn is_blank_page(magic: [u8; 2]) -> bool {
+    magic == [0, 0]
+        || magic == PageType::Undefined.to_magic()
+        || magic == PageType::FreeList.to_magic()
+}
+
+// The free list is rebuilt from the pages below the null page bar
+// if it's inconsistent, only the blank pages not referred by the trees are freed,
+// the others are reported.
+pub(crate) fn salvage(page_handler: &mut PageHandler) -> DbResult<SalvageReport> {
+    let (integrity_report, used_pids) = integrity::check_with_used_pids(page_handler)?;
+
+    let mut unreadable_pids = BTreeSet::new();
+    let mut questionable_pids = BTreeSet::new();
+    for anomaly in &integrity_report.anomalies {
+        match anomaly {
+            IntegrityAnomaly::UnreadablePage { pid } => {
+                unreadable_pids.insert(*pid);
+            }
+
+            IntegrityAnomaly::InvalidSlotBar { pid, .. } |
+            IntegrityAnomaly::DataLenMismatch { pid, .. } => {
+                questionable_pids.insert(*pid);
+            }
+
+            _ => (),
+        }
+    }
+
+    let free_list_broken = integrity_report.anomalies.iter().any(is_free_list_anomaly)
+        || page_handler.free_page_count().is_err();
+
+    let mut report = SalvageReport {
+        anomalies: integrity_report.anomalies,
+        ..SalvageReport::default()
+    };
+
+    if free_list_broken {
+        let first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.get_first_page()?);
+        let reserved_pids = first_page_wrapper.get_reserved_pids();
+        let null_page_bar = first_page_wrapper.get_null_page_bar()
+            .min(page_handler.stored_page_count()?);
+
+        let mut free_pids = vec![];
+
+        page_handler.begin_scan();
+        for pid in 1..null_page_bar {
+            if pid == SHADOW_HEADER_PID || reserved_pids.contains(&pid) || used_pids.contains(&pid) {
+                continue;
+            }
+            if unreadable_pids.contains(&pid) || questionable_pids.contains(&pid) {
+                continue;
+            }
+
+            let page = match page_handler.pipeline_read_page(pid) {
+                Ok(page) => page,
+                Err(_) => {
+                    unreadable_pids.insert(pid);
+                    continue;
+                }
+            };
+
+            if is_blank_page([page.data[0], page.data[1]]) {
+                free_pids.push(pid);
+            } else {
+                questionable_pids.insert(pid);
+            }
+        }
+        page_handler.end_scan();
+
+        page_handler.with_write_transaction(|page_handler| {
+            let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(page_handler.get_first_page()?);
+            first_page_wrapper.set_free_list_size(0);
+            first_page_wrapper.set_free_list_page_id(0);
+            page_handler.pipeline_write_page(&first_page_wrapper.0)?;
+
+            if !free_pids.is_empty() {
+                page_handler.free_pages(&free_pids)?;
+            }
+            Ok(())
+        })?;
+
+        report.free_list_rebuilt = true;
+        report.recovered_free_pages = free_pids.len() as u32;
+    }
+
+    #[cfg(feature = "log")]
+    for pid in &unreadable_pids {
+        eprintln!("salvage, unreadable page: {}", *pid);
+    }
+
+    report.unreadable_pages = unreadable_pids.into_iter().collect();
+    report.questionable_pages = questionable_pids.into_iter().collect();
+
+    Ok(report)
+}
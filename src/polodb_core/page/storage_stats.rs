@@ -0,0 +1,31 @@
+use std::fmt;
+
+/**
+ * How much of the database is live and how much is free,
+ * helps to decide when to vacuum.
+ */
+#[derive(Debug, Clone)]
+pub struct StorageStats {
+    pub page_count:          u32,
+
+    // pages on the free list, including the free list pages
+    pub free_pages:          u32,
+
+    // data pages tracked by data_page_map
+    pub tracked_data_pages:  u32,
+
+    // the remaining bytes of the tracked partially-filled data pages
+    pub free_bytes_estimate: u64,
+
+    pub last_commit_db_size: u64,
+}
+
+impl fmt::Display for StorageStats {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StorageStats(page_count: {}, free_pages: {}, tracked_data_pages: {}, free_bytes_estimate: {}, last_commit_db_size: {})",
+               self.page_count, self.free_pages, self.tracked_data_pages,
+               self.free_bytes_estimate, self.last_commit_db_size)
+    }
+
+}
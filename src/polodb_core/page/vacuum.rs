@@ -0,0 +1,191 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::rc::Rc;
+use polodb_bson::{Document, Value};
+use crate::DbResult;
+use crate::btree::{BTreeNode, HEADER_SIZE, ITEM_SIZE};
+use crate::data_ticket::DataTicket;
+use crate::meta_doc_helper::{MetaDocEntry, meta_doc_key};
+use super::page::RawPage;
+use super::page_handler::PageHandler;
+use super::header_page_wrapper::HeaderPageWrapper;
+
+/**
+ * The result of a vacuum
+ */
+#[derive(Debug, Clone, Default)]
+pub struct VacuumStats {
+    pub docs_relocated:  u32,
+
+    // data pages emptied by packing the documents,
+    // including the empty ones kept for reuse
+    pub pages_freed:     u32,
+
+    // bytes of the file truncated
+    pub bytes_reclaimed: u64,
+}
+
+impl fmt::Display for VacuumStats {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VacuumStats(docs_relocated: {}, pages_freed: {}, bytes_reclaimed: {})",
+               self.docs_relocated, self.pages_freed, self.bytes_reclaimed)
+    }
+
+}
+
+// how the entries of a btree are relocated
+#[derive(Clone, Copy, PartialEq)]
+enum TreeKind {
+    Documents,
+
+    // the entries refer to the tickets of the documents,
+    // which are rewritten to the relocated ones
+    Index,
+}
+
+struct VacuumContext {
+    item_size:      u32,
+    old_pids:       BTreeSet<u32>,
+    new_pids:       BTreeSet<u32>,
+    docs_relocated: u32,
+
+    // (pid, index) of the old ticket -> the new ticket
+    moved_tickets:  HashMap<(u32, u16), DataTicket>,
+}
+
+impl VacuumContext {
+
+    // the index entry with the ticket of the document rewritten,
+    // None if the document is not moved
+    fn remap_index_entry(&self, bytes: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        let mut entry_doc = Document::from_bytes(bytes)?;
+        let doc_ticket = match entry_doc.get("value") {
+            Some(Value::Binary(ticket_bytes)) if ticket_bytes.len() == 6 => DataTicket::from_store_bytes(ticket_bytes),
+            _ => return Ok(None),
+        };
+        let new_ticket = match self.moved_tickets.get(&(doc_ticket.pid, doc_ticket.index)) {
+            Some(new_ticket) => new_ticket,
+            None => return Ok(None),
+        };
+
+        entry_doc.insert("value".into(), Value::Binary(Rc::new(new_ticket.to_store_bytes().to_vec())));
+        Ok(Some(entry_doc.to_bytes()?))
+    }
+
+    fn relocate_ticket(&mut self, page_handler: &mut PageHandler, data_ticket: &DataTicket, kind: TreeKind) -> DbResult<DataTicket> {
+        let mut bytes = match page_handler.get_raw_from_ticket(data_ticket)? {
+            Some(bytes) => bytes,
+            None => return Ok(data_ticket.clone()),
+        };
+
+        if kind == TreeKind::Index {
+            if let Some(remapped) = self.remap_index_entry(&bytes)? {
+                bytes = remapped;
+            }
+        }
+
+        // the page may be freed, and reused by the new ticket
+        page_handler.release_data_ticket(data_ticket)?;
+        let new_ticket = page_handler.store_bytes(&bytes)?;
+
+        self.old_pids.insert(data_ticket.pid);
+        self.new_pids.insert(new_ticket.pid);
+        self.moved_tickets.insert((data_ticket.pid, data_ticket.index), new_ticket.clone());
+        self.docs_relocated += 1;
+
+        Ok(new_ticket)
+    }
+
+    fn relocate_btree(&mut self, page_handler: &mut PageHandler, parent_pid: u32, pid: u32, kind: TreeKind) -> DbResult<()> {
+        let page = page_handler.pipeline_read_page(pid)?;
+        let btree_node = BTreeNode::from_raw(&page, parent_pid, self.item_size, page_handler)?;
+        if btree_node.is_empty() {
+            return Ok(());
+        }
+
+        let mut content = Vec::with_capacity(btree_node.content.len());
+        for item in &btree_node.content {
+            let mut new_item = item.clone();
+            new_item.data_ticket = self.relocate_ticket(page_handler, &item.data_ticket, kind)?;
+            content.push(new_item);
+        }
+
+        let new_btree_node = btree_node.clone_with_contents(content, btree_node.indexes.clone());
+        let mut page = RawPage::new(pid, page_handler.page_size);
+        new_btree_node.to_raw(&mut page)?;
+        page_handler.pipeline_write_page(&page)?;
+
+        for child_pid in &btree_node.indexes {
+            if *child_pid != 0 {
+                self.relocate_btree(page_handler, pid, *child_pid, kind)?;
+            }
+        }
+
+        Ok(())
+    }
+
+}
+
+fn collect_docs(page_handler: &mut PageHandler, item_size: u32, parent_pid: u32, pid: u32, result: &mut Vec<Rc<Document>>) -> DbResult<()> {
+    let page = page_handler.pipeline_read_page(pid)?;
+    let btree_node = BTreeNode::from_raw(&page, parent_pid, item_size, page_handler)?;
+
+    for item in &btree_node.content {
+        if let Some(doc) = page_handler.get_doc_from_ticket(&item.data_ticket)? {
+            result.push(doc);
+        }
+    }
+
+    for child_pid in &btree_node.indexes {
+        if *child_pid != 0 {
+            collect_docs(page_handler, item_size, pid, *child_pid, result)?;
+        }
+    }
+
+    Ok(())
+}
+
+// relocate the documents of the meta tree, the collections and the indexes,
+// must be called in a write transaction
+pub(crate) fn relocate_all_docs(page_handler: &mut PageHandler) -> DbResult<VacuumStats> {
+    let mut ctx = VacuumContext {
+        item_size: (page_handler.page_size - HEADER_SIZE) / ITEM_SIZE,
+        old_pids: BTreeSet::new(),
+        new_pids: BTreeSet::new(),
+        docs_relocated: 0,
+        moved_tickets: HashMap::new(),
+    };
+
+    let first_page = page_handler.get_first_page()?;
+    let meta_pid = HeaderPageWrapper::from_raw_page(first_page).get_meta_page_id();
+
+    let mut meta_docs = vec![];
+    collect_docs(page_handler, ctx.item_size, 0, meta_pid, &mut meta_docs)?;
+
+    ctx.relocate_btree(page_handler, 0, meta_pid, TreeKind::Documents)?;
+
+    for meta_doc in &meta_docs {
+        let entry = MetaDocEntry::from_doc(meta_doc);
+        ctx.relocate_btree(page_handler, 0, entry.root_pid, TreeKind::Documents)?;
+
+        // the documents are relocated first,
+        // so the index entries are rewritten to the new tickets
+        if let Some(Value::Document(indexes_doc)) = meta_doc.get(meta_doc_key::INDEXES) {
+            for (_, index_option) in indexes_doc.iter() {
+                if let Value::Document(option_doc) = index_option {
+                    let root_pid = option_doc.get(meta_doc_key::index::ROOT_PID).unwrap().unwrap_int();
+                    ctx.relocate_btree(page_handler, 0, root_pid as u32, TreeKind::Index)?;
+                }
+            }
+        }
+    }
+
+    let pages_freed = ctx.old_pids.len().saturating_sub(ctx.new_pids.len()) as u32;
+
+    Ok(VacuumStats {
+        docs_relocated: ctx.docs_relocated,
+        pages_freed,
+        bytes_reclaimed: 0,
+    })
+}
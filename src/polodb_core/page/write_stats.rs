@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+// count of the recent commits/checkpoints
+// considered by the rolling ratio
+const WRITE_STATS_WINDOW: usize = 64;
+
+enum WriteEvent {
+    Commit {
+        journal_frames: u32,
+        logical_pages:  u32,
+    },
+    Checkpoint {
+        main_file_pages: u32,
+    },
+}
+
+/**
+ * Physical writes of the recent commits and checkpoints
+ */
+#[derive(Debug, Clone, Default)]
+pub struct WriteStats {
+    // frames appended to the journal by the last write transaction
+    pub last_commit_journal_frames:  u32,
+
+    // distinct pages changed by the last write transaction
+    pub last_commit_logical_pages:   u32,
+
+    // pages written to the main file by the last checkpoint
+    pub last_checkpoint_pages:       u32,
+
+    // (journal frames + main file pages) / logical pages
+    // over the recent window, 0.0 if nothing is committed
+    pub write_amplification:         f64,
+}
+
+impl fmt::Display for WriteStats {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WriteStats(last_commit_journal_frames: {}, last_commit_logical_pages: {}, last_checkpoint_pages: {}, write_amplification: {:.3})",
+               self.last_commit_journal_frames, self.last_commit_logical_pages,
+               self.last_checkpoint_pages, self.write_amplification)
+    }
+
+}
+
+pub(crate) struct WriteStatsTracker {
+    events:                      VecDeque<WriteEvent>,
+    last_commit_journal_frames:  u32,
+    last_commit_logical_pages:   u32,
+    last_checkpoint_pages:       u32,
+}
+
+impl WriteStatsTracker {
+
+    pub(crate) fn new() -> WriteStatsTracker {
+        WriteStatsTracker {
+            events: VecDeque::with_capacity(WRITE_STATS_WINDOW),
+            last_commit_journal_frames: 0,
+            last_commit_logical_pages: 0,
+            last_checkpoint_pages: 0,
+        }
+    }
+
+    fn push_event(&mut self, event: WriteEvent) {
+        if self.events.len() >= WRITE_STATS_WINDOW {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub(crate) fn record_commit(&mut self, journal_frames: u32, logical_pages: u32) {
+        self.last_commit_journal_frames = journal_frames;
+        self.last_commit_logical_pages = logical_pages;
+        self.push_event(WriteEvent::Commit {
+            journal_frames,
+            logical_pages,
+        });
+    }
+
+    pub(crate) fn record_checkpoint(&mut self, main_file_pages: u32) {
+        self.last_checkpoint_pages = main_file_pages;
+        self.push_event(WriteEvent::Checkpoint {
+            main_file_pages,
+        });
+    }
+
+    pub(crate) fn write_amplification(&self) -> f64 {
+        let mut physical: u64 = 0;
+        let mut logical: u64 = 0;
+
+        for event in &self.events {
+            match event {
+                WriteEvent::Commit { journal_frames, logical_pages } => {
+                    physical += *journal_frames as u64;
+                    logical += *logical_pages as u64;
+                }
+
+                WriteEvent::Checkpoint { main_file_pages } => {
+                    physical += *main_file_pages as u64;
+                }
+
+            }
+        }
+
+        if logical == 0 {
+            return 0.0;
+        }
+
+        (physical as f64) / (logical as f64)
+    }
+
+    pub(crate) fn stats(&self) -> WriteStats {
+        WriteStats {
+            last_commit_journal_frames: self.last_commit_journal_frames,
+            last_commit_logical_pages: self.last_commit_logical_pages,
+            last_checkpoint_pages: self.last_checkpoint_pages,
+            write_amplification: self.write_amplification(),
+        }
+    }
+
+}
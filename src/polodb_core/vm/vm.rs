@@ -633,7 +633,8 @@ impl<'a> VM<'a> {
 impl<'a> Drop for VM<'a> {
 
     fn drop(&mut self) {
-        if self.rollback_on_drop {
+        // the transaction maybe rolled back by the page handler
+        if self.rollback_on_drop && self.page_handler.transaction_type().is_some() {
             let result = self.page_handler.rollback();
             #[cfg(debug_assertions)]
             if let Err(err) = result {